@@ -0,0 +1,13 @@
+#![no_main]
+
+use compiler_test::fuzz::{self, FuzzCase};
+use libfuzzer_sys::fuzz_target;
+
+// Compares the JIT at each opt level with the interpreter, and reports a
+// mismatch with the smallest program that still shows it.
+fuzz_target!(|case: FuzzCase| {
+    if let Err(mismatch) = fuzz::check(&case) {
+        let minimized = fuzz::minimize(&case, &mismatch);
+        panic!("{}", fuzz::reproducer(&minimized, &mismatch));
+    }
+});
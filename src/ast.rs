@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Program {
@@ -27,6 +26,15 @@ pub enum Stmt {
     EnumDecl(EnumDecl),
     EnumDef(EnumDef),
     TypeAlias(TypeAlias),
+    // a statement annotated with where it starts in the source, for debug info
+    Spanned(Span, Box<Stmt>),
+}
+
+// 1-based, like compiler diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -39,6 +47,9 @@ pub enum AstType {
     U16,
     U32,
     U64,
+    // as wide as the target's pointers, like `size_t` and `ptrdiff_t`
+    Usize,
+    Isize,
     F32,
     F64,
     Bool,
@@ -47,6 +58,12 @@ pub enum AstType {
     Struct(String),
     Enum(String),
     TypeAlias(String),
+    FuncPtr {
+        params: Vec<AstType>,
+        return_type: Option<Box<AstType>>,
+    },
+    // C's `va_list`, only meaningful inside a variadic function
+    VaList,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -123,6 +140,12 @@ pub struct FuncDecl {
     pub name: String,
     pub params: Vec<(String, AstType)>,
     pub return_type: Option<AstType>,
+    // accepts extra arguments after `params`, like C's `...`
+    #[serde(default)]
+    pub variadic: bool,
+    // defined outside the program (libc, host callbacks) and imported by name
+    #[serde(default)]
+    pub extern_: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -137,6 +160,13 @@ pub struct FuncCall {
     pub args: Vec<Expr>,
 }
 
+// call through a function pointer value, e.g. `table(i)(a, b)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallIndirect {
+    pub callee: Box<Expr>,
+    pub args: Vec<Expr>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IfStmt {
     pub condition: Box<Expr>,
@@ -146,10 +176,27 @@ pub struct IfStmt {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LoopStmt {
+    #[serde(default)]
+    pub kind: LoopKind,
     pub condition: Box<Expr>,
     pub body: Block,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum LoopKind {
+    // while (condition) { body }
+    #[default]
+    While,
+    // do { body } while (condition);
+    DoWhile,
+    // for (init; condition; step) { body }
+    // `init` is scoped to the loop and `step` also runs on `continue`.
+    For {
+        init: Option<Box<Stmt>>,
+        step: Option<Box<Stmt>>,
+    },
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Variable_ {
     pub name: String,
@@ -177,6 +224,9 @@ pub enum Expr {
     Binary(Box<Binary>),
     Unary(Box<Unary>),
     FuncCall(FuncCall),
+    // address of a declared function, typed as `AstType::FuncPtr`
+    FuncAddr(String),
+    CallIndirect(CallIndirect),
     StructDef(StructDef),
     EnumDef(EnumDef),
 }
@@ -199,8 +249,97 @@ pub struct Return {
     pub value: Option<Box<Expr>>,
 }
 
+impl AstType {
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            AstType::I8
+                | AstType::I16
+                | AstType::I32
+                | AstType::I64
+                | AstType::U8
+                | AstType::U16
+                | AstType::U32
+                | AstType::U64
+                | AstType::Usize
+                | AstType::Isize
+                | AstType::Char
+        )
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, AstType::I8 | AstType::I16 | AstType::I32 | AstType::I64 | AstType::Isize)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, AstType::F32 | AstType::F64)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float() || *self == AstType::Bool
+    }
+}
+
+impl FuncDecl {
+    // the type of a pointer to this function
+    pub fn func_ptr_type(&self) -> AstType {
+        AstType::FuncPtr {
+            params: self.params.iter().map(|(_, type_)| type_.clone()).collect(),
+            return_type: self.return_type.clone().map(Box::new),
+        }
+    }
+}
+
+impl Stmt {
+    // the statement under any `Spanned` annotations
+    pub fn unspanned(&self) -> &Stmt {
+        match self {
+            Stmt::Spanned(_, stmt) => stmt.unspanned(),
+            stmt => stmt,
+        }
+    }
+
+    pub fn unspanned_mut(&mut self) -> &mut Stmt {
+        match self {
+            Stmt::Spanned(_, stmt) => stmt.unspanned_mut(),
+            stmt => stmt,
+        }
+    }
+}
+
+// Removes every `Spanned` annotation, for comparing ASTs by structure alone.
+fn strip_spans(stmts: &mut [Stmt]) {
+    for stmt in stmts.iter_mut() {
+        while let Stmt::Spanned(_, inner) = stmt {
+            *stmt = std::mem::replace(&mut **inner, Stmt::Break);
+        }
+        match stmt {
+            Stmt::Block(block) | Stmt::FuncDef(FuncDef { body: block, .. }) => strip_spans(block),
+            Stmt::If(if_stmt) => {
+                strip_spans(&mut if_stmt.then_branch);
+                if let Some(else_branch) = &mut if_stmt.else_branch {
+                    strip_spans(else_branch);
+                }
+            }
+            Stmt::Loop(loop_stmt) => {
+                strip_spans(&mut loop_stmt.body);
+                if let LoopKind::For { init, step } = &mut loop_stmt.kind {
+                    for stmt in [init, step].into_iter().flatten() {
+                        strip_spans(std::slice::from_mut(&mut **stmt));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 // Example usage:
 impl Program {
+    pub fn strip_spans(&mut self) {
+        strip_spans(&mut self.statements);
+    }
+
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
@@ -1,16 +1,106 @@
 use crate::{
+    ast::{self, *},
+    compiler::CodegenConfig,
+    debug::{DebugInfo, FunctionDebug, LocalDebug},
+    error::{CompileError, CompileResult},
+    fuel,
+    host::{self, HostArgs, HostReturn},
     module::ModuleType,
-    ast::*,
+    report::{self, CompileOptions, CompileReport},
+    sandbox::{Sandbox, SandboxOptions},
+    stack::{self, StackOptions},
+    trap::{self, TrapRegistry},
+    unwind::UnwindRegistry,
+    variadic::{self, VarargsAbi},
 };
 use cranelift::prelude::*;
-use cranelift_module::{FuncId, Linkage, ModuleResult};
-use std::collections::HashMap;
+use cranelift::prelude::Block;
+use cranelift_codegen::ir::{Endianness, Function, GlobalValue, SourceLoc, StackSlot, UserExternalName, ValueLabel};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+use std::ptr;
+use target_lexicon::Architecture;
 
 pub struct Codegen {
     module: ModuleType,
     func_ctx: FunctionBuilderContext,
-    functions: HashMap<String, FuncId>,
-    variables: HashMap<String, Variable>,
+    functions: HashMap<String, FunctionEntry>,
+    globals: HashMap<String, GlobalVar>,
+    // signatures `extern` declarations must match, from `Compiler::register_function`
+    extern_signatures: HashMap<String, AstType>,
+    // names the JIT binds to host pointers, from `Compiler::register_symbol` too
+    host_symbols: HashSet<String>,
+    // SysV `%al`-setting trampolines, keyed by callee and vector register count
+    variadic_shims: HashMap<(FuncId, u8), FuncId>,
+    options: CompileOptions,
+    report: CompileReport,
+    debug: Option<DebugInfo>,
+    // `.eh_frame` for JIT code
+    unwind: UnwindRegistry,
+    // trap sites of JIT code, for reporting traps
+    traps: TrapRegistry,
+    // source line of the top-level item being compiled, when the AST has spans
+    line: u32,
+    // what integer `+`, `-`, `*`, negation and narrowing conversions do on overflow
+    overflow: Overflow,
+    // whether JIT functions are compiled to consume fuel, and how much the next
+    // call gets
+    consume_fuel: bool,
+    fuel: u64,
+    // the linear memory and allowed imports of sandboxed code
+    sandbox: Option<Sandbox>,
+    // per-function state, reset by `define_function`
+    scopes: Vec<HashMap<String, LocalVar>>,
+    loops: Vec<LoopTarget>,
+    return_type: Option<AstType>,
+    varargs: Option<VarargsFrame>,
+    // the fuel counter, once the function has used it
+    fuel_counter: Option<GlobalValue>,
+    next_var: usize,
+    // every local declared so far, indexed like the `Variable`s
+    locals: Vec<LocalDebug>,
+    // functions used by the code being compiled, marked `referenced` once it's defined
+    references: HashSet<String>,
+}
+
+struct FunctionEntry {
+    id: FuncId,
+    decl: FuncDecl,
+    defined: bool,
+    referenced: bool,
+}
+
+#[derive(Clone)]
+struct LocalVar {
+    var: Variable,
+    type_: AstType,
+}
+
+#[derive(Clone)]
+struct GlobalVar {
+    id: DataId,
+    type_: AstType,
+}
+
+enum VarRef {
+    Local(LocalVar),
+    Global(GlobalVar),
+}
+
+// what `va_start` needs to know about the current variadic function
+struct VarargsFrame {
+    reg_save_area: StackSlot,
+    // `va_list` offsets just past the named params
+    gp_offset: u32,
+    fp_offset: u32,
+    // where the stack-passed variadic arguments start, relative to the frame pointer
+    overflow_offset: i64,
+}
+
+// where `continue` and `break` jump to for the innermost loop
+struct LoopTarget {
+    continue_block: Block,
+    exit_block: Block,
 }
 
 impl Codegen {
@@ -19,127 +109,913 @@ impl Codegen {
             module,
             func_ctx: FunctionBuilderContext::new(),
             functions: HashMap::new(),
-            variables: HashMap::new(),
+            globals: HashMap::new(),
+            extern_signatures: HashMap::new(),
+            host_symbols: HashSet::new(),
+            variadic_shims: HashMap::new(),
+            options: CompileOptions::default(),
+            report: CompileReport::default(),
+            debug: None,
+            unwind: UnwindRegistry::default(),
+            traps: TrapRegistry::default(),
+            line: 0,
+            overflow: Overflow::Wrap,
+            consume_fuel: false,
+            fuel: 0,
+            sandbox: None,
+            scopes: Vec::new(),
+            loops: Vec::new(),
+            return_type: None,
+            varargs: None,
+            fuel_counter: None,
+            next_var: 0,
+            locals: Vec::new(),
+            references: HashSet::new(),
+        }
+    }
+
+    pub fn module(&self) -> &ModuleType {
+        &self.module
+    }
+
+    pub fn module_mut(&mut self) -> &mut ModuleType {
+        &mut self.module
+    }
+
+    // The module, on its own. Sandboxed code has the address of the sandbox's
+    // memory built in, and that goes away with the `Codegen`, so its module
+    // can't be taken out.
+    pub fn into_module(self) -> CompileResult<ModuleType> {
+        if self.sandbox.is_some() {
+            return Err(CompileError::Unsupported(
+                "taking the module out of a sandboxed codegen".to_string(),
+            ));
+        }
+        Ok(self.module)
+    }
+
+    pub fn expect_extern_signature(&mut self, name: &str, type_: AstType) {
+        self.extern_signatures.insert(name.to_string(), type_);
+    }
+
+    pub fn add_host_symbol(&mut self, name: &str) {
+        self.host_symbols.insert(name.to_string());
+    }
+
+    pub fn set_options(&mut self, options: CompileOptions) {
+        self.options = options;
+    }
+
+    // Applies everything `config` sets, as the methods below would.
+    pub fn configure(&mut self, config: &CodegenConfig) -> CompileResult<()> {
+        self.set_checked_arithmetic(config.checked_arithmetic);
+        self.set_consume_fuel(config.consume_fuel);
+        if let Some(options) = &config.sandbox {
+            self.enable_sandbox(options.clone())?;
+        }
+        if let Some(file) = &config.debug_file {
+            self.enable_debug_info(file);
+        }
+        Ok(())
+    }
+
+    // Makes integer arithmetic in functions compiled from here on trap with
+    // `trap::OVERFLOW` instead of wrapping. `wrapping_*` calls still wrap, and
+    // float-to-integer conversions trap instead of saturating.
+    pub fn set_checked_arithmetic(&mut self, checked: bool) {
+        self.overflow = if checked { Overflow::Trap } else { Overflow::Wrap };
+    }
+
+    // Makes JIT functions compiled from here on take a unit of fuel on entry and
+    // each time a loop checks its condition, trapping with `trap::OUT_OF_FUEL`
+    // once there's none left. Fuel starts at 0; see `set_fuel`.
+    pub fn set_consume_fuel(&mut self, consume: bool) {
+        self.consume_fuel = consume;
+    }
+
+    // The fuel the next call starts with, and after a call, what it left.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> u64 {
+        self.fuel
+    }
+
+    // Compiles the program to keep out of the host's memory: its strings live
+    // in a linear memory of its own, and only the imports `options` allows can
+    // be declared. Has to come before anything is compiled, into a JIT module.
+    pub fn enable_sandbox(&mut self, options: SandboxOptions) -> CompileResult<()> {
+        if !matches!(self.module, ModuleType::JITModule(_)) {
+            return Err(CompileError::Unsupported("sandboxing object code".to_string()));
+        }
+        if !self.functions.is_empty() || !self.globals.is_empty() {
+            return Err(CompileError::Unsupported("sandboxing code after compiling some".to_string()));
         }
+        self.sandbox = Some(Sandbox::new(options));
+        Ok(())
+    }
+
+    // The sandbox's linear memory, where its `char*` values are offsets into.
+    pub fn memory(&self) -> Option<&[u8]> {
+        self.sandbox.as_ref().map(Sandbox::memory)
     }
 
-    pub fn compile_program(&mut self, program: Program) -> ModuleResult<()> {
+    pub fn memory_mut(&mut self) -> Option<&mut [u8]> {
+        self.sandbox.as_mut().map(Sandbox::memory_mut)
+    }
+
+    // Records line tables and variable locations for functions compiled from
+    // here on, for `emit_object` to write as DWARF or, in the JIT, for GDB.
+    pub fn enable_debug_info(&mut self, source_file: &str) {
+        self.debug = Some(DebugInfo::new(source_file));
+    }
+
+    // Finishes an object module into the bytes of an object file, with DWARF
+    // sections if debug info is enabled.
+    pub fn emit_object(self) -> CompileResult<Vec<u8>> {
+        let Some(debug) = &self.debug else {
+            return self.module.emit_object();
+        };
+        let ModuleType::ObjectModule(module) = self.module else {
+            return Err(CompileError::Unsupported("emitting an object from a JIT module".to_string()));
+        };
+        let sections = debug.object_sections(module.isa())?;
+        let mut product = module.finish();
+        sections.write_to(&mut product)?;
+        product.emit().map_err(|err| CompileError::Object(err.to_string()))
+    }
+
+    // what was captured for every function defined so far
+    pub fn report(&self) -> &CompileReport {
+        &self.report
+    }
+
+    pub fn take_report(&mut self) -> CompileReport {
+        std::mem::take(&mut self.report)
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<FuncId> {
+        self.functions.get(name).map(|entry| entry.id)
+    }
+
+    pub fn compile_program(&mut self, program: Program) -> CompileResult<()> {
         for stmt in program.statements {
             self.compile_stmt(stmt)?;
         }
         Ok(())
     }
 
-    fn compile_stmt(&mut self, stmt: Stmt) -> ModuleResult<()> {
+    pub fn finalize(&mut self) -> CompileResult<()> {
+        self.check_definitions()?;
+        if let ModuleType::JITModule(jit) = &mut self.module {
+            jit.finalize_definitions()?;
+            self.unwind.register(jit.isa(), |func_id| jit.get_finalized_function(func_id))?;
+            self.traps.register(|func_id| jit.get_finalized_function(func_id));
+            if let Some(debug) = &mut self.debug {
+                debug.register_jit(jit.isa(), |func_id| jit.get_finalized_function(func_id))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_finalized_function(&self, func: &str) -> CompileResult<*const u8> {
+        let func_id = self
+            .get_function(func)
+            .ok_or_else(|| CompileError::UndefinedFunction(func.to_string()))?;
+
+        match &self.module {
+            ModuleType::JITModule(jit) => Ok(jit.get_finalized_function(func_id)),
+            ModuleType::ObjectModule(_) => Err(CompileError::Unsupported(
+                "running functions from an object module".to_string(),
+            )),
+        }
+    }
+
+    // Calls a function compiled into the JIT module; see `ModuleType::call`.
+    pub fn call<A: HostArgs, R: HostReturn>(&mut self, func: &str, args: A) -> CompileResult<R> {
+        self.call_with_stack(func, args, StackOptions::default())
+    }
+
+    pub fn call_with_stack<A: HostArgs, R: HostReturn>(
+        &mut self,
+        func: &str,
+        args: A,
+        stack: StackOptions,
+    ) -> CompileResult<R> {
+        let entry = self
+            .functions
+            .get(func)
+            .ok_or_else(|| CompileError::UndefinedFunction(func.to_string()))?;
+        if !entry.defined {
+            return Err(CompileError::MissingDefinition(func.to_string()));
+        }
+        let declared = entry.decl.func_ptr_type();
+        let requested = AstType::FuncPtr {
+            params: A::param_types(),
+            return_type: R::return_type().map(Box::new),
+        };
+        if !host_compatible(&requested, &declared, &|ty| self.clif_type(ty)) {
+            return Err(CompileError::EntrySignature {
+                name: func.to_string(),
+                signature: format!("{:?}", declared),
+                requested: format!("{:?}", requested),
+            });
+        }
+        let func_id = entry.id;
+        self.finalize()?;
+        let Some(counter) = fuel::address(&self.module) else {
+            return self.module.call_with_stack(func_id, args, stack);
+        };
+        unsafe {
+            ptr::write_volatile(counter, self.fuel);
+            let result = self.module.call_with_stack(func_id, args, stack);
+            self.fuel = ptr::read_volatile(counter);
+            result
+        }
+    }
+
+    pub fn run<R: HostReturn>(&mut self, func: &str) -> CompileResult<R> {
+        self.call(func, ())
+    }
+
+    pub fn run_main<R: HostReturn>(&mut self) -> CompileResult<R> {
+        self.run("main")
+    }
+
+    // Functions that are called or addressed but neither defined nor `extern`
+    // can't be resolved by the JIT.
+    fn check_definitions(&self) -> CompileResult<()> {
+        match self
+            .functions
+            .iter()
+            .find(|(name, entry)| entry.referenced && !self.has_definition(name, entry))
+        {
+            Some((name, _)) => Err(CompileError::MissingDefinition(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // Fails on a function the body just compiled calls that can't be defined by
+    // the time it's finalized, since once defined, the body would stay pending in
+    // the module and fail every later finalization. Unless `complete`, functions
+    // the program can still define are fine; imports the JIT can't find never are.
+    fn check_references(&self, complete: bool) -> CompileResult<()> {
+        let missing = self.references.iter().find(|name| {
+            let entry = &self.functions[*name];
+            (complete || entry.decl.extern_) && !self.has_definition(name, entry)
+        });
+        match missing {
+            Some(name) => Err(CompileError::MissingDefinition(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // Whether `entry` is defined, or is an import the JIT can resolve: cranelift-jit
+    // panics when finalizing code that calls an import it can't find. Imports
+    // into objects are left for the linker.
+    fn has_definition(&self, name: &str, entry: &FunctionEntry) -> bool {
+        if entry.defined {
+            return true;
+        }
+        if !entry.decl.extern_ {
+            return false;
+        }
+        match self.module {
+            ModuleType::JITModule(_) => self.host_symbols.contains(name) || host::dynamic_symbol_exists(name),
+            ModuleType::ObjectModule(_) => true,
+        }
+    }
+
+    fn reference_function(&mut self, name: &str) -> CompileResult<(FuncId, FuncDecl)> {
+        let entry = self
+            .functions
+            .get_mut(name)
+            .ok_or_else(|| CompileError::UndefinedFunction(name.to_string()))?;
+        self.references.insert(name.to_string());
+        Ok((entry.id, entry.decl.clone()))
+    }
+
+    fn commit_references(&mut self) {
+        for name in self.references.drain() {
+            if let Some(entry) = self.functions.get_mut(&name) {
+                entry.referenced = true;
+            }
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: Stmt) -> CompileResult<()> {
         match stmt {
-            Stmt::FuncDecl(func_decl) => self.declare_function(func_decl),
+            Stmt::Spanned(span, stmt) => {
+                self.line = span.line;
+                self.compile_stmt(*stmt)
+            }
+            Stmt::FuncDecl(func_decl) => self.declare_function(func_decl).map(|_| ()),
             Stmt::FuncDef(func_def) => self.define_function(func_def),
-            Stmt::VarDecl(var_decl) => self.declare_variable(var_decl),
-            Stmt::If(if_stmt) => {
-                let mut ctx = self.module.make_context();
-                let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
-                self.compile_if_stmt_in_func(if_stmt, &mut builder)
-            },
-            Stmt::Loop(loop_stmt) => {
-                let mut ctx = self.module.make_context();
-                let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
-                self.compile_loop_stmt_in_func(loop_stmt.clone(), &mut builder)
-            },
-            Stmt::Assign(assign) => {
-                let mut ctx = self.module.make_context();
-                let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
-                self.compile_assign(assign, &mut builder)
-            },
-            _ => unimplemented!("Statement type not yet implemented"),
+            Stmt::VarDecl(var_decl) => self.define_global(var_decl),
+            Stmt::If(_) => Err(CompileError::OutsideFunction("if")),
+            Stmt::Loop(_) => Err(CompileError::OutsideFunction("loop")),
+            Stmt::Assign(_) => Err(CompileError::OutsideFunction("assignment")),
+            _ => Err(CompileError::Unsupported(format!("top-level {:?}", stmt))),
         }
     }
 
-    fn compile_standalone_stmt<T>(
-        &mut self,
-        stmt: T,
-        compiler_fn: fn(&mut Self, T, &mut FunctionBuilder) -> ModuleResult<()>
-    ) -> ModuleResult<()> {
-        let mut ctx = self.module.make_context();
-        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
-        compiler_fn(self, stmt, &mut builder)
+    fn declare_function(&mut self, func_decl: FuncDecl) -> CompileResult<FuncId> {
+        if let Some(entry) = self.functions.get(&func_decl.name) {
+            // param names may differ between a prototype and the definition
+            let previous = entry.decl.func_ptr_type();
+            let found = func_decl.func_ptr_type();
+            if previous != found || entry.decl.variadic != func_decl.variadic {
+                return Err(CompileError::ConflictingDeclaration {
+                    name: func_decl.name,
+                    previous,
+                    found,
+                });
+            }
+            return Ok(entry.id);
+        }
+
+        let linkage = if func_decl.extern_ {
+            if let Some(sandbox) = &self.sandbox {
+                sandbox.check_import(&func_decl)?;
+            }
+            if let Some(registered) = self.extern_signatures.get(&func_decl.name) {
+                let declared = func_decl.func_ptr_type();
+                if declared != *registered || func_decl.variadic {
+                    return Err(CompileError::ExternSignature {
+                        name: func_decl.name.clone(),
+                        declared,
+                        registered: registered.clone(),
+                    });
+                }
+            }
+            Linkage::Import
+        } else {
+            Linkage::Export
+        };
+
+        let sig = self.make_signature(&func_decl)?;
+        let func_id = self
+            .module
+            .declare_function(&func_decl.name, linkage, &sig)?;
+        self.functions.insert(
+            func_decl.name.clone(),
+            FunctionEntry {
+                id: func_id,
+                decl: func_decl,
+                defined: false,
+                referenced: false,
+            },
+        );
+        Ok(func_id)
     }
 
-    fn declare_function(&mut self, func_decl: FuncDecl) -> ModuleResult<()> {
+    fn make_signature(&self, func_decl: &FuncDecl) -> CompileResult<Signature> {
+        let params: Vec<AstType> = func_decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        let mut sig = self.make_func_ptr_signature(&params, func_decl.return_type.as_ref())?;
+        if func_decl.extern_ {
+            self.import_signature(&mut sig, &params, func_decl.return_type.as_ref())?;
+        }
+        // Extra params capture the argument registers the named params leave free,
+        // so the prologue can spill them into the register save area. Callers always
+        // use their own per-call-site signature.
+        if func_decl.variadic && variadic::varargs_abi(self.module.isa().triple()) == VarargsAbi::SysV {
+            let (gp, fp) = variadic::sysv_register_counts(&params);
+            let gp_free = variadic::SYSV_GP_REGS.saturating_sub(gp);
+            let fp_free = variadic::SYSV_FP_REGS.saturating_sub(fp);
+            sig.params.extend((0..gp_free).map(|_| AbiParam::new(types::I64)));
+            sig.params.extend((0..fp_free).map(|_| AbiParam::new(types::F64)));
+        }
+        Ok(sig)
+    }
+
+    fn make_func_ptr_signature(
+        &self,
+        params: &[AstType],
+        return_type: Option<&AstType>,
+    ) -> CompileResult<Signature> {
         let mut sig = self.module.make_signature();
-        for (_name, param_type) in &func_decl.params {
+        for param_type in params {
             let abi_param = self.convert_type(param_type)?;
             sig.params.push(abi_param);
         }
-        if let Some(return_type) = func_decl.return_type {
-            sig.returns.push(self.convert_type(&return_type)?);
+        if let Some(return_type) = return_type {
+            sig.returns.push(self.convert_type(return_type)?);
         }
+        Ok(sig)
+    }
 
-        let func_id = self
-            .module
-            .declare_function(&func_decl.name, Linkage::Export, &sig)?;
-        self.functions.insert(func_decl.name, func_id);
+    // Imports into a sandbox take and return host pointers where the program
+    // has offsets. Only `char*` is translated: function pointers would let them
+    // call the program with pointers, and any other pointer would be a raw host
+    // address, so those are left out.
+    fn import_signature(
+        &self,
+        sig: &mut Signature,
+        params: &[AstType],
+        return_type: Option<&AstType>,
+    ) -> CompileResult<()> {
+        if self.sandbox.is_none() {
+            return Ok(());
+        }
+        let pointer_type = self.module.target_config().pointer_type();
+        let types = params.iter().zip(&mut sig.params).chain(return_type.into_iter().zip(&mut sig.returns));
+        for (type_, abi_param) in types {
+            match type_ {
+                AstType::String => abi_param.value_type = pointer_type,
+                type_ if !type_.is_numeric() => {
+                    return Err(CompileError::Unsupported(format!("{:?} in a sandboxed import", type_)))
+                }
+                _ => {}
+            }
+        }
         Ok(())
     }
 
-    fn define_function(&mut self, func_def: FuncDef) -> ModuleResult<()> {
-        let func_id = self.functions.get(&func_def.decl.name).unwrap().to_owned();
+    fn define_function(&mut self, func_def: FuncDef) -> CompileResult<()> {
+        let func_id = self.declare_function(func_def.decl.clone())?;
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = self.make_signature(&func_def.decl)?;
+        self.compile_function_body(&func_def.decl, &func_def.body, None, &mut ctx)?;
+        self.check_references(false)?;
+        self.define_compiled(&func_def.decl.name, func_id, &mut ctx)?;
+        self.commit_references();
+        if let Some(entry) = self.functions.get_mut(&func_def.decl.name) {
+            entry.defined = true;
+        }
+        Ok(())
+    }
+
+    // Compiles `body` followed by `result` into an anonymous function without
+    // params that returns the value of `result`, if it has one. The function isn't
+    // finalized or run.
+    pub fn compile_anonymous(
+        &mut self,
+        body: &ast::Block,
+        result: Option<&Expr>,
+    ) -> CompileResult<(FuncId, Option<AstType>)> {
+        let decl = FuncDecl {
+            name: String::new(),
+            params: Vec::new(),
+            return_type: None,
+            variadic: false,
+            extern_: false,
+        };
         let mut ctx = self.module.make_context();
         ctx.func.signature = self.module.make_signature();
+        let result_type = self.compile_function_body(&decl, body, result, &mut ctx)?;
+        self.check_references(true)?;
+        let func_id = self.module.declare_anonymous_function(&ctx.func.signature)?;
+        self.define_compiled("<anonymous>", func_id, &mut ctx)?;
+        self.commit_references();
+        Ok((func_id, result_type))
+    }
+
+    // Builds the CLIF for a function into `ctx.func`, whose signature is already
+    // set. A `result` expression is returned after the body, and its type added
+    // to the signature.
+    fn compile_function_body(
+        &mut self,
+        decl: &FuncDecl,
+        body: &ast::Block,
+        result: Option<&Expr>,
+        ctx: &mut codegen::Context,
+    ) -> CompileResult<Option<AstType>> {
+        self.scopes = vec![HashMap::new()];
+        self.loops.clear();
+        self.return_type = decl.return_type.clone();
+        self.next_var = 0;
+        self.locals.clear();
+        self.references.clear();
+        if self.debug.is_some() {
+            ctx.func.collect_debug_info();
+        }
 
-        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.func_ctx);
+        // the builder borrows the context for the whole body, so take it out of `self`
+        let mut func_ctx = std::mem::take(&mut self.func_ctx);
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        // line 0 is an AST without spans
+        if self.tracks_lines() && self.line > 0 {
+            // srclocs are stored relative to the first one set; starting from
+            // this line keeps the stack check inserted before the body representable
+            builder.func.params.ensure_base_srcloc(SourceLoc::new(self.line));
+            builder.set_srcloc(SourceLoc::new(self.line));
+        }
         let entry_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
         builder.switch_to_block(entry_block);
         builder.seal_block(entry_block);
+        self.fuel_counter = None;
+        self.consume_fuel(&mut builder)?;
+
+        let params = builder.block_params(entry_block).to_vec();
+        let (named, spilled) = params.split_at(decl.params.len());
+        for ((name, type_), value) in decl.params.iter().zip(named) {
+            let var = self.declare_local(name, type_.clone(), &mut builder)?;
+            self.define_local(var, *value, &mut builder);
+        }
+        // everything declared so far is a param
+        self.locals.iter_mut().for_each(|local| local.param = true);
+        self.varargs = None;
+        if decl.variadic {
+            self.spill_variadic_registers(decl, spilled, &mut builder)?;
+        }
+
+        let result = self.compile_block(body, &mut builder).and_then(|()| match result {
+            Some(expr) => self.compile_result_return(expr, &mut builder),
+            None => self.compile_fallthrough_return(&mut builder).map(|()| None),
+        });
+        // on error the builder is abandoned and `self.func_ctx` keeps the fresh default
+        if result.is_ok() {
+            builder.finalize();
+            self.func_ctx = func_ctx;
+        }
+        self.scopes.clear();
+        result
+    }
+
+    fn define_compiled(&mut self, name: &str, func_id: FuncId, ctx: &mut codegen::Context) -> CompileResult<()> {
+        if matches!(self.module, ModuleType::JITModule(_)) {
+            let srcloc = if self.line > 0 { SourceLoc::new(self.line) } else { SourceLoc::default() };
+            stack::insert_stack_check(&mut self.module, &mut ctx.func, srcloc)?;
+        }
+        let function = report::define_function(&mut self.module, name, func_id, ctx, self.options)?;
+        self.report.functions.push(function);
+        if let ModuleType::JITModule(jit) = &self.module {
+            self.unwind.add_function(func_id, ctx, jit.isa())?;
+            self.traps.add_function(func_id, name, ctx);
+        }
+        if let Some(debug) = &mut self.debug {
+            let return_type = self.return_type.clone();
+            let isa = self.module.isa();
+            let function = FunctionDebug::new(name, func_id, self.line, return_type, ctx, &self.locals, isa)?;
+            debug.functions.push(function);
+        }
+        Ok(())
+    }
+
+    // SysV prologue for a variadic definition: store the argument registers not taken
+    // by named params at their slots in the register save area.
+    fn spill_variadic_registers(
+        &mut self,
+        decl: &FuncDecl,
+        spilled: &[Value],
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<()> {
+        if variadic::varargs_abi(self.module.isa().triple()) != VarargsAbi::SysV {
+            return Err(CompileError::Unsupported(format!(
+                "variadic function definitions on {}",
+                self.module.isa().triple()
+            )));
+        }
+        // `va_start` finds the stack-passed arguments through the frame pointer
+        if !self.module.isa().flags().preserve_frame_pointers() {
+            return Err(CompileError::Unsupported(
+                "defining variadic functions without `preserve_frame_pointers`".to_string(),
+            ));
+        }
+
+        let params: Vec<AstType> = decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        let (gp, fp) = variadic::sysv_register_counts(&params);
+        let reg_save_area = builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            variadic::SYSV_REG_SAVE_AREA_SIZE,
+            4,
+        ));
+
+        let gp_slots = (gp..variadic::SYSV_GP_REGS).map(|reg| reg * 8);
+        let fp_slots = (fp..variadic::SYSV_FP_REGS).map(|reg| variadic::SYSV_GP_SAVE_SIZE + reg * 16);
+        for (value, offset) in spilled.iter().zip(gp_slots.chain(fp_slots)) {
+            builder.ins().stack_store(*value, reg_save_area, offset as i32);
+        }
+
+        // named params beyond the registers sit on the stack, above the saved frame
+        // pointer and return address
+        let stack_params = gp.saturating_sub(variadic::SYSV_GP_REGS) + fp.saturating_sub(variadic::SYSV_FP_REGS);
+        self.varargs = Some(VarargsFrame {
+            reg_save_area,
+            gp_offset: gp.min(variadic::SYSV_GP_REGS) * 8,
+            fp_offset: variadic::SYSV_GP_SAVE_SIZE + fp.min(variadic::SYSV_FP_REGS) * 16,
+            overflow_offset: 16 + 8 * stack_params as i64,
+        });
+        Ok(())
+    }
+
+    // returns `expr` from a function whose signature has no returns yet
+    fn compile_result_return(
+        &mut self,
+        expr: &Expr,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<AstType>> {
+        let result = match expr {
+            // calls to functions without a return value are fine here
+            Expr::FuncCall(func_call) => self.compile_func_call(func_call, builder)?,
+            expr => Some(self.compile_expr(expr, None, builder)?),
+        };
+        match result {
+            Some((value, type_)) => {
+                let abi_param = self.convert_type(&type_)?;
+                builder.func.signature.returns.push(abi_param);
+                builder.ins().return_(&[value]);
+                Ok(Some(type_))
+            }
+            None => {
+                builder.ins().return_(&[]);
+                Ok(None)
+            }
+        }
+    }
+
+    // falling off the end of a function returns zero, like `main` in C
+    fn compile_fallthrough_return(&mut self, builder: &mut FunctionBuilder) -> CompileResult<()> {
+        match self.return_type.clone() {
+            Some(return_type) => {
+                let zero = self.zero_value(&return_type, builder)?;
+                builder.ins().return_(&[zero]);
+            }
+            None => {
+                builder.ins().return_(&[]);
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_local(
+        &mut self,
+        name: &str,
+        type_: AstType,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Variable> {
+        let var = Variable::new(self.next_var);
+        self.next_var += 1;
+        builder.declare_var(var, self.clif_type(&type_)?);
+        self.locals.push(LocalDebug {
+            name: name.to_string(),
+            type_: type_.clone(),
+            param: false,
+        });
+        self.scopes
+            .last_mut()
+            .ok_or(CompileError::OutsideFunction("variable declaration"))?
+            .insert(name.to_string(), LocalVar { var, type_ });
+        Ok(var)
+    }
+
+    // Instructions are tagged with source lines for debug info and, in the JIT,
+    // for the locations of traps.
+    fn tracks_lines(&self) -> bool {
+        self.debug.is_some() || matches!(self.module, ModuleType::JITModule(_))
+    }
 
-        let statements = func_def.body;
-        for stmt in &statements {
-            self.compile_stmt_in_func(stmt, &mut builder)?;
+    // Assigns `value` to a local, tagging it for debug info's variable locations.
+    fn define_local(&self, var: Variable, value: Value, builder: &mut FunctionBuilder) {
+        builder.def_var(var, value);
+        if self.debug.is_some() {
+            builder.set_val_label(value, ValueLabel::new(var.index()));
         }
+    }
+
+    fn lookup_variable(&self, name: &str) -> CompileResult<VarRef> {
+        if let Some(local) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Ok(VarRef::Local(local.clone()));
+        }
+        self.globals
+            .get(name)
+            .cloned()
+            .map(VarRef::Global)
+            .ok_or_else(|| CompileError::UndefinedVariable(name.to_string()))
+    }
 
-        builder.finalize();
-        self.module.define_function(func_id, &mut ctx)?;
+    fn load_variable(&mut self, var: &VarRef, builder: &mut FunctionBuilder) -> CompileResult<(Value, AstType)> {
+        match var {
+            VarRef::Local(local) => Ok((builder.use_var(local.var), local.type_.clone())),
+            VarRef::Global(global) => {
+                let addr = self.global_addr(global.id, builder);
+                let value = builder
+                    .ins()
+                    .load(self.clif_type(&global.type_)?, MemFlags::trusted(), addr, 0);
+                Ok((value, global.type_.clone()))
+            }
+        }
+    }
+
+    fn global_addr(&mut self, data_id: DataId, builder: &mut FunctionBuilder) -> Value {
+        let global_value = self.module.declare_data_in_func(data_id, builder.func);
+        let pointer_type = self.module.target_config().pointer_type();
+        builder.ins().symbol_value(pointer_type, global_value)
+    }
+
+    fn define_global(&mut self, var_decl: VarDecl) -> CompileResult<()> {
+        let size = self.clif_type(&var_decl.type_)?.bytes();
+        let data_id = self.module.declare_data(&var_decl.name, Linkage::Export, true, false)?;
+        let mut data = DataDescription::new();
+        data.set_align(size as u64);
+        self.references.clear();
+        match &var_decl.init {
+            Some(init) => self.constant_initializer(&var_decl, init, &mut data)?,
+            None => data.define_zeroinit(size as usize),
+        }
+        self.module.define_data(data_id, &data)?;
+        self.commit_references();
+        self.globals.insert(
+            var_decl.name,
+            GlobalVar {
+                id: data_id,
+                type_: var_decl.type_,
+            },
+        );
         Ok(())
     }
 
-    fn declare_variable(&mut self, var_decl: VarDecl) -> ModuleResult<()> {
-        let var = Variable::new(self.variables.len());
-        self.variables.insert(var_decl.name.clone(), var);
+    // Global initializers are constants, as in C: numeric literals, string
+    // literals and function addresses.
+    fn constant_initializer(&mut self, var_decl: &VarDecl, init: &Expr, data: &mut DataDescription) -> CompileResult<()> {
+        let type_ = &var_decl.type_;
+        let literal = match init {
+            Expr::Unary(unary) if unary.op == UnaryOp::Neg => match &*unary.expr {
+                Expr::Literal(Literal::Int(value)) => Some(Literal::Int(value.wrapping_neg())),
+                Expr::Literal(Literal::Float(value)) => Some(Literal::Float(-value)),
+                _ => None,
+            },
+            Expr::Literal(literal) => Some(literal.clone()),
+            _ => None,
+        };
+        let bytes = match (literal, type_) {
+            (Some(Literal::String(value)), AstType::String) => {
+                if let Some(sandbox) = &mut self.sandbox {
+                    let offset = sandbox.add_string(&value)?;
+                    let bytes = match self.module.isa().endianness() {
+                        Endianness::Little => offset.to_le_bytes(),
+                        Endianness::Big => offset.to_be_bytes(),
+                    };
+                    data.define(bytes.into());
+                    return Ok(());
+                }
+                let string_id = self.string_data(&value)?;
+                let pointer_bytes = self.module.target_config().pointer_bytes() as usize;
+                data.define(vec![0; pointer_bytes].into_boxed_slice());
+                let string = self.module.declare_data_in_data(string_id, data);
+                data.write_data_addr(0, string, 0);
+                return Ok(());
+            }
+            (None, AstType::FuncPtr { .. }) => {
+                let Expr::FuncAddr(name) = init else {
+                    return Err(self.non_constant(var_decl));
+                };
+                let (func_id, decl) = self.reference_function(name)?;
+                self.check_addressable(&decl)?;
+                let found = decl.func_ptr_type();
+                if found != *type_ {
+                    return Err(CompileError::TypeMismatch {
+                        expected: type_.clone(),
+                        found,
+                    });
+                }
+                let pointer_bytes = self.module.target_config().pointer_bytes() as usize;
+                data.define(vec![0; pointer_bytes].into_boxed_slice());
+                let func = self.module.declare_func_in_data(func_id, data);
+                data.write_function_addr(0, func);
+                return Ok(());
+            }
+            (Some(literal), type_) if type_.is_numeric() || *type_ == AstType::Char => {
+                let pointer_bytes = self.module.target_config().pointer_bytes() as usize;
+                constant_bytes(&literal, type_, pointer_bytes).ok_or_else(|| self.non_constant(var_decl))?
+            }
+            _ => return Err(self.non_constant(var_decl)),
+        };
+        let bytes = match self.module.isa().endianness() {
+            Endianness::Little => bytes,
+            Endianness::Big => bytes.into_iter().rev().collect(),
+        };
+        data.define(bytes.into_boxed_slice());
         Ok(())
     }
 
-    fn compile_stmt_in_func(&mut self, stmt: &Stmt, builder: &mut FunctionBuilder) -> ModuleResult<()> {
+    fn non_constant(&self, var_decl: &VarDecl) -> CompileError {
+        CompileError::Unsupported(format!("non-constant initializer for global `{}`", var_decl.name))
+    }
+
+    fn declare_variable(
+        &mut self,
+        var_decl: &VarDecl,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<()> {
+        let value = match &var_decl.init {
+            Some(init) => {
+                let (value, type_) = self.compile_expr(init, Some(&var_decl.type_), builder)?;
+                self.cast_value(value, &type_, &var_decl.type_, builder)?
+            }
+            // a `va_list` variable points at its own stack storage, like C's `va_list[1]`
+            None if var_decl.type_ == AstType::VaList => {
+                let slot = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    variadic::SYSV_VA_LIST_SIZE,
+                    3,
+                ));
+                let pointer_type = self.module.target_config().pointer_type();
+                builder.ins().stack_addr(pointer_type, slot, 0)
+            }
+            None => self.zero_value(&var_decl.type_, builder)?,
+        };
+        let var = self.declare_local(&var_decl.name, var_decl.type_.clone(), builder)?;
+        self.define_local(var, value, builder);
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: &ast::Block, builder: &mut FunctionBuilder) -> CompileResult<()> {
+        self.scopes.push(HashMap::new());
+        let result = block
+            .iter()
+            .try_for_each(|stmt| self.compile_stmt_in_func(stmt, builder));
+        self.scopes.pop();
+        result
+    }
+
+    fn compile_stmt_in_func(&mut self, stmt: &Stmt, builder: &mut FunctionBuilder) -> CompileResult<()> {
         match stmt {
-            Stmt::Return(ret) => self.compile_return(ret.clone(), builder),
-            Stmt::Expr(expr) => {
-                self.compile_expr(expr.clone(), builder);
-                Ok(())
+            Stmt::Spanned(span, stmt) => {
+                if self.tracks_lines() {
+                    builder.set_srcloc(SourceLoc::new(span.line));
+                }
+                self.compile_stmt_in_func(stmt, builder)
+            }
+            Stmt::VarDecl(var_decl) => self.declare_variable(var_decl, builder),
+            Stmt::Assign(assign) => self.compile_assign(assign, builder),
+            Stmt::Return(ret) => self.compile_return(ret, builder),
+            Stmt::Expr(Expr::FuncCall(func_call)) | Stmt::FuncCall(func_call) => {
+                self.compile_func_call(func_call, builder).map(|_| ())
+            }
+            Stmt::Expr(Expr::CallIndirect(call)) => {
+                self.compile_call_indirect(call, builder).map(|_| ())
             }
-            Stmt::If(if_stmt) => self.compile_if_stmt_in_func(if_stmt.clone(), builder),
+            Stmt::Expr(expr) => self.compile_expr(expr, None, builder).map(|_| ()),
+            Stmt::Block(block) => self.compile_block(block, builder),
+            Stmt::If(if_stmt) => self.compile_if_stmt_in_func(if_stmt, builder),
             Stmt::Loop(loop_stmt) => self.compile_loop_stmt_in_func(loop_stmt, builder),
-            _ => unimplemented!("Statement type not yet implemented in function body"),
+            Stmt::Break => {
+                let target = self.loops.last().ok_or(CompileError::OutsideLoop("break"))?;
+                let exit_block = target.exit_block;
+                builder.ins().jump(exit_block, &[]);
+                self.switch_to_dead_block(builder);
+                Ok(())
+            }
+            Stmt::Continue => {
+                let target = self.loops.last().ok_or(CompileError::OutsideLoop("continue"))?;
+                let continue_block = target.continue_block;
+                builder.ins().jump(continue_block, &[]);
+                self.switch_to_dead_block(builder);
+                Ok(())
+            }
+            _ => Err(CompileError::Unsupported(format!("{:?} in a function body", stmt))),
         }
     }
 
+    // After a terminator, code that follows is unreachable. It still has to land in
+    // some block, so give it a fresh one without predecessors.
+    fn switch_to_dead_block(&mut self, builder: &mut FunctionBuilder) {
+        let dead_block = builder.create_block();
+        builder.switch_to_block(dead_block);
+        builder.seal_block(dead_block);
+    }
+
     fn compile_return(
         &mut self,
-        ret: Return,
+        ret: &Return,
         builder: &mut FunctionBuilder,
-    ) -> ModuleResult<()> {
-        if let Some(expr) = ret.value {
-            let value = self.compile_expr(*expr, builder);
-            builder.ins().return_(&[value]);
-        } else {
-            builder.ins().return_(&[]);
+    ) -> CompileResult<()> {
+        match (&ret.value, self.return_type.clone()) {
+            (Some(expr), Some(return_type)) => {
+                let (value, type_) = self.compile_expr(expr, Some(&return_type), builder)?;
+                let value = self.cast_value(value, &type_, &return_type, builder)?;
+                builder.ins().return_(&[value]);
+            }
+            (None, None) => {
+                builder.ins().return_(&[]);
+            }
+            (Some(expr), None) => {
+                let (_, found) = self.compile_expr(expr, None, builder)?;
+                return Err(CompileError::Unsupported(format!(
+                    "returning a {:?} from a function without a return type",
+                    found
+                )));
+            }
+            (None, Some(expected)) => {
+                return Err(CompileError::Unsupported(format!(
+                    "empty return from a function returning {:?}",
+                    expected
+                )));
+            }
         }
+        self.switch_to_dead_block(builder);
         Ok(())
     }
 
     fn compile_if_stmt_in_func(
         &mut self,
-        if_stmt: IfStmt,
+        if_stmt: &IfStmt,
         builder: &mut FunctionBuilder,
-    ) -> ModuleResult<()> {
-        let condition = self.compile_expr(*if_stmt.condition, builder);
+    ) -> CompileResult<()> {
+        let condition = self.compile_condition(&if_stmt.condition, builder)?;
         let then_block = builder.create_block();
         let else_block = builder.create_block();
         let merge_block = builder.create_block();
@@ -147,24 +1023,20 @@ impl Codegen {
         builder
             .ins()
             .brif(condition, then_block, &[], else_block, &[]);
+        builder.seal_block(then_block);
+        builder.seal_block(else_block);
 
         // Then block
         builder.switch_to_block(then_block);
-        for stmt in if_stmt.then_branch {
-            self.compile_stmt_in_func(&stmt, builder)?;
-        }
+        self.compile_block(&if_stmt.then_branch, builder)?;
         builder.ins().jump(merge_block, &[]);
-        builder.seal_block(then_block);
 
         // Else block
         builder.switch_to_block(else_block);
-        if let Some(else_branch) = if_stmt.else_branch {
-            for stmt in else_branch {
-                self.compile_stmt_in_func(&stmt, builder)?;
-            }
+        if let Some(else_branch) = &if_stmt.else_branch {
+            self.compile_block(else_branch, builder)?;
         }
         builder.ins().jump(merge_block, &[]);
-        builder.seal_block(else_block);
 
         // Merge block
         builder.switch_to_block(merge_block);
@@ -173,29 +1045,95 @@ impl Codegen {
         Ok(())
     }
 
+    // Block layout per loop kind (`continue` jumps to the block marked *):
+    //
+    //   While:   jump header | header*: brif cond body, exit | body: ...; jump header
+    //   DoWhile: jump body   | body: ...; jump latch | latch*: brif cond body, exit
+    //   For:     init; jump header | header: brif cond body, exit
+    //            | body: ...; jump latch | latch*: step; jump header
     fn compile_loop_stmt_in_func(
         &mut self,
-        loop_stmt: LoopStmt,
+        loop_stmt: &LoopStmt,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<()> {
+        // the for-loop init variable lives in a scope wrapping the whole loop
+        self.scopes.push(HashMap::new());
+        let result = self.compile_loop_blocks(loop_stmt, builder);
+        self.scopes.pop();
+        result
+    }
+
+    fn compile_loop_blocks(
+        &mut self,
+        loop_stmt: &LoopStmt,
         builder: &mut FunctionBuilder,
-    ) -> ModuleResult<()> {
+    ) -> CompileResult<()> {
         let loop_header = builder.create_block();
         let loop_body = builder.create_block();
         let exit_block = builder.create_block();
 
-        builder.ins().jump(loop_header, &[]);
-        builder.switch_to_block(loop_header);
+        let (init, step) = match &loop_stmt.kind {
+            LoopKind::For { init, step } => (init.as_deref(), step.as_deref()),
+            _ => (None, None),
+        };
+        if let Some(init) = init {
+            self.compile_stmt_in_func(init, builder)?;
+        }
 
-        let condition = self.compile_expr(*loop_stmt.condition, builder);
-        builder
-            .ins()
-            .brif(condition, loop_body, &[], exit_block, &[]);
+        match loop_stmt.kind {
+            LoopKind::While => {
+                builder.ins().jump(loop_header, &[]);
+                builder.switch_to_block(loop_header);
+                self.consume_fuel(builder)?;
+                let condition = self.compile_condition(&loop_stmt.condition, builder)?;
+                builder
+                    .ins()
+                    .brif(condition, loop_body, &[], exit_block, &[]);
+                builder.seal_block(loop_body);
 
-        builder.switch_to_block(loop_body);
-        for stmt in &loop_stmt.body {
-            self.compile_stmt_in_func(stmt, builder)?;
+                self.compile_loop_body(&loop_stmt.body, (loop_body, loop_header, exit_block), builder)?;
+                builder.ins().jump(loop_header, &[]);
+                builder.seal_block(loop_header);
+            }
+            LoopKind::DoWhile => {
+                let latch = loop_header;
+                builder.ins().jump(loop_body, &[]);
+
+                self.compile_loop_body(&loop_stmt.body, (loop_body, latch, exit_block), builder)?;
+                builder.ins().jump(latch, &[]);
+                builder.seal_block(latch);
+
+                builder.switch_to_block(latch);
+                self.consume_fuel(builder)?;
+                let condition = self.compile_condition(&loop_stmt.condition, builder)?;
+                builder
+                    .ins()
+                    .brif(condition, loop_body, &[], exit_block, &[]);
+                builder.seal_block(loop_body);
+            }
+            LoopKind::For { .. } => {
+                let latch = builder.create_block();
+                builder.ins().jump(loop_header, &[]);
+                builder.switch_to_block(loop_header);
+                self.consume_fuel(builder)?;
+                let condition = self.compile_condition(&loop_stmt.condition, builder)?;
+                builder
+                    .ins()
+                    .brif(condition, loop_body, &[], exit_block, &[]);
+                builder.seal_block(loop_body);
+
+                self.compile_loop_body(&loop_stmt.body, (loop_body, latch, exit_block), builder)?;
+                builder.ins().jump(latch, &[]);
+                builder.seal_block(latch);
+
+                builder.switch_to_block(latch);
+                if let Some(step) = step {
+                    self.compile_stmt_in_func(step, builder)?;
+                }
+                builder.ins().jump(loop_header, &[]);
+                builder.seal_block(loop_header);
+            }
         }
-        builder.ins().jump(loop_header, &[]);
-        builder.seal_block(loop_body);
 
         builder.switch_to_block(exit_block);
         builder.seal_block(exit_block);
@@ -203,93 +1141,1052 @@ impl Codegen {
         Ok(())
     }
 
+    // Function entries and loop conditions take fuel, which bounds how long
+    // any program runs.
+    fn consume_fuel(&mut self, builder: &mut FunctionBuilder) -> CompileResult<()> {
+        if !self.consume_fuel || !matches!(self.module, ModuleType::JITModule(_)) {
+            return Ok(());
+        }
+        let counter = match self.fuel_counter {
+            Some(counter) => counter,
+            None => *self.fuel_counter.insert(fuel::counter(&mut self.module, builder.func)?),
+        };
+        fuel::consume(counter, self.module.target_config().pointer_type(), builder);
+        Ok(())
+    }
+
+    fn compile_loop_body(
+        &mut self,
+        body: &ast::Block,
+        targets: (Block, Block, Block),
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<()> {
+        let (body_block, continue_block, exit_block) = targets;
+        builder.switch_to_block(body_block);
+        self.loops.push(LoopTarget {
+            continue_block,
+            exit_block,
+        });
+        let result = self.compile_block(body, builder);
+        self.loops.pop();
+        result
+    }
+
     fn compile_assign(
         &mut self,
-        assign:  Assign,
+        assign: &Assign,
         builder: &mut FunctionBuilder,
-    ) -> ModuleResult<()> {
-        let value = self.compile_expr(*assign.value, builder);
-        let var = self.variables.get(&assign.target.name).unwrap();
-        builder.def_var(*var, value);
+    ) -> CompileResult<()> {
+        let var = self.lookup_variable(&assign.target.name)?;
+        let target_type = match &var {
+            VarRef::Local(local) => local.type_.clone(),
+            VarRef::Global(global) => global.type_.clone(),
+        };
+        let (value, type_) = self.compile_expr(&assign.value, Some(&target_type), builder)?;
+        let value = self.cast_value(value, &type_, &target_type, builder)?;
+        match var {
+            VarRef::Local(local) => self.define_local(local.var, value, builder),
+            VarRef::Global(global) => {
+                let addr = self.global_addr(global.id, builder);
+                builder.ins().store(MemFlags::trusted(), value, addr, 0);
+            }
+        }
         Ok(())
     }
 
-    fn compile_expr(&mut self, expr: Expr, builder: &mut FunctionBuilder) -> Value {
+    fn compile_condition(&mut self, expr: &Expr, builder: &mut FunctionBuilder) -> CompileResult<Value> {
+        let (value, type_) = self.compile_expr(expr, Some(&AstType::Bool), builder)?;
+        self.cast_value(value, &type_, &AstType::Bool, builder)
+    }
+
+    // `expected` is only a hint used to type integer and float literals; callers
+    // still cast the result to whatever type they need.
+    fn compile_expr(
+        &mut self,
+        expr: &Expr,
+        expected: Option<&AstType>,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
         match expr {
-            Expr::Literal(literal) => self.compile_literal(literal, builder),
+            Expr::Literal(literal) => self.compile_literal(literal, expected, builder),
             Expr::Variable(variable) => self.compile_variable(variable, builder),
-            Expr::Binary(binary) => self.compile_binary(*binary, builder),
-            Expr::Unary(unary) => self.compile_unary(*unary, builder),
-            Expr::FuncCall(func_call) => self.compile_func_call(func_call, builder),
-            _ => unimplemented!("Expression type not yet implemented"),
+            Expr::Binary(binary) => self.compile_binary(binary, expected, self.overflow, builder),
+            Expr::Unary(unary) => self.compile_unary(unary, expected, builder),
+            Expr::FuncCall(func_call) => self
+                .compile_func_call(func_call, builder)?
+                .ok_or_else(|| CompileError::VoidValue(func_call.name.clone())),
+            Expr::FuncAddr(name) => self.compile_func_addr(name, builder),
+            Expr::CallIndirect(call) => self
+                .compile_call_indirect(call, builder)?
+                .ok_or_else(|| CompileError::VoidValue("indirect call".to_string())),
+            _ => Err(CompileError::Unsupported(format!("expression {:?}", expr))),
         }
     }
 
-    fn compile_literal(&self, literal: Literal, builder: &mut FunctionBuilder) -> Value {
-        match literal {
-            Literal::Int(value) => builder.ins().iconst(types::I64, value),
-            Literal::Float(value) => builder.ins().f64const(value),
-            Literal::Bool(value) => builder.ins().iconst(types::I8, value as i64),
-            _ => unimplemented!("Literal type not yet implemented"),
+    fn compile_literal(
+        &mut self,
+        literal: &Literal,
+        expected: Option<&AstType>,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
+        let typed = match (literal, expected) {
+            (Literal::Int(value), Some(ty)) if ty.is_integer() => {
+                (builder.ins().iconst(self.clif_type(ty)?, *value), ty.clone())
+            }
+            (Literal::Int(value), Some(AstType::F32)) => {
+                (builder.ins().f32const(*value as f32), AstType::F32)
+            }
+            (Literal::Int(value), Some(AstType::F64)) => {
+                (builder.ins().f64const(*value as f64), AstType::F64)
+            }
+            // like C's `int`, widened when the value doesn't fit
+            (Literal::Int(value), _) if i32::try_from(*value).is_ok() => {
+                (builder.ins().iconst(types::I32, *value), AstType::I32)
+            }
+            (Literal::Int(value), _) => (builder.ins().iconst(types::I64, *value), AstType::I64),
+            (Literal::Float(value), Some(AstType::F32)) => {
+                (builder.ins().f32const(*value as f32), AstType::F32)
+            }
+            (Literal::Float(value), _) => (builder.ins().f64const(*value), AstType::F64),
+            (Literal::Bool(value), _) => (builder.ins().iconst(types::I8, *value as i64), AstType::Bool),
+            (Literal::Char(value), _) => {
+                (builder.ins().iconst(types::I8, *value as u8 as i64), AstType::Char)
+            }
+            (Literal::String(value), _) => (self.compile_string(value, builder)?, AstType::String),
+        };
+        Ok(typed)
+    }
+
+    // a NUL-terminated copy of the string in read-only data, like a C string literal
+    fn compile_string(&mut self, value: &str, builder: &mut FunctionBuilder) -> CompileResult<Value> {
+        if let Some(sandbox) = &mut self.sandbox {
+            let offset = sandbox.add_string(value)?;
+            return Ok(builder.ins().iconst(types::I32, offset as i64));
         }
+        let data_id = self.string_data(value)?;
+        Ok(self.global_addr(data_id, builder))
     }
 
-    fn compile_variable(&self, variable: Variable_, builder: &mut FunctionBuilder) -> Value {
-        let var = *self.variables.get(&variable.name).unwrap();
-        builder.use_var(var)
+    // a NUL-terminated copy of `value`
+    fn string_data(&mut self, value: &str) -> CompileResult<DataId> {
+        let data_id = self.module.declare_anonymous_data(false, false)?;
+        let mut data = DataDescription::new();
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        data.define(bytes.into_boxed_slice());
+        self.module.define_data(data_id, &data)?;
+        Ok(data_id)
     }
 
-    fn compile_binary(&mut self, binary: Binary, builder: &mut FunctionBuilder) -> Value {
-        let left = self.compile_expr(*binary.left, builder);
-        let right = self.compile_expr(*binary.right, builder);
-        match binary.op {
+    fn compile_variable(
+        &mut self,
+        variable: &Variable_,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
+        let var = self.lookup_variable(&variable.name)?;
+        self.load_variable(&var, builder)
+    }
+
+    fn compile_binary(
+        &mut self,
+        binary: &Binary,
+        expected: Option<&AstType>,
+        overflow: Overflow,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
+        let is_comparison = matches!(
+            binary.op,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Lt | BinaryOp::Le
+        );
+        let operand_hint = if is_comparison { None } else { expected };
+
+        // A literal operand takes the type of the other side, so `i + 1` stays an `i32`.
+        // Literals have no side effects, so compiling the right side first is fine.
+        let ((left, left_type), (right, right_type)) = match (&*binary.left, &*binary.right) {
+            (Expr::Literal(_), right) if !matches!(right, Expr::Literal(_)) => {
+                let right = self.compile_expr(right, operand_hint, builder)?;
+                let left = self.compile_expr(&binary.left, Some(&promoted(&right.1)), builder)?;
+                (left, right)
+            }
+            (left, right) => {
+                let left = self.compile_expr(left, operand_hint, builder)?;
+                let right = self.compile_expr(right, Some(&promoted(&left.1)), builder)?;
+                (left, right)
+            }
+        };
+
+        if matches!(binary.op, BinaryOp::Shl | BinaryOp::Shr) {
+            if !left_type.is_integer() || !right_type.is_integer() {
+                return Err(CompileError::TypeMismatch {
+                    expected: AstType::I64,
+                    found: if left_type.is_integer() { right_type } else { left_type },
+                });
+            }
+            let value = match (&binary.op, left_type.is_signed()) {
+                (BinaryOp::Shl, _) => builder.ins().ishl(left, right),
+                (_, true) => builder.ins().sshr(left, right),
+                (_, false) => builder.ins().ushr(left, right),
+            };
+            return Ok((value, left_type));
+        }
+
+        let type_ = common_type(&left_type, &right_type, self.module.target_config().pointer_type())?;
+        // function pointers can only be compared for equality
+        if !type_.is_numeric() && !matches!(binary.op, BinaryOp::Eq | BinaryOp::Ne) {
+            return Err(CompileError::Unsupported(format!("{:?} on {:?}", binary.op, type_)));
+        }
+        let left = self.cast_value(left, &left_type, &type_, builder)?;
+        let right = self.cast_value(right, &right_type, &type_, builder)?;
+
+        if type_.is_float() {
+            let value = match binary.op {
+                BinaryOp::Add => builder.ins().fadd(left, right),
+                BinaryOp::Sub => builder.ins().fsub(left, right),
+                BinaryOp::Mul => builder.ins().fmul(left, right),
+                BinaryOp::Div => builder.ins().fdiv(left, right),
+                BinaryOp::Eq => builder.ins().fcmp(FloatCC::Equal, left, right),
+                BinaryOp::Ne => builder.ins().fcmp(FloatCC::NotEqual, left, right),
+                BinaryOp::Gt => builder.ins().fcmp(FloatCC::GreaterThan, left, right),
+                BinaryOp::Ge => builder.ins().fcmp(FloatCC::GreaterThanOrEqual, left, right),
+                BinaryOp::Lt => builder.ins().fcmp(FloatCC::LessThan, left, right),
+                BinaryOp::Le => builder.ins().fcmp(FloatCC::LessThanOrEqual, left, right),
+                _ => {
+                    return Err(CompileError::Unsupported(format!(
+                        "{:?} on {:?}",
+                        binary.op, type_
+                    )))
+                }
+            };
+            let result_type = if is_comparison { AstType::Bool } else { type_ };
+            return Ok((value, result_type));
+        }
+
+        let signed = type_.is_signed();
+        let value = match binary.op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul if overflow == Overflow::Trap => {
+                self.checked_arithmetic(&binary.op, left, right, signed, builder)
+            }
             BinaryOp::Add => builder.ins().iadd(left, right),
             BinaryOp::Sub => builder.ins().isub(left, right),
             BinaryOp::Mul => builder.ins().imul(left, right),
-            BinaryOp::Div => builder.ins().sdiv(left, right),
+            BinaryOp::Div if signed => builder.ins().sdiv(left, right),
+            BinaryOp::Div => builder.ins().udiv(left, right),
+            BinaryOp::Mod if signed => builder.ins().srem(left, right),
+            BinaryOp::Mod => builder.ins().urem(left, right),
+            BinaryOp::BitAnd => builder.ins().band(left, right),
+            BinaryOp::BitOr => builder.ins().bor(left, right),
+            BinaryOp::BitXor => builder.ins().bxor(left, right),
             BinaryOp::Eq => builder.ins().icmp(IntCC::Equal, left, right),
             BinaryOp::Ne => builder.ins().icmp(IntCC::NotEqual, left, right),
-            BinaryOp::Gt => builder.ins().icmp(IntCC::SignedGreaterThan, left, right),
-            BinaryOp::Lt => builder.ins().icmp(IntCC::SignedLessThan, left, right),
-            _ => unimplemented!("Binary operation not yet implemented"),
-        }
+            BinaryOp::Gt if signed => builder.ins().icmp(IntCC::SignedGreaterThan, left, right),
+            BinaryOp::Gt => builder.ins().icmp(IntCC::UnsignedGreaterThan, left, right),
+            BinaryOp::Ge if signed => builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, left, right),
+            BinaryOp::Ge => builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, left, right),
+            BinaryOp::Lt if signed => builder.ins().icmp(IntCC::SignedLessThan, left, right),
+            BinaryOp::Lt => builder.ins().icmp(IntCC::UnsignedLessThan, left, right),
+            BinaryOp::Le if signed => builder.ins().icmp(IntCC::SignedLessThanOrEqual, left, right),
+            BinaryOp::Le => builder.ins().icmp(IntCC::UnsignedLessThanOrEqual, left, right),
+            BinaryOp::Shl | BinaryOp::Shr => unreachable!("shifts are handled above"),
+        };
+        let result_type = if is_comparison { AstType::Bool } else { type_ };
+        Ok((value, result_type))
     }
 
-    fn compile_unary(&mut self, unary: Unary, builder: &mut FunctionBuilder) -> Value {
-        let expr = self.compile_expr(*unary.expr, builder);
+    fn compile_unary(
+        &mut self,
+        unary: &Unary,
+        expected: Option<&AstType>,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
         match unary.op {
-            UnaryOp::Neg => builder.ins().ineg(expr),
-            UnaryOp::Not => builder.ins().bnot(expr),
-        }
-    }
-
-    fn compile_func_call(&mut self, func_call: FuncCall, builder: &mut FunctionBuilder) -> Value {
-        let func_id = *self.functions.get(&func_call.name).unwrap();
-        let func_ref = &self.module.declare_func_in_func(func_id, &mut builder.func);
-        let args: Vec<Value> = func_call
-            .args
-            .into_iter()
-            .map(|arg| self.compile_expr(arg, builder))
-            .collect();
-        let call = builder.ins().call(*func_ref, &args);
-        builder.inst_results(call)[0]
-    }
-
-    fn convert_type(&self, ast_type: &AstType) -> ModuleResult<AbiParam> {
-        let cranelift_type = match ast_type {
-            AstType::I8 => types::I8,
-            AstType::I16 => types::I16,
-            AstType::I32 => types::I32,
-            AstType::I64 => types::I64,
-            // AstType::U8 => types::U8,
-            // AstType::U16 => types::U16,
-            // AstType::U32 => types::U32,
-            // AstType::U64 => types::U64,
-            AstType::F32 => types::F32,
-            AstType::F64 => types::F64,
-            AstType::Bool => types::I8,
-            _ => unimplemented!("Type not yet implemented"),
+            UnaryOp::Neg => self.compile_negation(&unary.expr, expected, self.overflow, builder),
+            // logical not, as in C: `!x` is `x == 0`
+            UnaryOp::Not => {
+                let condition = self.compile_condition(&unary.expr, builder)?;
+                let value = builder.ins().icmp_imm(IntCC::Equal, condition, 0);
+                Ok((value, AstType::Bool))
+            }
+        }
+    }
+
+    fn compile_negation(
+        &mut self,
+        expr: &Expr,
+        expected: Option<&AstType>,
+        overflow: Overflow,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
+        let (value, from) = self.compile_expr(expr, expected, builder)?;
+        let type_ = promoted(&from);
+        let value = self.cast_value(value, &from, &type_, builder)?;
+        if type_.is_float() {
+            return Ok((builder.ins().fneg(value), type_));
+        }
+        if overflow == Overflow::Trap {
+            // only `MIN` overflows, and for unsigned types everything but 0
+            let overflowed = if type_.is_signed() {
+                let bits = builder.func.dfg.value_type(value).bits();
+                builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN >> (64 - bits))
+            } else {
+                builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
+            };
+            builder.ins().trapnz(overflowed, trap::OVERFLOW);
+        }
+        Ok((builder.ins().ineg(value), type_))
+    }
+
+    // `+`, `-` and `*` of integers, trapping on overflow.
+    fn checked_arithmetic(
+        &self,
+        op: &BinaryOp,
+        left: Value,
+        right: Value,
+        signed: bool,
+        builder: &mut FunctionBuilder,
+    ) -> Value {
+        let (value, overflowed) = if self.has_overflow_ops() {
+            match (op, signed) {
+                (BinaryOp::Add, true) => builder.ins().sadd_overflow(left, right),
+                (BinaryOp::Add, false) => builder.ins().uadd_overflow(left, right),
+                (BinaryOp::Sub, true) => builder.ins().ssub_overflow(left, right),
+                (BinaryOp::Sub, false) => builder.ins().usub_overflow(left, right),
+                (BinaryOp::Mul, true) => builder.ins().smul_overflow(left, right),
+                _ => builder.ins().umul_overflow(left, right),
+            }
+        } else {
+            match (op, signed) {
+                // the result has the wrong sign: both operands' sign differs from it
+                (BinaryOp::Add, true) => {
+                    let value = builder.ins().iadd(left, right);
+                    let left_sign = builder.ins().bxor(value, left);
+                    let right_sign = builder.ins().bxor(value, right);
+                    let both = builder.ins().band(left_sign, right_sign);
+                    (value, builder.ins().icmp_imm(IntCC::SignedLessThan, both, 0))
+                }
+                (BinaryOp::Add, false) => {
+                    let value = builder.ins().iadd(left, right);
+                    (value, builder.ins().icmp(IntCC::UnsignedLessThan, value, left))
+                }
+                // the operands' signs differ and the result's differs from `left`'s
+                (BinaryOp::Sub, true) => {
+                    let value = builder.ins().isub(left, right);
+                    let operand_signs = builder.ins().bxor(left, right);
+                    let result_sign = builder.ins().bxor(left, value);
+                    let both = builder.ins().band(operand_signs, result_sign);
+                    (value, builder.ins().icmp_imm(IntCC::SignedLessThan, both, 0))
+                }
+                (BinaryOp::Sub, false) => {
+                    let value = builder.ins().isub(left, right);
+                    (value, builder.ins().icmp(IntCC::UnsignedLessThan, left, right))
+                }
+                // the high half of the full product isn't just the low half's sign
+                (BinaryOp::Mul, true) => {
+                    let value = builder.ins().imul(left, right);
+                    let high = builder.ins().smulhi(left, right);
+                    let bits = builder.func.dfg.value_type(value).bits();
+                    let sign = builder.ins().sshr_imm(value, i64::from(bits - 1));
+                    (value, builder.ins().icmp(IntCC::NotEqual, high, sign))
+                }
+                _ => {
+                    let value = builder.ins().imul(left, right);
+                    let high = builder.ins().umulhi(left, right);
+                    (value, builder.ins().icmp_imm(IntCC::NotEqual, high, 0))
+                }
+            }
         };
-        Ok(AbiParam::new(cranelift_type))
+        builder.ins().trapnz(overflowed, trap::OVERFLOW);
+        value
+    }
+
+    // x86_64 and aarch64 lower Cranelift's overflow-flag instructions; other
+    // targets get the checks spelled out.
+    fn has_overflow_ops(&self) -> bool {
+        matches!(
+            self.module.isa().triple().architecture,
+            Architecture::X86_64 | Architecture::Aarch64(_)
+        )
+    }
+
+    fn compile_func_call(
+        &mut self,
+        func_call: &FuncCall,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        if let "va_start" | "va_arg" | "va_end" = func_call.name.as_str() {
+            return self.compile_va_intrinsic(func_call, builder);
+        }
+        if let Some((overflow, op)) = arithmetic_intrinsic(&func_call.name) {
+            return self.compile_arithmetic_intrinsic(func_call, overflow, op, builder).map(Some);
+        }
+
+        // a variable holding a function pointer shadows a function of the same name
+        if let Ok(var) = self.lookup_variable(&func_call.name) {
+            let (callee, type_) = self.load_variable(&var, builder)?;
+            return self.compile_indirect_call_value(&func_call.name, callee, &type_, &func_call.args, builder);
+        }
+
+        let (func_id, decl) = self.reference_function(&func_call.name)?;
+        if decl.variadic {
+            return self.compile_variadic_call(func_id, &decl, &func_call.args, builder);
+        }
+        let params: Vec<AstType> = decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        let mut args = self.compile_call_args(&func_call.name, &params, &func_call.args, builder)?;
+        if decl.extern_ {
+            self.pointers_to_host(Some(&decl.name), &mut args, &params, builder);
+        }
+
+        let func_ref = self.module.declare_func_in_func(func_id, builder.func);
+        let call = builder.ins().call(func_ref, &args);
+        Ok(decl
+            .return_type
+            .clone()
+            .map(|return_type| (self.result_from_host(&decl, builder.inst_results(call)[0], builder), return_type)))
+    }
+
+    // Sandboxed offsets among the arguments to `import`, as host pointers. The
+    // ones `SandboxOptions::lengths` pairs with a length must have that many
+    // bytes in memory. Variadic arguments, with no `import`, have no lengths.
+    fn pointers_to_host(&self, import: Option<&str>, args: &mut [Value], types: &[AstType], builder: &mut FunctionBuilder) {
+        let Some(sandbox) = &self.sandbox else {
+            return;
+        };
+        let pointer_type = self.module.target_config().pointer_type();
+        let lengths = import.map_or(&[][..], |import| sandbox.lengths(import));
+        for i in 0..args.len() {
+            if types[i] != AstType::String {
+                continue;
+            }
+            let len = lengths
+                .iter()
+                .find(|(pointer, _)| *pointer == i)
+                .map(|&(_, length)| args[length]);
+            args[i] = sandbox.host_pointer(args[i], len, pointer_type, builder);
+        }
+    }
+
+    // What `decl` returned, with a host pointer from a sandboxed import as an offset.
+    fn result_from_host(&self, decl: &FuncDecl, result: Value, builder: &mut FunctionBuilder) -> Value {
+        match &self.sandbox {
+            Some(sandbox) if decl.extern_ && decl.return_type == Some(AstType::String) => {
+                sandbox.offset_of(result, builder)
+            }
+            _ => result,
+        }
+    }
+
+    // Each call site gets its own signature: the declared params followed by the
+    // promoted types of the extra arguments.
+    fn compile_variadic_call(
+        &mut self,
+        func_id: FuncId,
+        decl: &FuncDecl,
+        args: &[Expr],
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        let abi = variadic::varargs_abi(self.module.isa().triple());
+        if abi == VarargsAbi::Unsupported {
+            return Err(CompileError::Unsupported(format!(
+                "variadic calls on {}",
+                self.module.isa().triple()
+            )));
+        }
+        if args.len() < decl.params.len() {
+            return Err(CompileError::ArgumentCount {
+                name: decl.name.clone(),
+                expected: decl.params.len(),
+                found: args.len(),
+            });
+        }
+
+        let (fixed_args, extra_args) = args.split_at(decl.params.len());
+        let params: Vec<AstType> = decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        let mut values = self.compile_call_args(&decl.name, &params, fixed_args, builder)?;
+        let mut sig = self.make_func_ptr_signature(&params, decl.return_type.as_ref())?;
+        if decl.extern_ {
+            self.pointers_to_host(Some(&decl.name), &mut values, &params, builder);
+            self.import_signature(&mut sig, &params, decl.return_type.as_ref())?;
+        }
+        for arg in extra_args {
+            // an integer literal is an `int` unless it doesn't fit, as in C
+            let hint = match arg {
+                Expr::Literal(Literal::Int(value)) if i32::try_from(*value).is_ok() => Some(AstType::I32),
+                _ => None,
+            };
+            let (value, type_) = self.compile_expr(arg, hint.as_ref(), builder)?;
+            let promoted = variadic::promote(&type_);
+            let mut value = self.cast_value(value, &type_, &promoted, builder)?;
+            if decl.extern_ {
+                self.pointers_to_host(None, std::slice::from_mut(&mut value), std::slice::from_ref(&promoted), builder);
+            }
+            if abi == VarargsAbi::IntegerRegisters && promoted.is_float() {
+                value = builder.ins().bitcast(types::I64, MemFlags::new(), value);
+            }
+            sig.params.push(AbiParam::new(builder.func.dfg.value_type(value)));
+            values.push(value);
+        }
+
+        let callee_id = if abi == VarargsAbi::SysV {
+            let vector_regs = sig
+                .params
+                .iter()
+                .filter(|param| param.value_type.is_float())
+                .count()
+                .min(8) as u8;
+            self.sysv_variadic_shim(func_id, vector_regs)?
+        } else {
+            func_id
+        };
+        let func_ref = self.module.declare_func_in_func(callee_id, builder.func);
+        let sig_ref = builder.func.dfg.ext_funcs[func_ref].signature;
+        builder.func.dfg.signatures[sig_ref] = sig;
+
+        let call = builder.ins().call(func_ref, &values);
+        Ok(decl
+            .return_type
+            .clone()
+            .map(|return_type| (self.result_from_host(decl, builder.inst_results(call)[0], builder), return_type)))
+    }
+
+    fn sysv_variadic_shim(&mut self, target: FuncId, vector_regs: u8) -> CompileResult<FuncId> {
+        if let Some(shim_id) = self.variadic_shims.get(&(target, vector_regs)) {
+            return Ok(*shim_id);
+        }
+
+        // callers override the signature per call site, so declare it empty
+        let shim_id = self
+            .module
+            .declare_anonymous_function(&self.module.make_signature())?;
+        // relocation targets are resolved through the names this function imports
+        let mut func = Function::new();
+        let target_name = func.declare_imported_user_function(UserExternalName::new(0, target.as_u32()));
+        let is_pic = self.module.isa().flags().is_pic();
+        let (bytes, relocs) = variadic::sysv_al_shim(vector_regs, ExternalName::user(target_name), is_pic);
+        self.module
+            .define_function_bytes(shim_id, &func, 16, &bytes, &relocs)?;
+
+        self.variadic_shims.insert((target, vector_regs), shim_id);
+        Ok(shim_id)
+    }
+
+    // `wrapping_add(a, b)`, `checked_neg(a)` and so on: integer arithmetic that
+    // wraps or traps on overflow whether or not checked arithmetic is enabled.
+    fn compile_arithmetic_intrinsic(
+        &mut self,
+        func_call: &FuncCall,
+        overflow: Overflow,
+        op: Option<BinaryOp>,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<(Value, AstType)> {
+        let expected_args = if op.is_some() { 2 } else { 1 };
+        if func_call.args.len() != expected_args {
+            return Err(CompileError::ArgumentCount {
+                name: func_call.name.clone(),
+                expected: expected_args,
+                found: func_call.args.len(),
+            });
+        }
+        let (value, type_) = match op {
+            Some(op) => {
+                let binary = Binary {
+                    op,
+                    left: Box::new(func_call.args[0].clone()),
+                    right: Box::new(func_call.args[1].clone()),
+                };
+                self.compile_binary(&binary, None, overflow, builder)?
+            }
+            None => self.compile_negation(&func_call.args[0], None, overflow, builder)?,
+        };
+        if !type_.is_integer() {
+            return Err(CompileError::TypeMismatch {
+                expected: AstType::I64,
+                found: type_,
+            });
+        }
+        Ok((value, type_))
+    }
+
+    fn compile_va_intrinsic(
+        &mut self,
+        func_call: &FuncCall,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        // `va_arg` reads whatever the caller left, which could be any pointer
+        if self.sandbox.is_some() {
+            return Err(CompileError::Unsupported(format!("`{}` in sandboxed code", func_call.name)));
+        }
+        let expected_args = match func_call.name.as_str() {
+            "va_arg" => 2,
+            _ => 1,
+        };
+        // `va_start(ap, last)` is accepted too; the named param isn't needed
+        let valid_count = func_call.args.len() == expected_args
+            || (func_call.name == "va_start" && func_call.args.len() == 2);
+        if !valid_count {
+            return Err(CompileError::ArgumentCount {
+                name: func_call.name.clone(),
+                expected: expected_args,
+                found: func_call.args.len(),
+            });
+        }
+
+        let (va_list, type_) = self.compile_expr(&func_call.args[0], None, builder)?;
+        if type_ != AstType::VaList {
+            return Err(CompileError::TypeMismatch {
+                expected: AstType::VaList,
+                found: type_,
+            });
+        }
+
+        match func_call.name.as_str() {
+            "va_start" => {
+                self.compile_va_start(va_list, builder)?;
+                Ok(None)
+            }
+            "va_arg" => {
+                let Expr::Type(arg_type) = &func_call.args[1] else {
+                    return Err(CompileError::Unsupported(format!(
+                        "va_arg type argument {:?}",
+                        func_call.args[1]
+                    )));
+                };
+                let value = self.compile_va_arg(va_list, arg_type, builder)?;
+                Ok(Some((value, arg_type.clone())))
+            }
+            // nothing to release: the `va_list` lives in the caller's frame
+            _ => Ok(None),
+        }
+    }
+
+    fn compile_va_start(&mut self, va_list: Value, builder: &mut FunctionBuilder) -> CompileResult<()> {
+        let frame = self
+            .varargs
+            .as_ref()
+            .ok_or(CompileError::OutsideVariadic("va_start"))?;
+        let pointer_type = self.module.target_config().pointer_type();
+        let flags = MemFlags::trusted();
+
+        let gp_offset = builder.ins().iconst(types::I32, frame.gp_offset as i64);
+        builder.ins().store(flags, gp_offset, va_list, variadic::SYSV_GP_OFFSET);
+        let fp_offset = builder.ins().iconst(types::I32, frame.fp_offset as i64);
+        builder.ins().store(flags, fp_offset, va_list, variadic::SYSV_FP_OFFSET);
+
+        let frame_pointer = builder.ins().get_frame_pointer(pointer_type);
+        let overflow_arg_area = builder.ins().iadd_imm(frame_pointer, frame.overflow_offset);
+        builder.ins().store(flags, overflow_arg_area, va_list, variadic::SYSV_OVERFLOW_ARG_AREA);
+        let reg_save_area = builder.ins().stack_addr(pointer_type, frame.reg_save_area, 0);
+        builder.ins().store(flags, reg_save_area, va_list, variadic::SYSV_REG_SAVE_AREA);
+        Ok(())
+    }
+
+    // Take the next argument from the register save area while registers of its class
+    // remain, otherwise from the overflow area.
+    fn compile_va_arg(
+        &mut self,
+        va_list: Value,
+        arg_type: &AstType,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Value> {
+        if self.varargs.is_none() {
+            return Err(CompileError::OutsideVariadic("va_arg"));
+        }
+        if *arg_type == AstType::F32 {
+            // a float argument has already been promoted by the caller
+            return Err(CompileError::TypeMismatch {
+                expected: AstType::F64,
+                found: AstType::F32,
+            });
+        }
+        let (field, limit, step) = if arg_type.is_float() {
+            (variadic::SYSV_FP_OFFSET, variadic::SYSV_REG_SAVE_AREA_SIZE, 16)
+        } else {
+            (variadic::SYSV_GP_OFFSET, variadic::SYSV_GP_SAVE_SIZE, 8)
+        };
+        let value_type = self.clif_type(arg_type)?;
+        let pointer_type = self.module.target_config().pointer_type();
+        let flags = MemFlags::trusted();
+
+        let in_registers = builder.create_block();
+        let on_stack = builder.create_block();
+        let done = builder.create_block();
+        let address = builder.append_block_param(done, pointer_type);
+
+        let offset = builder.ins().load(types::I32, flags, va_list, field);
+        let fits = builder.ins().icmp_imm(IntCC::UnsignedLessThan, offset, limit as i64);
+        builder.ins().brif(fits, in_registers, &[], on_stack, &[]);
+        builder.seal_block(in_registers);
+        builder.seal_block(on_stack);
+
+        builder.switch_to_block(in_registers);
+        let reg_save_area = builder
+            .ins()
+            .load(pointer_type, flags, va_list, variadic::SYSV_REG_SAVE_AREA);
+        let wide_offset = builder.ins().uextend(pointer_type, offset);
+        let register_address = builder.ins().iadd(reg_save_area, wide_offset);
+        let next_offset = builder.ins().iadd_imm(offset, step);
+        builder.ins().store(flags, next_offset, va_list, field);
+        builder.ins().jump(done, &[register_address]);
+
+        builder.switch_to_block(on_stack);
+        let stack_address = builder
+            .ins()
+            .load(pointer_type, flags, va_list, variadic::SYSV_OVERFLOW_ARG_AREA);
+        let next_address = builder.ins().iadd_imm(stack_address, 8);
+        builder
+            .ins()
+            .store(flags, next_address, va_list, variadic::SYSV_OVERFLOW_ARG_AREA);
+        builder.ins().jump(done, &[stack_address]);
+
+        builder.switch_to_block(done);
+        builder.seal_block(done);
+        Ok(builder.ins().load(value_type, flags, address, 0))
+    }
+
+    fn compile_call_args(
+        &mut self,
+        name: &str,
+        params: &[AstType],
+        args: &[Expr],
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Vec<Value>> {
+        if params.len() != args.len() {
+            return Err(CompileError::ArgumentCount {
+                name: name.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for (arg, param_type) in args.iter().zip(params) {
+            let (value, type_) = self.compile_expr(arg, Some(param_type), builder)?;
+            values.push(self.cast_value(value, &type_, param_type, builder)?);
+        }
+        Ok(values)
+    }
+
+    fn compile_func_addr(&mut self, name: &str, builder: &mut FunctionBuilder) -> CompileResult<(Value, AstType)> {
+        let (func_id, decl) = self.reference_function(name)?;
+        self.check_addressable(&decl)?;
+        let type_ = decl.func_ptr_type();
+
+        let func_ref = self.module.declare_func_in_func(func_id, builder.func);
+        let pointer_type = self.module.target_config().pointer_type();
+        Ok((builder.ins().func_addr(pointer_type, func_ref), type_))
+    }
+
+    // Sandboxed code calls imports with offsets where they take pointers, which
+    // only direct calls translate.
+    fn check_addressable(&self, decl: &FuncDecl) -> CompileResult<()> {
+        if self.sandbox.is_some() && decl.extern_ {
+            return Err(CompileError::Unsupported(format!(
+                "the address of import `{}` in sandboxed code",
+                decl.name
+            )));
+        }
+        Ok(())
+    }
+
+    fn compile_call_indirect(
+        &mut self,
+        call: &CallIndirect,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        let (callee, callee_type) = self.compile_expr(&call.callee, None, builder)?;
+        self.compile_indirect_call_value("indirect call", callee, &callee_type, &call.args, builder)
+    }
+
+    fn compile_indirect_call_value(
+        &mut self,
+        name: &str,
+        callee: Value,
+        callee_type: &AstType,
+        args: &[Expr],
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        let AstType::FuncPtr {
+            params,
+            return_type,
+        } = callee_type
+        else {
+            return Err(CompileError::NotCallable(callee_type.clone()));
+        };
+
+        let args = self.compile_call_args(name, params, args, builder)?;
+        let sig = self.make_func_ptr_signature(params, return_type.as_deref())?;
+        let sig_ref = builder.import_signature(sig);
+        if self.sandbox.is_some() {
+            builder.ins().trapz(callee, trap::NULL_CALL);
+        }
+        let call = builder.ins().call_indirect(sig_ref, callee, &args);
+        Ok(return_type
+            .as_deref()
+            .map(|return_type| (builder.inst_results(call)[0], return_type.clone())))
+    }
+
+    // implicit conversions between numeric types, following C's rules
+    fn cast_value(
+        &self,
+        value: Value,
+        from: &AstType,
+        to: &AstType,
+        builder: &mut FunctionBuilder,
+    ) -> CompileResult<Value> {
+        if from == to {
+            return Ok(value);
+        }
+        if !from.is_numeric() || !to.is_numeric() {
+            return Err(CompileError::TypeMismatch {
+                expected: to.clone(),
+                found: from.clone(),
+            });
+        }
+
+        let from_ty = self.clif_type(from)?;
+        let to_ty = self.clif_type(to)?;
+        let value = match (from.is_float(), to.is_float()) {
+            (false, false) if *to == AstType::Bool => builder.ins().icmp_imm(IntCC::NotEqual, value, 0),
+            (false, false) if to_ty.bits() > from_ty.bits() && from.is_signed() => {
+                builder.ins().sextend(to_ty, value)
+            }
+            (false, false) if to_ty.bits() > from_ty.bits() => builder.ins().uextend(to_ty, value),
+            (false, false) if to_ty.bits() < from_ty.bits() => {
+                let narrowed = builder.ins().ireduce(to_ty, value);
+                if self.overflow == Overflow::Trap {
+                    self.check_narrowing(value, narrowed, from, to, builder);
+                }
+                narrowed
+            }
+            (false, false) => value,
+            (false, true) if from.is_signed() => builder.ins().fcvt_from_sint(to_ty, value),
+            (false, true) => builder.ins().fcvt_from_uint(to_ty, value),
+            (true, false) if *to == AstType::Bool => {
+                let zero = self.zero_value(from, builder)?;
+                builder.ins().fcmp(FloatCC::NotEqual, value, zero)
+            }
+            // with checked arithmetic, values out of range trap instead of saturating,
+            // with `INTEGER_OVERFLOW` (and NaN with `BAD_CONVERSION_TO_INTEGER`) like
+            // Cranelift's trapping conversions
+            (true, false) if self.overflow == Overflow::Trap && to_ty.bits() < 32 => {
+                let wide = if to.is_signed() {
+                    builder.ins().fcvt_to_sint(types::I32, value)
+                } else {
+                    builder.ins().fcvt_to_uint(types::I32, value)
+                };
+                let narrowed = builder.ins().ireduce(to_ty, wide);
+                let widened = if to.is_signed() {
+                    builder.ins().sextend(types::I32, narrowed)
+                } else {
+                    builder.ins().uextend(types::I32, narrowed)
+                };
+                let overflowed = builder.ins().icmp(IntCC::NotEqual, widened, wide);
+                builder.ins().trapnz(overflowed, TrapCode::INTEGER_OVERFLOW);
+                narrowed
+            }
+            (true, false) if self.overflow == Overflow::Trap && to.is_signed() => {
+                builder.ins().fcvt_to_sint(to_ty, value)
+            }
+            (true, false) if self.overflow == Overflow::Trap => builder.ins().fcvt_to_uint(to_ty, value),
+            // Cranelift only converts to 32 and 64 bits; narrower results saturate
+            // at 32 bits and are clamped from there
+            (true, false) if to_ty.bits() < 32 => {
+                let bits = to_ty.bits();
+                let clamped = if to.is_signed() {
+                    let wide = builder.ins().fcvt_to_sint_sat(types::I32, value);
+                    let max = builder.ins().iconst(types::I32, (1i64 << (bits - 1)) - 1);
+                    let min = builder.ins().iconst(types::I32, -(1i64 << (bits - 1)));
+                    let below_max = builder.ins().smin(wide, max);
+                    builder.ins().smax(below_max, min)
+                } else {
+                    let wide = builder.ins().fcvt_to_uint_sat(types::I32, value);
+                    let max = builder.ins().iconst(types::I32, (1i64 << bits) - 1);
+                    builder.ins().umin(wide, max)
+                };
+                builder.ins().ireduce(to_ty, clamped)
+            }
+            (true, false) if to.is_signed() => builder.ins().fcvt_to_sint_sat(to_ty, value),
+            (true, false) => builder.ins().fcvt_to_uint_sat(to_ty, value),
+            (true, true) if to_ty.bits() > from_ty.bits() => builder.ins().fpromote(to_ty, value),
+            (true, true) => builder.ins().fdemote(to_ty, value),
+        };
+        Ok(value)
+    }
+
+    // Traps unless `narrowed` still has the value of `value`.
+    fn check_narrowing(&self, value: Value, narrowed: Value, from: &AstType, to: &AstType, builder: &mut FunctionBuilder) {
+        let from_ty = builder.func.dfg.value_type(value);
+        let widened = if to.is_signed() {
+            builder.ins().sextend(from_ty, narrowed)
+        } else {
+            builder.ins().uextend(from_ty, narrowed)
+        };
+        let mut overflowed = builder.ins().icmp(IntCC::NotEqual, widened, value);
+        // a large unsigned value can narrow to a negative one that sign-extends back
+        if to.is_signed() && !from.is_signed() {
+            let negative = builder.ins().icmp_imm(IntCC::SignedLessThan, narrowed, 0);
+            overflowed = builder.ins().bor(overflowed, negative);
+        }
+        builder.ins().trapnz(overflowed, trap::OVERFLOW);
+    }
+
+    fn zero_value(&self, type_: &AstType, builder: &mut FunctionBuilder) -> CompileResult<Value> {
+        let value = match type_ {
+            AstType::F32 => builder.ins().f32const(0.0),
+            AstType::F64 => builder.ins().f64const(0.0),
+            _ => builder.ins().iconst(self.clif_type(type_)?, 0),
+        };
+        Ok(value)
+    }
+
+    fn convert_type(&self, ast_type: &AstType) -> CompileResult<AbiParam> {
+        Ok(AbiParam::new(self.clif_type(ast_type)?))
+    }
+
+    fn clif_type(&self, ast_type: &AstType) -> CompileResult<Type> {
+        if self.sandbox.is_some() {
+            match ast_type {
+                // offsets into the sandbox's memory
+                AstType::String => return Ok(types::I32),
+                // a host pointer that nothing could translate or check
+                AstType::VaList => return Err(CompileError::Unsupported("va_list in sandboxed code".to_string())),
+                _ => {}
+            }
+        }
+        clif_type(ast_type, self.module.target_config().pointer_type())
+    }
+}
+
+pub(crate) fn clif_type(ast_type: &AstType, pointer_type: Type) -> CompileResult<Type> {
+    let cranelift_type = match ast_type {
+        AstType::I8 | AstType::U8 => types::I8,
+        AstType::I16 | AstType::U16 => types::I16,
+        AstType::I32 | AstType::U32 => types::I32,
+        AstType::I64 | AstType::U64 => types::I64,
+        AstType::F32 => types::F32,
+        AstType::F64 => types::F64,
+        AstType::Bool | AstType::Char => types::I8,
+        AstType::Usize | AstType::Isize => pointer_type,
+        AstType::String | AstType::FuncPtr { .. } | AstType::VaList => pointer_type,
+        _ => return Err(CompileError::Unsupported(format!("type {:?}", ast_type))),
+    };
+    Ok(cranelift_type)
+}
+
+// The in-memory bytes of a numeric literal converted to `type_`, least
+// significant first, or `None` for literals that aren't numbers.
+fn constant_bytes(literal: &Literal, type_: &AstType, pointer_bytes: usize) -> Option<Vec<u8>> {
+    let (int, float) = match *literal {
+        Literal::Int(value) => (value, value as f64),
+        Literal::Float(value) => (value as i64, value),
+        Literal::Bool(value) => (value as i64, value as i64 as f64),
+        Literal::Char(value) => (value as i64, value as u32 as f64),
+        Literal::String(_) => return None,
+    };
+    let bytes = match type_ {
+        AstType::F32 => (float as f32).to_le_bytes().to_vec(),
+        AstType::F64 => float.to_le_bytes().to_vec(),
+        AstType::Bool => vec![(int != 0 || float != 0.0) as u8],
+        AstType::I8 | AstType::U8 | AstType::Char => int.to_le_bytes()[..1].to_vec(),
+        AstType::I16 | AstType::U16 => int.to_le_bytes()[..2].to_vec(),
+        AstType::I32 | AstType::U32 => int.to_le_bytes()[..4].to_vec(),
+        AstType::I64 | AstType::U64 => int.to_le_bytes().to_vec(),
+        AstType::Usize | AstType::Isize => int.to_le_bytes()[..pointer_bytes].to_vec(),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+// What integer arithmetic does when the result doesn't fit its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overflow {
+    Wrap,
+    Trap,
+}
+
+// The overflow behavior and operation of `wrapping_*` and `checked_*`
+// intrinsics; negation has no `BinaryOp`.
+pub(crate) fn arithmetic_intrinsic(name: &str) -> Option<(Overflow, Option<BinaryOp>)> {
+    let (overflow, op) = name.split_once('_')?;
+    let overflow = match overflow {
+        "wrapping" => Overflow::Wrap,
+        "checked" => Overflow::Trap,
+        _ => return None,
+    };
+    let op = match op {
+        "add" => Some(BinaryOp::Add),
+        "sub" => Some(BinaryOp::Sub),
+        "mul" => Some(BinaryOp::Mul),
+        "neg" => None,
+        _ => return None,
+    };
+    Some((overflow, op))
+}
+
+// the type both operands of a binary operator are converted to
+pub(crate) fn common_type(left: &AstType, right: &AstType, pointer_type: Type) -> CompileResult<AstType> {
+    let (left, right) = (&promoted(left), &promoted(right));
+    if left == right {
+        return Ok(left.clone());
+    }
+    if !left.is_numeric() || !right.is_numeric() {
+        return Err(CompileError::TypeMismatch {
+            expected: left.clone(),
+            found: right.clone(),
+        });
+    }
+    if left.is_float() || right.is_float() {
+        let wide = *left == AstType::F64 || *right == AstType::F64;
+        return Ok(if wide { AstType::F64 } else { AstType::F32 });
+    }
+
+    // by width, so pointer-sized types rank with the integers they match
+    let rank = |ty: &AstType| match ty {
+        AstType::Bool => 0,
+        _ => clif_type(ty, pointer_type).map_or(0, |ty| ty.bits()),
+    };
+    // at equal width the unsigned type wins, as in C
+    let wider = match rank(left).cmp(&rank(right)) {
+        std::cmp::Ordering::Greater => left,
+        std::cmp::Ordering::Less => right,
+        std::cmp::Ordering::Equal if left.is_signed() => right,
+        std::cmp::Ordering::Equal => left,
+    };
+    Ok(wider.clone())
+}
+
+// Whether the host can pass or take `host` where the program has `declared`.
+// Integers only need the same width, as in C, but `bool` has to be `_Bool`
+// both ways: only those are sure to hold nothing but 0 or 1.
+pub(crate) fn host_compatible(
+    host: &AstType,
+    declared: &AstType,
+    clif_type: &dyn Fn(&AstType) -> CompileResult<Type>,
+) -> bool {
+    match (host, declared) {
+        (
+            AstType::FuncPtr {
+                params: host_params,
+                return_type: host_return,
+            },
+            AstType::FuncPtr { params, return_type },
+        ) => {
+            host_params.len() == params.len()
+                && host_params.iter().zip(params).all(|(host, declared)| host_compatible(host, declared, clif_type))
+                && match (host_return, return_type) {
+                    (Some(host), Some(declared)) => host_compatible(host, declared, clif_type),
+                    (host, declared) => host.is_none() && declared.is_none(),
+                }
+        }
+        (AstType::Bool, other) | (other, AstType::Bool) => *other == AstType::Bool,
+        (host, declared) => {
+            host == declared || matches!((clif_type(host), clif_type(declared)), (Ok(host), Ok(declared)) if host == declared)
+        }
+    }
+}
+
+// `_Bool` and `char` operands are promoted to `int` before arithmetic, as in C.
+pub(crate) fn promoted(ty: &AstType) -> AstType {
+    match ty {
+        AstType::Bool | AstType::Char => AstType::I32,
+        _ => ty.clone(),
     }
 }
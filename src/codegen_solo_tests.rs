@@ -1,26 +1,25 @@
-use anyhow::Result;
-use control::ControlPlane;
-use cranelift::prelude::*;
-use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
-use cranelift_codegen::ir::Constant;
-use cranelift_codegen::ir::ExternalName;
-use cranelift_codegen::*;
-use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{
-    DataDescription, DataId, FuncId, FuncOrDataId, Linkage, Module, ModuleDeclarations,
-    ModuleResult,
-};
-use cranelift_object::ObjectModule;
-use delegate::delegate;
-use ir::{FuncRef, Function, GlobalValue};
-use isa::{TargetFrontendConfig, TargetIsa};
+use anyhow::{bail, Result};
+use cranelift_codegen::ir::{AbiParam, Function, InstBuilder, Signature, SourceLoc, Type, Value};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{DataDescription, DataId, FuncId, FuncOrDataId, Linkage, Module};
 use std::collections::HashMap;
-use target_lexicon;
+use std::ops::{Deref, DerefMut};
+use crate::host::{HostArgs, HostReturn};
 use crate::module::ModuleType;
+use crate::report::{self, CompileOptions, CompileReport};
+use crate::stack::{self, StackOptions};
+use crate::trap::TrapRegistry;
+use crate::unwind::UnwindRegistry;
 
 pub struct CodegenSolo {
     pub module: ModuleType,
     pub functions: HashMap<String, FuncId>,
+    pub options: CompileOptions,
+    pub report: CompileReport,
+    // so that traps in JIT code, stack overflows included, become errors
+    unwind: UnwindRegistry,
+    traps: TrapRegistry,
 }
 
 
@@ -29,102 +28,205 @@ impl CodegenSolo {
         Self {
             module,
             functions: HashMap::new(),
+            options: CompileOptions::all(),
+            report: CompileReport::default(),
+            unwind: UnwindRegistry::default(),
+            traps: TrapRegistry::default(),
         }
     }
 
-    pub fn run<T>(&self, func: String) -> Result<T> {
-        let func_id = self.functions.get(&func).unwrap();
+    // A signature in the module's default calling convention.
+    pub fn signature(&self, params: &[Type], returns: &[Type]) -> Signature {
+        let mut signature = self.module.make_signature();
+        signature.params.extend(params.iter().map(|&type_| AbiParam::new(type_)));
+        signature.returns.extend(returns.iter().map(|&type_| AbiParam::new(type_)));
+        signature
+    }
 
-        let func_ptr = match &self.module {
-            ModuleType::JITModule(jit) => jit.get_finalized_function(*func_id),
-            ModuleType::ObjectModule(_) => {
-                return Err(anyhow::anyhow!("Cannot run functions from object module"))
-            }
+    // Declares `name` and defines it with the body `build` generates, starting
+    // in an entry block whose params are the function's. Blocks are sealed
+    // afterwards, so `build` only has to terminate them. Declaring first lets
+    // the body call itself, and functions declared with `declare` can call
+    // each other.
+    pub fn define(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        build: impl FnOnce(&mut SoloBuilder) -> Result<()>,
+    ) -> Result<FuncId> {
+        let func_id = self.declare(name, &signature)?;
+        let mut func = Function::new();
+        func.signature = signature;
+        let mut func_builder_ctx = FunctionBuilderContext::new();
+        let mut builder = SoloBuilder {
+            builder: FunctionBuilder::new(&mut func, &mut func_builder_ctx),
+            module: &mut self.module,
+            params: Vec::new(),
         };
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.params = builder.block_params(entry).to_vec();
+        build(&mut builder)?;
+        builder.seal_all_blocks();
+        builder.builder.finalize();
+
+        let mut ctx = self.module.make_context();
+        ctx.func = func;
+        self.define_function(name, func_id, &mut ctx)?;
+        Ok(func_id)
+    }
 
-        let func: fn() -> T = unsafe { std::mem::transmute(func_ptr) };
-        Ok(func())
+    // Declares a function this module exports, to be defined with `define`.
+    pub fn declare(&mut self, name: &str, signature: &Signature) -> Result<FuncId> {
+        Ok(self.module.declare_function(name, Linkage::Export, signature)?)
     }
 
-    pub fn run_main<T>(&self) -> Result<T> {
-        self.run("main".to_string())
+    // Declares a function from outside the module, like libc's, for `SoloBuilder::call`.
+    pub fn import(&mut self, name: &str, signature: &Signature) -> Result<FuncId> {
+        Ok(self.module.declare_function(name, Linkage::Import, signature)?)
     }
-}
 
-#[cfg(test)]
-mod tests {
+    // Defines read-only data named `name`, for `SoloBuilder::data_addr`.
+    pub fn data(&mut self, name: &str, bytes: &[u8]) -> Result<DataId> {
+        let data_id = self.module.declare_data(name, Linkage::Local, false, false)?;
+        let mut data = DataDescription::new();
+        data.define(bytes.into());
+        self.module.define_data(data_id, &data)?;
+        Ok(data_id)
+    }
 
-    use cranelift_codegen::ir::{types, AbiParam, Function, Type};
-    use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
-    use cranelift_module::{Linkage, Module};
+    // Defines `func_id` and records it as `name`, capturing its report.
+    pub fn define_function(&mut self, name: &str, func_id: FuncId, ctx: &mut Context) -> Result<()> {
+        if matches!(self.module, ModuleType::JITModule(_)) {
+            stack::insert_stack_check(&mut self.module, &mut ctx.func, SourceLoc::default())?;
+        }
+        let function = report::define_function(&mut self.module, name, func_id, ctx, self.options)?;
+        self.report.functions.push(function);
+        self.functions.insert(name.to_string(), func_id);
+        if let ModuleType::JITModule(jit) = &self.module {
+            self.unwind.add_function(func_id, ctx, jit.isa())?;
+            self.traps.add_function(func_id, name, ctx);
+        }
+        Ok(())
+    }
 
-    use super::*;
-    fn get_compiler() -> Result<CodegenSolo> {
-        let mut flags_builder = cranelift_codegen::settings::builder();
-        let shared_flags = cranelift_codegen::settings::Flags::new(flags_builder);
+    // Makes what's been defined so far callable; `call` does this itself.
+    pub fn finalize(&mut self) -> Result<()> {
+        if let ModuleType::JITModule(jit) = &mut self.module {
+            jit.finalize_definitions()?;
+            self.unwind.register(jit.isa(), |func_id| jit.get_finalized_function(func_id))?;
+            self.traps.register(|func_id| jit.get_finalized_function(func_id));
+        }
+        Ok(())
+    }
 
-        let triple = target_lexicon::HOST;
-        let isa_builder = cranelift_native::builder().unwrap();
-        let isa = isa_builder.finish(shared_flags)?;
+    pub fn call<A: HostArgs, R: HostReturn>(&mut self, func: &str, args: A) -> Result<R> {
+        self.call_with_stack(func, args, StackOptions::default())
+    }
 
-        let libcall_names = cranelift_module::default_libcall_names();
-        let jit_builder = JITBuilder::with_isa(isa, libcall_names);
-        let jit_module = JITModule::new(jit_builder);
+    pub fn call_with_stack<A: HostArgs, R: HostReturn>(&mut self, func: &str, args: A, stack: StackOptions) -> Result<R> {
+        let func_id = *self
+            .functions
+            .get(func)
+            .ok_or_else(|| anyhow::anyhow!("Unknown function {}", func))?;
+        self.finalize()?;
+        Ok(self.module.call_with_stack(func_id, args, stack)?)
+    }
 
-        let codegen = CodegenSolo::new(ModuleType::JITModule(jit_module));
-        Ok(codegen)
+    pub fn run<R: HostReturn>(&mut self, func: &str) -> Result<R> {
+        self.call(func, ())
     }
-    #[test]
-    fn test_return_i32() {
-        // i32 main() { return 0; }
-        let mut codegen = get_compiler().unwrap();
 
-        // Define function signature with i32 return type
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
+    pub fn run_with_stack<R: HostReturn>(&mut self, func: &str, stack: StackOptions) -> Result<R> {
+        self.call_with_stack(func, (), stack)
+    }
 
-        // Assign the signature to the function
-        let mut func = Function::new();
-        func.signature = signature.clone(); // <-- Add this line
+    pub fn run_main<R: HostReturn>(&mut self) -> Result<R> {
+        self.run("main")
+    }
+}
 
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
+// The `FunctionBuilder` for a body being defined with `CodegenSolo::define`,
+// which can also refer to the module's functions and data by name.
+pub struct SoloBuilder<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut ModuleType,
+    params: Vec<Value>,
+}
 
-        let block = func_builder.create_block();
-        func_builder.switch_to_block(block);
-        func_builder.seal_block(block);
+impl SoloBuilder<'_> {
+    // the values of the function's params, in the entry block
+    pub fn params(&self) -> &[Value] {
+        &self.params
+    }
 
-        // Return 0
-        let zero = func_builder.ins().iconst(types::I32, 0);
-        func_builder.ins().return_(&[zero]);
-        func_builder.finalize();
+    // Calls a function declared in the module by name, checking `args` against
+    // its signature, and returns its results.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>> {
+        let Some(FuncOrDataId::Func(func_id)) = self.module.get_name(name) else {
+            bail!("no function named `{}`", name);
+        };
+        let signature = &self.module.declarations().get_function_decl(func_id).signature;
+        let params: Vec<Type> = signature.params.iter().map(|param| param.value_type).collect();
+        let found: Vec<Type> = args.iter().map(|&arg| self.builder.func.dfg.value_type(arg)).collect();
+        if params != found {
+            bail!("`{}` takes {:?}, but was called with {:?}", name, params, found);
+        }
+        let func_ref = self.module.declare_func_in_func(func_id, self.builder.func);
+        let call = self.builder.ins().call(func_ref, args);
+        Ok(self.builder.inst_results(call).to_vec())
+    }
 
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
+    // The address of data defined with `CodegenSolo::data`.
+    pub fn data_addr(&mut self, name: &str) -> Result<Value> {
+        let Some(FuncOrDataId::Data(data_id)) = self.module.get_name(name) else {
+            bail!("no data named `{}`", name);
+        };
+        let global = self.module.declare_data_in_func(data_id, self.builder.func);
+        let pointer_type = self.module.target_config().pointer_type();
+        Ok(self.builder.ins().global_value(pointer_type, global))
+    }
+}
 
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
-            .unwrap();
+impl<'a> Deref for SoloBuilder<'a> {
+    type Target = FunctionBuilder<'a>;
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
+    fn deref(&self) -> &Self::Target {
+        &self.builder
+    }
+}
 
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
+impl DerefMut for SoloBuilder<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.builder
+    }
+}
 
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
+#[cfg(test)]
+mod tests {
 
+    use cranelift::prelude::*;
+
+    use super::*;
+    use crate::compiler::CompilerBuilder;
+    use crate::error::CompileError;
+    fn get_compiler() -> Result<CodegenSolo> {
+        Ok(CodegenSolo::new(CompilerBuilder::new().jit_module()?))
+    }
+    #[test]
+    fn test_return_i32() {
+        // i32 main() { return 0; }
+        let mut codegen = get_compiler().unwrap();
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                let zero = builder.ins().iconst(types::I32, 0);
+                builder.ins().return_(&[zero]);
+                Ok(())
+            })
+            .unwrap();
 
-        println!("Declarations:\n{:?}", codegen.module.declarations());
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 0);
     }
@@ -133,73 +235,27 @@ mod tests {
     fn test_if_else() {
         // i32 main() { if (0) { return 1; } else { return 0; } }
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        // Create the basic blocks
-        let entry_block = func_builder.create_block();
-        let true_block = func_builder.create_block();
-        let false_block = func_builder.create_block();
-
-        // Switch to entry block
-        func_builder.switch_to_block(entry_block);
-
-        // Create condition (0)
-        let zero = func_builder.ins().iconst(types::I32, 0);
-
-        // Create the if-else branch
-        func_builder
-            .ins()
-            .brif(zero, true_block, &[], false_block, &[]);
-
-        // True block
-        func_builder.switch_to_block(true_block);
-        let one = func_builder.ins().iconst(types::I32, 1);
-        func_builder.ins().return_(&[one]);
-
-        // False block
-        func_builder.switch_to_block(false_block);
-        let zero_return = func_builder.ins().iconst(types::I32, 0);
-        func_builder.ins().return_(&[zero_return]);
-
-        // Seal all blocks
-        func_builder.seal_block(entry_block);
-        func_builder.seal_block(true_block);
-        func_builder.seal_block(false_block);
-
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                let true_block = builder.create_block();
+                let false_block = builder.create_block();
+
+                // Create the if-else branch on the condition (0)
+                let zero = builder.ins().iconst(types::I32, 0);
+                builder.ins().brif(zero, true_block, &[], false_block, &[]);
+
+                builder.switch_to_block(true_block);
+                let one = builder.ins().iconst(types::I32, 1);
+                builder.ins().return_(&[one]);
+
+                builder.switch_to_block(false_block);
+                let zero_return = builder.ins().iconst(types::I32, 0);
+                builder.ins().return_(&[zero_return]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 0); // Since condition is 0 (false), it should return 0
     }
@@ -208,90 +264,41 @@ mod tests {
     fn test_while() {
         // int main() { int i = 0; while (i < 10) { i++; } return i; }
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        // Create basic blocks
-        let entry_block = func_builder.create_block();
-        let loop_header = func_builder.create_block();
-        let loop_body = func_builder.create_block();
-        let exit_block = func_builder.create_block();
-
-        // Start with entry block
-        func_builder.switch_to_block(entry_block);
-
-        // Create a variable slot for our counter
-        let i_var = Variable::new(0);
-        func_builder.declare_var(i_var, types::I32);
-
-        // Initialize i = 0
-        let zero = func_builder.ins().iconst(types::I32, 0);
-        func_builder.def_var(i_var, zero);
-
-        // Jump to loop header
-        func_builder.ins().jump(loop_header, &[]);
-
-        // Loop header: check condition (i < 10)
-        func_builder.switch_to_block(loop_header);
-        let i_val = func_builder.use_var(i_var);
-        let ten = func_builder.ins().iconst(types::I32, 10);
-        let condition = func_builder.ins().icmp(IntCC::SignedLessThan, i_val, ten);
-        func_builder
-            .ins()
-            .brif(condition, loop_body, &[], exit_block, &[]);
-
-        // Loop body: increment i
-        func_builder.switch_to_block(loop_body);
-        let i_val = func_builder.use_var(i_var);
-        let one = func_builder.ins().iconst(types::I32, 1);
-        let i_plus_one = func_builder.ins().iadd(i_val, one);
-        func_builder.def_var(i_var, i_plus_one);
-        func_builder.ins().jump(loop_header, &[]);
-
-        // Exit block: return i
-        func_builder.switch_to_block(exit_block);
-        let return_val = func_builder.use_var(i_var);
-        func_builder.ins().return_(&[return_val]);
-
-        // Seal all blocks
-        func_builder.seal_block(entry_block);
-        func_builder.seal_block(loop_header);
-        func_builder.seal_block(loop_body);
-        func_builder.seal_block(exit_block);
-
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                let loop_header = builder.create_block();
+                let loop_body = builder.create_block();
+                let exit_block = builder.create_block();
+
+                // Create a variable slot for our counter, initialized to 0
+                let i_var = Variable::new(0);
+                builder.declare_var(i_var, types::I32);
+                let zero = builder.ins().iconst(types::I32, 0);
+                builder.def_var(i_var, zero);
+                builder.ins().jump(loop_header, &[]);
+
+                // Loop header: check condition (i < 10)
+                builder.switch_to_block(loop_header);
+                let i_val = builder.use_var(i_var);
+                let condition = builder.ins().icmp_imm(IntCC::SignedLessThan, i_val, 10);
+                builder.ins().brif(condition, loop_body, &[], exit_block, &[]);
+
+                // Loop body: increment i
+                builder.switch_to_block(loop_body);
+                let i_val = builder.use_var(i_var);
+                let i_plus_one = builder.ins().iadd_imm(i_val, 1);
+                builder.def_var(i_var, i_plus_one);
+                builder.ins().jump(loop_header, &[]);
+
+                // Exit block: return i
+                builder.switch_to_block(exit_block);
+                let return_val = builder.use_var(i_var);
+                builder.ins().return_(&[return_val]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 10); // The loop will increment i until it equals 10
     }
@@ -299,117 +306,110 @@ mod tests {
     fn test_function_definition_and_call() {
         // int add(int a, int b) { return a + b; }
         // int main() { return add(1, 2); }
-
         let mut codegen = get_compiler().unwrap();
-
-        // Define signature for add function (takes two i32, returns i32)
-        let mut add_signature = codegen.module.make_signature();
-        add_signature.params.push(AbiParam::new(types::I32));
-        add_signature.params.push(AbiParam::new(types::I32));
-        add_signature.returns.push(AbiParam::new(types::I32));
-
-        // Create add function
-        let mut add_func = Function::new();
-        add_func.signature = add_signature.clone();
-
-        let mut add_func_builder_ctx = FunctionBuilderContext::new();
-        let mut add_func_builder = FunctionBuilder::new(&mut add_func, &mut add_func_builder_ctx);
-
-        // Create entry block for add function
-        let add_entry_block = add_func_builder.create_block();
-        add_func_builder.switch_to_block(add_entry_block);
-
-        // Get the parameters a and b
-        let a = add_func_builder.append_block_param(add_entry_block, types::I32);
-        let b = add_func_builder.append_block_param(add_entry_block, types::I32);
-
-        // Perform the addition: a + b
-        let sum = add_func_builder.ins().iadd(a, b);
-        add_func_builder.ins().return_(&[sum]);
-
-        add_func_builder.seal_block(add_entry_block);
-        add_func_builder.finalize();
-
-        // Declare add function
-        let add_func_id = codegen
-            .module
-            .declare_function("add", Linkage::Export, &add_signature)
+        let add_signature = codegen.signature(&[types::I32, types::I32], &[types::I32]);
+        codegen
+            .define("add", add_signature, |builder| {
+                let [a, b] = builder.params()[..] else { unreachable!() };
+                let sum = builder.ins().iadd(a, b);
+                builder.ins().return_(&[sum]);
+                Ok(())
+            })
             .unwrap();
-
-        // Create context and assign add function
-        let mut add_ctx = codegen.module.make_context();
-        add_ctx.func = add_func;
-
-        // Define add function
+        let main_signature = codegen.signature(&[], &[types::I32]);
         codegen
-            .module
-            .define_function(add_func_id, &mut add_ctx)
+            .define("main", main_signature, |builder| {
+                let one = builder.ins().iconst(types::I32, 1);
+                let two = builder.ins().iconst(types::I32, 2);
+
+                // calls are checked against the callee's signature
+                let wide = builder.ins().iconst(types::I64, 2);
+                assert!(builder.call("add", &[one, wide]).is_err());
+                assert!(builder.call("sub", &[one, two]).is_err());
+
+                let result = builder.call("add", &[one, two])?[0];
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // Now, create the main function that calls add(1, 2)
-
-        let mut main_signature = codegen.module.make_signature();
-        main_signature.returns.push(AbiParam::new(types::I32));
-
-        let mut main_func = Function::new();
-        main_func.signature = main_signature.clone();
-
-        let mut main_func_builder_ctx = FunctionBuilderContext::new();
-        let mut main_func_builder =
-            FunctionBuilder::new(&mut main_func, &mut main_func_builder_ctx);
-
-        // Create entry block for main function
-        let main_entry_block = main_func_builder.create_block();
-        main_func_builder.switch_to_block(main_entry_block);
-
-        // Define the arguments to pass to add (1, 2)
-        let one = main_func_builder.ins().iconst(types::I32, 1);
-        let two = main_func_builder.ins().iconst(types::I32, 2);
-
-        // Convert FuncId to FuncRef
-        let add_func_ref = codegen
-            .module
-            .declare_func_in_func(add_func_id, &mut main_func_builder.func);
-
-        // Call the add function
-        let call = main_func_builder.ins().call(add_func_ref, &[one, two]);
-        let result = main_func_builder.inst_results(call)[0];
-
-        // Return the result of add(1, 2)
-        main_func_builder.ins().return_(&[result]);
+        let result = codegen.run_main::<i32>().unwrap();
 
-        main_func_builder.seal_block(main_entry_block);
-        main_func_builder.finalize();
+        // Assert that main returns the expected result (1 + 2 = 3)
+        assert_eq!(result, 3);
 
-        // Declare main function
-        let main_func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &main_signature)
-            .unwrap();
+        // add can be called directly, but only with its own signature
+        assert_eq!(codegen.call::<_, i32>("add", (40, 2)).unwrap(), 42);
+        assert!(codegen.call::<_, i64>("add", (40, 2)).is_err());
 
-        // Create context and assign main function
-        let mut main_ctx = codegen.module.make_context();
-        main_ctx.func = main_func;
+        let names: Vec<&str> = codegen.report.functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(names, ["add", "main"]);
+        let add = codegen.report.function("add").unwrap();
+        assert!(add.clif.as_ref().unwrap().contains("iadd"));
+        assert!(add.optimized_clif.is_some() && add.disasm.is_some());
+        assert!(add.stats.code_size > 0);
+    }
 
-        // Define main function
+    #[test]
+    fn test_mutual_recursion() {
+        // bool is_even(int n) { return n == 0 ? 1 : is_odd(n - 1); }
+        // bool is_odd(int n) { return n == 0 ? 0 : is_even(n - 1); }
+        let mut codegen = get_compiler().unwrap();
+        let signature = codegen.signature(&[types::I32], &[types::I8]);
+        codegen.declare("is_odd", &signature).unwrap();
+        for (name, other, at_zero) in [("is_even", "is_odd", 1), ("is_odd", "is_even", 0)] {
+            codegen
+                .define(name, signature.clone(), |builder| {
+                    let n = builder.params()[0];
+                    let zero = builder.create_block();
+                    let recurse = builder.create_block();
+                    builder.ins().brif(n, recurse, &[], zero, &[]);
+
+                    builder.switch_to_block(zero);
+                    let result = builder.ins().iconst(types::I8, at_zero);
+                    builder.ins().return_(&[result]);
+
+                    builder.switch_to_block(recurse);
+                    let n = builder.ins().iadd_imm(n, -1);
+                    let result = builder.call(other, &[n])?[0];
+                    builder.ins().return_(&[result]);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        codegen.finalize().unwrap();
+        assert_eq!(codegen.call::<_, i8>("is_even", (10,)).unwrap(), 1);
+        assert_eq!(codegen.call::<_, i8>("is_odd", (7,)).unwrap(), 1);
+        assert_eq!(codegen.call::<_, i8>("is_odd", (4,)).unwrap(), 0);
+    }
 
+    #[test]
+    fn test_stack_overflow() {
+        // long recurse(long n) { return recurse(n + 1) + 1; }
+        let mut codegen = get_compiler().unwrap();
+        let signature = codegen.signature(&[types::I64], &[types::I64]);
         codegen
-            .module
-            .define_function(main_func_id, &mut main_ctx)
+            .define("recurse", signature, |builder| {
+                // The recursive call, which never stops
+                let n = builder.params()[0];
+                let next = builder.ins().iadd_imm(n, 1);
+                let result = builder.call("recurse", &[next])?[0];
+                let result = builder.ins().iadd_imm(result, 1);
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
+        // The stack check at its entry turns the overflow into an error
+        let err = codegen.call::<_, i64>("recurse", (0i64,)).unwrap_err();
+        let err = err.downcast::<CompileError>().unwrap();
+        assert!(matches!(&err, CompileError::StackOverflow(location) if location.function == "recurse"), "{}", err);
 
-        // Insert main function into codegen and run it
-        codegen.functions.insert("main".to_string(), main_func_id);
-        let result = codegen.run_main::<i32>().unwrap();
-
-        // Assert that main returns the expected result (1 + 2 = 3)
-        assert_eq!(result, 3);
+        // Also on a thread of its own, with a small stack
+        let err = codegen
+            .call_with_stack::<_, i64>("recurse", (0i64,), StackOptions::thread(64 * 1024))
+            .unwrap_err();
+        assert!(matches!(err.downcast::<CompileError>().unwrap(), CompileError::StackOverflow(_)));
     }
 
     #[test]
@@ -422,70 +422,35 @@ mod tests {
         //     return x;
         // }
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        // Create entry block
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create stack slot for x
-        let stack_slot = func_builder.create_sized_stack_slot(StackSlotData::new(
-            StackSlotKind::ExplicitSlot,
-            4, // size in bytes for i32
-            8, // alignment in bytes
-        ));
-
-        // Store initial value (42) to stack
-        let forty_two = func_builder.ins().iconst(types::I32, 42);
-        func_builder.ins().stack_store(forty_two, stack_slot, 0);
-
-        // Load address of stack slot (simulating &x)
-        let ptr = func_builder.ins().stack_addr(types::I64, stack_slot, 0);
-
-        // Store new value (100) through the pointer
-        let hundred = func_builder.ins().iconst(types::I32, 100);
-        func_builder.ins().store(MemFlags::new(), hundred, ptr, 0);
-
-        // Load final value and return
-        let result = func_builder.ins().stack_load(types::I32, stack_slot, 0);
-        func_builder.ins().return_(&[result]);
-
-        // Seal the block and finalize
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let pointer_type = codegen.module.target_config().pointer_type();
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Create stack slot for x
+                let stack_slot = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    4, // size in bytes for i32
+                    8, // alignment in bytes
+                ));
+
+                // Store initial value (42) to stack
+                let forty_two = builder.ins().iconst(types::I32, 42);
+                builder.ins().stack_store(forty_two, stack_slot, 0);
+
+                // Load address of stack slot (simulating &x)
+                let ptr = builder.ins().stack_addr(pointer_type, stack_slot, 0);
+
+                // Store new value (100) through the pointer
+                let hundred = builder.ins().iconst(types::I32, 100);
+                builder.ins().store(MemFlags::new(), hundred, ptr, 0);
+
+                // Load final value and return
+                let result = builder.ins().stack_load(types::I32, stack_slot, 0);
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 100); // Should return 100 after pointer modification
     }
@@ -504,141 +469,60 @@ mod tests {
         //     return sum + diff + prod + quot + rem; // 54
         // }
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create the values
-        let a = func_builder.ins().iconst(types::I32, 10);
-        let b = func_builder.ins().iconst(types::I32, 3);
-
-        // Perform arithmetic operations
-        let sum = func_builder.ins().iadd(a, b);
-        let diff = func_builder.ins().isub(a, b);
-        let prod = func_builder.ins().imul(a, b);
-        let quot = func_builder.ins().sdiv(a, b);
-        let rem = func_builder.ins().srem(a, b);
-
-        // Sum all results
-        let temp1 = func_builder.ins().iadd(sum, diff);
-        let temp2 = func_builder.ins().iadd(temp1, prod);
-        let temp3 = func_builder.ins().iadd(temp2, quot);
-        let final_result = func_builder.ins().iadd(temp3, rem);
-
-        func_builder.ins().return_(&[final_result]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Declare and define function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                let a = builder.ins().iconst(types::I32, 10);
+                let b = builder.ins().iconst(types::I32, 3);
+
+                let sum = builder.ins().iadd(a, b);
+                let diff = builder.ins().isub(a, b);
+                let prod = builder.ins().imul(a, b);
+                let quot = builder.ins().sdiv(a, b);
+                let rem = builder.ins().srem(a, b);
+
+                let temp1 = builder.ins().iadd(sum, diff);
+                let temp2 = builder.ins().iadd(temp1, prod);
+                let temp3 = builder.ins().iadd(temp2, quot);
+                let final_result = builder.ins().iadd(temp3, rem);
+                builder.ins().return_(&[final_result]);
+                Ok(())
+            })
             .unwrap();
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
 
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 54); // 13 + 7 + 30 + 3 + 1 = 54
     }
 
     #[test]
     fn test_simd() {
-        // Get compiler instance
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create SIMD vector type I32x4 (vector of 4 32-bit integers)
-        let i32x4_type = types::I32X4;
-
-        // Create first vector [1,2,3,4]
-        let a_const = func_builder.ins().iconst(types::I32, 1);
-        let b_const = func_builder.ins().iconst(types::I32, 2);
-        let c_const = func_builder.ins().iconst(types::I32, 3);
-        let d_const = func_builder.ins().iconst(types::I32, 4);
-
-        // Create second vector [10,20,30,40]
-        let e_const = func_builder.ins().iconst(types::I32, 10);
-        let f_const = func_builder.ins().iconst(types::I32, 20);
-        let g_const = func_builder.ins().iconst(types::I32, 30);
-        let h_const = func_builder.ins().iconst(types::I32, 40);
-
-        // Create SIMD vectors
-        let a_vector = func_builder.ins().scalar_to_vector(i32x4_type, a_const);
-        let a_vector = func_builder.ins().insertlane(a_vector, b_const, 1);
-        let a_vector = func_builder.ins().insertlane(a_vector, c_const, 2);
-        let a_vector = func_builder.ins().insertlane(a_vector, d_const, 3);
-
-        let b_vector = func_builder.ins().scalar_to_vector(i32x4_type, e_const);
-        let b_vector = func_builder.ins().insertlane(b_vector, f_const, 1);
-        let b_vector = func_builder.ins().insertlane(b_vector, g_const, 2);
-        let b_vector = func_builder.ins().insertlane(b_vector, h_const, 3);
-
-        // Add vectors
-        let result_vector = func_builder.ins().iadd(a_vector, b_vector);
-
-        // Extract first lane (should be 11 = 1 + 10)
-        let result = func_builder.ins().extractlane(result_vector, 0);
-
-        // Return the result
-        func_builder.ins().return_(&[result]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Build [1,2,3,4] and [10,20,30,40] as I32X4 vectors, lane by lane
+                let mut vector = |lanes: [i64; 4]| {
+                    let first = builder.ins().iconst(types::I32, lanes[0]);
+                    let mut vector = builder.ins().scalar_to_vector(types::I32X4, first);
+                    for (lane, &value) in lanes.iter().enumerate().skip(1) {
+                        let value = builder.ins().iconst(types::I32, value);
+                        vector = builder.ins().insertlane(vector, value, lane as u8);
+                    }
+                    vector
+                };
+                let a_vector = vector([1, 2, 3, 4]);
+                let b_vector = vector([10, 20, 30, 40]);
+
+                // Add vectors
+                let result_vector = builder.ins().iadd(a_vector, b_vector);
+
+                // Extract first lane (should be 11 = 1 + 10)
+                let result = builder.ins().extractlane(result_vector, 0);
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 11); // First element should be 1 + 10 = 11
     }
@@ -655,73 +539,32 @@ mod tests {
         //     p.y = 2;
         //     return p.x + p.y;
         // }
-        // Create compiler instance
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create a stack slot for our Point struct (8 bytes: 4 for x, 4 for y)
-        let point_struct = func_builder.create_sized_stack_slot(StackSlotData::new(
-            StackSlotKind::ExplicitSlot,
-            8, // size in bytes (4 bytes each for x and y)
-            4, // alignment
-        ));
-
-        // Store x = 1 at offset 0
-        let x_value = func_builder.ins().iconst(types::I32, 1);
-        func_builder.ins().stack_store(x_value, point_struct, 0);
-
-        // Store y = 2 at offset 4
-        let y_value = func_builder.ins().iconst(types::I32, 2);
-        func_builder.ins().stack_store(y_value, point_struct, 4);
-
-        // Load x and y back from memory
-        let loaded_x = func_builder.ins().stack_load(types::I32, point_struct, 0);
-        let loaded_y = func_builder.ins().stack_load(types::I32, point_struct, 4);
-
-        // Add x + y
-        let result = func_builder.ins().iadd(loaded_x, loaded_y);
-
-        // Return the result
-        func_builder.ins().return_(&[result]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Create a stack slot for our Point struct (8 bytes: 4 for x, 4 for y)
+                let point_struct = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    8, // size in bytes (4 bytes each for x and y)
+                    4, // alignment
+                ));
+
+                // Store x = 1 at offset 0 and y = 2 at offset 4
+                let x_value = builder.ins().iconst(types::I32, 1);
+                builder.ins().stack_store(x_value, point_struct, 0);
+                let y_value = builder.ins().iconst(types::I32, 2);
+                builder.ins().stack_store(y_value, point_struct, 4);
+
+                // Load x and y back from memory and add them
+                let loaded_x = builder.ins().stack_load(types::I32, point_struct, 0);
+                let loaded_y = builder.ins().stack_load(types::I32, point_struct, 4);
+                let result = builder.ins().iadd(loaded_x, loaded_y);
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 3); // Should return 1 + 2 = 3
     }
@@ -744,91 +587,44 @@ mod tests {
         //     line.end.y = 4;
         //     return line.start.x + line.start.y + line.end.x + line.end.y;
         // }
-
         let mut codegen = get_compiler().unwrap();
-
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create a stack slot for Line struct
-        // Size = 16 bytes (2 Points  (2 i32s × 4 bytes))
-        // Layout:
-        // 0-3:   start.x
-        // 4-7:   start.y
-        // 8-11:  end.x
-        // 12-15: end.y
-        let line_struct = func_builder.create_sized_stack_slot(StackSlotData::new(
-            StackSlotKind::ExplicitSlot,
-            16, // total size in bytes
-            4,  // alignment
-        ));
-
-        // Store line.start.x = 1 at offset 0
-        let start_x = func_builder.ins().iconst(types::I32, 1);
-        func_builder.ins().stack_store(start_x, line_struct, 0);
-
-        // Store line.start.y = 2 at offset 4
-        let start_y = func_builder.ins().iconst(types::I32, 2);
-        func_builder.ins().stack_store(start_y, line_struct, 4);
-
-        // Store line.end.x = 3 at offset 8
-        let end_x = func_builder.ins().iconst(types::I32, 3);
-        func_builder.ins().stack_store(end_x, line_struct, 8);
-
-        // Store line.end.y = 4 at offset 12
-        let end_y = func_builder.ins().iconst(types::I32, 4);
-        func_builder.ins().stack_store(end_y, line_struct, 12);
-
-        // Load all values back from memory
-        let loaded_start_x = func_builder.ins().stack_load(types::I32, line_struct, 0);
-        let loaded_start_y = func_builder.ins().stack_load(types::I32, line_struct, 4);
-        let loaded_end_x = func_builder.ins().stack_load(types::I32, line_struct, 8);
-        let loaded_end_y = func_builder.ins().stack_load(types::I32, line_struct, 12);
-
-        // Add all values: start.x + start.y + end.x + end.y
-        let sum1 = func_builder.ins().iadd(loaded_start_x, loaded_start_y);
-        let sum2 = func_builder.ins().iadd(loaded_end_x, loaded_end_y);
-        let result = func_builder.ins().iadd(sum1, sum2);
-
-        // Return the result
-        func_builder.ins().return_(&[result]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Create a stack slot for Line struct
+                // Size = 16 bytes (2 Points  (2 i32s × 4 bytes))
+                // Layout:
+                // 0-3:   start.x
+                // 4-7:   start.y
+                // 8-11:  end.x
+                // 12-15: end.y
+                let line_struct = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    16, // total size in bytes
+                    4,  // alignment
+                ));
+
+                // Store line.start.x = 1, line.start.y = 2, line.end.x = 3 and line.end.y = 4
+                for (offset, value) in [(0, 1), (4, 2), (8, 3), (12, 4)] {
+                    let value = builder.ins().iconst(types::I32, value);
+                    builder.ins().stack_store(value, line_struct, offset);
+                }
+
+                // Load all values back from memory
+                let loaded_start_x = builder.ins().stack_load(types::I32, line_struct, 0);
+                let loaded_start_y = builder.ins().stack_load(types::I32, line_struct, 4);
+                let loaded_end_x = builder.ins().stack_load(types::I32, line_struct, 8);
+                let loaded_end_y = builder.ins().stack_load(types::I32, line_struct, 12);
+
+                // Add all values: start.x + start.y + end.x + end.y
+                let sum1 = builder.ins().iadd(loaded_start_x, loaded_start_y);
+                let sum2 = builder.ins().iadd(loaded_end_x, loaded_end_y);
+                let result = builder.ins().iadd(sum1, sum2);
+                builder.ins().return_(&[result]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 10); // Should return 1 + 2 + 3 + 4 = 10
     }
@@ -843,83 +639,43 @@ mod tests {
         //     arr[3] = 4;
         //     return arr[0] + arr[1] + arr[2] + arr[3];
         // }
-
         let mut codegen = get_compiler().unwrap();
+        let pointer_type = codegen.module.target_config().pointer_type();
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Create a stack slot for our array (16 bytes: 4 integers × 4 bytes each)
+                let array = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    16, // size in bytes (4 integers × 4 bytes)
+                    4,  // alignment
+                ));
+
+                // Get base address of array
+                let base_addr = builder.ins().stack_addr(pointer_type, array, 0);
+                let sum = sum_of_four(builder, base_addr);
+                builder.ins().return_(&[sum]);
+                Ok(())
+            })
+            .unwrap();
 
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create a stack slot for our array (16 bytes: 4 integers × 4 bytes each)
-        let array = func_builder.create_sized_stack_slot(StackSlotData::new(
-            StackSlotKind::ExplicitSlot,
-            16, // size in bytes (4 integers × 4 bytes)
-            4,  // alignment
-        ));
-
-        // Get base address of array
-        let base_addr = func_builder.ins().stack_addr(types::I64, array, 0);
+        let result = codegen.run_main::<i32>().unwrap();
+        assert_eq!(result, 10); // Should return 1 + 2 + 3 + 4 = 10
+    }
 
-        // Store values in array
+    // Stores 1, 2, 3 and 4 as i32s at `base_addr` and sums them after loading them back.
+    fn sum_of_four(builder: &mut SoloBuilder, base_addr: Value) -> Value {
         for i in 0..4 {
-            let value = func_builder.ins().iconst(types::I32, (i + 1) as i64);
-            let offset = (i * 4) as i32; // Each integer is 4 bytes
-            func_builder
-                .ins()
-                .store(MemFlags::new(), value, base_addr, offset);
+            let value = builder.ins().iconst(types::I32, (i + 1) as i64);
+            let offset = i * 4; // Each integer is 4 bytes
+            builder.ins().store(MemFlags::new(), value, base_addr, offset);
         }
-
-        // Load values back and sum them
-        let mut sum = func_builder.ins().iconst(types::I32, 0);
+        let mut sum = builder.ins().iconst(types::I32, 0);
         for i in 0..4 {
-            let offset = (i * 4) as i32;
-            let loaded_value =
-                func_builder
-                    .ins()
-                    .load(types::I32, MemFlags::new(), base_addr, offset);
-            sum = func_builder.ins().iadd(sum, loaded_value);
+            let loaded_value = builder.ins().load(types::I32, MemFlags::new(), base_addr, i * 4);
+            sum = builder.ins().iadd(sum, loaded_value);
         }
-
-        // Return the sum
-        func_builder.ins().return_(&[sum]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
-            .unwrap();
-
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
-        let result = codegen.run_main::<i32>().unwrap();
-        assert_eq!(result, 10); // Should return 1 + 2 + 3 + 4 = 10
+        sum
     }
 
     #[test]
@@ -935,279 +691,104 @@ mod tests {
         //     free(ptr);
         //     return sum;
         // }
-
         let mut codegen = get_compiler().unwrap();
+        let pointer_type = codegen.module.target_config().pointer_type();
+        // malloc takes a size_t and returns a pointer, free takes the pointer
+        codegen.import("malloc", &codegen.signature(&[pointer_type], &[pointer_type])).unwrap();
+        codegen.import("free", &codegen.signature(&[pointer_type], &[])).unwrap();
 
-        let mut signature = codegen.module.make_signature();
-        signature.returns.push(AbiParam::new(types::I32));
-
-        let mut func = Function::new();
-        func.signature = signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-
-        // Create malloc signature (size_t -> void*)
-        let mut malloc_sig = codegen.module.make_signature();
-        malloc_sig.params.push(AbiParam::new(types::I64)); // size parameter
-        malloc_sig.returns.push(AbiParam::new(types::I64)); // pointer return
-
-        // Create free signature (void* -> void)
-        let mut free_sig = codegen.module.make_signature();
-        free_sig.params.push(AbiParam::new(types::I64)); // pointer parameter
-
-        // Declare malloc and free functions
-        let malloc_func_id = codegen
-            .module
-            .declare_function("malloc", Linkage::Import, &malloc_sig)
-            .unwrap();
-
-        let free_func_id = codegen
-            .module
-            .declare_function("free", Linkage::Import, &free_sig)
-            .unwrap();
-
-        // Get function references
-        let malloc_ref = codegen
-            .module
-            .declare_func_in_func(malloc_func_id, &mut func_builder.func);
-        let free_ref = codegen
-            .module
-            .declare_func_in_func(free_func_id, &mut func_builder.func);
-
-        // Call malloc(sizeof(int) * 4)
-        let size = func_builder.ins().iconst(types::I64, 16); // 4 ints * 4 bytes
-        let call_malloc = func_builder.ins().call(malloc_ref, &[size]);
-        let heap_ptr = func_builder.inst_results(call_malloc)[0];
-
-        // Store values in heap memory
-        for i in 0..4 {
-            let value = func_builder.ins().iconst(types::I32, (i + 1) as i64);
-            let offset = (i * 4) as i32; // Each integer is 4 bytes
-            func_builder
-                .ins()
-                .store(MemFlags::new(), value, heap_ptr, offset);
-        }
-
-        // Load and sum values
-        let mut sum = func_builder.ins().iconst(types::I32, 0);
-        for i in 0..4 {
-            let offset = (i * 4) as i32;
-            let loaded_value =
-                func_builder
-                    .ins()
-                    .load(types::I32, MemFlags::new(), heap_ptr, offset);
-            sum = func_builder.ins().iadd(sum, loaded_value);
-        }
-
-        // Call free(ptr)
-        func_builder.ins().call(free_ref, &[heap_ptr]);
-
-        // Return the sum
-        func_builder.ins().return_(&[sum]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-
-        // Declare function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &signature)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // Call malloc(sizeof(int) * 4)
+                let size = builder.ins().iconst(pointer_type, 16); // 4 ints * 4 bytes
+                let heap_ptr = builder.call("malloc", &[size])?[0];
+                let sum = sum_of_four(builder, heap_ptr);
+                builder.call("free", &[heap_ptr])?;
+                builder.ins().return_(&[sum]);
+                Ok(())
+            })
             .unwrap();
 
-        // Create context and assign function
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        // Define the function
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize function definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 10); // Should return 1 + 2 + 3 + 4 = 10
     }
 
     #[test]
     fn test_printf_call() {
-        // Initialize the compiler
         let mut codegen = get_compiler().unwrap();
-    
-        // Define the signature for `printf`
-        let mut printf_sig = codegen.module.make_signature();
-        printf_sig.params.push(AbiParam::new(types::I64));  // pointer to format string
-        printf_sig.params.push(AbiParam::new(types::I32));  // integer argument
-        printf_sig.returns.push(AbiParam::new(types::I32)); // return value
-    
-        // Declare `printf` as an imported function
-        let printf_func_id = codegen
-            .module
-            .declare_function("printf", Linkage::Import, &printf_sig)
-            .unwrap();
-    
-        // Define the main function signature
-        let mut main_signature = codegen.module.make_signature();
-        main_signature.returns.push(AbiParam::new(types::I32));
-    
-        let mut func = Function::new();
-        func.signature = main_signature.clone();
-    
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-    
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
-    
-        // Define the format string as a static global with explicit null termination
-        let format_str = b"Hello, %d!\n\0"; // Using a byte string with null termination
-        let format_str_id = codegen
-            .module
-            .declare_data("format_str", Linkage::Local, false, false)
-            .unwrap();
-    
-        let mut data_desc = DataDescription::new();
-        data_desc.define(format_str.to_vec().into_boxed_slice());
-    
-        // Define the data contents in the module context
-        codegen.module.define_data(format_str_id, &data_desc).unwrap();
-    
-        // Explicitly resolve the format string address in the runtime
-        let format_ptr = codegen.module.declare_data_in_func(format_str_id, &mut func_builder.func);
-        let format_ptr_addr = func_builder.ins().global_value(types::I64, format_ptr);
-    
-        // Prepare the integer argument for `printf`
-        let arg = func_builder.ins().iconst(types::I32, 42);
-    
-        // Get printf function reference and call it
-        let printf_ref = codegen
-            .module
-            .declare_func_in_func(printf_func_id, &mut func_builder.func);
-        let call = func_builder.ins().call(printf_ref, &[format_ptr_addr, arg]);
-    
-        // Store the printf return value but don't use it
-        let _printf_ret = func_builder.inst_results(call)[0];
-    
-        // Return 0
-        let zero = func_builder.ins().iconst(types::I32, 0);
-        func_builder.ins().return_(&[zero]);
-    
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-    
-        // Declare and define the main function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &main_signature)
+        let pointer_type = codegen.module.target_config().pointer_type();
+
+        // printf with a pointer to the format string and one integer argument
+        codegen.import("printf", &codegen.signature(&[pointer_type, types::I32], &[types::I32])).unwrap();
+        // The format string as a static global with explicit null termination
+        codegen.data("format_str", b"Hello, %d!\n\0").unwrap();
+
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                let format_ptr_addr = builder.data_addr("format_str")?;
+                let arg = builder.ins().iconst(types::I32, 42);
+                // printf's return value isn't used
+                builder.call("printf", &[format_ptr_addr, arg])?;
+
+                let zero = builder.ins().iconst(types::I32, 0);
+                builder.ins().return_(&[zero]);
+                Ok(())
+            })
             .unwrap();
 
-        // print the CLIF IR
-        println!("Function CLIF IR:\n{}", func.display());
-    
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-    
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-    
-        // Finalize definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-    
-        // Run main and assert result
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<i32>().unwrap();
         assert_eq!(result, 0);
     }
-    
 
     #[test]
-    fn test_exp_call() {
-        // Initialize the compiler
+    fn test_builder_name_lookup() {
         let mut codegen = get_compiler().unwrap();
+        codegen.import("abs", &codegen.signature(&[types::I32], &[types::I32])).unwrap();
+        codegen.data("table", &[1, 2, 3, 4]).unwrap();
 
-        // Define the signature for `exp` function (assumes it takes a f64 and returns an f64)
-        let mut exp_sig = codegen.module.make_signature();
-        exp_sig.params.push(AbiParam::new(types::F64)); // `exp` input as a double (f64)
-        exp_sig.returns.push(AbiParam::new(types::F64)); // `exp` output as a double (f64)
-
-        // Declare `exp` as an imported function
-        let exp_func_id = codegen
-            .module
-            .declare_function("exp", Linkage::Import, &exp_sig)
+        let signature = codegen.signature(&[], &[types::I32]);
+        codegen
+            .define("main", signature, |builder| {
+                // functions and data are looked up by kind as well as name
+                let err = builder.data_addr("abs").unwrap_err();
+                assert_eq!(err.to_string(), "no data named `abs`");
+                let err = builder.call("table", &[]).unwrap_err();
+                assert_eq!(err.to_string(), "no function named `table`");
+                assert!(builder.data_addr("missing").is_err());
+
+                builder.data_addr("table")?;
+                let zero = builder.ins().iconst(types::I32, 0);
+                builder.ins().return_(&[zero]);
+                Ok(())
+            })
             .unwrap();
+        assert_eq!(codegen.run_main::<i32>().unwrap(), 0);
+    }
 
-        // Define the main function signature
-        let mut main_signature = codegen.module.make_signature();
-        main_signature.returns.push(AbiParam::new(types::F64));
-
-        let mut func = Function::new();
-        func.signature = main_signature.clone();
-
-        let mut func_builder_ctx = FunctionBuilderContext::new();
-        let mut func_builder = FunctionBuilder::new(&mut func, &mut func_builder_ctx);
-
-        let entry_block = func_builder.create_block();
-        func_builder.switch_to_block(entry_block);
+    #[test]
+    fn test_exp_call() {
+        let mut codegen = get_compiler().unwrap();
+        // libm's exp takes and returns a double
+        codegen.import("exp", &codegen.signature(&[types::F64], &[types::F64])).unwrap();
 
-        // Prepare the argument for `exp`
         let value = 2.0;
-        let arg = func_builder.ins().f64const(value); // Compute exp(1.0)
-
-        // Get `exp` function reference and call it
-        let exp_ref = codegen
-            .module
-            .declare_func_in_func(exp_func_id, &mut func_builder.func);
-        let call = func_builder.ins().call(exp_ref, &[arg]);
-
-        // Retrieve the result of `exp`
-        let exp_result = func_builder.inst_results(call)[0];
-
-        // Return the result
-        func_builder.ins().return_(&[exp_result]);
-
-        func_builder.seal_block(entry_block);
-        func_builder.finalize();
-
-        // Declare and define the main function
-        let func_id = codegen
-            .module
-            .declare_function("main", Linkage::Export, &main_signature)
+        let signature = codegen.signature(&[], &[types::F64]);
+        codegen
+            .define("main", signature, |builder| {
+                let arg = builder.ins().f64const(value);
+                let exp_result = builder.call("exp", &[arg])?[0];
+                builder.ins().return_(&[exp_result]);
+                Ok(())
+            })
             .unwrap();
 
-        let mut ctx = codegen.module.make_context();
-        ctx.func = func;
-
-        codegen.module.define_function(func_id, &mut ctx).unwrap();
-
-        // Finalize definitions
-        match &mut codegen.module {
-            ModuleType::JITModule(jit) => jit.finalize_definitions().unwrap(),
-            ModuleType::ObjectModule(_) => panic!("Cannot finalize definitions in object module"),
-        }
-
-        // Run main and assert the result (exp(1) ≈ 2.71828)
-        codegen.functions.insert("main".to_string(), func_id);
         let result = codegen.run_main::<f64>().unwrap();
-
         assert!(
             (result - value.exp()).abs() < 1e-5,
             "exp({}) result was incorrect",
             value
         );
     }
-
 }
-
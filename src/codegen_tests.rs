@@ -0,0 +1,1208 @@
+use crate::ast::*;
+use crate::codegen::{clif_type, common_type, Codegen};
+use crate::compiler::{
+    compile_to_executable, compile_to_object, link_executable, private_temp_dir, Compiler, CompilerBuilder,
+};
+use crate::error::{CompileError, CompileResult};
+use crate::host::HostReturn;
+use crate::report::{CompileOptions, CompileReport};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use cranelift::prelude::types;
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+fn get_compiler() -> Codegen {
+    Compiler::new().unwrap().build()
+}
+
+fn compile_and_run<T: HostReturn>(program: Program) -> CompileResult<T> {
+    let mut codegen = get_compiler();
+    codegen.compile_program(program)?;
+    codegen.finalize()?;
+    codegen.run_main()
+}
+
+fn func(name: &str, params: &[(&str, AstType)], return_type: Option<AstType>, body: Block) -> Stmt {
+    Stmt::FuncDef(FuncDef {
+        decl: FuncDecl {
+            name: name.to_string(),
+            params: params
+                .iter()
+                .map(|(name, type_)| (name.to_string(), type_.clone()))
+                .collect(),
+            return_type,
+            variadic: false,
+            extern_: false,
+        },
+        body,
+    })
+}
+
+fn extern_decl(name: &str, params: &[(&str, AstType)], return_type: Option<AstType>, variadic: bool) -> Stmt {
+    Stmt::FuncDecl(FuncDecl {
+        name: name.to_string(),
+        params: params
+            .iter()
+            .map(|(name, type_)| (name.to_string(), type_.clone()))
+            .collect(),
+        return_type,
+        variadic,
+        extern_: true,
+    })
+}
+
+fn main_returning_i32(body: Block) -> Program {
+    Program {
+        statements: vec![func("main", &[], Some(AstType::I32), body)],
+    }
+}
+
+fn int(value: i64) -> Expr {
+    Expr::Literal(Literal::Int(value))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::Variable(Variable_ {
+        name: name.to_string(),
+        type_: AstType::I32,
+    })
+}
+
+fn typed_var(name: &str, type_: AstType) -> Expr {
+    Expr::Variable(Variable_ {
+        name: name.to_string(),
+        type_,
+    })
+}
+
+fn string(value: &str) -> Expr {
+    Expr::Literal(Literal::String(value.to_string()))
+}
+
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::FuncCall(FuncCall {
+        name: name.to_string(),
+        args,
+    })
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(Box::new(Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }))
+}
+
+fn decl_i32(name: &str, init: Expr) -> Stmt {
+    Stmt::VarDecl(VarDecl {
+        name: name.to_string(),
+        type_: AstType::I32,
+        init: Some(Box::new(init)),
+    })
+}
+
+fn assign(name: &str, value: Expr) -> Stmt {
+    Stmt::Assign(Assign {
+        target: Variable_ {
+            name: name.to_string(),
+            type_: AstType::I32,
+        },
+        value: Box::new(value),
+    })
+}
+
+fn ret(value: Expr) -> Stmt {
+    Stmt::Return(Return {
+        value: Some(Box::new(value)),
+    })
+}
+
+// for (int i = 0; i < end; i = i + 1) { body }
+fn counting_for(end: i64, body: Block) -> Stmt {
+    Stmt::Loop(LoopStmt {
+        kind: LoopKind::For {
+            init: Some(Box::new(decl_i32("i", int(0)))),
+            step: Some(Box::new(assign("i", binary(BinaryOp::Add, var("i"), int(1))))),
+        },
+        condition: Box::new(binary(BinaryOp::Lt, var("i"), int(end))),
+        body,
+    })
+}
+
+#[test]
+fn test_while_loop() {
+    // int main() { int i = 0; while (i < 10) { i = i + 1; } return i; }
+    let program = main_returning_i32(vec![
+        decl_i32("i", int(0)),
+        Stmt::Loop(LoopStmt {
+            kind: LoopKind::While,
+            condition: Box::new(binary(BinaryOp::Lt, var("i"), int(10))),
+            body: vec![assign("i", binary(BinaryOp::Add, var("i"), int(1)))],
+        }),
+        ret(var("i")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 10);
+}
+
+#[test]
+fn test_for_loop() {
+    // int main() { int sum = 0; for (int i = 0; i < 5; i = i + 1) { sum = sum + i; } return sum; }
+    let program = main_returning_i32(vec![
+        decl_i32("sum", int(0)),
+        counting_for(5, vec![assign("sum", binary(BinaryOp::Add, var("sum"), var("i")))]),
+        ret(var("sum")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 10);
+}
+
+#[test]
+fn test_for_loop_continue_runs_step() {
+    // sum of the even numbers below 10; `continue` must still increment `i`
+    let program = main_returning_i32(vec![
+        decl_i32("sum", int(0)),
+        counting_for(
+            10,
+            vec![
+                Stmt::If(IfStmt {
+                    condition: Box::new(binary(
+                        BinaryOp::Ne,
+                        binary(BinaryOp::Mod, var("i"), int(2)),
+                        int(0),
+                    )),
+                    then_branch: vec![Stmt::Continue],
+                    else_branch: None,
+                }),
+                assign("sum", binary(BinaryOp::Add, var("sum"), var("i"))),
+            ],
+        ),
+        ret(var("sum")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 20);
+}
+
+#[test]
+fn test_for_loop_break() {
+    // for (int i = 0; i < 100; i = i + 1) { if (i == 7) { break; } count = count + 1; }
+    let program = main_returning_i32(vec![
+        decl_i32("count", int(0)),
+        counting_for(
+            100,
+            vec![
+                Stmt::If(IfStmt {
+                    condition: Box::new(binary(BinaryOp::Eq, var("i"), int(7))),
+                    then_branch: vec![Stmt::Break],
+                    else_branch: None,
+                }),
+                assign("count", binary(BinaryOp::Add, var("count"), int(1))),
+            ],
+        ),
+        ret(var("count")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 7);
+}
+
+#[test]
+fn test_for_loop_init_is_scoped_to_loop() {
+    // the loop's `i` shadows the outer one and disappears after the loop
+    let program = main_returning_i32(vec![
+        decl_i32("i", int(42)),
+        counting_for(3, vec![]),
+        ret(var("i")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 42);
+
+    let program = main_returning_i32(vec![counting_for(3, vec![]), ret(var("i"))]);
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(err, CompileError::UndefinedVariable(name) if name == "i"));
+}
+
+#[test]
+fn test_do_while_runs_body_once() {
+    // int main() { int n = 0; do { n = n + 1; } while (n > 100); return n; }
+    let program = main_returning_i32(vec![
+        decl_i32("n", int(0)),
+        Stmt::Loop(LoopStmt {
+            kind: LoopKind::DoWhile,
+            condition: Box::new(binary(BinaryOp::Gt, var("n"), int(100))),
+            body: vec![assign("n", binary(BinaryOp::Add, var("n"), int(1)))],
+        }),
+        ret(var("n")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 1);
+}
+
+#[test]
+fn test_do_while_continue_tests_condition() {
+    // do { n = n + 1; if (n < 5) { continue; } n = n + 100; } while (n < 5);
+    let program = main_returning_i32(vec![
+        decl_i32("n", int(0)),
+        Stmt::Loop(LoopStmt {
+            kind: LoopKind::DoWhile,
+            condition: Box::new(binary(BinaryOp::Lt, var("n"), int(5))),
+            body: vec![
+                assign("n", binary(BinaryOp::Add, var("n"), int(1))),
+                Stmt::If(IfStmt {
+                    condition: Box::new(binary(BinaryOp::Lt, var("n"), int(5))),
+                    then_branch: vec![Stmt::Continue],
+                    else_branch: None,
+                }),
+                assign("n", binary(BinaryOp::Add, var("n"), int(100))),
+            ],
+        }),
+        ret(var("n")),
+    ]);
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 105);
+}
+
+fn prototype(name: &str, params: &[(&str, AstType)], return_type: Option<AstType>) -> Stmt {
+    let Stmt::FuncDef(def) = func(name, params, return_type, vec![]) else {
+        unreachable!()
+    };
+    Stmt::FuncDecl(def.decl)
+}
+
+#[test]
+fn test_prototype_then_definition() {
+    // int twice(int x); int main() { return twice(21); } int twice(int y) { return y * 2; }
+    let body = || vec![ret(binary(BinaryOp::Mul, var("y"), int(2)))];
+    let twice = || func("twice", &[("y", AstType::I32)], Some(AstType::I32), body());
+    let mut program = main_returning_i32(vec![ret(call("twice", vec![int(21)]))]);
+    program.statements.insert(0, prototype("twice", &[("x", AstType::I32)], Some(AstType::I32)));
+    program.statements.push(twice());
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 42);
+
+    // a definition has to match its prototype's params and return type
+    for mismatched in [
+        prototype("twice", &[("x", AstType::I64)], Some(AstType::I32)),
+        prototype("twice", &[("x", AstType::I32)], Some(AstType::F64)),
+        prototype("twice", &[], Some(AstType::I32)),
+    ] {
+        let program = Program {
+            statements: vec![mismatched, twice()],
+        };
+        let err = compile_and_run::<i32>(program).unwrap_err();
+        assert!(matches!(&err, CompileError::ConflictingDeclaration { name, .. } if name == "twice"), "{}", err);
+    }
+}
+
+#[test]
+fn test_loop_stmt_json_defaults_to_while() {
+    let json = r#"{"condition":{"Literal":{"Bool":false}},"body":[]}"#;
+    let loop_stmt: LoopStmt = serde_json::from_str(json).unwrap();
+    assert_eq!(loop_stmt.kind, LoopKind::While);
+}
+
+fn binop_ptr_type() -> AstType {
+    AstType::FuncPtr {
+        params: vec![AstType::I32, AstType::I32],
+        return_type: Some(Box::new(AstType::I32)),
+    }
+}
+
+// int add(int a, int b) { return a + b; }  int sub(int a, int b) { return a - b; }
+fn add_and_sub() -> Vec<Stmt> {
+    let params = [("a", AstType::I32), ("b", AstType::I32)];
+    vec![
+        func("add", &params, Some(AstType::I32), vec![ret(binary(BinaryOp::Add, var("a"), var("b")))]),
+        func("sub", &params, Some(AstType::I32), vec![ret(binary(BinaryOp::Sub, var("a"), var("b")))]),
+    ]
+}
+
+#[test]
+fn test_function_pointer_variable() {
+    // int (*op)(int, int) = add; return op(5, 3);
+    let mut program = main_returning_i32(vec![
+        Stmt::VarDecl(VarDecl {
+            name: "op".to_string(),
+            type_: binop_ptr_type(),
+            init: Some(Box::new(Expr::FuncAddr("add".to_string()))),
+        }),
+        ret(call("op", vec![int(5), int(3)])),
+    ]);
+    program.statements.splice(0..0, add_and_sub());
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 8);
+}
+
+#[test]
+fn test_function_pointer_parameter() {
+    // int apply(int (*op)(int, int), int a, int b) { return op(a, b); }
+    let mut program = main_returning_i32(vec![ret(call(
+        "apply",
+        vec![Expr::FuncAddr("sub".to_string()), int(5), int(3)],
+    ))]);
+    program.statements.splice(0..0, add_and_sub());
+    program.statements.insert(
+        2,
+        func(
+            "apply",
+            &[("op", binop_ptr_type()), ("a", AstType::I32), ("b", AstType::I32)],
+            Some(AstType::I32),
+            vec![ret(call("op", vec![var("a"), var("b")]))],
+        ),
+    );
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 2);
+}
+
+#[test]
+fn test_call_indirect_through_returned_pointer() {
+    // a tiny dispatch table: pick(0) is `sub`, anything else is `add`
+    let mut program = main_returning_i32(vec![ret(Expr::CallIndirect(CallIndirect {
+        callee: Box::new(call("pick", vec![int(0)])),
+        args: vec![int(10), int(4)],
+    }))]);
+    program.statements.splice(0..0, add_and_sub());
+    program.statements.insert(
+        2,
+        func(
+            "pick",
+            &[("which", AstType::I32)],
+            Some(binop_ptr_type()),
+            vec![
+                Stmt::If(IfStmt {
+                    condition: Box::new(var("which")),
+                    then_branch: vec![ret(Expr::FuncAddr("add".to_string()))],
+                    else_branch: None,
+                }),
+                ret(Expr::FuncAddr("sub".to_string())),
+            ],
+        ),
+    );
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 6);
+}
+
+#[test]
+fn test_function_pointer_signature_mismatch() {
+    // int (*op)(int) = add;
+    let unary_ptr = AstType::FuncPtr {
+        params: vec![AstType::I32],
+        return_type: Some(Box::new(AstType::I32)),
+    };
+    let mut program = main_returning_i32(vec![
+        Stmt::VarDecl(VarDecl {
+            name: "op".to_string(),
+            type_: unary_ptr.clone(),
+            init: Some(Box::new(Expr::FuncAddr("add".to_string()))),
+        }),
+        ret(call("op", vec![int(1)])),
+    ]);
+    program.statements.splice(0..0, add_and_sub());
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(
+        err,
+        CompileError::TypeMismatch { expected, found } if expected == unary_ptr && found == binop_ptr_type()
+    ));
+}
+
+#[test]
+fn test_calling_non_function_value() {
+    let program = main_returning_i32(vec![decl_i32("x", int(1)), ret(call("x", vec![]))]);
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(err, CompileError::NotCallable(AstType::I32)));
+}
+
+// int snprintf(char *buf, size_t size, const char *format, ...);
+fn snprintf_decl() -> Stmt {
+    extern_decl(
+        "snprintf",
+        &[("buf", AstType::String), ("size", AstType::U64), ("format", AstType::String)],
+        Some(AstType::I32),
+        true,
+    )
+}
+
+#[test]
+fn test_variadic_snprintf_mixed_arguments() {
+    // float and char arguments are promoted to double and int at the call site
+    let params = [
+        ("buf", AstType::String),
+        ("n", AstType::I32),
+        ("x", AstType::F64),
+        ("y", AstType::F32),
+        ("c", AstType::Char),
+        ("big", AstType::I64),
+    ];
+    let program = Program {
+        statements: vec![
+            snprintf_decl(),
+            func(
+                "format",
+                &params,
+                Some(AstType::I32),
+                vec![ret(call(
+                    "snprintf",
+                    vec![
+                        typed_var("buf", AstType::String),
+                        int(64),
+                        string("%d %.2f %.2f %c %ld %s %d"),
+                        var("n"),
+                        typed_var("x", AstType::F64),
+                        typed_var("y", AstType::F32),
+                        typed_var("c", AstType::Char),
+                        typed_var("big", AstType::I64),
+                        string("hi"),
+                        int(-1),
+                    ],
+                ))],
+            ),
+        ],
+    };
+
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+    let format: extern "C" fn(*mut u8, i32, f64, f32, u8, i64) -> i32 =
+        unsafe { std::mem::transmute(codegen.get_finalized_function("format").unwrap()) };
+
+    let mut buf = [0u8; 64];
+    let len = format(buf.as_mut_ptr(), 42, 3.5, 2.25, b'x', 123_456_789_012);
+    let expected = "42 3.50 2.25 x 123456789012 hi -1";
+    assert_eq!(len as usize, expected.len());
+    assert_eq!(&buf[..expected.len()], expected.as_bytes());
+}
+
+#[test]
+fn test_variadic_snprintf_many_floats() {
+    // more doubles than there are vector argument registers
+    let mut args = vec![typed_var("buf", AstType::String), int(64), string("%.0f %.0f %.0f %.0f %.0f %.0f %.0f %.0f %.0f %.0f")];
+    args.extend((1..=10).map(|i| Expr::Literal(Literal::Float(i as f64))));
+    let program = Program {
+        statements: vec![
+            snprintf_decl(),
+            func("format", &[("buf", AstType::String)], Some(AstType::I32), vec![ret(call("snprintf", args))]),
+        ],
+    };
+
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+    let mut buf = [0u8; 64];
+    let len = codegen.call::<_, i32>("format", (buf.as_mut_ptr(),)).unwrap() as usize;
+    assert_eq!(std::str::from_utf8(&buf[..len]).unwrap(), "1 2 3 4 5 6 7 8 9 10");
+}
+
+#[test]
+fn test_variadic_printf() {
+    // int main() { return printf("%s=%d\n", "x", 5); }
+    let mut program = main_returning_i32(vec![ret(call("printf", vec![string("%s=%d\n"), string("x"), int(5)]))]);
+    program.statements.insert(
+        0,
+        extern_decl("printf", &[("format", AstType::String)], Some(AstType::I32), true),
+    );
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 4);
+}
+
+#[test]
+fn test_variadic_call_missing_fixed_arguments() {
+    let mut program = main_returning_i32(vec![ret(call("snprintf", vec![string("%d"), int(1)]))]);
+    program.statements.insert(0, snprintf_decl());
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(err, CompileError::ArgumentCount { expected: 3, found: 2, .. }));
+}
+
+fn va_list_decl(name: &str) -> Stmt {
+    Stmt::VarDecl(VarDecl {
+        name: name.to_string(),
+        type_: AstType::VaList,
+        init: None,
+    })
+}
+
+fn va_arg(list: &str, type_: AstType) -> Expr {
+    call("va_arg", vec![typed_var(list, AstType::VaList), Expr::Type(type_)])
+}
+
+// T sum(int count, ...) { va_list args; va_start(args); T total = 0;
+//   for (int i = 0; i < count; i++) { total += va_arg(args, T); } va_end(args); return total; }
+fn variadic_sum(name: &str, type_: AstType) -> Stmt {
+    let total = || typed_var("total", type_.clone());
+    Stmt::FuncDef(FuncDef {
+        decl: FuncDecl {
+            name: name.to_string(),
+            params: vec![("count".to_string(), AstType::I32)],
+            return_type: Some(type_.clone()),
+            variadic: true,
+            extern_: false,
+        },
+        body: vec![
+            va_list_decl("args"),
+            Stmt::Expr(call("va_start", vec![typed_var("args", AstType::VaList)])),
+            Stmt::VarDecl(VarDecl {
+                name: "total".to_string(),
+                type_: type_.clone(),
+                init: Some(Box::new(int(0))),
+            }),
+            counting_for_var(
+                "count",
+                vec![Stmt::Assign(Assign {
+                    target: Variable_ {
+                        name: "total".to_string(),
+                        type_: type_.clone(),
+                    },
+                    value: Box::new(binary(BinaryOp::Add, total(), va_arg("args", type_.clone()))),
+                })],
+            ),
+            Stmt::Expr(call("va_end", vec![typed_var("args", AstType::VaList)])),
+            ret(total()),
+        ],
+    })
+}
+
+// for (int i = 0; i < end; i = i + 1) { body } with `end` a variable
+fn counting_for_var(end: &str, body: Block) -> Stmt {
+    Stmt::Loop(LoopStmt {
+        kind: LoopKind::For {
+            init: Some(Box::new(decl_i32("i", int(0)))),
+            step: Some(Box::new(assign("i", binary(BinaryOp::Add, var("i"), int(1))))),
+        },
+        condition: Box::new(binary(BinaryOp::Lt, var("i"), var(end))),
+        body,
+    })
+}
+
+#[test]
+fn test_variadic_definition_called_from_compiled_code() {
+    // the readme's `int sum(int count, ...)`
+    let mut program = main_returning_i32(vec![ret(call("sum", vec![int(4), int(1), int(2), int(3), int(4)]))]);
+    program.statements.insert(0, variadic_sum("sum", AstType::I32));
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 10);
+}
+
+#[test]
+fn test_variadic_definition_called_from_c_abi() {
+    let program = Program {
+        statements: vec![variadic_sum("sum", AstType::I64), variadic_sum("fsum", AstType::F64)],
+    };
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+
+    let sum: unsafe extern "C" fn(i32, ...) -> i64 =
+        unsafe { std::mem::transmute(codegen.get_finalized_function("sum").unwrap()) };
+    let fsum: unsafe extern "C" fn(i32, ...) -> f64 =
+        unsafe { std::mem::transmute(codegen.get_finalized_function("fsum").unwrap()) };
+    unsafe {
+        assert_eq!(sum(0), 0);
+        assert_eq!(sum(3, 1i64, 2i64, 3i64), 6);
+        // five arguments fit in registers, the rest come from the overflow area
+        assert_eq!(sum(9, 1i64, 2i64, 3i64, 4i64, 5i64, 6i64, 7i64, 8i64, 9i64), 45);
+        assert_eq!(fsum(2, 0.5f64, 0.25f64), 0.75);
+        // eight doubles in xmm registers, two more on the stack
+        let total = fsum(10, 1.0f64, 2.0f64, 3.0f64, 4.0f64, 5.0f64, 6.0f64, 7.0f64, 8.0f64, 9.0f64, 10.0f64);
+        assert_eq!(total, 55.0);
+    }
+}
+
+#[test]
+fn test_va_list_forwarded_to_vsnprintf() {
+    // int format(char *buf, const char *fmt, ...) { va_list ap; va_start(ap);
+    //   int n = vsnprintf(buf, 64, fmt, ap); va_end(ap); return n; }
+    let program = Program {
+        statements: vec![
+            extern_decl(
+                "vsnprintf",
+                &[
+                    ("buf", AstType::String),
+                    ("size", AstType::U64),
+                    ("format", AstType::String),
+                    ("ap", AstType::VaList),
+                ],
+                Some(AstType::I32),
+                false,
+            ),
+            Stmt::FuncDef(FuncDef {
+                decl: FuncDecl {
+                    name: "format".to_string(),
+                    params: vec![("buf".to_string(), AstType::String), ("fmt".to_string(), AstType::String)],
+                    return_type: Some(AstType::I32),
+                    variadic: true,
+                    extern_: false,
+                },
+                body: vec![
+                    va_list_decl("ap"),
+                    Stmt::Expr(call("va_start", vec![typed_var("ap", AstType::VaList)])),
+                    decl_i32(
+                        "n",
+                        call(
+                            "vsnprintf",
+                            vec![
+                                typed_var("buf", AstType::String),
+                                int(64),
+                                typed_var("fmt", AstType::String),
+                                typed_var("ap", AstType::VaList),
+                            ],
+                        ),
+                    ),
+                    Stmt::Expr(call("va_end", vec![typed_var("ap", AstType::VaList)])),
+                    ret(var("n")),
+                ],
+            }),
+        ],
+    };
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+    let format: unsafe extern "C" fn(*mut u8, *const u8, ...) -> i32 =
+        unsafe { std::mem::transmute(codegen.get_finalized_function("format").unwrap()) };
+
+    let mut buf = [0u8; 64];
+    let len = unsafe { format(buf.as_mut_ptr(), c"%d %.1f %s %ld".as_ptr() as *const u8, 7i32, 1.5f64, c"ok".as_ptr(), -3i64) };
+    assert_eq!(std::str::from_utf8(&buf[..len as usize]).unwrap(), "7 1.5 ok -3");
+}
+
+#[test]
+fn test_va_start_outside_variadic_function() {
+    let program = main_returning_i32(vec![
+        va_list_decl("args"),
+        Stmt::Expr(call("va_start", vec![typed_var("args", AstType::VaList)])),
+        ret(int(0)),
+    ]);
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(err, CompileError::OutsideVariadic("va_start")));
+}
+
+extern "C" fn host_scale(value: i32, factor: f64) -> f64 {
+    value as f64 * factor
+}
+
+static HOST_COUNTER: std::sync::atomic::AtomicI64 = std::sync::atomic::AtomicI64::new(0);
+
+extern "C" fn host_bump(by: i64) {
+    HOST_COUNTER.fetch_add(by, std::sync::atomic::Ordering::SeqCst);
+}
+
+#[test]
+fn test_extern_host_function() {
+    // extern double host_scale(int value, double factor);
+    // int main() { return host_scale(4, 2.5); }
+    let mut program = main_returning_i32(vec![ret(call(
+        "host_scale",
+        vec![int(4), Expr::Literal(Literal::Float(2.5))],
+    ))]);
+    program.statements.insert(
+        0,
+        extern_decl(
+            "host_scale",
+            &[("value", AstType::I32), ("factor", AstType::F64)],
+            Some(AstType::F64),
+            false,
+        ),
+    );
+
+    let mut compiler = Compiler::new().unwrap();
+    compiler.register_function("host_scale", host_scale as extern "C" fn(i32, f64) -> f64);
+    let mut codegen = compiler.build();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 10);
+}
+
+#[test]
+fn test_extern_host_function_signature_mismatch() {
+    // declared with an `i64` where the host function takes an `i32`
+    let program = Program {
+        statements: vec![extern_decl(
+            "host_scale",
+            &[("value", AstType::I64), ("factor", AstType::F64)],
+            Some(AstType::F64),
+            false,
+        )],
+    };
+
+    let mut compiler = Compiler::new().unwrap();
+    compiler.register_function("host_scale", host_scale as extern "C" fn(i32, f64) -> f64);
+    let err = compiler.build().compile_program(program).unwrap_err();
+    assert!(matches!(err, CompileError::ExternSignature { name, .. } if name == "host_scale"));
+}
+
+#[test]
+fn test_extern_registered_symbol() {
+    // extern void bump(long by); int main() { bump(3); bump(4); return 0; }
+    let mut program = main_returning_i32(vec![
+        Stmt::FuncCall(FuncCall {
+            name: "bump".to_string(),
+            args: vec![int(3)],
+        }),
+        Stmt::Expr(call("bump", vec![int(4)])),
+        ret(int(0)),
+    ]);
+    program
+        .statements
+        .insert(0, extern_decl("bump", &[("by", AstType::I64)], None, false));
+
+    let mut compiler = Compiler::new().unwrap();
+    compiler.register_symbol("bump", host_bump as *const u8);
+    let mut codegen = compiler.build();
+    codegen.compile_program(program).unwrap();
+    codegen.finalize().unwrap();
+    codegen.run_main::<i32>().unwrap();
+    assert_eq!(HOST_COUNTER.load(std::sync::atomic::Ordering::SeqCst), 7);
+}
+
+#[test]
+fn test_extern_resolves_process_symbol() {
+    // without a registration the import is looked up in the running process (libc here)
+    let mut program = main_returning_i32(vec![ret(call("labs", vec![int(-12)]))]);
+    program.statements.insert(
+        0,
+        extern_decl("labs", &[("value", AstType::I64)], Some(AstType::I64), false),
+    );
+    assert_eq!(compile_and_run::<i32>(program).unwrap(), 12);
+}
+
+#[test]
+fn test_unresolved_extern_is_an_error() {
+    // a prototype that's never defined is an import, which nothing provides here
+    let program = crate::parser::parse("int f(int a);\nint main() { return f(1); }").unwrap();
+    let err = compile_and_run::<i32>(program).unwrap_err();
+    assert!(matches!(&err, CompileError::MissingDefinition(name) if name == "f"), "{}", err);
+}
+
+#[test]
+fn test_call_with_arguments() {
+    // long scale(int value, double factor, bool negate) { ... }
+    let program = Program {
+        statements: vec![func(
+            "scale",
+            &[("value", AstType::I32), ("factor", AstType::F64), ("negate", AstType::Bool)],
+            Some(AstType::I64),
+            vec![
+                Stmt::If(IfStmt {
+                    condition: Box::new(typed_var("negate", AstType::Bool)),
+                    then_branch: vec![ret(Expr::Unary(Box::new(Unary {
+                        op: UnaryOp::Neg,
+                        expr: Box::new(binary(BinaryOp::Mul, var("value"), typed_var("factor", AstType::F64))),
+                    })))],
+                    else_branch: None,
+                }),
+                ret(binary(BinaryOp::Mul, var("value"), typed_var("factor", AstType::F64))),
+            ],
+        )],
+    };
+
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    assert_eq!(codegen.call::<_, i64>("scale", (4, 2.5, false)).unwrap(), 10);
+    assert_eq!(codegen.call::<_, i64>("scale", (4, 2.5, true)).unwrap(), -10);
+}
+
+#[test]
+fn test_call_signature_mismatch() {
+    let program = Program {
+        statements: vec![func("id", &[("x", AstType::I32)], Some(AstType::I32), vec![ret(var("x"))])],
+    };
+
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    let err = codegen.call::<_, i32>("id", (1i64,)).unwrap_err();
+    assert!(matches!(err, CompileError::EntrySignature { name, .. } if name == "id"));
+    let err = codegen.call::<_, f64>("id", (1,)).unwrap_err();
+    assert!(matches!(err, CompileError::EntrySignature { .. }));
+    let err = codegen.call::<_, i32>("id", ()).unwrap_err();
+    assert!(matches!(err, CompileError::EntrySignature { .. }));
+    assert_eq!(codegen.call::<_, i32>("id", (7,)).unwrap(), 7);
+}
+
+#[test]
+fn test_bool_entry_points_need_bool() {
+    let source = "
+char two() { return 2; }
+_Bool yes() { return 1; }
+int flag(_Bool b) { return b; }
+";
+    let mut codegen = get_compiler();
+    codegen.compile_program(crate::parser::parse(source).unwrap()).unwrap();
+
+    // a `char` is as wide as a `bool`, but could hold 2
+    let err = codegen.call::<_, bool>("two", ()).unwrap_err();
+    assert!(matches!(err, CompileError::EntrySignature { .. }), "{}", err);
+    assert_eq!(codegen.call::<_, u8>("two", ()).unwrap(), 2);
+    assert!(codegen.call::<_, bool>("yes", ()).unwrap());
+    assert!(codegen.call::<_, u8>("yes", ()).is_err());
+    assert_eq!(codegen.call::<_, i32>("flag", (true,)).unwrap(), 1);
+    assert!(codegen.call::<_, i32>("flag", (1u8,)).is_err());
+
+    // the module only checks CLIF types, so it reads a `bool` as a byte
+    let two = codegen.get_function("two").unwrap();
+    assert!(codegen.module_mut().call::<_, bool>(two, ()).unwrap());
+}
+
+#[test]
+fn test_call_without_definition() {
+    let program = Program {
+        statements: vec![extern_decl("labs", &[("x", AstType::I64)], Some(AstType::I64), false)],
+    };
+
+    let mut codegen = get_compiler();
+    codegen.compile_program(program).unwrap();
+    let err = codegen.call::<_, i64>("labs", (-1i64,)).unwrap_err();
+    assert!(matches!(err, CompileError::MissingDefinition(name) if name == "labs"));
+    let err = codegen.run_main::<i32>().unwrap_err();
+    assert!(matches!(err, CompileError::UndefinedFunction(name) if name == "main"));
+}
+
+fn float(value: f64) -> Expr {
+    Expr::Literal(Literal::Float(value))
+}
+
+fn decl_f64(name: &str, init: Expr) -> Stmt {
+    Stmt::VarDecl(VarDecl {
+        name: name.to_string(),
+        type_: AstType::F64,
+        init: Some(Box::new(init)),
+    })
+}
+
+fn assign_f64(name: &str, value: Expr) -> Stmt {
+    Stmt::Assign(Assign {
+        target: Variable_ {
+            name: name.to_string(),
+            type_: AstType::F64,
+        },
+        value: Box::new(value),
+    })
+}
+
+// a path in the temp dir that no other test (or test process) uses
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("compiler_test-{}-{}", std::process::id(), name))
+}
+
+fn build_and_run(name: &str, program: Program) -> Output {
+    let exe = scratch_path(name);
+    compile_to_executable(program, &exe).unwrap();
+    let output = Command::new(&exe).output().unwrap();
+    fs::remove_file(&exe).unwrap();
+    output
+}
+
+// The readme snippets the AST can express so far, with the exit code each
+// should produce. Structs, switch, arrays, pointers, globals, strings, `&&`/`||`
+// and typedefs aren't supported yet.
+fn readme_examples() -> Vec<(&'static str, Program, i32)> {
+    let f64_var = |name| typed_var(name, AstType::F64);
+    let sqrt_approx = func(
+        "sqrt_approx",
+        &[("x", AstType::F64)],
+        Some(AstType::F64),
+        vec![
+            decl_f64("guess", binary(BinaryOp::Div, f64_var("x"), float(2.0))),
+            counting_for(
+                5,
+                vec![assign_f64(
+                    "guess",
+                    binary(
+                        BinaryOp::Div,
+                        binary(BinaryOp::Add, f64_var("guess"), binary(BinaryOp::Div, f64_var("x"), f64_var("guess"))),
+                        float(2.0),
+                    ),
+                )],
+            ),
+            ret(f64_var("guess")),
+        ],
+    );
+
+    let mut calls = main_returning_i32(vec![ret(call("add", vec![int(1), int(2)]))]);
+    calls.statements.splice(0..0, add_and_sub());
+    let mut floats = main_returning_i32(vec![
+        decl_f64("result", call("sqrt_approx", vec![float(16.0)])),
+        ret(f64_var("result")),
+    ]);
+    floats.statements.insert(0, sqrt_approx);
+    let mut func_ptrs = main_returning_i32(vec![
+        Stmt::VarDecl(VarDecl {
+            name: "op".to_string(),
+            type_: binop_ptr_type(),
+            init: Some(Box::new(Expr::FuncAddr("add".to_string()))),
+        }),
+        ret(call("op", vec![int(5), int(3)])),
+    ]);
+    func_ptrs.statements.splice(0..0, add_and_sub());
+    let mut variadic = main_returning_i32(vec![ret(call("sum", vec![int(4), int(1), int(2), int(3), int(4)]))]);
+    variadic.statements.insert(0, variadic_sum("sum", AstType::I32));
+
+    vec![
+        ("definition", main_returning_i32(vec![ret(int(0))]), 0),
+        (
+            "if_else",
+            main_returning_i32(vec![Stmt::If(IfStmt {
+                condition: Box::new(int(0)),
+                then_branch: vec![ret(int(1))],
+                else_branch: Some(vec![ret(int(0))]),
+            })]),
+            0,
+        ),
+        ("calls", calls, 3),
+        (
+            "while",
+            main_returning_i32(vec![
+                decl_i32("i", int(0)),
+                Stmt::Loop(LoopStmt {
+                    kind: LoopKind::While,
+                    condition: Box::new(binary(BinaryOp::Lt, var("i"), int(10))),
+                    body: vec![assign("i", binary(BinaryOp::Add, var("i"), int(1)))],
+                }),
+                ret(var("i")),
+            ]),
+            10,
+        ),
+        ("float_functions", floats, 4),
+        (
+            "compound_assignment",
+            main_returning_i32(vec![
+                decl_i32("x", int(5)),
+                assign("x", binary(BinaryOp::Add, var("x"), int(3))),
+                assign("x", binary(BinaryOp::Mul, var("x"), int(2))),
+                ret(var("x")),
+            ]),
+            16,
+        ),
+        (
+            "bitwise",
+            main_returning_i32(vec![
+                decl_i32("x", int(5)),
+                decl_i32("y", int(3)),
+                ret(binary(
+                    BinaryOp::BitOr,
+                    binary(BinaryOp::BitAnd, var("x"), var("y")),
+                    binary(BinaryOp::BitXor, var("x"), var("y")),
+                )),
+            ]),
+            7,
+        ),
+        ("function_pointers", func_ptrs, 8),
+        ("variadic", variadic, 10),
+    ]
+}
+
+#[test]
+fn test_readme_examples_as_executables() {
+    for (name, program, expected) in readme_examples() {
+        let output = build_and_run(name, program);
+        assert_eq!(output.status.code(), Some(expected), "readme example `{}`", name);
+    }
+}
+
+#[test]
+fn test_object_links_against_libc() {
+    // the printf example: string data, an import and a variadic call in one object
+    let mut program = main_returning_i32(vec![ret(call("printf", vec![string("%s=%d\n"), string("x"), int(5)]))]);
+    program.statements.insert(
+        0,
+        extern_decl("printf", &[("format", AstType::String)], Some(AstType::I32), true),
+    );
+
+    let object = scratch_path("printf.o");
+    let exe = scratch_path("printf");
+    compile_to_object(program, &object, Triple::host()).unwrap();
+    link_executable(&[&object], &exe).unwrap();
+    let output = Command::new(&exe).output().unwrap();
+    fs::remove_file(&object).unwrap();
+    fs::remove_file(&exe).unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(output.stdout, b"x=5\n");
+}
+
+#[test]
+fn test_link_without_main() {
+    let program = Program {
+        statements: vec![func("helper", &[], Some(AstType::I32), vec![ret(int(1))])],
+    };
+    let exe = scratch_path("no_main");
+    let err = compile_to_executable(program, &exe).unwrap_err();
+    assert!(matches!(err, CompileError::Link(_)));
+    assert!(!exe.with_extension("o").exists());
+}
+
+#[test]
+fn test_private_temp_dirs() {
+    let first = private_temp_dir("compiler_test-private").unwrap();
+    let second = private_temp_dir("compiler_test-private").unwrap();
+    assert_ne!(first, second);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+    fs::remove_dir(&first).unwrap();
+    fs::remove_dir(&second).unwrap();
+}
+
+#[test]
+fn test_executable_named_like_an_object() {
+    let program = Program {
+        statements: vec![func("main", &[], Some(AstType::I32), vec![ret(int(3))])],
+    };
+    let exe = scratch_path("main.o");
+    compile_to_executable(program, &exe).unwrap();
+    let output = Command::new(&exe).output().unwrap();
+    fs::remove_file(&exe).unwrap();
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn test_compile_report() {
+    let mut compiler = Compiler::new().unwrap();
+    compiler.options(CompileOptions::all());
+    let mut codegen = compiler.build();
+    let add = func(
+        "add",
+        &[("a", AstType::I32), ("b", AstType::I32)],
+        Some(AstType::I32),
+        vec![ret(binary(BinaryOp::Add, typed_var("a", AstType::I32), typed_var("b", AstType::I32)))],
+    );
+    codegen.compile_program(Program { statements: vec![add] }).unwrap();
+    codegen
+        .compile_program(main_returning_i32(vec![ret(call("add", vec![int(40), int(2)]))]))
+        .unwrap();
+
+    let report = codegen.report();
+    let names: Vec<&str> = report.functions.iter().map(|function| function.name.as_str()).collect();
+    assert_eq!(names, ["add", "main"]);
+    let add = report.function("add").unwrap();
+    assert_eq!(
+        add.clif.as_deref().unwrap(),
+        "function u0:0(i32, i32) -> i32 system_v {
+block0(v0: i32, v1: i32):
+    v2 = iadd v0, v1
+    return v2
+
+block1:
+    v3 = iconst.i32 0
+    return v3  ; v3 = 0
+}
+"
+    );
+    // the unreachable fallback return is gone once optimized
+    assert_eq!((add.stats.blocks, add.stats.optimized_blocks), (2, 1));
+    assert!(add.disasm.as_deref().unwrap().contains("ret"));
+    assert!(add.stats.code_size > 0);
+    assert_eq!(report.total_code_size(), add.stats.code_size + report.functions[1].stats.code_size);
+
+    // the report survives serialization, for golden files
+    let json = serde_json::to_string(report).unwrap();
+    assert_eq!(serde_json::from_str::<CompileReport>(&json).unwrap(), *report);
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 42);
+}
+
+#[test]
+fn test_compile_report_is_opt_in() {
+    let mut codegen = get_compiler();
+    codegen.compile_program(main_returning_i32(vec![ret(int(0))])).unwrap();
+    let main = codegen.report().function("main").unwrap();
+    assert_eq!((&main.clif, &main.optimized_clif, &main.disasm), (&None, &None, &None));
+    assert!(main.stats.code_size > 0);
+}
+
+#[test]
+fn test_pointer_sized_integers() {
+    let source = "
+size_t count = 3000000000;
+size_t twice(size_t n) { return n * 2; }
+ptrdiff_t distance(ptrdiff_t from, ptrdiff_t to) { return to - from; }
+int main() {
+    ptrdiff_t back = distance(10, 4);
+    if (back != -6) {
+        return 0;
+    }
+    return twice(count) > count;
+}
+";
+    let mut codegen = get_compiler();
+    codegen.compile_program(crate::parser::parse(source).unwrap()).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 1);
+    assert_eq!(codegen.call::<_, usize>("twice", (21usize,)).unwrap(), 42);
+    assert_eq!(codegen.call::<_, isize>("distance", (5isize, 2isize)).unwrap(), -3);
+    assert!(codegen.call::<_, u32>("twice", (21usize,)).is_err());
+}
+
+#[test]
+fn test_pointer_sized_types_follow_the_target() {
+    for pointer_type in [types::I32, types::I64] {
+        assert_eq!(clif_type(&AstType::Usize, pointer_type).unwrap(), pointer_type);
+        assert_eq!(clif_type(&AstType::Isize, pointer_type).unwrap(), pointer_type);
+    }
+    // with 32-bit pointers `long` is wider than `size_t` and holds all its values, so the
+    // signed side wins; at equal widths the unsigned side does
+    assert_eq!(common_type(&AstType::Usize, &AstType::I64, types::I32).unwrap(), AstType::I64);
+    assert_eq!(common_type(&AstType::Usize, &AstType::I64, types::I64).unwrap(), AstType::Usize);
+    assert_eq!(common_type(&AstType::Isize, &AstType::U32, types::I32).unwrap(), AstType::U32);
+    assert_eq!(common_type(&AstType::Isize, &AstType::U32, types::I64).unwrap(), AstType::Isize);
+
+    // Cranelift has no 32-bit backends to build objects with yet
+    for target in ["i686-unknown-linux-gnu", "riscv32imac-unknown-none-elf"] {
+        let err = CompilerBuilder::new()
+            .triple(Triple::from_str(target).unwrap())
+            .isa()
+            .err()
+            .unwrap();
+        assert!(matches!(err, CompileError::Target(_)), "{}", err);
+    }
+}
+
+#[test]
+fn test_bool_and_char_operands_are_promoted() {
+    let source = "
+_Bool bool_sum(_Bool a, _Bool b) { return a + b; }
+int bool_int_sum(_Bool a, _Bool b) { return a + b; }
+int char_sum(char a, char b) { return a + b; }
+int char_plus_literal(char c) { return c + 300; }
+int negated(char c) { return -c; }
+";
+    let mut codegen = get_compiler();
+    codegen.compile_program(crate::parser::parse(source).unwrap()).unwrap();
+
+    // the sum is an `int`, and only becomes a `_Bool` again when returned
+    assert!(codegen.call::<_, bool>("bool_sum", (true, true)).unwrap());
+    assert!(!codegen.call::<_, bool>("bool_sum", (false, false)).unwrap());
+    assert_eq!(codegen.call::<_, i32>("bool_int_sum", (true, true)).unwrap(), 2);
+    assert_eq!(codegen.call::<_, i32>("char_sum", (200u8, 100u8)).unwrap(), 300);
+    assert_eq!(codegen.call::<_, i32>("char_plus_literal", (255u8,)).unwrap(), 555);
+    assert_eq!(codegen.call::<_, i32>("negated", (200u8,)).unwrap(), -200);
+}
+
+#[test]
+fn test_narrow_float_conversions_saturate() {
+    let source = "
+int plain_char() { char c = 300.5; return c; }
+int unsigned_char() { unsigned char u = -3.0; return u; }
+signed char to_signed_char(double x) { return x; }
+unsigned char to_unsigned_char(double x) { return x; }
+short to_short(double x) { return x; }
+unsigned short to_unsigned_short(double x) { return x; }
+";
+    let mut codegen = get_compiler();
+    codegen.compile_program(crate::parser::parse(source).unwrap()).unwrap();
+
+    // `char` is unsigned here
+    assert_eq!(codegen.call::<_, i32>("plain_char", ()).unwrap(), 255);
+    assert_eq!(codegen.call::<_, i32>("unsigned_char", ()).unwrap(), 0);
+
+    // out-of-range values clamp to the type's bounds instead of wrapping, even
+    // past the 32 bits Cranelift converts to
+    let signed_chars = [
+        (126.9, 126),
+        (127.5, 127),
+        (300.5, 127),
+        (-128.5, -128),
+        (-300.0, -128),
+        (1e20, 127),
+        (f64::NAN, 0),
+    ];
+    for (x, expected) in signed_chars {
+        assert_eq!(codegen.call::<_, i8>("to_signed_char", (x,)).unwrap(), expected, "signed char {}", x);
+    }
+    for (x, expected) in [(255.9, 255), (256.0, 255), (-3.0, 0), (-1e20, 0), (1e20, 255)] {
+        assert_eq!(codegen.call::<_, u8>("to_unsigned_char", (x,)).unwrap(), expected, "unsigned char {}", x);
+    }
+    let shorts = [(32767.5, 32767), (40000.0, 32767), (-32768.5, -32768), (-40000.0, -32768), (-1e20, -32768)];
+    for (x, expected) in shorts {
+        assert_eq!(codegen.call::<_, i16>("to_short", (x,)).unwrap(), expected, "short {}", x);
+    }
+    for (x, expected) in [(65535.5, 65535), (70000.0, 65535), (-1.0, 0), (1e20, 65535)] {
+        assert_eq!(codegen.call::<_, u16>("to_unsigned_short", (x,)).unwrap(), expected, "unsigned short {}", x);
+    }
+}
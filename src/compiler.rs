@@ -0,0 +1,397 @@
+use crate::{
+    ast::{AstType, Program},
+    codegen::Codegen,
+    error::{CompileError, CompileResult},
+    host::HostFunction,
+    module::ModuleType,
+    report::CompileOptions,
+    sandbox::SandboxOptions,
+};
+use cranelift_codegen::ir::LibCall;
+use cranelift_codegen::isa::{self, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{env, fs, io};
+use target_lexicon::{Architecture, BinaryFormat, Triple};
+
+// Sets up a JIT `Codegen`, including the host symbols that `extern` declarations
+// in the compiled program resolve to.
+pub struct Compiler {
+    jit_builder: JITBuilder,
+    host_functions: Vec<(String, AstType)>,
+    // every name bound with `register_symbol` or `register_function`
+    host_symbols: Vec<String>,
+    options: CompileOptions,
+    config: CodegenConfig,
+}
+
+impl Compiler {
+    pub fn new() -> CompileResult<Self> {
+        CompilerBuilder::new().compiler()
+    }
+
+    pub fn with_isa(isa: OwnedTargetIsa) -> Self {
+        Self::with_jit_builder(JITBuilder::with_isa(isa, cranelift_module::default_libcall_names()))
+    }
+
+    fn with_jit_builder(jit_builder: JITBuilder) -> Self {
+        Self {
+            jit_builder,
+            host_functions: Vec::new(),
+            host_symbols: Vec::new(),
+            options: CompileOptions::default(),
+            config: CodegenConfig::default(),
+        }
+    }
+
+    // Binds `name` to `ptr` for `extern` declarations. Nothing checks that the
+    // declared signature matches; prefer `register_function` where possible.
+    pub fn register_symbol(&mut self, name: &str, ptr: *const u8) -> &mut Self {
+        self.jit_builder.symbol(name, ptr);
+        self.host_symbols.push(name.to_string());
+        self
+    }
+
+    // Binds `name` to a host function whose signature an `extern` declaration of
+    // `name` has to match exactly.
+    pub fn register_function<F: HostFunction>(&mut self, name: &str, function: F) -> &mut Self {
+        self.jit_builder.symbol(name, function.as_ptr());
+        self.host_functions.push((name.to_string(), F::func_ptr_type()));
+        self.host_symbols.push(name.to_string());
+        self
+    }
+
+    // What to capture in the `Codegen::report` of each function.
+    pub fn options(&mut self, options: CompileOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Codegen {
+        let mut codegen = Codegen::new(ModuleType::JITModule(JITModule::new(self.jit_builder)));
+        codegen.set_options(self.options);
+        codegen
+            .configure(&self.config)
+            .expect("a new JIT codegen takes any config");
+        for (name, type_) in self.host_functions {
+            codegen.expect_extern_signature(&name, type_);
+        }
+        for name in self.host_symbols {
+            codegen.add_host_symbol(&name);
+        }
+        codegen
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    None,
+    Speed,
+    SpeedAndSize,
+}
+
+impl OptLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OptLevel::None => "none",
+            OptLevel::Speed => "speed",
+            OptLevel::SpeedAndSize => "speed_and_size",
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" | "0" => Ok(OptLevel::None),
+            "speed" | "1" | "2" => Ok(OptLevel::Speed),
+            "speed_and_size" | "s" => Ok(OptLevel::SpeedAndSize),
+            _ => Err(format!("unknown opt level `{}`", s)),
+        }
+    }
+}
+
+// What the program is compiled to do beyond the target and Cranelift settings,
+// set through `CompilerBuilder` and applied by `Codegen::configure`.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenConfig {
+    pub debug_file: Option<String>,
+    pub checked_arithmetic: bool,
+    pub consume_fuel: bool,
+    pub sandbox: Option<SandboxOptions>,
+}
+
+type LibcallNames = Arc<dyn Fn(LibCall) -> String + Send + Sync>;
+
+// Configures the target ISA and Cranelift settings, and builds the JIT or object
+// modules for them. The host triple gets the host's CPU features, other targets
+// their baseline, and either can be adjusted with `cpu_flag`.
+#[derive(Clone)]
+pub struct CompilerBuilder {
+    triple: Triple,
+    opt_level: OptLevel,
+    enable_verifier: bool,
+    is_pic: bool,
+    cpu_flags: Vec<(String, String)>,
+    libcall_names: LibcallNames,
+    config: CodegenConfig,
+}
+
+impl Default for CompilerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompilerBuilder {
+    pub fn new() -> Self {
+        Self {
+            triple: Triple::host(),
+            opt_level: OptLevel::None,
+            enable_verifier: true,
+            is_pic: false,
+            cpu_flags: Vec::new(),
+            libcall_names: Arc::from(cranelift_module::default_libcall_names()),
+            config: CodegenConfig::default(),
+        }
+    }
+
+    pub fn triple(&mut self, triple: Triple) -> &mut Self {
+        self.triple = triple;
+        self
+    }
+
+    pub fn opt_level(&mut self, opt_level: OptLevel) -> &mut Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    // Checks the CLIF of every function before compiling it; on by default.
+    pub fn enable_verifier(&mut self, enable: bool) -> &mut Self {
+        self.enable_verifier = enable;
+        self
+    }
+
+    // Position-independent code, needed to link objects into PIE executables.
+    pub fn is_pic(&mut self, is_pic: bool) -> &mut Self {
+        self.is_pic = is_pic;
+        self
+    }
+
+    // Sets an ISA-specific setting, like `has_avx2` on x86_64. Unknown names and
+    // values are reported by `isa`.
+    pub fn cpu_flag(&mut self, name: &str, value: &str) -> &mut Self {
+        self.cpu_flags.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Names the functions that instructions without a native lowering, like
+    // `ceil` on x86_64 without SSE4.1, are compiled into calls to.
+    pub fn libcall_names(&mut self, names: impl Fn(LibCall) -> String + Send + Sync + 'static) -> &mut Self {
+        self.libcall_names = Arc::new(names);
+        self
+    }
+
+    // DWARF line tables and variable locations for code compiled from
+    // `source_file`: sections in ELF objects, and registered with GDB for the JIT.
+    pub fn debug_info(&mut self, source_file: &str) -> &mut Self {
+        self.config.debug_file = Some(source_file.to_string());
+        self
+    }
+
+    // Integer `+`, `-`, `*`, negation and narrowing conversions trap with
+    // `trap::OVERFLOW` when the result doesn't fit, instead of wrapping.
+    // Conversions from floats trap like Cranelift's `fcvt_to_sint` instead of
+    // saturating.
+    pub fn checked_arithmetic(&mut self, checked: bool) -> &mut Self {
+        self.config.checked_arithmetic = checked;
+        self
+    }
+
+    // JIT code takes fuel on function entry and each loop iteration, and traps
+    // with `trap::OUT_OF_FUEL` when `Codegen::set_fuel` didn't give it enough.
+    pub fn consume_fuel(&mut self, consume: bool) -> &mut Self {
+        self.config.consume_fuel = consume;
+        self
+    }
+
+    // JIT code whose strings are offsets into a linear memory of its own, and
+    // which can only import the host functions `options` allows.
+    pub fn sandbox(&mut self, options: SandboxOptions) -> &mut Self {
+        self.config.sandbox = Some(options);
+        self
+    }
+
+    pub fn isa(&self) -> CompileResult<OwnedTargetIsa> {
+        self.build_isa(false)
+    }
+
+    fn build_isa(&self, jit: bool) -> CompileResult<OwnedTargetIsa> {
+        let mut flags_builder = settings::builder();
+        // JIT code touches large frames a page at a time, so they can't reach
+        // past the guard page below the host's stack; s390x has no inline probes
+        if jit && self.triple.architecture != Architecture::S390x {
+            flags_builder.set("enable_probestack", "true").unwrap();
+            flags_builder.set("probestack_strategy", "inline").unwrap();
+        }
+        // needed by variadic definitions
+        flags_builder.set("preserve_frame_pointers", "true").unwrap();
+        // `.eh_frame` for JIT code, so host panics can unwind through it
+        flags_builder.set("unwind_info", "true").unwrap();
+        flags_builder.set("opt_level", self.opt_level.as_str()).unwrap();
+        flags_builder.set("enable_verifier", bool_setting(self.enable_verifier)).unwrap();
+        flags_builder.set("is_pic", bool_setting(self.is_pic)).unwrap();
+
+        let mut isa_builder = if self.triple == Triple::host() {
+            cranelift_native::builder().map_err(|msg| CompileError::Target(msg.to_string()))?
+        } else {
+            isa::lookup(self.triple.clone()).map_err(|err| CompileError::Target(err.to_string()))?
+        };
+        for (name, value) in &self.cpu_flags {
+            isa_builder
+                .set(name, value)
+                .map_err(|err| CompileError::Target(format!("CPU flag `{}={}`: {}", name, value, err)))?;
+        }
+        isa_builder
+            .finish(settings::Flags::new(flags_builder))
+            .map_err(|err| CompileError::Target(err.to_string()))
+    }
+
+    // A JIT `Compiler`, which can only target the host.
+    pub fn compiler(&self) -> CompileResult<Compiler> {
+        if self.triple != Triple::host() {
+            return Err(CompileError::Target(format!(
+                "the JIT only runs code for the host, not {}",
+                self.triple
+            )));
+        }
+        let mut compiler = Compiler::with_jit_builder(JITBuilder::with_isa(self.build_isa(true)?, self.boxed_libcall_names()));
+        compiler.config = self.config.clone();
+        Ok(compiler)
+    }
+
+    pub fn jit_module(&self) -> CompileResult<ModuleType> {
+        let jit_builder = self.compiler()?.jit_builder;
+        Ok(ModuleType::JITModule(JITModule::new(jit_builder)))
+    }
+
+    // An object module named `name`, the name recorded in the object file.
+    pub fn object_module(&self, name: &str) -> CompileResult<ModuleType> {
+        if self.is_pic && !pic_objects(&self.triple) {
+            return Err(CompileError::Target(format!(
+                "position-independent code for {} objects",
+                self.triple.binary_format
+            )));
+        }
+        let builder = ObjectBuilder::new(self.isa()?, name, self.boxed_libcall_names())?;
+        Ok(ModuleType::ObjectModule(ObjectModule::new(builder)))
+    }
+
+    pub fn object_codegen(&self, name: &str) -> CompileResult<Codegen> {
+        if self.config.debug_file.is_some() && self.triple.binary_format != BinaryFormat::Elf {
+            return Err(CompileError::Unsupported(format!(
+                "debug info in {} objects",
+                self.triple.binary_format
+            )));
+        }
+        let mut codegen = Codegen::new(self.object_module(name)?);
+        codegen.configure(&self.config)?;
+        Ok(codegen)
+    }
+
+    fn boxed_libcall_names(&self) -> Box<dyn Fn(LibCall) -> String + Send + Sync> {
+        let names = self.libcall_names.clone();
+        Box::new(move |libcall| names(libcall))
+    }
+}
+
+// Whether objects for `triple` should be position-independent: ELF and Mach-O
+// executables are linked as PIE by default on most systems, while Cranelift can't
+// emit the GOT relocations PIC needs in COFF.
+pub fn pic_objects(triple: &Triple) -> bool {
+    triple.binary_format != BinaryFormat::Coff
+}
+
+fn bool_setting(value: bool) -> &'static str {
+    if value {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+// Compiles `program` for `triple` into a relocatable object file at `path`.
+pub fn compile_to_object(program: Program, path: &Path, triple: Triple) -> CompileResult<()> {
+    let is_pic = pic_objects(&triple);
+    compile_to_object_with(program, path, CompilerBuilder::new().triple(triple).is_pic(is_pic))
+}
+
+pub fn compile_to_object_with(program: Program, path: &Path, builder: &CompilerBuilder) -> CompileResult<()> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut codegen = builder.object_codegen(&name)?;
+    codegen.compile_program(program)?;
+    let bytes = codegen.emit_object()?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+// Links object files into an executable with the system C compiler, which also
+// pulls in the C runtime that calls `main` and exits with its return value.
+pub fn link_executable(objects: &[&Path], output: &Path) -> CompileResult<()> {
+    let result = Command::new("cc").args(objects).arg("-o").arg(output).output()?;
+    if !result.status.success() {
+        return Err(CompileError::Link(
+            String::from_utf8_lossy(&result.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Compiles `program` for the host and links it into an executable at `path`.
+pub fn compile_to_executable(program: Program, path: &Path) -> CompileResult<()> {
+    compile_to_executable_with(program, path, CompilerBuilder::new().is_pic(true))
+}
+
+pub fn compile_to_executable_with(program: Program, path: &Path, builder: &CompilerBuilder) -> CompileResult<()> {
+    // not next to `path`, which could itself end in `.o`
+    let dir = private_temp_dir("compiler_test")?;
+    let object = dir.join("program.o");
+    let linked = compile_to_object_with(program, &object, builder).and_then(|()| link_executable(&[&object], path));
+    // the object is only scratch, so failing to remove it isn't an error
+    let _ = fs::remove_dir_all(&dir);
+    linked
+}
+
+// Creates a new directory in the system's temp dir that only this user can
+// get into, for scratch files. Names that already exist are skipped rather
+// than reused, since anyone could have made them, or made them links.
+pub fn private_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    static DIRS: AtomicUsize = AtomicUsize::new(0);
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        let dir = env::temp_dir().join(format!(
+            "{}-{}-{}",
+            prefix,
+            process::id(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        match builder.create(&dir) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| dir),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::compiler::{pic_objects, CompilerBuilder, OptLevel};
+use crate::error::{CompileError, CompileResult};
+use crate::module::ModuleType;
+use crate::parser::parse;
+use cranelift::prelude::*;
+use cranelift_codegen::ir::LibCall;
+use cranelift_codegen::settings;
+use cranelift_module::{FuncId, Linkage};
+use object::{BinaryFormat, Object, ObjectSection, ObjectSymbol};
+use std::str::FromStr;
+use target_lexicon::{Architecture, Triple};
+
+// Defines `f(x: f64) -> f64 { ceil(x) }` in `module`.
+fn define_ceil(module: &mut ModuleType) -> CompileResult<FuncId> {
+    let mut ctx = module.make_context();
+    ctx.func.signature.params.push(AbiParam::new(types::F64));
+    ctx.func.signature.returns.push(AbiParam::new(types::F64));
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = builder.create_block();
+    builder.append_block_params_for_function_params(block);
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+    let x = builder.block_params(block)[0];
+    let result = builder.ins().ceil(x);
+    builder.ins().return_(&[result]);
+    builder.finalize();
+
+    let func_id = module.declare_function("f", Linkage::Export, &ctx.func.signature)?;
+    module.define_function(func_id, &mut ctx)?;
+    Ok(func_id)
+}
+
+#[test]
+fn test_builder_sets_flags() {
+    let isa = CompilerBuilder::new()
+        .opt_level(OptLevel::Speed)
+        .enable_verifier(false)
+        .is_pic(true)
+        .isa()
+        .unwrap();
+    assert_eq!(isa.flags().opt_level(), settings::OptLevel::Speed);
+    assert!(!isa.flags().enable_verifier());
+    assert!(isa.flags().is_pic());
+    assert!(isa.flags().preserve_frame_pointers());
+
+    let isa = CompilerBuilder::new().isa().unwrap();
+    assert_eq!(isa.flags().opt_level(), settings::OptLevel::None);
+    assert!(isa.flags().enable_verifier());
+    assert!(!isa.flags().is_pic());
+}
+
+#[test]
+fn test_builder_rejects_unknown_cpu_flags() {
+    let err = CompilerBuilder::new().cpu_flag("has_nothing", "true").isa().err().unwrap();
+    assert!(matches!(&err, CompileError::Target(message) if message.contains("has_nothing")), "{}", err);
+}
+
+#[test]
+fn test_jit_only_targets_the_host() {
+    let other = if Triple::host().architecture == Architecture::X86_64 {
+        "aarch64-unknown-linux-gnu"
+    } else {
+        "x86_64-unknown-linux-gnu"
+    };
+    let err = CompilerBuilder::new()
+        .triple(Triple::from_str(other).unwrap())
+        .jit_module()
+        .err()
+        .unwrap();
+    assert!(matches!(err, CompileError::Target(_)), "{}", err);
+}
+
+#[test]
+fn test_jit_module_runs_code() {
+    let mut module = CompilerBuilder::new().opt_level(OptLevel::SpeedAndSize).jit_module().unwrap();
+    let func_id = define_ceil(&mut module).unwrap();
+    assert_eq!(module.call::<_, f64>(func_id, (1.25,)).unwrap(), 2.0);
+}
+
+// Without SSE4.1 there is no `roundsd`, so `ceil` becomes a call to a libcall.
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_cpu_flags_and_libcall_names() {
+    let mut module = CompilerBuilder::new()
+        .cpu_flag("has_sse41", "false")
+        .libcall_names(|libcall| match libcall {
+            LibCall::CeilF64 => "my_ceil".to_string(),
+            other => cranelift_module::default_libcall_names()(other),
+        })
+        .object_module("rounding")
+        .unwrap();
+    define_ceil(&mut module).unwrap();
+    let object = module.emit_object().unwrap();
+    assert!(object.windows(b"my_ceil".len()).any(|window| window == b"my_ceil"));
+
+    let mut module = CompilerBuilder::new().object_module("rounding").unwrap();
+    define_ceil(&mut module).unwrap();
+    let object = module.emit_object().unwrap();
+    assert!(!object.windows(b"ceil\0".len()).any(|window| window == b"ceil\0"));
+}
+
+#[test]
+fn test_verifier_rejects_invalid_clif() {
+    let mut module = CompilerBuilder::new().jit_module().unwrap();
+    let mut ctx = module.make_context();
+    ctx.func.signature.returns.push(AbiParam::new(types::I32));
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = builder.create_block();
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+    // returns an i64 from a function declared to return i32
+    let value = builder.ins().iconst(types::I64, 1);
+    builder.ins().return_(&[value]);
+    builder.finalize();
+
+    let func_id = module.declare_function("bad", Linkage::Export, &ctx.func.signature).unwrap();
+    let err = module.define_function(func_id, &mut ctx).unwrap_err();
+    assert!(err.to_string().contains("Verifier"), "{}", err);
+}
+
+const CROSS_SOURCE: &str = "
+int counter = 7;
+size_t limit = 5;
+char *greeting = \"hi\";
+int add(int a, int b) { return a + b; }
+int (*op)(int, int) = add;
+int main() {
+    counter += op(1, 2);
+    return counter;
+}
+";
+
+// Compiles `CROSS_SOURCE` into an object for `target`, plus a variadic call to
+// `printf` where the target supports one.
+fn cross_object(target: &str, variadic: bool) -> Vec<u8> {
+    let triple = Triple::from_str(target).unwrap();
+    let mut source = CROSS_SOURCE.to_string();
+    if variadic {
+        source.push_str("int printf(char *fmt, ...);\nvoid hello() { printf(\"%s %f\\n\", greeting, 1.5); }\n");
+    }
+    let mut builder = CompilerBuilder::new();
+    builder.triple(triple.clone()).is_pic(pic_objects(&triple));
+    let mut codegen = builder.object_codegen("cross").unwrap();
+    codegen.compile_program(parse(&source).unwrap()).unwrap();
+    codegen.into_module().unwrap().emit_object().unwrap()
+}
+
+#[test]
+fn test_cross_compiled_objects() {
+    let targets = [
+        ("x86_64-unknown-linux-gnu", BinaryFormat::Elf, object::Architecture::X86_64, true),
+        ("aarch64-unknown-linux-gnu", BinaryFormat::Elf, object::Architecture::Aarch64, true),
+        ("riscv64gc-unknown-linux-gnu", BinaryFormat::Elf, object::Architecture::Riscv64, true),
+        ("s390x-unknown-linux-gnu", BinaryFormat::Elf, object::Architecture::S390x, true),
+        ("x86_64-apple-darwin", BinaryFormat::MachO, object::Architecture::X86_64, true),
+        ("aarch64-apple-darwin", BinaryFormat::MachO, object::Architecture::Aarch64, false),
+        ("x86_64-pc-windows-msvc", BinaryFormat::Coff, object::Architecture::X86_64, false),
+        ("aarch64-pc-windows-msvc", BinaryFormat::Coff, object::Architecture::Aarch64, false),
+    ];
+    for (target, format, architecture, variadic) in targets {
+        let bytes = cross_object(target, variadic);
+        let file = object::File::parse(&*bytes).unwrap();
+        assert_eq!(file.architecture(), architecture, "{}", target);
+        // COFF objects don't record a pointer width
+        assert!(file.is_64() || format == BinaryFormat::Coff, "{}", target);
+        assert_eq!(file.endianness() == object::Endianness::Big, target.starts_with("s390x"), "{}", target);
+        assert_eq!(file.format(), format, "{}", target);
+
+        // Mach-O prefixes C symbols with `_`
+        let symbol = |name: &str| {
+            let prefix = if format == BinaryFormat::MachO { "_" } else { "" };
+            let name = format!("{}{}", prefix, name);
+            file.symbols()
+                .find(|symbol| symbol.name() == Ok(&name))
+                .unwrap_or_else(|| panic!("{} has no symbol {}", target, name))
+        };
+        for name in ["main", "add", "counter", "greeting", "op"] {
+            assert!(symbol(name).is_definition(), "{} {}", target, name);
+        }
+        // pointers are as wide as the target's `TargetFrontendConfig` says
+        if format == BinaryFormat::Elf {
+            let sizes = [symbol("op").size(), symbol("greeting").size(), symbol("limit").size()];
+            assert_eq!(sizes, [8, 8, 8], "{}", target);
+        }
+        if variadic {
+            assert!(symbol("printf").is_undefined(), "{}", target);
+        }
+
+        // initializers are laid out in the target's byte order
+        let counter = symbol("counter");
+        let section = file.section_by_index(counter.section_index().unwrap()).unwrap();
+        let offset = (counter.address() - section.address()) as usize;
+        let expected = if target.starts_with("s390x") { [0, 0, 0, 7] } else { [7, 0, 0, 0] };
+        assert_eq!(section.data().unwrap()[offset..offset + 4], expected, "{}", target);
+    }
+}
+
+#[test]
+fn test_unsupported_cross_compilation() {
+    // Apple's arm64 ABI passes variadic arguments on the stack
+    let mut codegen = CompilerBuilder::new()
+        .triple(Triple::from_str("aarch64-apple-darwin").unwrap())
+        .object_codegen("variadic")
+        .unwrap();
+    let program = parse("int printf(char *fmt, ...);\nint main() { return printf(\"%d\", 1); }").unwrap();
+    let err = codegen.compile_program(program).unwrap_err();
+    assert!(matches!(err, CompileError::Unsupported(_)), "{}", err);
+
+    let err = CompilerBuilder::new()
+        .triple(Triple::from_str("x86_64-pc-windows-msvc").unwrap())
+        .is_pic(true)
+        .object_module("pic")
+        .err()
+        .unwrap();
+    assert!(matches!(err, CompileError::Target(_)), "{}", err);
+}
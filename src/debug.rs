@@ -0,0 +1,668 @@
+use crate::ast::AstType;
+use crate::error::{CompileError, CompileResult};
+use cranelift_codegen::gimli::write::{
+    Address, AttributeValue, DebugFrame, DwarfUnit, EndianVec, Expression, FrameTable, LineProgram, LineString,
+    Location, LocationList, Range, RangeList, Sections, UnitEntryId, Writer,
+};
+use cranelift_codegen::gimli::{self, constants, Encoding, Format, LineEncoding, Register, RunTimeEndian, SectionId};
+use cranelift_codegen::ir::{Endianness, ValueLabel};
+use cranelift_codegen::isa::unwind::{systemv, UnwindInfo};
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::{Context, LabelValueLoc};
+use cranelift_module::FuncId;
+use cranelift_object::object::write::{Object, Relocation, Symbol, SymbolSection};
+use cranelift_object::object::{
+    self, BinaryFormat, RelocationEncoding, RelocationFlags, RelocationKind, SectionKind, SymbolFlags, SymbolKind,
+    SymbolScope,
+};
+use cranelift_object::ObjectProduct;
+use std::collections::HashMap;
+use std::ptr::{self, addr_of_mut};
+use std::sync::Mutex;
+
+// A local variable or parameter of the function being compiled. Its index in
+// the function's locals is the `ValueLabel` its values are tagged with.
+#[derive(Debug, Clone)]
+pub(crate) struct LocalDebug {
+    pub name: String,
+    pub type_: AstType,
+    pub param: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum VariableLocation {
+    // a DWARF register number
+    Register(u16),
+    CfaOffset(i64),
+}
+
+struct VariableDebug {
+    local: LocalDebug,
+    // (start, end, location), as offsets into the function's code
+    ranges: Vec<(u32, u32, VariableLocation)>,
+}
+
+// What DWARF needs to know about one compiled function.
+pub(crate) struct FunctionDebug {
+    name: String,
+    func_id: FuncId,
+    line: u32,
+    return_type: Option<AstType>,
+    size: u32,
+    // (code offset, source line), sorted by offset
+    rows: Vec<(u32, u32)>,
+    variables: Vec<VariableDebug>,
+    unwind: Option<systemv::UnwindInfo>,
+}
+
+impl FunctionDebug {
+    // Reads the line table and variable locations out of `ctx` after the
+    // function is compiled.
+    pub(crate) fn new(
+        name: &str,
+        func_id: FuncId,
+        line: u32,
+        return_type: Option<AstType>,
+        ctx: &Context,
+        locals: &[LocalDebug],
+        isa: &dyn TargetIsa,
+    ) -> CompileResult<Self> {
+        let code = ctx.compiled_code().expect("defined functions are compiled");
+        let size = code.code_buffer().len() as u32;
+
+        let mut rows = Vec::new();
+        for srcloc in code.buffer.get_srclocs_sorted() {
+            if srcloc.loc.is_default() {
+                continue;
+            }
+            let line = srcloc.loc.bits();
+            if rows.last().map(|&(_, last)| last) != Some(line) {
+                rows.push((srcloc.start, line));
+            }
+        }
+        // the prologue belongs to the function's declaration
+        if rows.first().is_none_or(|&(offset, _)| offset > 0) {
+            rows.insert(0, (0, line));
+        }
+
+        let mut variables = Vec::new();
+        for (index, local) in locals.iter().enumerate() {
+            let Some(ranges) = code.value_labels_ranges.get(&ValueLabel::from_u32(index as u32)) else {
+                continue;
+            };
+            let ranges = ranges
+                .iter()
+                .filter_map(|range| {
+                    let location = match range.loc {
+                        LabelValueLoc::Reg(reg) => {
+                            VariableLocation::Register(isa.map_regalloc_reg_to_dwarf(reg).ok()?)
+                        }
+                        LabelValueLoc::CFAOffset(offset) => VariableLocation::CfaOffset(offset),
+                    };
+                    Some((range.start, range.end, location))
+                })
+                .collect();
+            variables.push(VariableDebug {
+                local: local.clone(),
+                ranges,
+            });
+        }
+
+        let unwind = match code.create_unwind_info(isa).map_err(object_error)? {
+            Some(UnwindInfo::SystemV(info)) => Some(info),
+            _ => None,
+        };
+        Ok(Self {
+            name: name.to_string(),
+            func_id,
+            line,
+            return_type,
+            size,
+            rows,
+            variables,
+            unwind,
+        })
+    }
+}
+
+// Debug info collected while compiling from `file`, written as DWARF into
+// object files or registered with GDB for JIT code.
+pub(crate) struct DebugInfo {
+    file: String,
+    pub(crate) functions: Vec<FunctionDebug>,
+    // how many of `functions` were already registered with GDB
+    registered: usize,
+    registrations: Vec<JitRegistration>,
+}
+
+impl DebugInfo {
+    pub(crate) fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            functions: Vec::new(),
+            registered: 0,
+            registrations: Vec::new(),
+        }
+    }
+
+    // The `.debug_*` sections for every function in an object file, which have
+    // to be written before the module is finished.
+    pub(crate) fn object_sections(&self, isa: &dyn TargetIsa) -> CompileResult<DebugSections> {
+        let sections = self.write_sections(&self.functions, isa, |function, offset| Address::Symbol {
+            symbol: function.func_id.as_u32() as usize,
+            addend: offset as i64,
+        })?;
+        Ok(DebugSections(sections))
+    }
+
+    // Hands the functions finalized since the last call to GDB, as an ELF image
+    // with their symbols and DWARF at the addresses `address` gives.
+    pub(crate) fn register_jit(
+        &mut self,
+        isa: &dyn TargetIsa,
+        address: impl Fn(FuncId) -> *const u8,
+    ) -> CompileResult<()> {
+        let functions = &self.functions[self.registered..];
+        if functions.is_empty() {
+            return Ok(());
+        }
+        let start = |function: &FunctionDebug| address(function.func_id) as u64;
+        let sections = self.write_sections(functions, isa, |function, offset| {
+            Address::Constant(start(function) + offset)
+        })?;
+
+        let architecture = match isa.triple().architecture {
+            target_lexicon::Architecture::X86_64 => object::Architecture::X86_64,
+            target_lexicon::Architecture::Aarch64(_) => object::Architecture::Aarch64,
+            target_lexicon::Architecture::Riscv64(_) => object::Architecture::Riscv64,
+            target_lexicon::Architecture::S390x => object::Architecture::S390x,
+            other => return Err(CompileError::Unsupported(format!("debug info for {}", other))),
+        };
+        let endian = match isa.endianness() {
+            Endianness::Little => object::Endianness::Little,
+            Endianness::Big => object::Endianness::Big,
+        };
+        let mut image = Object::new(BinaryFormat::Elf, architecture, endian);
+        for (id, section) in &sections {
+            let object_id = image.add_section(Vec::new(), id.name().as_bytes().to_vec(), SectionKind::Debug);
+            image.set_section_data(object_id, section.data.slice().to_vec(), 1);
+        }
+        for function in functions {
+            image.add_symbol(Symbol {
+                name: function.name.as_bytes().to_vec(),
+                value: start(function),
+                size: function.size as u64,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Compilation,
+                weak: false,
+                section: SymbolSection::Absolute,
+                flags: SymbolFlags::None,
+            });
+        }
+        let image = image.write().map_err(object_error)?;
+
+        self.registrations.push(JitRegistration::new(image));
+        self.registered = self.functions.len();
+        Ok(())
+    }
+
+    // DWARF 4 for `functions`, with a `.debug_frame` from their unwind info.
+    fn write_sections(
+        &self,
+        functions: &[FunctionDebug],
+        isa: &dyn TargetIsa,
+        address: impl Fn(&FunctionDebug, u64) -> Address,
+    ) -> CompileResult<Vec<(SectionId, DwarfSection)>> {
+        let endian = match isa.endianness() {
+            Endianness::Little => RunTimeEndian::Little,
+            Endianness::Big => RunTimeEndian::Big,
+        };
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: isa.pointer_bytes(),
+        };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let comp_dir = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| ".".to_string());
+        let mut program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(comp_dir.clone().into_bytes()),
+            LineString::String(self.file.clone().into_bytes()),
+            None,
+        );
+        let file = program.add_file(
+            LineString::String(self.file.clone().into_bytes()),
+            program.default_directory(),
+            None,
+        );
+        for function in functions {
+            program.begin_sequence(Some(address(function, 0)));
+            for &(offset, line) in &function.rows {
+                let row = program.row();
+                row.address_offset = offset as u64;
+                row.file = file;
+                row.line = line as u64;
+                program.generate_row();
+            }
+            program.end_sequence(function.size as u64);
+        }
+        dwarf.unit.line_program = program;
+
+        let ranges = RangeList(
+            functions
+                .iter()
+                .map(|function| Range::StartLength {
+                    begin: address(function, 0),
+                    length: function.size as u64,
+                })
+                .collect(),
+        );
+        let ranges = dwarf.unit.ranges.add(ranges);
+        let root = dwarf.unit.root();
+        let entry = dwarf.unit.get_mut(root);
+        entry.set(constants::DW_AT_producer, AttributeValue::String(b"compiler_test".to_vec()));
+        entry.set(constants::DW_AT_language, AttributeValue::Language(constants::DW_LANG_C99));
+        entry.set(constants::DW_AT_name, AttributeValue::String(self.file.clone().into_bytes()));
+        entry.set(constants::DW_AT_comp_dir, AttributeValue::String(comp_dir.into_bytes()));
+        entry.set(constants::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0)));
+        entry.set(constants::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+
+        let mut types = TypeEntries::default();
+        for function in functions {
+            let subprogram = dwarf.unit.add(root, constants::DW_TAG_subprogram);
+            let return_type = function
+                .return_type
+                .as_ref()
+                .and_then(|type_| types.get(&mut dwarf, type_, isa.pointer_bytes()));
+            let mut frame_base = Expression::new();
+            frame_base.op(constants::DW_OP_call_frame_cfa);
+            let entry = dwarf.unit.get_mut(subprogram);
+            entry.set(constants::DW_AT_name, AttributeValue::String(function.name.clone().into_bytes()));
+            entry.set(constants::DW_AT_external, AttributeValue::Flag(true));
+            entry.set(constants::DW_AT_low_pc, AttributeValue::Address(address(function, 0)));
+            entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(function.size as u64));
+            entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+            entry.set(constants::DW_AT_decl_line, AttributeValue::Udata(function.line as u64));
+            entry.set(constants::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+            if let Some(type_) = return_type {
+                entry.set(constants::DW_AT_type, AttributeValue::UnitRef(type_));
+            }
+
+            for variable in &function.variables {
+                let tag = if variable.local.param {
+                    constants::DW_TAG_formal_parameter
+                } else {
+                    constants::DW_TAG_variable
+                };
+                let type_ = types.get(&mut dwarf, &variable.local.type_, isa.pointer_bytes());
+                let locations = variable
+                    .ranges
+                    .iter()
+                    .map(|&(start, end, location)| {
+                        let mut data = Expression::new();
+                        match location {
+                            VariableLocation::Register(register) => data.op_reg(Register(register)),
+                            VariableLocation::CfaOffset(offset) => data.op_fbreg(offset),
+                        }
+                        Location::StartEnd {
+                            begin: address(function, start as u64),
+                            end: address(function, end as u64),
+                            data,
+                        }
+                    })
+                    .collect();
+                let locations = dwarf.unit.locations.add(LocationList(locations));
+                let child = dwarf.unit.add(subprogram, tag);
+                let entry = dwarf.unit.get_mut(child);
+                entry.set(constants::DW_AT_name, AttributeValue::String(variable.local.name.clone().into_bytes()));
+                entry.set(constants::DW_AT_location, AttributeValue::LocationListRef(locations));
+                if let Some(type_) = type_ {
+                    entry.set(constants::DW_AT_type, AttributeValue::UnitRef(type_));
+                }
+            }
+        }
+
+        let mut sections = Sections::new(DwarfSection::new(endian));
+        dwarf.write(&mut sections).map_err(object_error)?;
+
+        let mut frames = FrameTable::default();
+        if let Some(cie) = isa.create_systemv_cie() {
+            let cie = frames.add_cie(cie);
+            for function in functions {
+                if let Some(unwind) = &function.unwind {
+                    frames.add_fde(cie, unwind.to_fde(address(function, 0)));
+                }
+            }
+        }
+        let mut debug_frame = DebugFrame(DwarfSection::new(endian));
+        frames.write_debug_frame(&mut debug_frame).map_err(object_error)?;
+
+        let mut result = Vec::new();
+        sections.for_each(|id, section| -> gimli::write::Result<()> {
+            if section.data.len() > 0 {
+                result.push((id, section.clone()));
+            }
+            Ok(())
+        })
+        .map_err(object_error)?;
+        if debug_frame.0.data.len() > 0 {
+            result.push((SectionId::DebugFrame, debug_frame.0));
+        }
+        Ok(result)
+    }
+}
+
+pub(crate) struct DebugSections(Vec<(SectionId, DwarfSection)>);
+
+impl DebugSections {
+    // Adds the sections to an ELF object, relocated against its symbols.
+    pub(crate) fn write_to(self, product: &mut ObjectProduct) -> CompileResult<()> {
+        let ObjectProduct { object, functions, .. } = product;
+        if object.format() != BinaryFormat::Elf {
+            return Err(CompileError::Unsupported(format!("debug info in {:?} objects", object.format())));
+        }
+
+        let mut ids = HashMap::new();
+        for (id, section) in &self.0 {
+            let object_id = object.add_section(Vec::new(), id.name().as_bytes().to_vec(), SectionKind::Debug);
+            ids.insert(*id, object_id);
+            // relocated fields are filled in by the linker
+            let mut data = section.data.slice().to_vec();
+            for relocation in &section.relocations {
+                let offset = relocation.offset as usize;
+                data[offset..offset + relocation.size as usize].fill(0);
+            }
+            object.set_section_data(object_id, data, 1);
+        }
+        for (id, section) in &self.0 {
+            for relocation in &section.relocations {
+                let symbol = match relocation.target {
+                    RelocationTarget::Function(func_id) => {
+                        functions[func_id].expect("functions with debug info are declared").0
+                    }
+                    RelocationTarget::Section(section) => object.section_symbol(ids[&section]),
+                };
+                let relocation = Relocation {
+                    offset: relocation.offset,
+                    symbol,
+                    addend: relocation.addend,
+                    flags: RelocationFlags::Generic {
+                        kind: RelocationKind::Absolute,
+                        encoding: RelocationEncoding::Generic,
+                        size: relocation.size * 8,
+                    },
+                };
+                object.add_relocation(ids[id], relocation).map_err(object_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn object_error(err: impl std::fmt::Display) -> CompileError {
+    CompileError::Object(err.to_string())
+}
+
+// `DW_TAG_base_type` and `DW_TAG_pointer_type` entries, added as they're used.
+#[derive(Default)]
+struct TypeEntries {
+    entries: HashMap<String, UnitEntryId>,
+}
+
+impl TypeEntries {
+    fn get(&mut self, dwarf: &mut DwarfUnit, type_: &AstType, pointer_bytes: u8) -> Option<UnitEntryId> {
+        let (name, encoding, size) = match type_ {
+            AstType::I8 => ("signed char", constants::DW_ATE_signed, 1),
+            AstType::I16 => ("short", constants::DW_ATE_signed, 2),
+            AstType::I32 => ("int", constants::DW_ATE_signed, 4),
+            AstType::I64 => ("long", constants::DW_ATE_signed, 8),
+            AstType::U8 => ("unsigned char", constants::DW_ATE_unsigned, 1),
+            AstType::U16 => ("unsigned short", constants::DW_ATE_unsigned, 2),
+            AstType::U32 => ("unsigned int", constants::DW_ATE_unsigned, 4),
+            AstType::U64 => ("unsigned long", constants::DW_ATE_unsigned, 8),
+            AstType::Usize => ("size_t", constants::DW_ATE_unsigned, pointer_bytes),
+            AstType::Isize => ("ptrdiff_t", constants::DW_ATE_signed, pointer_bytes),
+            AstType::F32 => ("float", constants::DW_ATE_float, 4),
+            AstType::F64 => ("double", constants::DW_ATE_float, 8),
+            AstType::Bool => ("_Bool", constants::DW_ATE_boolean, 1),
+            AstType::Char => ("char", constants::DW_ATE_signed_char, 1),
+            AstType::String => {
+                let char_type = self.get(dwarf, &AstType::Char, pointer_bytes);
+                return Some(self.pointer("char *", char_type, dwarf, pointer_bytes));
+            }
+            AstType::FuncPtr { .. } | AstType::VaList => {
+                return Some(self.pointer("void *", None, dwarf, pointer_bytes));
+            }
+            _ => return None,
+        };
+        if let Some(&id) = self.entries.get(name) {
+            return Some(id);
+        }
+        let root = dwarf.unit.root();
+        let id = dwarf.unit.add(root, constants::DW_TAG_base_type);
+        let entry = dwarf.unit.get_mut(id);
+        entry.set(constants::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        entry.set(constants::DW_AT_encoding, AttributeValue::Encoding(encoding));
+        entry.set(constants::DW_AT_byte_size, AttributeValue::Data1(size));
+        self.entries.insert(name.to_string(), id);
+        Some(id)
+    }
+
+    fn pointer(
+        &mut self,
+        name: &str,
+        target: Option<UnitEntryId>,
+        dwarf: &mut DwarfUnit,
+        pointer_bytes: u8,
+    ) -> UnitEntryId {
+        if let Some(&id) = self.entries.get(name) {
+            return id;
+        }
+        let root = dwarf.unit.root();
+        let id = dwarf.unit.add(root, constants::DW_TAG_pointer_type);
+        let entry = dwarf.unit.get_mut(id);
+        entry.set(constants::DW_AT_byte_size, AttributeValue::Data1(pointer_bytes));
+        if let Some(target) = target {
+            entry.set(constants::DW_AT_type, AttributeValue::UnitRef(target));
+        }
+        self.entries.insert(name.to_string(), id);
+        id
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RelocationTarget {
+    Function(FuncId),
+    Section(SectionId),
+}
+
+#[derive(Debug, Clone)]
+struct DwarfRelocation {
+    offset: u64,
+    size: u8,
+    target: RelocationTarget,
+    addend: i64,
+}
+
+// A DWARF section being written, with the relocations an object file needs for
+// it. Offsets into other sections are also written in place, which is all an
+// in-memory JIT image needs.
+#[derive(Debug, Clone)]
+struct DwarfSection {
+    data: EndianVec<RunTimeEndian>,
+    relocations: Vec<DwarfRelocation>,
+}
+
+impl DwarfSection {
+    fn new(endian: RunTimeEndian) -> Self {
+        Self {
+            data: EndianVec::new(endian),
+            relocations: Vec::new(),
+        }
+    }
+}
+
+impl Writer for DwarfSection {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.data.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.data.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.data.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> gimli::write::Result<()> {
+        match address {
+            Address::Constant(value) => self.write_udata(value, size),
+            Address::Symbol { symbol, addend } => {
+                self.relocations.push(DwarfRelocation {
+                    offset: self.len() as u64,
+                    size,
+                    target: RelocationTarget::Function(FuncId::from_u32(symbol as u32)),
+                    addend,
+                });
+                self.write_udata(addend as u64, size)
+            }
+        }
+    }
+
+    fn write_offset(&mut self, val: usize, section: SectionId, size: u8) -> gimli::write::Result<()> {
+        self.relocations.push(DwarfRelocation {
+            offset: self.len() as u64,
+            size,
+            target: RelocationTarget::Section(section),
+            addend: val as i64,
+        });
+        self.write_udata(val as u64, size)
+    }
+
+    fn write_offset_at(
+        &mut self,
+        offset: usize,
+        val: usize,
+        section: SectionId,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        self.relocations.push(DwarfRelocation {
+            offset: offset as u64,
+            size,
+            target: RelocationTarget::Section(section),
+            addend: val as i64,
+        });
+        self.write_udata_at(offset, val as u64, size)
+    }
+}
+
+// GDB's JIT interface: GDB puts a breakpoint in `__jit_debug_register_code` and
+// reads the entry `__jit_debug_descriptor` points at whenever it's hit.
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/JIT-Interface.html
+#[repr(C)]
+pub(crate) struct JitCodeEntry {
+    pub next: *mut JitCodeEntry,
+    pub prev: *mut JitCodeEntry,
+    pub symfile_addr: *const u8,
+    pub symfile_size: u64,
+}
+
+#[repr(C)]
+pub(crate) struct JitDescriptor {
+    pub version: u32,
+    pub action_flag: u32,
+    pub relevant_entry: *mut JitCodeEntry,
+    pub first_entry: *mut JitCodeEntry,
+}
+
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub(crate) static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+#[no_mangle]
+#[inline(never)]
+extern "C" fn __jit_debug_register_code() {
+    // keeps the call from being optimized away
+    std::hint::black_box(());
+}
+
+// guards `__jit_debug_descriptor` and the list of entries
+pub(crate) static JIT_DEBUG_LOCK: Mutex<()> = Mutex::new(());
+
+// An image registered with GDB until dropped.
+struct JitRegistration {
+    entry: *mut JitCodeEntry,
+    _image: Box<[u8]>,
+}
+
+// the entry is only touched with `JIT_DEBUG_LOCK` held
+unsafe impl Send for JitRegistration {}
+
+impl JitRegistration {
+    fn new(image: Vec<u8>) -> Self {
+        let image = image.into_boxed_slice();
+        let entry = Box::into_raw(Box::new(JitCodeEntry {
+            next: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            symfile_addr: image.as_ptr(),
+            symfile_size: image.len() as u64,
+        }));
+        let _guard = JIT_DEBUG_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = addr_of_mut!(__jit_debug_descriptor);
+            (*entry).next = (*descriptor).first_entry;
+            if !(*entry).next.is_null() {
+                (*(*entry).next).prev = entry;
+            }
+            (*descriptor).first_entry = entry;
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+        }
+        Self { entry, _image: image }
+    }
+}
+
+impl Drop for JitRegistration {
+    fn drop(&mut self) {
+        let _guard = JIT_DEBUG_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = addr_of_mut!(__jit_debug_descriptor);
+            let entry = self.entry;
+            if (*entry).prev.is_null() {
+                (*descriptor).first_entry = (*entry).next;
+            } else {
+                (*(*entry).prev).next = (*entry).next;
+            }
+            if !(*entry).next.is_null() {
+                (*(*entry).next).prev = (*entry).prev;
+            }
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).action_flag = JIT_NOACTION;
+            (*descriptor).relevant_entry = ptr::null_mut();
+            drop(Box::from_raw(entry));
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::compiler::CompilerBuilder;
+use crate::debug::{__jit_debug_descriptor, JIT_DEBUG_LOCK};
+use crate::parser::parse;
+use cranelift_codegen::gimli;
+use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget};
+use std::ptr::addr_of;
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+const SOURCE: &str = "int add(int a, int b) {
+    int sum = a + b;
+    return sum;
+}
+
+int main() {
+    int x = add(1, 2);
+    return x;
+}
+";
+
+// The named section of `file`, with relocations applied as a linker would
+// with the text section at address 0.
+fn relocated_section(file: &object::File, name: &str) -> Vec<u8> {
+    let Some(section) = file.section_by_name(name) else {
+        return Vec::new();
+    };
+    let mut data = section.data().unwrap().to_vec();
+    for (offset, relocation) in section.relocations() {
+        let RelocationTarget::Symbol(symbol) = relocation.target() else {
+            panic!("unexpected relocation target in {}", name);
+        };
+        let value = file.symbol_by_index(symbol).unwrap().address() as i64 + relocation.addend();
+        let bytes = if file.is_little_endian() { value.to_le_bytes() } else { value.to_be_bytes() };
+        let size = relocation.size() as usize / 8;
+        let bytes = if file.is_little_endian() { &bytes[..size] } else { &bytes[8 - size..] };
+        data[offset as usize..offset as usize + size].copy_from_slice(bytes);
+    }
+    data
+}
+
+fn load_dwarf(file: &object::File) -> gimli::DwarfSections<Vec<u8>> {
+    gimli::DwarfSections::load(|id| -> Result<_, gimli::Error> { Ok(relocated_section(file, id.name())) }).unwrap()
+}
+
+fn endian(file: &object::File) -> gimli::RunTimeEndian {
+    if file.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    }
+}
+
+// (address, line) for every row of the line tables
+fn line_rows(file: &object::File) -> Vec<(u64, u64)> {
+    let sections = load_dwarf(file);
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian(file)));
+    let mut rows = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().unwrap() {
+        let unit = dwarf.unit(header).unwrap();
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+        let mut program_rows = program.rows();
+        while let Some((_, row)) = program_rows.next_row().unwrap() {
+            if !row.end_sequence() {
+                rows.push((row.address(), row.line().map_or(0, |line| line.get())));
+            }
+        }
+    }
+    rows
+}
+
+// the source lines code in `[start, start + size)` maps back to
+fn lines_in(rows: &[(u64, u64)], start: u64, size: u64) -> Vec<u64> {
+    let mut lines: Vec<u64> = rows
+        .iter()
+        .filter(|(address, _)| (start..start + size).contains(address))
+        .map(|&(_, line)| line)
+        .collect();
+    lines.sort();
+    lines.dedup();
+    lines
+}
+
+// (tag, name) of every named DIE, and whether it has a location
+fn entries(file: &object::File) -> Vec<(gimli::DwTag, String, bool)> {
+    let sections = load_dwarf(file);
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian(file)));
+    let mut result = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().unwrap() {
+        let unit = dwarf.unit(header).unwrap();
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            if let Some(name) = entry.attr_value(gimli::DW_AT_name).unwrap() {
+                let name = dwarf.attr_string(&unit, name).unwrap().to_string_lossy().into_owned();
+                let located = entry.attr(gimli::DW_AT_location).unwrap().is_some();
+                result.push((entry.tag(), name, located));
+            }
+        }
+    }
+    result
+}
+
+fn symbol<'a>(file: &'a object::File, name: &str) -> object::Symbol<'a, 'a> {
+    file.symbols().find(|symbol| symbol.name() == Ok(name)).unwrap()
+}
+
+#[test]
+fn test_object_line_tables() {
+    for target in ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu", "s390x-unknown-linux-gnu"] {
+        let mut codegen = CompilerBuilder::new()
+            .triple(Triple::from_str(target).unwrap())
+            .debug_info("debug.c")
+            .object_codegen("debug")
+            .unwrap();
+        codegen.compile_program(parse(SOURCE).unwrap()).unwrap();
+        let bytes = codegen.emit_object().unwrap();
+        let file = object::File::parse(&*bytes).unwrap();
+
+        let rows = line_rows(&file);
+        let add = symbol(&file, "add");
+        assert_eq!(lines_in(&rows, add.address(), add.size()), [1, 2, 3], "{}", target);
+        let main = symbol(&file, "main");
+        assert_eq!(lines_in(&rows, main.address(), main.size()), [6, 7, 8], "{}", target);
+
+        let entries = entries(&file);
+        for name in ["add", "main"] {
+            assert!(entries.contains(&(gimli::DW_TAG_subprogram, name.to_string(), false)), "{}", target);
+        }
+        for name in ["a", "b"] {
+            assert!(entries.contains(&(gimli::DW_TAG_formal_parameter, name.to_string(), true)), "{}", target);
+        }
+        for name in ["sum", "x"] {
+            assert!(entries.contains(&(gimli::DW_TAG_variable, name.to_string(), true)), "{}", target);
+        }
+    }
+}
+
+#[test]
+fn test_objects_without_debug_info() {
+    let mut codegen = CompilerBuilder::new().object_codegen("plain").unwrap();
+    codegen.compile_program(parse(SOURCE).unwrap()).unwrap();
+    let bytes = codegen.emit_object().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    assert!(file.section_by_name(".debug_line").is_none());
+
+    let err = CompilerBuilder::new()
+        .triple(Triple::from_str("x86_64-apple-darwin").unwrap())
+        .debug_info("debug.c")
+        .object_codegen("macho")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("debug info"), "{}", err);
+}
+
+// the images currently registered with GDB
+fn registered_images() -> Vec<Vec<u8>> {
+    let _guard = JIT_DEBUG_LOCK.lock().unwrap();
+    let mut images = Vec::new();
+    unsafe {
+        let descriptor = addr_of!(__jit_debug_descriptor);
+        assert_eq!((*descriptor).version, 1);
+        let mut entry = (*descriptor).first_entry;
+        while !entry.is_null() {
+            images.push(std::slice::from_raw_parts((*entry).symfile_addr, (*entry).symfile_size as usize).to_vec());
+            entry = (*entry).next;
+        }
+    }
+    images
+}
+
+#[test]
+fn test_jit_code_is_registered_with_gdb() {
+    let mut codegen = CompilerBuilder::new().debug_info("jit.c").compiler().unwrap().build();
+    codegen.compile_program(parse(SOURCE).unwrap()).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 3);
+    let main_address = codegen.get_finalized_function("main").unwrap() as u64;
+
+    // other tests may have images registered too
+    let has_main = |image: &Vec<u8>| {
+        let file = object::File::parse(&**image).unwrap();
+        file.symbols().any(|symbol| symbol.name() == Ok("main") && symbol.address() == main_address)
+    };
+    let images = registered_images();
+    let image = images.iter().find(|image| has_main(image)).expect("main is registered");
+    let file = object::File::parse(&**image).unwrap();
+    let rows = line_rows(&file);
+    let main = symbol(&file, "main");
+    assert_eq!(lines_in(&rows, main.address(), main.size()), [6, 7, 8]);
+    let add = symbol(&file, "add");
+    assert_eq!(lines_in(&rows, add.address(), add.size()), [1, 2, 3]);
+
+    drop(codegen);
+    assert!(!registered_images().iter().any(has_main));
+}
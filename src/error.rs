@@ -0,0 +1,89 @@
+use crate::ast::AstType;
+use crate::trap::TrapLocation;
+use cranelift_codegen::ir::TrapCode;
+use cranelift_module::ModuleError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CompileError {
+    #[error(transparent)]
+    Module(Box<ModuleError>),
+    #[error("unsupported target: {0}")]
+    Target(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("failed to emit object file: {0}")]
+    Object(String),
+    #[error("linking failed: {0}")]
+    Link(String),
+    #[error("extern function `{name}` is declared as {declared:?} but the host provides {registered:?}")]
+    ExternSignature {
+        name: String,
+        declared: AstType,
+        registered: AstType,
+    },
+    #[error("function `{name}` is declared as {previous:?} but redeclared as {found:?}")]
+    ConflictingDeclaration {
+        name: String,
+        previous: AstType,
+        found: AstType,
+    },
+    #[error("`{0}` is not an allowed import in the sandbox")]
+    ImportNotAllowed(String),
+    #[error("string literals don't fit in the sandbox's {0} bytes of memory")]
+    SandboxMemory(u32),
+    #[error("import `{name}` has no `char*` param {pointer} with a `size_t` length in param {length}")]
+    SandboxLength {
+        name: String,
+        pointer: usize,
+        length: usize,
+    },
+    #[error("undefined variable `{0}`")]
+    UndefinedVariable(String),
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String),
+    #[error("function `{0}` is declared but has no definition")]
+    MissingDefinition(String),
+    #[error("function `{name}` has signature {signature}, but was called as {requested}")]
+    EntrySignature {
+        name: String,
+        signature: String,
+        requested: String,
+    },
+    #[error("type mismatch: expected {expected:?}, found {found:?}")]
+    TypeMismatch { expected: AstType, found: AstType },
+    #[error("function `{name}` expects {expected} arguments, found {found}")]
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("value of type {0:?} is not callable")]
+    NotCallable(AstType),
+    #[error("`{0}` used outside of a loop")]
+    OutsideLoop(&'static str),
+    #[error("`{0}` used outside of a variadic function")]
+    OutsideVariadic(&'static str),
+    #[error("`{0}` used outside of a function")]
+    OutsideFunction(&'static str),
+    #[error("function `{0}` has no return value")]
+    VoidValue(String),
+    #[error("trap {} in {location}", crate::trap::code_name(.code))]
+    Trap { code: TrapCode, location: TrapLocation },
+    #[error("stack overflow in {0}")]
+    StackOverflow(TrapLocation),
+    #[error("out of fuel in {0}")]
+    OutOfFuel(TrapLocation),
+    #[error("host function panicked: {0}")]
+    HostPanic(String),
+    #[error("{0} is not supported yet")]
+    Unsupported(String),
+}
+
+impl From<ModuleError> for CompileError {
+    fn from(err: ModuleError) -> Self {
+        CompileError::Module(Box::new(err))
+    }
+}
+
+pub type CompileResult<T> = Result<T, CompileError>;
@@ -0,0 +1,44 @@
+use crate::error::CompileResult;
+use crate::module::ModuleType;
+use crate::trap;
+use cranelift::prelude::*;
+use cranelift_codegen::ir::GlobalValue;
+use cranelift_module::{DataDescription, FuncOrDataId, Linkage, Module};
+
+// The counter instrumented code decrements, one per module.
+const FUEL_SYMBOL: &str = "__fuel";
+
+// The fuel counter's address in a function being built, declared on first use.
+pub(crate) fn counter(module: &mut ModuleType, func: &mut codegen::ir::Function) -> CompileResult<GlobalValue> {
+    let id = match module.get_name(FUEL_SYMBOL) {
+        Some(FuncOrDataId::Data(id)) => id,
+        _ => {
+            let id = module.declare_data(FUEL_SYMBOL, Linkage::Local, true, false)?;
+            let mut data = DataDescription::new();
+            data.define_zeroinit(8);
+            module.define_data(id, &data)?;
+            id
+        }
+    };
+    Ok(module.declare_data_in_func(id, func))
+}
+
+// Takes one unit of fuel, trapping with `trap::OUT_OF_FUEL` if there's none left.
+pub(crate) fn consume(counter: GlobalValue, pointer_type: Type, builder: &mut FunctionBuilder) {
+    let address = builder.ins().global_value(pointer_type, counter);
+    let fuel = builder.ins().load(types::I64, MemFlags::trusted(), address, 0);
+    builder.ins().trapz(fuel, trap::OUT_OF_FUEL);
+    let fuel = builder.ins().iadd_imm(fuel, -1);
+    builder.ins().store(MemFlags::trusted(), fuel, address, 0);
+}
+
+// Where a finalized JIT module keeps its counter, if any of its code consumes fuel.
+pub(crate) fn address(module: &ModuleType) -> Option<*mut u64> {
+    let ModuleType::JITModule(jit) = module else {
+        return None;
+    };
+    let Some(FuncOrDataId::Data(id)) = jit.get_name(FUEL_SYMBOL) else {
+        return None;
+    };
+    Some(jit.get_finalized_data(id).0 as *mut u64)
+}
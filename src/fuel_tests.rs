@@ -0,0 +1,77 @@
+use crate::compiler::CompilerBuilder;
+use crate::test_helpers::{jit, out_of_fuel};
+
+#[test]
+fn test_infinite_loops_run_out_of_fuel() {
+    let source = "int spin() {
+    while (1) { }
+    return 0;
+}
+int count(int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) {
+        total += i;
+    }
+    return total;
+}
+";
+    let mut codegen = jit(CompilerBuilder::new().consume_fuel(true), source);
+    codegen.set_fuel(10_000);
+    let err = codegen.call::<_, i32>("spin", ()).unwrap_err();
+    assert_eq!(err.to_string(), "out of fuel in `spin` at line 2");
+    assert_eq!(codegen.fuel(), 0);
+
+    // a function with nothing left can't even start
+    let location = out_of_fuel(codegen.call::<_, i32>("count", (3,)).unwrap_err());
+    assert_eq!(location.line, Some(5));
+}
+
+#[test]
+fn test_fuel_use_is_deterministic() {
+    let source = "int count(int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) {
+        total += i;
+    }
+    return total;
+}
+int down(int n) {
+    do { n--; } while (n > 0);
+    return n;
+}
+int fib(int n) {
+    if (n < 2) { return n; }
+    return fib(n - 1) + fib(n - 2);
+}
+";
+    let mut codegen = jit(CompilerBuilder::new().consume_fuel(true), source);
+    // one for the call and one per check of the condition
+    codegen.set_fuel(100);
+    assert_eq!(codegen.call::<_, i32>("count", (10,)).unwrap(), 45);
+    assert_eq!(codegen.fuel(), 100 - 1 - 11);
+    codegen.set_fuel(100);
+    assert_eq!(codegen.call::<_, i32>("down", (5,)).unwrap(), 0);
+    assert_eq!(codegen.fuel(), 100 - 1 - 5);
+
+    // fib(n) makes 2 * fib(n + 1) - 1 calls
+    codegen.set_fuel(177);
+    assert_eq!(codegen.call::<_, i32>("fib", (10,)).unwrap(), 55);
+    assert_eq!(codegen.fuel(), 0);
+    codegen.set_fuel(176);
+    out_of_fuel(codegen.call::<_, i32>("fib", (10,)).unwrap_err());
+}
+
+#[test]
+fn test_fuel_is_opt_in() {
+    let source = "int count(int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) {
+        total += i;
+    }
+    return total;
+}
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    assert_eq!(codegen.call::<_, i32>("count", (10,)).unwrap(), 45);
+    assert_eq!(codegen.fuel(), 0);
+}
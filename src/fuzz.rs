@@ -0,0 +1,707 @@
+use crate::ast::*;
+use crate::compiler::{CompilerBuilder, OptLevel};
+use crate::error::CompileError;
+use crate::interpreter::{Interpreter, Value};
+use crate::reduce;
+use crate::trap;
+use arbitrary::{Arbitrary, Unstructured};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+// How much fuel a generated program may use. Ones the interpreter can't finish
+// with this much aren't compared.
+pub const FUEL: u64 = 100_000;
+
+pub const OPT_LEVELS: [OptLevel; 3] = [OptLevel::None, OptLevel::Speed, OptLevel::SpeedAndSize];
+
+// A generated program and how it's compiled. Its `long main()` is what's run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzCase {
+    pub program: Program,
+    pub checked: bool,
+}
+
+impl<'a> Arbitrary<'a> for FuzzCase {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let checked = u.arbitrary()?;
+        let program = Generator::new(u).program()?;
+        Ok(Self { program, checked })
+    }
+}
+
+// What running a program's `main` did. Errors, traps among them, are compared
+// by their messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Returned(i64),
+    Failed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Returned(value) => write!(f, "returned {}", value),
+            Outcome::Failed(message) => write!(f, "failed: {}", message),
+        }
+    }
+}
+
+// The JIT at `opt_level` disagreeing with the interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub opt_level: OptLevel,
+    pub expected: Outcome,
+    pub found: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the interpreter {}, but the JIT at opt level {} {}",
+            self.expected,
+            self.opt_level.as_str(),
+            self.found
+        )
+    }
+}
+
+// The interpreter's outcome, or `None` if the program runs out of fuel.
+pub fn interpret(case: &FuzzCase) -> Option<Outcome> {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_checked_arithmetic(case.checked);
+    interpreter.set_consume_fuel(true);
+    interpreter.set_fuel(FUEL);
+    let result = interpreter
+        .load_program(case.program.clone())
+        .and_then(|()| interpreter.run_main::<i64>());
+    match result {
+        Err(CompileError::OutOfFuel(_)) => None,
+        Ok(Some(Value::Int(value))) => Some(Outcome::Returned(value)),
+        Ok(value) => Some(Outcome::Failed(format!("`main` returned {:?}", value))),
+        Err(err) => Some(Outcome::Failed(err.to_string())),
+    }
+}
+
+// Compiles and runs the program with the same fuel as the interpreter, which
+// the JIT uses up at the same points. Panics in the compiler are failures too.
+pub fn run_jit(case: &FuzzCase, opt_level: OptLevel) -> Outcome {
+    let run = || {
+        let mut builder = CompilerBuilder::new();
+        builder
+            .opt_level(opt_level)
+            .checked_arithmetic(case.checked)
+            .consume_fuel(true);
+        let mut codegen = builder.compiler()?.build();
+        codegen.compile_program(case.program.clone())?;
+        codegen.set_fuel(FUEL);
+        codegen.run_main::<i64>()
+    };
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(value)) => Outcome::Returned(value),
+        Ok(Err(err)) => Outcome::Failed(err.to_string()),
+        Err(payload) => Outcome::Failed(format!("compiler panicked: {}", trap::panic_message(&*payload))),
+    }
+}
+
+// Runs `case` through the JIT at each opt level, comparing it with the
+// interpreter. Programs that run out of fuel pass.
+pub fn check(case: &FuzzCase) -> Result<(), Mismatch> {
+    let Some(expected) = interpret(case) else {
+        return Ok(());
+    };
+    for opt_level in OPT_LEVELS {
+        let found = run_jit(case, opt_level);
+        if found != expected {
+            return Err(Mismatch {
+                opt_level,
+                expected,
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+// The smallest program the reducer finds for which the JIT at the mismatch's
+// opt level still fails the same way. A JIT that returns a wrong value has to
+// keep returning a different one than an interpreter that runs to completion.
+pub fn minimize(case: &FuzzCase, mismatch: &Mismatch) -> FuzzCase {
+    let still_fails = |program: &Program| {
+        let candidate = FuzzCase {
+            program: program.clone(),
+            checked: case.checked,
+        };
+        let Some(expected) = interpret(&candidate) else {
+            return false;
+        };
+        let found = run_jit(&candidate, mismatch.opt_level);
+        if found == expected {
+            return false;
+        }
+        match (&mismatch.found, &found) {
+            (Outcome::Failed(message), Outcome::Failed(found)) => message == found,
+            (Outcome::Returned(_), Outcome::Returned(_)) => matches!(expected, Outcome::Returned(_)),
+            _ => false,
+        }
+    };
+    let program = reduce::reduce(case.program.clone(), still_fails);
+    FuzzCase {
+        program,
+        checked: case.checked,
+    }
+}
+
+// How to reproduce a minimized mismatch from the command line, with the
+// program's AST as JSON to save to `repro.json`.
+pub fn reproducer(case: &FuzzCase, mismatch: &Mismatch) -> String {
+    let json = case.program.to_json().expect("ASTs serialize");
+    format!(
+        "{}\n\ncompiler_test run{} -O {} repro.json\n\n{}",
+        mismatch,
+        if case.checked { " --checked" } else { "" },
+        mismatch.opt_level.as_str(),
+        json
+    )
+}
+
+// integer types the generator declares values of
+const INTEGER_TYPES: [AstType; 11] = [
+    AstType::I8,
+    AstType::I16,
+    AstType::I32,
+    AstType::I64,
+    AstType::U8,
+    AstType::U16,
+    AstType::U32,
+    AstType::U64,
+    AstType::Usize,
+    AstType::Isize,
+    AstType::Char,
+];
+
+const MAX_FUNCTIONS: usize = 4;
+const MAX_PARAMS: usize = 3;
+const MAX_GLOBALS: usize = 3;
+const MAX_BLOCK_LEN: usize = 6;
+const MAX_NESTING: usize = 3;
+const MAX_LOOP_NESTING: usize = 2;
+const MAX_EXPR_DEPTH: usize = 4;
+const MAX_TRIP_COUNT: i64 = 5;
+
+// What an expression being generated has to be. Integer literals take their
+// type from the context they're used in, so only `Number` may be one on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    // an integer type, whatever the context
+    Integer,
+    // an integer type or `bool`, whatever the context
+    NonFloat,
+    // any numeric type
+    Number,
+}
+
+struct Binding {
+    name: String,
+    type_: AstType,
+    // loop counters are only read
+    assignable: bool,
+}
+
+// Generates well-typed programs: every value is numeric and every conversion
+// implicit, loops count up to a small bound, functions only call the ones
+// defined before them, and integer-only operators get operands whose type is
+// an integer whatever the context. Division by zero and overflow in checked
+// arithmetic are possible, and trap in both implementations.
+struct Generator<'u, 'a> {
+    u: &'u mut Unstructured<'a>,
+    functions: Vec<FuncDecl>,
+    globals: Vec<Binding>,
+    scopes: Vec<Vec<Binding>>,
+    return_type: Option<AstType>,
+    nesting: usize,
+    loop_nesting: usize,
+    next_name: usize,
+}
+
+impl<'u, 'a> Generator<'u, 'a> {
+    fn new(u: &'u mut Unstructured<'a>) -> Self {
+        Self {
+            u,
+            functions: Vec::new(),
+            globals: Vec::new(),
+            scopes: Vec::new(),
+            return_type: None,
+            nesting: 0,
+            loop_nesting: 0,
+            next_name: 0,
+        }
+    }
+
+    fn program(mut self) -> arbitrary::Result<Program> {
+        let mut statements = Vec::new();
+        for _ in 0..self.u.int_in_range(0..=MAX_GLOBALS)? {
+            statements.push(self.global()?);
+        }
+        for index in 0..self.u.int_in_range(0..=MAX_FUNCTIONS - 1)? {
+            let return_type = if self.u.ratio(1, 5)? { None } else { Some(self.value_type()?) };
+            let mut params = Vec::new();
+            for _ in 0..self.u.int_in_range(0..=MAX_PARAMS)? {
+                params.push((self.name("p"), self.value_type()?));
+            }
+            statements.push(self.function(format!("f{}", index), params, return_type)?);
+        }
+        statements.push(self.function("main".to_string(), Vec::new(), Some(AstType::I64))?);
+        Ok(Program { statements })
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn value_type(&mut self) -> arbitrary::Result<AstType> {
+        Ok(match self.u.int_in_range(0..=14)? {
+            0..=10 => INTEGER_TYPES[self.u.choose_index(INTEGER_TYPES.len())?].clone(),
+            11 => AstType::Bool,
+            12 => AstType::F32,
+            _ => AstType::F64,
+        })
+    }
+
+    // Globals are initialized with constants, which may be negated.
+    fn global(&mut self) -> arbitrary::Result<Stmt> {
+        let type_ = self.value_type()?;
+        let literal = Expr::Literal(self.literal(&type_)?);
+        let init = match literal {
+            Expr::Literal(Literal::Int(_) | Literal::Float(_)) if self.u.arbitrary()? => Expr::Unary(Box::new(Unary {
+                op: UnaryOp::Neg,
+                expr: Box::new(literal),
+            })),
+            literal => literal,
+        };
+        let name = self.name("g");
+        self.globals.push(Binding {
+            name: name.clone(),
+            type_: type_.clone(),
+            assignable: true,
+        });
+        Ok(Stmt::VarDecl(VarDecl {
+            name,
+            type_,
+            init: Some(Box::new(init)),
+        }))
+    }
+
+    fn function(&mut self, name: String, params: Vec<(String, AstType)>, return_type: Option<AstType>) -> arbitrary::Result<Stmt> {
+        let decl = FuncDecl {
+            name,
+            params: params.clone(),
+            return_type: return_type.clone(),
+            variadic: false,
+            extern_: false,
+        };
+        self.return_type = return_type.clone();
+        let scope = params
+            .into_iter()
+            .map(|(name, type_)| Binding {
+                name,
+                type_,
+                assignable: true,
+            })
+            .collect();
+        self.scopes = vec![scope];
+        let mut body = self.block()?;
+        if return_type.is_some() {
+            let value = self.expr(Kind::Number, 0)?;
+            body.push(Stmt::Return(Return {
+                value: Some(Box::new(value)),
+            }));
+        }
+        self.scopes.clear();
+        self.functions.push(decl.clone());
+        Ok(Stmt::FuncDef(FuncDef { decl, body }))
+    }
+
+    fn block(&mut self) -> arbitrary::Result<Block> {
+        self.scopes.push(Vec::new());
+        self.nesting += 1;
+        let mut block = Vec::new();
+        let len = if self.nesting > MAX_NESTING { 0 } else { self.u.int_in_range(0..=MAX_BLOCK_LEN)? };
+        for _ in 0..len {
+            self.stmts(&mut block)?;
+        }
+        self.nesting -= 1;
+        self.scopes.pop();
+        Ok(block)
+    }
+
+    // Adds a statement, or a loop with the counter it needs.
+    fn stmts(&mut self, block: &mut Block) -> arbitrary::Result<()> {
+        let stmt = match self.u.int_in_range(0..=11)? {
+            0..=2 => {
+                let type_ = self.value_type()?;
+                let init = if self.u.ratio(1, 8)? { None } else { Some(Box::new(self.expr(Kind::Number, 0)?)) };
+                let name = self.name("v");
+                self.bind(&name, &type_, true);
+                Stmt::VarDecl(VarDecl { name, type_, init })
+            }
+            3..=5 => match self.assignable()? {
+                Some(target) => {
+                    let value = self.expr(Kind::Number, 0)?;
+                    Stmt::Assign(Assign {
+                        target,
+                        value: Box::new(value),
+                    })
+                }
+                None => return Ok(()),
+            },
+            6 | 7 => {
+                let condition = self.expr(Kind::Number, 0)?;
+                let then_branch = self.block()?;
+                let else_branch = if self.u.arbitrary()? { Some(self.block()?) } else { None };
+                Stmt::If(IfStmt {
+                    condition: Box::new(condition),
+                    then_branch,
+                    else_branch,
+                })
+            }
+            8 | 9 if self.loop_nesting < MAX_LOOP_NESTING => return self.counted_loop(block),
+            10 => match self.call_returning(|_| true)? {
+                Some(call) => Stmt::FuncCall(call),
+                None => return Ok(()),
+            },
+            _ => {
+                // leaving early, from a loop or the function
+                let condition = self.expr(Kind::Number, 0)?;
+                let exit = match self.u.int_in_range(0..=2)? {
+                    0 if self.loop_nesting > 0 => Stmt::Break,
+                    1 if self.loop_nesting > 0 => Stmt::Continue,
+                    _ => {
+                        let value = match self.return_type.clone() {
+                            Some(_) => Some(Box::new(self.expr(Kind::Number, 0)?)),
+                            None => None,
+                        };
+                        Stmt::Return(Return { value })
+                    }
+                };
+                Stmt::If(IfStmt {
+                    condition: Box::new(condition),
+                    then_branch: vec![exit],
+                    else_branch: None,
+                })
+            }
+        };
+        block.push(stmt);
+        Ok(())
+    }
+
+    // `for`, `while` or `do`-`while` over a counter from 0 to a small bound,
+    // incremented before anything in the body can `continue`.
+    fn counted_loop(&mut self, block: &mut Block) -> arbitrary::Result<()> {
+        let counter = self.name("i");
+        let variable = Expr::Variable(Variable_ {
+            name: counter.clone(),
+            type_: AstType::I32,
+        });
+        let condition = Box::new(binary(
+            BinaryOp::Lt,
+            variable.clone(),
+            Expr::Literal(Literal::Int(self.u.int_in_range(0..=MAX_TRIP_COUNT)?)),
+        ));
+        let declaration = Stmt::VarDecl(VarDecl {
+            name: counter.clone(),
+            type_: AstType::I32,
+            init: Some(Box::new(Expr::Literal(Literal::Int(0)))),
+        });
+        let increment = Stmt::Assign(Assign {
+            target: Variable_ {
+                name: counter.clone(),
+                type_: AstType::I32,
+            },
+            value: Box::new(binary(BinaryOp::Add, variable, Expr::Literal(Literal::Int(1)))),
+        });
+
+        self.loop_nesting += 1;
+        // a `for` counter is scoped to the loop, the others to the enclosing block
+        let for_loop = self.u.arbitrary()?;
+        if for_loop {
+            self.scopes.push(Vec::new());
+        }
+        self.bind(&counter, &AstType::I32, false);
+        let body = self.block();
+        if for_loop {
+            self.scopes.pop();
+        }
+        self.loop_nesting -= 1;
+        let mut body = body?;
+
+        let loop_stmt = if for_loop {
+            LoopStmt {
+                kind: LoopKind::For {
+                    init: Some(Box::new(declaration)),
+                    step: Some(Box::new(increment)),
+                },
+                condition,
+                body,
+            }
+        } else {
+            block.push(declaration);
+            body.insert(0, increment);
+            let kind = if self.u.arbitrary()? { LoopKind::While } else { LoopKind::DoWhile };
+            LoopStmt { kind, condition, body }
+        };
+        block.push(Stmt::Loop(loop_stmt));
+        Ok(())
+    }
+
+    fn bind(&mut self, name: &str, type_: &AstType, assignable: bool) {
+        let scope = self.scopes.last_mut().expect("statements are generated in functions");
+        scope.push(Binding {
+            name: name.to_string(),
+            type_: type_.clone(),
+            assignable,
+        });
+    }
+
+    fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        self.scopes.iter().flatten().chain(&self.globals)
+    }
+
+    fn assignable(&mut self) -> arbitrary::Result<Option<Variable_>> {
+        let candidates: Vec<Variable_> = self
+            .bindings()
+            .filter(|binding| binding.assignable)
+            .map(|binding| Variable_ {
+                name: binding.name.clone(),
+                type_: binding.type_.clone(),
+            })
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(candidates[self.u.choose_index(candidates.len())?].clone()))
+    }
+
+    // A variable of a type `kind` allows, if there is one.
+    fn variable(&mut self, kind: Kind) -> arbitrary::Result<Option<Expr>> {
+        let candidates: Vec<Expr> = self
+            .bindings()
+            .filter(|binding| allows(kind, &binding.type_))
+            .map(|binding| {
+                Expr::Variable(Variable_ {
+                    name: binding.name.clone(),
+                    type_: binding.type_.clone(),
+                })
+            })
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(candidates[self.u.choose_index(candidates.len())?].clone()))
+    }
+
+    // A call to one of the functions defined so far whose return type passes
+    // `returns`, with any numeric arguments.
+    fn call_returning(&mut self, returns: impl Fn(Option<&AstType>) -> bool) -> arbitrary::Result<Option<FuncCall>> {
+        let candidates: Vec<FuncDecl> = self
+            .functions
+            .iter()
+            .filter(|decl| returns(decl.return_type.as_ref()))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let decl = &candidates[self.u.choose_index(candidates.len())?];
+        let mut args = Vec::new();
+        for _ in &decl.params {
+            args.push(self.expr(Kind::Number, MAX_EXPR_DEPTH - 1)?);
+        }
+        Ok(Some(FuncCall {
+            name: decl.name.clone(),
+            args,
+        }))
+    }
+
+    fn expr(&mut self, kind: Kind, depth: usize) -> arbitrary::Result<Expr> {
+        if depth >= MAX_EXPR_DEPTH || self.u.ratio(1, 3)? {
+            return self.leaf(kind);
+        }
+        let depth = depth + 1;
+        let expr = match (kind, self.u.int_in_range(0..=9)?) {
+            (_, 0..=2) => {
+                const OPS: [BinaryOp; 5] = [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div, BinaryOp::Mod];
+                let op = OPS[self.u.choose_index(OPS.len())?].clone();
+                match kind {
+                    // `%` isn't defined for floats
+                    Kind::Number if op != BinaryOp::Mod => {
+                        let left = self.expr(Kind::Number, depth)?;
+                        let right = self.divisor(&op, Kind::Number, depth)?;
+                        binary(op, left, right)
+                    }
+                    _ => self.integer_binary(op, kind, depth)?,
+                }
+            }
+            (Kind::Number, 3) | (Kind::NonFloat, 3) => {
+                // comparisons ignore the context, so any operands do
+                const OPS: [BinaryOp; 6] = [BinaryOp::Eq, BinaryOp::Ne, BinaryOp::Lt, BinaryOp::Le, BinaryOp::Gt, BinaryOp::Ge];
+                let op = OPS[self.u.choose_index(OPS.len())?].clone();
+                binary(op, self.expr(Kind::Number, depth)?, self.expr(Kind::Number, depth)?)
+            }
+            (_, 4) => {
+                const OPS: [BinaryOp; 5] = [BinaryOp::BitAnd, BinaryOp::BitOr, BinaryOp::BitXor, BinaryOp::Shl, BinaryOp::Shr];
+                let op = OPS[self.u.choose_index(OPS.len())?].clone();
+                // shifts take integers, not `bool`s
+                let operands = if matches!(op, BinaryOp::Shl | BinaryOp::Shr) { Kind::Integer } else { Kind::NonFloat };
+                let operands = if kind == Kind::Integer { Kind::Integer } else { operands };
+                self.integer_binary(op, operands, depth)?
+            }
+            (_, 5) => Expr::Unary(Box::new(Unary {
+                op: UnaryOp::Neg,
+                expr: Box::new(self.expr(kind, depth)?),
+            })),
+            (Kind::Number | Kind::NonFloat, 6) => Expr::Unary(Box::new(Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(self.expr(Kind::Number, depth)?),
+            })),
+            (_, 7) => {
+                const NAMES: [&str; 8] = [
+                    "wrapping_add",
+                    "wrapping_sub",
+                    "wrapping_mul",
+                    "wrapping_neg",
+                    "checked_add",
+                    "checked_sub",
+                    "checked_mul",
+                    "checked_neg",
+                ];
+                let name = NAMES[self.u.choose_index(NAMES.len())?];
+                // the operands get no context, so literals are `int`s
+                let mut args = vec![self.integer_or_literal(depth)?];
+                if !name.ends_with("neg") {
+                    args.push(self.integer_or_literal(depth)?);
+                }
+                Expr::FuncCall(FuncCall {
+                    name: name.to_string(),
+                    args,
+                })
+            }
+            _ => match self.call_returning(|return_type| return_type.is_some_and(|type_| allows(kind, type_)))? {
+                Some(call) => Expr::FuncCall(call),
+                None => self.leaf(kind)?,
+            },
+        };
+        Ok(expr)
+    }
+
+    // An operator of integers or `bool`s: at least one operand has to be of
+    // `kind` for a literal on the other side to take its type.
+    fn integer_binary(&mut self, op: BinaryOp, kind: Kind, depth: usize) -> arbitrary::Result<Expr> {
+        let operand = if kind == Kind::Number { Kind::NonFloat } else { kind };
+        let (left, right) = match self.u.int_in_range(0..=2)? {
+            0 => (self.expr(operand, depth)?, self.expr(operand, depth)?),
+            1 => (self.expr(operand, depth)?, Expr::Literal(self.int_literal(&op)?)),
+            _ => {
+                let right = self.expr(operand, depth)?;
+                let left = Expr::Literal(self.int_literal(&BinaryOp::Add)?);
+                // two literals are compiled left first, so the int one would take the outer hint
+                if matches!(right, Expr::Literal(_)) {
+                    (right, left)
+                } else {
+                    (left, right)
+                }
+            }
+        };
+        // a literal divisor isn't zero, but a computed one can be
+        let right = match (&op, right) {
+            (BinaryOp::Div | BinaryOp::Mod, Expr::Literal(Literal::Int(0))) => Expr::Literal(Literal::Int(1)),
+            (_, right) => right,
+        };
+        Ok(binary(op, left, right))
+    }
+
+    fn divisor(&mut self, op: &BinaryOp, kind: Kind, depth: usize) -> arbitrary::Result<Expr> {
+        let expr = self.expr(kind, depth)?;
+        Ok(match (op, expr) {
+            (BinaryOp::Div | BinaryOp::Mod, Expr::Literal(Literal::Int(0))) => Expr::Literal(Literal::Int(1)),
+            (_, expr) => expr,
+        })
+    }
+
+    fn integer_or_literal(&mut self, depth: usize) -> arbitrary::Result<Expr> {
+        if self.u.arbitrary()? {
+            Ok(Expr::Literal(self.int_literal(&BinaryOp::Add)?))
+        } else {
+            self.expr(Kind::Integer, depth)
+        }
+    }
+
+    fn leaf(&mut self, kind: Kind) -> arbitrary::Result<Expr> {
+        let choice = self.u.int_in_range(0..=3)?;
+        if choice < 2 {
+            if let Some(variable) = self.variable(kind)? {
+                return Ok(variable);
+            }
+        }
+        Ok(match kind {
+            Kind::Number if choice == 2 => {
+                let type_ = self.value_type()?;
+                Expr::Literal(self.literal(&type_)?)
+            }
+            Kind::Number => Expr::Literal(self.int_literal(&BinaryOp::Add)?),
+            Kind::NonFloat if choice == 2 => Expr::Literal(Literal::Bool(self.u.arbitrary()?)),
+            // `char` literals are `char`s in any context
+            _ => Expr::Literal(Literal::Char(char::from(self.u.int_in_range(32..=126)?))),
+        })
+    }
+
+    // A literal of `type_`'s kind, its value sometimes at the edges of the range.
+    fn literal(&mut self, type_: &AstType) -> arbitrary::Result<Literal> {
+        Ok(match type_ {
+            AstType::F32 | AstType::F64 => {
+                const SPECIAL: [f64; 6] = [0.0, -0.5, 1.5, 1e10, -3e38, 255.75];
+                if self.u.arbitrary()? {
+                    Literal::Float(SPECIAL[self.u.choose_index(SPECIAL.len())?])
+                } else {
+                    Literal::Float(f64::from(self.u.arbitrary::<i16>()?) / 8.0)
+                }
+            }
+            AstType::Bool => Literal::Bool(self.u.arbitrary()?),
+            _ => self.int_literal(&BinaryOp::Add)?,
+        })
+    }
+
+    fn int_literal(&mut self, op: &BinaryOp) -> arbitrary::Result<Literal> {
+        const EDGES: [i64; 8] = [0, 1, -1, 127, 128, 255, i32::MAX as i64, i64::MIN];
+        let value = match self.u.int_in_range(0..=3)? {
+            0 => EDGES[self.u.choose_index(EDGES.len())?],
+            1 => self.u.arbitrary()?,
+            _ => self.u.int_in_range(-20..=20)?,
+        };
+        // shift amounts beyond the width are masked, which is fine, but keep
+        // most of them meaningful
+        let value = match op {
+            BinaryOp::Shl | BinaryOp::Shr => value.rem_euclid(70),
+            _ => value,
+        };
+        Ok(Literal::Int(value))
+    }
+}
+
+// whether a value of `type_` can be used where `kind` is needed
+fn allows(kind: Kind, type_: &AstType) -> bool {
+    match kind {
+        Kind::Integer => type_.is_integer(),
+        Kind::NonFloat => type_.is_integer() || *type_ == AstType::Bool,
+        Kind::Number => type_.is_numeric(),
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(Box::new(Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }))
+}
@@ -0,0 +1,97 @@
+use crate::ast::*;
+use crate::compiler::{Compiler, OptLevel};
+use crate::fuzz::{self, FuzzCase, Mismatch, Outcome};
+use crate::parser::parse;
+use crate::reduce;
+use arbitrary::{Arbitrary, Unstructured};
+use proptest::prelude::*;
+
+fn case(data: &[u8]) -> Option<FuzzCase> {
+    FuzzCase::arbitrary(&mut Unstructured::new(data)).ok()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 64, failure_persistence: None, ..ProptestConfig::default() })]
+
+    #[test]
+    fn test_jit_matches_interpreter(data in proptest::collection::vec(any::<u8>(), 256..4096)) {
+        let Some(case) = case(&data) else {
+            return Ok(());
+        };
+        if let Err(mismatch) = fuzz::check(&case) {
+            let minimized = fuzz::minimize(&case, &mismatch);
+            panic!("{}", fuzz::reproducer(&minimized, &mismatch));
+        }
+    }
+}
+
+#[test]
+fn test_generated_programs_compile() {
+    let mut compiled = 0;
+    for seed in 0..200u32 {
+        let data: Vec<u8> = (0..2048u32).map(|i| (i.wrapping_mul(seed * 2 + 1) ^ (i >> 3) ^ seed) as u8).collect();
+        let Some(case) = case(&data) else {
+            continue;
+        };
+        let mut codegen = Compiler::new().unwrap().build();
+        if let Err(err) = codegen.compile_program(case.program.clone()) {
+            panic!("{}\n\n{}", err, case.program.to_json().unwrap());
+        }
+        compiled += 1;
+    }
+    assert!(compiled > 100, "only {} programs were generated", compiled);
+}
+
+#[test]
+fn test_reduce_keeps_what_is_interesting() {
+    let source = "
+int unused(int x) { return x * 2; }
+long main() {
+    long total = 0;
+    for (int i = 0; i < 3; i++) {
+        if (i == 1) { total = total + 7 / (i - 1); } else { total = total + i; }
+    }
+    return total;
+}
+";
+    let program = parse(source).unwrap();
+    let case = FuzzCase { program, checked: false };
+    let expected = fuzz::interpret(&case).unwrap();
+    assert_eq!(expected, Outcome::Failed("trap int_divz in `main` at line 6".to_string()));
+
+    let reduced = reduce::reduce(case.program.clone(), |program| {
+        let case = FuzzCase { program: program.clone(), checked: false };
+        fuzz::interpret(&case) == Some(expected.clone())
+    });
+    // the division by zero is left, its operands simplified, with the declaration it assigns to
+    let Stmt::FuncDef(main) = reduced.statements[0].unspanned() else {
+        panic!("expected `main`, got {:?}", reduced.statements);
+    };
+    assert_eq!(reduced.statements.len(), 1);
+    assert_eq!(main.body.len(), 2, "{:?}", main.body);
+    assert!(matches!(main.body[0].unspanned(), Stmt::VarDecl(decl) if decl.name == "total"));
+    let Stmt::Assign(assign) = main.body[1].unspanned() else {
+        panic!("expected an assignment, got {:?}", main.body[1]);
+    };
+    let zero = Box::new(Expr::Literal(Literal::Int(0)));
+    let division = Binary { op: BinaryOp::Div, left: zero.clone(), right: zero };
+    assert_eq!(*assign.value, Expr::Binary(Box::new(division)));
+}
+
+#[test]
+fn test_reproducer_round_trips() {
+    let program = parse("long main() { return 1; }").unwrap();
+    let case = FuzzCase { program, checked: true };
+    let mismatch = Mismatch {
+        opt_level: OptLevel::Speed,
+        expected: Outcome::Returned(1),
+        found: Outcome::Returned(2),
+    };
+    let reproducer = fuzz::reproducer(&case, &mismatch);
+    assert!(reproducer.starts_with(
+        "the interpreter returned 1, but the JIT at opt level speed returned 2\n\ncompiler_test run --checked -O speed repro.json\n\n"
+    ), "{}", reproducer);
+    let json = reproducer.split("\n\n").nth(2).unwrap();
+    assert_eq!(Program::from_json(json).unwrap(), case.program);
+    assert_eq!(fuzz::check(&case), Ok(()));
+}
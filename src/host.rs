@@ -0,0 +1,197 @@
+use crate::ast::AstType;
+use crate::codegen::clif_type;
+use crate::error::CompileResult;
+use cranelift_codegen::ir::{AbiParam, Signature, Type};
+use cranelift_codegen::isa::CallConv;
+use target_lexicon::Triple;
+
+// Rust types that can cross the boundary between JIT code and the host, and the
+// AstType each one corresponds to.
+pub trait HostType {
+    // What a function returning this type is called as, since a `bool` can't
+    // be trusted to hold only 0 or 1.
+    type Raw;
+
+    fn ast_type() -> AstType;
+    fn from_raw(raw: Self::Raw) -> Self;
+}
+
+macro_rules! impl_host_type {
+    ($($rust:ty => $ast:expr),* $(,)?) => {
+        $(impl HostType for $rust {
+            type Raw = Self;
+
+            fn ast_type() -> AstType {
+                $ast
+            }
+
+            fn from_raw(raw: Self) -> Self {
+                raw
+            }
+        })*
+    };
+}
+
+impl HostType for bool {
+    type Raw = u8;
+
+    fn ast_type() -> AstType {
+        AstType::Bool
+    }
+
+    fn from_raw(raw: u8) -> Self {
+        raw != 0
+    }
+}
+
+impl_host_type! {
+    i8 => AstType::I8,
+    i16 => AstType::I16,
+    i32 => AstType::I32,
+    i64 => AstType::I64,
+    u8 => AstType::U8,
+    u16 => AstType::U16,
+    u32 => AstType::U32,
+    u64 => AstType::U64,
+    usize => AstType::Usize,
+    isize => AstType::Isize,
+    f32 => AstType::F32,
+    f64 => AstType::F64,
+    // C strings and byte buffers
+    *const u8 => AstType::String,
+    *mut u8 => AstType::String,
+    *const std::ffi::c_char => AstType::String,
+    *mut std::ffi::c_char => AstType::String,
+}
+
+// A return type: any HostType, or `()` for functions without a return value.
+pub trait HostReturn {
+    type Raw;
+
+    fn return_type() -> Option<AstType>;
+    fn from_raw(raw: Self::Raw) -> Self;
+}
+
+impl HostReturn for () {
+    type Raw = ();
+
+    fn return_type() -> Option<AstType> {
+        None
+    }
+
+    fn from_raw(_raw: ()) -> Self {}
+}
+
+impl<T: HostType> HostReturn for T {
+    type Raw = T::Raw;
+
+    fn return_type() -> Option<AstType> {
+        Some(T::ast_type())
+    }
+
+    fn from_raw(raw: T::Raw) -> Self {
+        T::from_raw(raw)
+    }
+}
+
+// `extern "C"` function pointers that compiled code can call. A panic in an
+// `extern "C-unwind"` one unwinds through the compiled code and is returned by
+// `ModuleType::call` as `CompileError::HostPanic`; plain `extern "C"` ones abort
+// the process instead.
+pub trait HostFunction: Copy {
+    fn func_ptr_type() -> AstType;
+    fn as_ptr(self) -> *const u8;
+}
+
+macro_rules! impl_host_function {
+    ($($arg:ident),*) => {
+        impl_host_function!(@abi "C" $($arg),*);
+        impl_host_function!(@abi "C-unwind" $($arg),*);
+    };
+    (@abi $abi:literal $($arg:ident),*) => {
+        impl<R: HostReturn, $($arg: HostType),*> HostFunction for extern $abi fn($($arg),*) -> R {
+            fn func_ptr_type() -> AstType {
+                AstType::FuncPtr {
+                    params: vec![$($arg::ast_type()),*],
+                    return_type: R::return_type().map(Box::new),
+                }
+            }
+
+            fn as_ptr(self) -> *const u8 {
+                self as *const u8
+            }
+        }
+    };
+}
+
+impl_host_function!();
+impl_host_function!(A);
+impl_host_function!(A, B);
+impl_host_function!(A, B, C);
+impl_host_function!(A, B, C, D);
+impl_host_function!(A, B, C, D, E);
+impl_host_function!(A, B, C, D, E, F);
+
+// Argument lists for calling into JIT code: tuples of HostTypes.
+pub trait HostArgs {
+    fn param_types() -> Vec<AstType>;
+
+    /// # Safety
+    /// `ptr` has to be an `extern "C"` function taking exactly these arguments
+    /// and returning `R`. It may unwind, if what it calls does.
+    unsafe fn invoke<R: HostReturn>(self, ptr: *const u8) -> R;
+}
+
+macro_rules! impl_host_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: HostType),*> HostArgs for ($($arg,)*) {
+            fn param_types() -> Vec<AstType> {
+                vec![$($arg::ast_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            unsafe fn invoke<R: HostReturn>(self, ptr: *const u8) -> R {
+                let ($($arg,)*) = self;
+                let func: extern "C-unwind" fn($($arg),*) -> R::Raw = std::mem::transmute(ptr);
+                R::from_raw(func($($arg),*))
+            }
+        }
+    };
+}
+
+impl_host_args!();
+impl_host_args!(A);
+impl_host_args!(A, B);
+impl_host_args!(A, B, C);
+impl_host_args!(A, B, C, D);
+impl_host_args!(A, B, C, D, E);
+impl_host_args!(A, B, C, D, E, F);
+
+// The signature an `extern "C" fn(A) -> R` has on the host, with params and
+// returns lowered the same way `Codegen` lowers them.
+pub fn host_signature<A: HostArgs, R: HostReturn>(pointer_type: Type) -> CompileResult<Signature> {
+    let mut sig = Signature::new(CallConv::triple_default(&Triple::host()));
+    for type_ in A::param_types() {
+        sig.params.push(AbiParam::new(clif_type(&type_, pointer_type)?));
+    }
+    if let Some(type_) = R::return_type() {
+        sig.returns.push(AbiParam::new(clif_type(&type_, pointer_type)?));
+    }
+    Ok(sig)
+}
+
+// Whether the JIT's fallback lookup, through the libraries loaded into the
+// process, finds `name`.
+#[cfg(unix)]
+pub(crate) fn dynamic_symbol_exists(name: &str) -> bool {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return false;
+    };
+    unsafe { !libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()).is_null() }
+}
+
+// Not checked here; cranelift-jit still panics on symbols it can't find.
+#[cfg(not(unix))]
+pub(crate) fn dynamic_symbol_exists(_name: &str) -> bool {
+    true
+}
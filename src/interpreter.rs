@@ -0,0 +1,1371 @@
+use crate::ast::*;
+use crate::codegen::{arithmetic_intrinsic, clif_type, common_type, host_compatible, promoted, Overflow};
+use crate::error::{CompileError, CompileResult};
+use crate::host::HostReturn;
+use crate::stack;
+use crate::trap::{self, TrapLocation};
+use crate::variadic;
+use cranelift_codegen::ir::{Type, TrapCode};
+use cranelift_module::ModuleError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::{Add, Div, Mul, Sub};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+// A value of the interpreted program. Which `AstType` it has is tracked next
+// to it, as in `Codegen`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    // integers, `bool` and `char`: the bits of the type's width, sign-extended
+    // for signed types and zero-extended for the others
+    Int(i64),
+    F32(f32),
+    F64(f64),
+    // string literals compare by identity, like the pointers they compile to;
+    // `None` is a null pointer
+    String(Option<Rc<str>>),
+    FuncPtr(Option<Rc<str>>),
+    VaList(Rc<RefCell<VaList>>),
+}
+
+// What `va_arg` takes from: the promoted variadic arguments, read integers
+// and floats separately, as from the SysV register save area.
+#[derive(Debug, Default, PartialEq)]
+pub struct VaList {
+    integers: Vec<(Value, AstType)>,
+    floats: Vec<(Value, AstType)>,
+}
+
+type HostFunction = Box<dyn FnMut(&[Value]) -> Option<Value>>;
+
+// Evaluates an `ast::Program` directly, with the integer widths, wrapping,
+// conversions and traps of the code `Codegen` compiles it to. It's the oracle
+// the JIT is tested against, and runs programs where generating code isn't
+// allowed. Runtime errors are the ones the JIT reports, with the line of the
+// statement that was running.
+pub struct Interpreter {
+    functions: HashMap<String, FunctionEntry>,
+    globals: HashMap<String, Variable>,
+    // the order functions and globals were first declared in
+    declared: HashMap<String, usize>,
+    host_functions: HashMap<String, HostFunction>,
+    overflow: Overflow,
+    consume_fuel: bool,
+    fuel: u64,
+    max_call_depth: Option<usize>,
+    // the stack pointer below which evaluating more is a stack overflow
+    stack_floor: usize,
+    // source line of the top-level item being loaded
+    line: u32,
+    frames: Vec<Frame>,
+    // each string literal in the program evaluates to the same string every
+    // time, keyed by its address in the AST
+    strings: HashMap<usize, Rc<str>>,
+}
+
+struct FunctionEntry {
+    // the first declaration, which calls are checked against
+    decl: FuncDecl,
+    def: Option<Rc<FuncDef>>,
+    line: u32,
+    // how many functions and globals were declared before the definition;
+    // like `Codegen`, which compiles it right then, its body sees only those
+    visible: usize,
+}
+
+#[derive(Clone)]
+struct Variable {
+    value: Value,
+    type_: AstType,
+}
+
+struct Frame {
+    function: String,
+    // of the statement being run
+    line: u32,
+    scopes: Vec<HashMap<String, Variable>>,
+    return_type: Option<AstType>,
+    // the promoted extra arguments of a call to a variadic function
+    varargs: Option<Vec<(Value, AstType)>>,
+}
+
+// how a statement finished
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Option<Value>),
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            declared: HashMap::new(),
+            host_functions: HashMap::new(),
+            overflow: Overflow::Wrap,
+            consume_fuel: false,
+            fuel: 0,
+            max_call_depth: None,
+            stack_floor: 0,
+            line: 0,
+            frames: Vec::new(),
+            strings: HashMap::new(),
+        }
+    }
+
+    // Binds `extern` declarations of `name` to `function`, which gets the
+    // arguments converted to the declared param types, then the promoted
+    // variadic ones.
+    pub fn register_function(&mut self, name: &str, function: impl FnMut(&[Value]) -> Option<Value> + 'static) -> &mut Self {
+        self.host_functions.insert(name.to_string(), Box::new(function));
+        self
+    }
+
+    // `printf`, `puts` and `putchar`, writing to stdout.
+    pub fn register_stdio(&mut self) -> &mut Self {
+        let write = |bytes: &[u8]| {
+            let mut stdout = std::io::stdout().lock();
+            let written = stdout.write_all(bytes).and_then(|()| stdout.flush()).is_ok();
+            if written { bytes.len() as i64 } else { -1 }
+        };
+        self.register_function("printf", move |args| {
+            let text = format_printf(args);
+            Some(Value::Int(write(text.as_bytes())))
+        });
+        self.register_function("puts", move |args| {
+            let mut text = string_arg(args.first()).into_bytes();
+            text.push(b'\n');
+            Some(Value::Int(write(&text).min(0)))
+        });
+        self.register_function("putchar", move |args| {
+            let byte = match args.first() {
+                Some(Value::Int(value)) => *value as u8,
+                _ => 0,
+            };
+            Some(Value::Int(if write(&[byte]) < 0 { -1 } else { byte as i64 }))
+        })
+    }
+
+    // `Codegen::set_checked_arithmetic`
+    pub fn set_checked_arithmetic(&mut self, checked: bool) {
+        self.overflow = if checked { Overflow::Trap } else { Overflow::Wrap };
+    }
+
+    // `Codegen::set_consume_fuel`, taking fuel at the same points.
+    pub fn set_consume_fuel(&mut self, consume: bool) {
+        self.consume_fuel = consume;
+    }
+
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> u64 {
+        self.fuel
+    }
+
+    // Calls nested deeper than this are a stack overflow. Without a limit,
+    // only running low on the host thread's stack is.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = Some(depth);
+    }
+
+    // Declares the program's functions and globals, like `Codegen::compile_program`.
+    pub fn load_program(&mut self, program: Program) -> CompileResult<()> {
+        for stmt in program.statements {
+            self.load_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    // Calls a function of the program. Arguments are converted to its param
+    // types, as `Value::Int`s of those types.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> CompileResult<Option<Value>> {
+        let entry = self
+            .functions
+            .get(name)
+            .ok_or_else(|| CompileError::UndefinedFunction(name.to_string()))?;
+        if entry.def.is_none() {
+            return Err(CompileError::MissingDefinition(name.to_string()));
+        }
+        let params: Vec<AstType> = entry.decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        if params.len() != args.len() {
+            return Err(CompileError::ArgumentCount {
+                name: name.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+        let args = args
+            .iter()
+            .zip(&params)
+            .map(|(value, type_)| match value {
+                Value::Int(value) if is_int(type_) => Ok(Value::Int(wrap(*value, type_))),
+                value if has_type(value, type_) => Ok(value.clone()),
+                _ => Err(CompileError::TypeMismatch {
+                    expected: type_.clone(),
+                    found: value_type(value),
+                }),
+            })
+            .collect::<CompileResult<Vec<_>>>()?;
+
+        let depth = self.frames.len();
+        if depth == 0 {
+            self.stack_floor = stack::stack_end().map_or(0, |end| end + stack::RESERVED_STACK);
+        }
+        let result = self.call_function(name, args, None);
+        // an error leaves the frames it unwound through
+        self.frames.truncate(depth);
+        result
+    }
+
+    // Calls `main`, which has to take no arguments and return what `R` would
+    // be for `Codegen::run_main`.
+    pub fn run_main<R: HostReturn>(&mut self) -> CompileResult<Option<Value>> {
+        if let Some(entry) = self.functions.get("main") {
+            let declared = entry.decl.func_ptr_type();
+            let requested = AstType::FuncPtr {
+                params: Vec::new(),
+                return_type: R::return_type().map(Box::new),
+            };
+            if !host_compatible(&requested, &declared, &|ty| clif_type(ty, pointer_type())) {
+                return Err(CompileError::EntrySignature {
+                    name: "main".to_string(),
+                    signature: format!("{:?}", declared),
+                    requested: format!("{:?}", requested),
+                });
+            }
+        }
+        self.call("main", &[])
+    }
+
+    fn load_stmt(&mut self, stmt: Stmt) -> CompileResult<()> {
+        match stmt {
+            Stmt::Spanned(span, stmt) => {
+                self.line = span.line;
+                self.load_stmt(*stmt)
+            }
+            Stmt::FuncDecl(func_decl) => self.declare_function(func_decl),
+            Stmt::FuncDef(func_def) => self.define_function(func_def),
+            Stmt::VarDecl(var_decl) => self.define_global(var_decl),
+            Stmt::If(_) => Err(CompileError::OutsideFunction("if")),
+            Stmt::Loop(_) => Err(CompileError::OutsideFunction("loop")),
+            Stmt::Assign(_) => Err(CompileError::OutsideFunction("assignment")),
+            _ => Err(CompileError::Unsupported(format!("top-level {:?}", stmt))),
+        }
+    }
+
+    // The checks of `Codegen::declare_function`.
+    fn declare_function(&mut self, func_decl: FuncDecl) -> CompileResult<()> {
+        if let Some(entry) = self.functions.get(&func_decl.name) {
+            let previous = entry.decl.func_ptr_type();
+            let found = func_decl.func_ptr_type();
+            if previous != found || entry.decl.variadic != func_decl.variadic {
+                return Err(CompileError::ConflictingDeclaration {
+                    name: func_decl.name,
+                    previous,
+                    found,
+                });
+            }
+            return Ok(());
+        }
+        if self.globals.contains_key(&func_decl.name) {
+            return Err(ModuleError::IncompatibleDeclaration(func_decl.name).into());
+        }
+        self.declared.insert(func_decl.name.clone(), self.declared.len());
+        self.functions.insert(
+            func_decl.name.clone(),
+            FunctionEntry {
+                decl: func_decl,
+                def: None,
+                line: self.line,
+                visible: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn define_function(&mut self, func_def: FuncDef) -> CompileResult<()> {
+        self.declare_function(func_def.decl.clone())?;
+        let visible = self.declared.len();
+        let entry = self.functions.get_mut(&func_def.decl.name).expect("just declared");
+        if entry.def.is_some() {
+            return Err(ModuleError::DuplicateDefinition(func_def.decl.name).into());
+        }
+        entry.def = Some(Rc::new(func_def));
+        entry.line = self.line;
+        entry.visible = visible;
+        Ok(())
+    }
+
+    // Whether the running function can refer to the function or global `name`.
+    fn is_visible(&self, name: &str) -> bool {
+        let Some(frame) = self.frames.last() else {
+            return true;
+        };
+        let visible = self.functions[&frame.function].visible;
+        self.declared.get(name).is_some_and(|order| *order < visible)
+    }
+
+    // Global initializers are constants, converted as `Codegen` writes them
+    // into the global's data.
+    fn define_global(&mut self, var_decl: VarDecl) -> CompileResult<()> {
+        if self.globals.contains_key(&var_decl.name) {
+            return Err(ModuleError::DuplicateDefinition(var_decl.name).into());
+        }
+        if self.functions.contains_key(&var_decl.name) {
+            return Err(ModuleError::IncompatibleDeclaration(var_decl.name).into());
+        }
+        let type_ = var_decl.type_.clone();
+        let non_constant = || CompileError::Unsupported(format!("non-constant initializer for global `{}`", var_decl.name));
+        let value = match &var_decl.init {
+            None => zero_value(&type_)?,
+            Some(init) => {
+                let literal = match &**init {
+                    Expr::Unary(unary) if unary.op == UnaryOp::Neg => match &*unary.expr {
+                        Expr::Literal(Literal::Int(value)) => Some(Literal::Int(value.wrapping_neg())),
+                        Expr::Literal(Literal::Float(value)) => Some(Literal::Float(-value)),
+                        _ => None,
+                    },
+                    Expr::Literal(literal) => Some(literal.clone()),
+                    _ => None,
+                };
+                match (literal, &type_) {
+                    (Some(Literal::String(value)), AstType::String) => Value::String(Some(value.into())),
+                    (None, AstType::FuncPtr { .. }) => {
+                        let Expr::FuncAddr(name) = &**init else {
+                            return Err(non_constant());
+                        };
+                        let (value, found) = self.func_addr(name)?;
+                        if found != type_ {
+                            return Err(CompileError::TypeMismatch { expected: type_, found });
+                        }
+                        value
+                    }
+                    (Some(literal), type_) if type_.is_numeric() => constant(&literal, type_).ok_or_else(non_constant)?,
+                    _ => return Err(non_constant()),
+                }
+            }
+        };
+        self.declared.insert(var_decl.name.clone(), self.declared.len());
+        self.globals.insert(var_decl.name, Variable { value, type_ });
+        Ok(())
+    }
+
+    fn call_function(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        varargs: Option<Vec<(Value, AstType)>>,
+    ) -> CompileResult<Option<Value>> {
+        let entry = &self.functions[name];
+        let return_type = entry.decl.return_type.clone();
+        let Some(def) = entry.def.clone() else {
+            return self.call_host(name, args, varargs, return_type.as_ref());
+        };
+
+        let params = def.decl.params.iter().zip(args);
+        let scope = params
+            .map(|((name, type_), value)| {
+                let variable = Variable {
+                    value,
+                    type_: type_.clone(),
+                };
+                (name.clone(), variable)
+            })
+            .collect();
+        self.frames.push(Frame {
+            function: name.to_string(),
+            line: entry.line,
+            scopes: vec![scope],
+            return_type: return_type.clone(),
+            varargs: def.decl.variadic.then(|| varargs.unwrap_or_default()),
+        });
+        // the stack check comes before anything else the function does
+        if self.max_call_depth.is_some_and(|depth| self.frames.len() > depth) {
+            return Err(self.trap(TrapCode::STACK_OVERFLOW));
+        }
+        self.check_stack()?;
+        self.take_fuel()?;
+
+        let value = match self.exec_block(&def.body)? {
+            Flow::Return(value) => value,
+            // falling off the end returns zero, like `main` in C
+            Flow::Next => return_type.as_ref().map(zero_value).transpose()?,
+            Flow::Break => return Err(CompileError::OutsideLoop("break")),
+            Flow::Continue => return Err(CompileError::OutsideLoop("continue")),
+        };
+        self.frames.pop();
+        Ok(value)
+    }
+
+    fn call_host(
+        &mut self,
+        name: &str,
+        mut args: Vec<Value>,
+        varargs: Option<Vec<(Value, AstType)>>,
+        return_type: Option<&AstType>,
+    ) -> CompileResult<Option<Value>> {
+        if !self.functions[name].decl.extern_ {
+            return Err(CompileError::MissingDefinition(name.to_string()));
+        }
+        let function = self
+            .host_functions
+            .get_mut(name)
+            .ok_or_else(|| CompileError::Unsupported(format!("calling host function `{}` in the interpreter", name)))?;
+        args.extend(varargs.into_iter().flatten().map(|(value, _)| value));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| function(&args)))
+            .map_err(|payload| CompileError::HostPanic(trap::panic_message(&*payload)))?;
+        match (result, return_type) {
+            (Some(Value::Int(value)), Some(type_)) if is_int(type_) => Ok(Some(Value::Int(wrap(value, type_)))),
+            (Some(value), Some(type_)) if has_type(&value, type_) => Ok(Some(value)),
+            (_, None) => Ok(None),
+            (result, Some(type_)) => Err(CompileError::Unsupported(format!(
+                "host function `{}` returning {:?} as {:?}",
+                name, result, type_
+            ))),
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("statements only run in functions")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("statements only run in functions")
+    }
+
+    // The error the JIT reports for a trap with `code` at this point.
+    fn trap(&self, code: TrapCode) -> CompileError {
+        let frame = self.frame();
+        let location = TrapLocation {
+            function: frame.function.clone(),
+            line: (frame.line > 0).then_some(frame.line),
+        };
+        match code {
+            TrapCode::STACK_OVERFLOW => CompileError::StackOverflow(location),
+            trap::OUT_OF_FUEL => CompileError::OutOfFuel(location),
+            code => CompileError::Trap { code, location },
+        }
+    }
+
+    // Deeply nested calls and expressions recurse on the host's stack.
+    fn check_stack(&self) -> CompileResult<()> {
+        let marker = 0u8;
+        if (std::ptr::addr_of!(marker) as usize) < self.stack_floor {
+            return Err(self.trap(TrapCode::STACK_OVERFLOW));
+        }
+        Ok(())
+    }
+
+    fn take_fuel(&mut self) -> CompileResult<()> {
+        if !self.consume_fuel {
+            return Ok(());
+        }
+        if self.fuel == 0 {
+            return Err(self.trap(trap::OUT_OF_FUEL));
+        }
+        self.fuel -= 1;
+        Ok(())
+    }
+
+    fn exec_block(&mut self, block: &Block) -> CompileResult<Flow> {
+        self.frame_mut().scopes.push(HashMap::new());
+        let mut flow = Flow::Next;
+        for stmt in block {
+            flow = self.exec_stmt(stmt)?;
+            if !matches!(flow, Flow::Next) {
+                break;
+            }
+        }
+        self.frame_mut().scopes.pop();
+        Ok(flow)
+    }
+
+    fn exec_stmt(&mut self, mut stmt: &Stmt) -> CompileResult<Flow> {
+        // not recursing for spans saves a frame of host stack per statement
+        while let Stmt::Spanned(span, inner) = stmt {
+            self.frame_mut().line = span.line;
+            stmt = inner;
+        }
+        match stmt {
+            Stmt::VarDecl(var_decl) => {
+                self.declare_variable(var_decl)?;
+                Ok(Flow::Next)
+            }
+            Stmt::Assign(assign) => {
+                self.assign(assign)?;
+                Ok(Flow::Next)
+            }
+            Stmt::Return(ret) => self.exec_return(ret),
+            Stmt::Expr(Expr::FuncCall(func_call)) | Stmt::FuncCall(func_call) => {
+                self.eval_func_call(func_call)?;
+                Ok(Flow::Next)
+            }
+            Stmt::Expr(Expr::CallIndirect(call)) => {
+                self.eval_call_indirect(call)?;
+                Ok(Flow::Next)
+            }
+            Stmt::Expr(expr) => {
+                self.eval_expr(expr, None)?;
+                Ok(Flow::Next)
+            }
+            Stmt::Block(block) => self.exec_block(block),
+            Stmt::If(if_stmt) => {
+                if self.eval_condition(&if_stmt.condition)? {
+                    self.exec_block(&if_stmt.then_branch)
+                } else if let Some(else_branch) = &if_stmt.else_branch {
+                    self.exec_block(else_branch)
+                } else {
+                    Ok(Flow::Next)
+                }
+            }
+            Stmt::Loop(loop_stmt) => {
+                // the for-loop init variable lives in a scope wrapping the whole loop
+                self.frame_mut().scopes.push(HashMap::new());
+                let flow = self.exec_loop(loop_stmt)?;
+                self.frame_mut().scopes.pop();
+                Ok(flow)
+            }
+            Stmt::Break => Ok(Flow::Break),
+            Stmt::Continue => Ok(Flow::Continue),
+            _ => Err(CompileError::Unsupported(format!("{:?} in a function body", stmt))),
+        }
+    }
+
+    // Fuel is taken each time the condition is checked, as in the JIT.
+    fn exec_loop(&mut self, loop_stmt: &LoopStmt) -> CompileResult<Flow> {
+        let (init, step) = match &loop_stmt.kind {
+            LoopKind::For { init, step } => (init.as_deref(), step.as_deref()),
+            _ => (None, None),
+        };
+        if let Some(init) = init {
+            self.exec_stmt(init)?;
+        }
+        let check_first = loop_stmt.kind != LoopKind::DoWhile;
+        loop {
+            if check_first {
+                self.take_fuel()?;
+                if !self.eval_condition(&loop_stmt.condition)? {
+                    return Ok(Flow::Next);
+                }
+            }
+            match self.exec_block(&loop_stmt.body)? {
+                Flow::Break => return Ok(Flow::Next),
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Next | Flow::Continue => {}
+            }
+            if let Some(step) = step {
+                self.exec_stmt(step)?;
+            }
+            if !check_first {
+                self.take_fuel()?;
+                if !self.eval_condition(&loop_stmt.condition)? {
+                    return Ok(Flow::Next);
+                }
+            }
+        }
+    }
+
+    fn exec_return(&mut self, ret: &Return) -> CompileResult<Flow> {
+        match (&ret.value, self.frame().return_type.clone()) {
+            (Some(expr), Some(return_type)) => {
+                let (value, type_) = self.eval_expr(expr, Some(&return_type))?;
+                let value = self.cast_value(value, &type_, &return_type)?;
+                Ok(Flow::Return(Some(value)))
+            }
+            (None, None) => Ok(Flow::Return(None)),
+            (Some(expr), None) => {
+                let (_, found) = self.eval_expr(expr, None)?;
+                Err(CompileError::Unsupported(format!(
+                    "returning a {:?} from a function without a return type",
+                    found
+                )))
+            }
+            (None, Some(expected)) => Err(CompileError::Unsupported(format!(
+                "empty return from a function returning {:?}",
+                expected
+            ))),
+        }
+    }
+
+    fn declare_variable(&mut self, var_decl: &VarDecl) -> CompileResult<()> {
+        let value = match &var_decl.init {
+            Some(init) => {
+                let (value, type_) = self.eval_expr(init, Some(&var_decl.type_))?;
+                self.cast_value(value, &type_, &var_decl.type_)?
+            }
+            None if var_decl.type_ == AstType::VaList => Value::VaList(Rc::default()),
+            None => zero_value(&var_decl.type_)?,
+        };
+        let variable = Variable {
+            value,
+            type_: var_decl.type_.clone(),
+        };
+        let scope = self.frame_mut().scopes.last_mut().expect("functions have a scope");
+        scope.insert(var_decl.name.clone(), variable);
+        Ok(())
+    }
+
+    fn lookup_variable(&mut self, name: &str) -> CompileResult<&mut Variable> {
+        let visible = self.is_visible(name);
+        let frame = self.frames.last_mut().expect("statements only run in functions");
+        if let Some(variable) = frame.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            return Ok(variable);
+        }
+        self.globals
+            .get_mut(name)
+            .filter(|_| visible)
+            .ok_or_else(|| CompileError::UndefinedVariable(name.to_string()))
+    }
+
+    fn assign(&mut self, assign: &Assign) -> CompileResult<()> {
+        let target_type = self.lookup_variable(&assign.target.name)?.type_.clone();
+        let (value, type_) = self.eval_expr(&assign.value, Some(&target_type))?;
+        let value = self.cast_value(value, &type_, &target_type)?;
+        self.lookup_variable(&assign.target.name)?.value = value;
+        Ok(())
+    }
+
+    fn eval_condition(&mut self, expr: &Expr) -> CompileResult<bool> {
+        let (value, type_) = self.eval_expr(expr, Some(&AstType::Bool))?;
+        Ok(self.cast_value(value, &type_, &AstType::Bool)? != Value::Int(0))
+    }
+
+    // `expected` types literals, as in `Codegen::compile_expr`.
+    fn eval_expr(&mut self, expr: &Expr, expected: Option<&AstType>) -> CompileResult<(Value, AstType)> {
+        self.check_stack()?;
+        match expr {
+            Expr::Literal(literal) => Ok(self.eval_literal(literal, expected)),
+            Expr::Variable(variable) => {
+                let variable = self.lookup_variable(&variable.name)?;
+                Ok((variable.value.clone(), variable.type_.clone()))
+            }
+            Expr::Binary(binary) => self.eval_binary(binary, expected, self.overflow),
+            Expr::Unary(unary) => match unary.op {
+                UnaryOp::Neg => self.eval_negation(&unary.expr, expected, self.overflow),
+                UnaryOp::Not => {
+                    let condition = self.eval_condition(&unary.expr)?;
+                    Ok((Value::Int(!condition as i64), AstType::Bool))
+                }
+            },
+            Expr::FuncCall(func_call) => self
+                .eval_func_call(func_call)?
+                .ok_or_else(|| CompileError::VoidValue(func_call.name.clone())),
+            Expr::FuncAddr(name) => self.func_addr(name),
+            Expr::CallIndirect(call) => self
+                .eval_call_indirect(call)?
+                .ok_or_else(|| CompileError::VoidValue("indirect call".to_string())),
+            _ => Err(CompileError::Unsupported(format!("expression {:?}", expr))),
+        }
+    }
+
+    fn eval_literal(&mut self, literal: &Literal, expected: Option<&AstType>) -> (Value, AstType) {
+        match (literal, expected) {
+            (Literal::Int(value), Some(ty)) if ty.is_integer() => (Value::Int(wrap(*value, ty)), ty.clone()),
+            (Literal::Int(value), Some(AstType::F32)) => (Value::F32(*value as f32), AstType::F32),
+            (Literal::Int(value), Some(AstType::F64)) => (Value::F64(*value as f64), AstType::F64),
+            // like C's `int`, widened when the value doesn't fit
+            (Literal::Int(value), _) if i32::try_from(*value).is_ok() => (Value::Int(*value), AstType::I32),
+            (Literal::Int(value), _) => (Value::Int(*value), AstType::I64),
+            (Literal::Float(value), Some(AstType::F32)) => (Value::F32(*value as f32), AstType::F32),
+            (Literal::Float(value), _) => (Value::F64(*value), AstType::F64),
+            (Literal::Bool(value), _) => (Value::Int(*value as i64), AstType::Bool),
+            (Literal::Char(value), _) => (Value::Int(*value as u8 as i64), AstType::Char),
+            (Literal::String(value), _) => {
+                let string = self
+                    .strings
+                    .entry(value as *const String as usize)
+                    .or_insert_with(|| value.as_str().into());
+                (Value::String(Some(string.clone())), AstType::String)
+            }
+        }
+    }
+
+    fn eval_binary(
+        &mut self,
+        binary: &Binary,
+        expected: Option<&AstType>,
+        overflow: Overflow,
+    ) -> CompileResult<(Value, AstType)> {
+        let is_comparison = matches!(
+            binary.op,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Lt | BinaryOp::Le
+        );
+        let operand_hint = if is_comparison { None } else { expected };
+
+        // a literal operand takes the type of the other side
+        let ((left, left_type), (right, right_type)) = match (&*binary.left, &*binary.right) {
+            (Expr::Literal(_), right) if !matches!(right, Expr::Literal(_)) => {
+                let right = self.eval_expr(right, operand_hint)?;
+                let left = self.eval_expr(&binary.left, Some(&promoted(&right.1)))?;
+                (left, right)
+            }
+            (left, right) => {
+                let left = self.eval_expr(left, operand_hint)?;
+                let right = self.eval_expr(right, Some(&promoted(&left.1)))?;
+                (left, right)
+            }
+        };
+
+        if matches!(binary.op, BinaryOp::Shl | BinaryOp::Shr) {
+            if !left_type.is_integer() || !right_type.is_integer() {
+                return Err(CompileError::TypeMismatch {
+                    expected: AstType::I64,
+                    found: if left_type.is_integer() { right_type } else { left_type },
+                });
+            }
+            let (Value::Int(left), Value::Int(right)) = (&left, &right) else {
+                unreachable!("integers are `Value::Int`s")
+            };
+            // the amount is taken modulo the width, as Cranelift does
+            let amount = (*right as u64 % int_bits(&left_type) as u64) as u32;
+            let value = match (&binary.op, left_type.is_signed()) {
+                (BinaryOp::Shl, _) => left.wrapping_shl(amount),
+                (_, true) => left >> amount,
+                (_, false) => ((*left as u64) >> amount) as i64,
+            };
+            return Ok((Value::Int(wrap(value, &left_type)), left_type));
+        }
+
+        let type_ = common_type(&left_type, &right_type, pointer_type())?;
+        if !type_.is_numeric() && !matches!(binary.op, BinaryOp::Eq | BinaryOp::Ne) {
+            return Err(CompileError::Unsupported(format!("{:?} on {:?}", binary.op, type_)));
+        }
+        let left = self.cast_value(left, &left_type, &type_)?;
+        let right = self.cast_value(right, &right_type, &type_)?;
+        let result_type = if is_comparison { AstType::Bool } else { type_.clone() };
+        let unsupported = || CompileError::Unsupported(format!("{:?} on {:?}", binary.op, type_));
+
+        let value = match (left, right) {
+            (Value::Int(left), Value::Int(right)) => {
+                Value::Int(self.int_binary(&binary.op, left, right, &type_, overflow)?)
+            }
+            (Value::F32(left), Value::F32(right)) => match float_binary(&binary.op, left, right) {
+                Some(FloatResult::Value(value)) => Value::F32(value),
+                Some(FloatResult::Bool(value)) => Value::Int(value as i64),
+                None => return Err(unsupported()),
+            },
+            (Value::F64(left), Value::F64(right)) => match float_binary(&binary.op, left, right) {
+                Some(FloatResult::Value(value)) => Value::F64(value),
+                Some(FloatResult::Bool(value)) => Value::Int(value as i64),
+                None => return Err(unsupported()),
+            },
+            // pointers compare by address
+            (left, right) => {
+                let same = match (&left, &right) {
+                    (Value::String(left), Value::String(right)) => match (left, right) {
+                        (Some(left), Some(right)) => Rc::ptr_eq(left, right),
+                        (left, right) => left.is_none() && right.is_none(),
+                    },
+                    (Value::VaList(left), Value::VaList(right)) => Rc::ptr_eq(left, right),
+                    (left, right) => left == right,
+                };
+                Value::Int((same == (binary.op == BinaryOp::Eq)) as i64)
+            }
+        };
+        Ok((value, result_type))
+    }
+
+    fn int_binary(&self, op: &BinaryOp, left: i64, right: i64, type_: &AstType, overflow: Overflow) -> CompileResult<i64> {
+        let signed = type_.is_signed();
+        let value = match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul if overflow == Overflow::Trap => {
+                let (left, right) = (exact(left, type_), exact(right, type_));
+                let value = match op {
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Sub => left.checked_sub(right),
+                    _ => left.checked_mul(right),
+                };
+                match value {
+                    Some(value) if fits(value, type_) => value as i64,
+                    _ => return Err(self.trap(trap::OVERFLOW)),
+                }
+            }
+            BinaryOp::Add => left.wrapping_add(right),
+            BinaryOp::Sub => left.wrapping_sub(right),
+            BinaryOp::Mul => left.wrapping_mul(right),
+            BinaryOp::Div | BinaryOp::Mod if right == 0 => return Err(self.trap(TrapCode::INTEGER_DIVISION_BY_ZERO)),
+            BinaryOp::Div if signed => {
+                if left == min_value(type_) && right == -1 {
+                    return Err(self.trap(TrapCode::INTEGER_OVERFLOW));
+                }
+                left / right
+            }
+            BinaryOp::Div => ((left as u64) / (right as u64)) as i64,
+            BinaryOp::Mod if signed => left.wrapping_rem(right),
+            BinaryOp::Mod => ((left as u64) % (right as u64)) as i64,
+            BinaryOp::BitAnd => left & right,
+            BinaryOp::BitOr => left | right,
+            BinaryOp::BitXor => left ^ right,
+            op => {
+                let ordering = if signed { left.cmp(&right) } else { (left as u64).cmp(&(right as u64)) };
+                let result = match op {
+                    BinaryOp::Eq => ordering.is_eq(),
+                    BinaryOp::Ne => ordering.is_ne(),
+                    BinaryOp::Gt => ordering.is_gt(),
+                    BinaryOp::Ge => ordering.is_ge(),
+                    BinaryOp::Lt => ordering.is_lt(),
+                    BinaryOp::Le => ordering.is_le(),
+                    _ => unreachable!("shifts are handled by `eval_binary`"),
+                };
+                return Ok(result as i64);
+            }
+        };
+        Ok(wrap(value, type_))
+    }
+
+    fn eval_negation(
+        &mut self,
+        expr: &Expr,
+        expected: Option<&AstType>,
+        overflow: Overflow,
+    ) -> CompileResult<(Value, AstType)> {
+        let (value, from) = self.eval_expr(expr, expected)?;
+        let type_ = promoted(&from);
+        let value = match self.cast_value(value, &from, &type_)? {
+            Value::F32(value) => Value::F32(-value),
+            Value::F64(value) => Value::F64(-value),
+            Value::Int(value) => {
+                // only `MIN` overflows, and for unsigned types everything but 0
+                let overflowed = if type_.is_signed() { value == min_value(&type_) } else { value != 0 };
+                if overflow == Overflow::Trap && overflowed {
+                    return Err(self.trap(trap::OVERFLOW));
+                }
+                Value::Int(wrap(value.wrapping_neg(), &type_))
+            }
+            _ => {
+                return Err(CompileError::TypeMismatch {
+                    expected: AstType::I64,
+                    found: type_,
+                })
+            }
+        };
+        Ok((value, type_))
+    }
+
+    fn eval_func_call(&mut self, func_call: &FuncCall) -> CompileResult<Option<(Value, AstType)>> {
+        if let "va_start" | "va_arg" | "va_end" = func_call.name.as_str() {
+            return self.eval_va_intrinsic(func_call);
+        }
+        if let Some((overflow, op)) = arithmetic_intrinsic(&func_call.name) {
+            return self.eval_arithmetic_intrinsic(func_call, overflow, op).map(Some);
+        }
+
+        // a variable holding a function pointer shadows a function of the same name
+        if let Ok(variable) = self.lookup_variable(&func_call.name) {
+            let Variable { value, type_ } = variable.clone();
+            return self.call_value(&func_call.name, value, &type_, &func_call.args);
+        }
+
+        let decl = self
+            .functions
+            .get(&func_call.name)
+            .filter(|_| self.is_visible(&func_call.name))
+            .ok_or_else(|| CompileError::UndefinedFunction(func_call.name.clone()))?
+            .decl
+            .clone();
+        let params: Vec<AstType> = decl.params.iter().map(|(_, type_)| type_.clone()).collect();
+        let (args, varargs) = if decl.variadic {
+            if func_call.args.len() < params.len() {
+                return Err(CompileError::ArgumentCount {
+                    name: decl.name.clone(),
+                    expected: params.len(),
+                    found: func_call.args.len(),
+                });
+            }
+            let (fixed_args, extra_args) = func_call.args.split_at(params.len());
+            let args = self.eval_call_args(&decl.name, &params, fixed_args)?;
+            let mut varargs = Vec::with_capacity(extra_args.len());
+            for arg in extra_args {
+                // an integer literal is an `int` unless it doesn't fit, as in C
+                let hint = match arg {
+                    Expr::Literal(Literal::Int(value)) if i32::try_from(*value).is_ok() => Some(AstType::I32),
+                    _ => None,
+                };
+                let (value, type_) = self.eval_expr(arg, hint.as_ref())?;
+                let promoted = variadic::promote(&type_);
+                varargs.push((self.cast_value(value, &type_, &promoted)?, promoted));
+            }
+            (args, Some(varargs))
+        } else {
+            (self.eval_call_args(&func_call.name, &params, &func_call.args)?, None)
+        };
+
+        let value = self.call_function(&func_call.name, args, varargs)?;
+        Ok(value.zip(decl.return_type))
+    }
+
+    fn eval_call_args(&mut self, name: &str, params: &[AstType], args: &[Expr]) -> CompileResult<Vec<Value>> {
+        if params.len() != args.len() {
+            return Err(CompileError::ArgumentCount {
+                name: name.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for (arg, param_type) in args.iter().zip(params) {
+            let (value, type_) = self.eval_expr(arg, Some(param_type))?;
+            values.push(self.cast_value(value, &type_, param_type)?);
+        }
+        Ok(values)
+    }
+
+    fn func_addr(&self, name: &str) -> CompileResult<(Value, AstType)> {
+        let entry = self
+            .functions
+            .get(name)
+            .filter(|_| self.is_visible(name))
+            .ok_or_else(|| CompileError::UndefinedFunction(name.to_string()))?;
+        Ok((Value::FuncPtr(Some(name.into())), entry.decl.func_ptr_type()))
+    }
+
+    fn eval_call_indirect(&mut self, call: &CallIndirect) -> CompileResult<Option<(Value, AstType)>> {
+        let (callee, callee_type) = self.eval_expr(&call.callee, None)?;
+        self.call_value("indirect call", callee, &callee_type, &call.args)
+    }
+
+    fn call_value(
+        &mut self,
+        name: &str,
+        callee: Value,
+        callee_type: &AstType,
+        args: &[Expr],
+    ) -> CompileResult<Option<(Value, AstType)>> {
+        let AstType::FuncPtr { params, return_type } = callee_type else {
+            return Err(CompileError::NotCallable(callee_type.clone()));
+        };
+        let args = self.eval_call_args(name, params, args)?;
+        let Value::FuncPtr(Some(function)) = callee else {
+            return Err(self.trap(trap::NULL_CALL));
+        };
+        let value = self.call_function(&function, args, None)?;
+        Ok(value.zip(return_type.as_deref().cloned()))
+    }
+
+    // `wrapping_add(a, b)`, `checked_neg(a)` and so on.
+    fn eval_arithmetic_intrinsic(
+        &mut self,
+        func_call: &FuncCall,
+        overflow: Overflow,
+        op: Option<BinaryOp>,
+    ) -> CompileResult<(Value, AstType)> {
+        let expected_args = if op.is_some() { 2 } else { 1 };
+        if func_call.args.len() != expected_args {
+            return Err(CompileError::ArgumentCount {
+                name: func_call.name.clone(),
+                expected: expected_args,
+                found: func_call.args.len(),
+            });
+        }
+        let (value, type_) = match op {
+            Some(op) => {
+                let binary = Binary {
+                    op,
+                    left: Box::new(func_call.args[0].clone()),
+                    right: Box::new(func_call.args[1].clone()),
+                };
+                self.eval_binary(&binary, None, overflow)?
+            }
+            None => self.eval_negation(&func_call.args[0], None, overflow)?,
+        };
+        if !type_.is_integer() {
+            return Err(CompileError::TypeMismatch {
+                expected: AstType::I64,
+                found: type_,
+            });
+        }
+        Ok((value, type_))
+    }
+
+    fn eval_va_intrinsic(&mut self, func_call: &FuncCall) -> CompileResult<Option<(Value, AstType)>> {
+        let expected_args = match func_call.name.as_str() {
+            "va_arg" => 2,
+            _ => 1,
+        };
+        // `va_start(ap, last)` is accepted too; the named param isn't needed
+        let valid_count = func_call.args.len() == expected_args
+            || (func_call.name == "va_start" && func_call.args.len() == 2);
+        if !valid_count {
+            return Err(CompileError::ArgumentCount {
+                name: func_call.name.clone(),
+                expected: expected_args,
+                found: func_call.args.len(),
+            });
+        }
+
+        let (va_list, type_) = self.eval_expr(&func_call.args[0], None)?;
+        let Value::VaList(va_list) = va_list else {
+            return Err(CompileError::TypeMismatch {
+                expected: AstType::VaList,
+                found: type_,
+            });
+        };
+
+        match func_call.name.as_str() {
+            "va_start" => {
+                let varargs = self
+                    .frame()
+                    .varargs
+                    .clone()
+                    .ok_or(CompileError::OutsideVariadic("va_start"))?;
+                let (floats, integers) = varargs.into_iter().partition(|(_, type_)| type_.is_float());
+                *va_list.borrow_mut() = VaList { integers, floats };
+                Ok(None)
+            }
+            "va_arg" => {
+                let Expr::Type(arg_type) = &func_call.args[1] else {
+                    return Err(CompileError::Unsupported(format!(
+                        "va_arg type argument {:?}",
+                        func_call.args[1]
+                    )));
+                };
+                if self.frame().varargs.is_none() {
+                    return Err(CompileError::OutsideVariadic("va_arg"));
+                }
+                if *arg_type == AstType::F32 {
+                    // a float argument has already been promoted by the caller
+                    return Err(CompileError::TypeMismatch {
+                        expected: AstType::F64,
+                        found: AstType::F32,
+                    });
+                }
+                let mut va_list = va_list.borrow_mut();
+                let queue = if arg_type.is_float() { &mut va_list.floats } else { &mut va_list.integers };
+                if queue.is_empty() {
+                    return Err(CompileError::Unsupported("`va_arg` past the last variadic argument".to_string()));
+                }
+                // integers are read back at the width asked for, from a 64-bit slot
+                let value = match (queue.remove(0), arg_type) {
+                    ((Value::Int(value), _), type_) if is_int(type_) => Value::Int(wrap(value, type_)),
+                    ((value, type_), arg_type) if type_ == *arg_type => value,
+                    ((_, found), expected) => {
+                        return Err(CompileError::TypeMismatch {
+                            expected: expected.clone(),
+                            found,
+                        })
+                    }
+                };
+                Ok(Some((value, arg_type.clone())))
+            }
+            // nothing to release
+            _ => Ok(None),
+        }
+    }
+
+    // implicit conversions between numeric types, following `Codegen::cast_value`
+    fn cast_value(&self, value: Value, from: &AstType, to: &AstType) -> CompileResult<Value> {
+        if from == to {
+            return Ok(value);
+        }
+        if !from.is_numeric() || !to.is_numeric() {
+            return Err(CompileError::TypeMismatch {
+                expected: to.clone(),
+                found: from.clone(),
+            });
+        }
+        let value = match value {
+            Value::Int(value) if *to == AstType::Bool => Value::Int((value != 0) as i64),
+            Value::Int(value) if is_int(to) => {
+                // narrowing traps unless the value fits, with checked arithmetic
+                let narrowing = int_bits(to) < int_bits(from);
+                if narrowing && self.overflow == Overflow::Trap && !fits(exact(value, from), to) {
+                    return Err(self.trap(trap::OVERFLOW));
+                }
+                Value::Int(wrap(value, to))
+            }
+            // converted straight to the float type, so there's one rounding
+            Value::Int(value) => match (to, from.is_signed()) {
+                (AstType::F32, true) => Value::F32(value as f32),
+                (AstType::F32, false) => Value::F32(value as u64 as f32),
+                (_, true) => Value::F64(value as f64),
+                (_, false) => Value::F64(value as u64 as f64),
+            },
+            Value::F32(value) if self.overflow == Overflow::Trap => self.checked_float_to(value as f64, to)?,
+            Value::F64(value) if self.overflow == Overflow::Trap => self.checked_float_to(value, to)?,
+            Value::F32(value) => float_to(value as f64, to),
+            Value::F64(value) => float_to(value, to),
+            value => value,
+        };
+        Ok(value)
+    }
+
+    // `float_to`, but trapping like Cranelift's `fcvt_to_sint` and
+    // `fcvt_to_uint` on integers the value doesn't fit.
+    fn checked_float_to(&self, value: f64, to: &AstType) -> CompileResult<Value> {
+        if !to.is_integer() {
+            return Ok(float_to(value, to));
+        }
+        if value.is_nan() {
+            return Err(self.trap(TrapCode::BAD_CONVERSION_TO_INTEGER));
+        }
+        let bits = int_bits(to) as i32;
+        let (min, end) = if to.is_signed() {
+            (-(2f64.powi(bits - 1)), 2f64.powi(bits - 1))
+        } else {
+            (0.0, 2f64.powi(bits))
+        };
+        let truncated = value.trunc();
+        if truncated < min || truncated >= end {
+            return Err(self.trap(TrapCode::INTEGER_OVERFLOW));
+        }
+        Ok(float_to(value, to))
+    }
+}
+
+// Cranelift's `fcvt_to_*_sat`, `fcmp ne` against zero, and float conversions.
+fn float_to(value: f64, to: &AstType) -> Value {
+    match to {
+        AstType::Bool => Value::Int((value != 0.0) as i64),
+        AstType::F32 => Value::F32(value as f32),
+        AstType::F64 => Value::F64(value),
+        to => {
+            let bits = int_bits(to);
+            let value = match (to.is_signed(), bits) {
+                (true, 8) => value as i8 as i64,
+                (true, 16) => value as i16 as i64,
+                (true, 32) => value as i32 as i64,
+                (true, _) => value as i64,
+                (false, 8) => value as u8 as i64,
+                (false, 16) => value as u16 as i64,
+                (false, 32) => value as u32 as i64,
+                (false, _) => value as u64 as i64,
+            };
+            Value::Int(value)
+        }
+    }
+}
+
+enum FloatResult<F> {
+    Value(F),
+    Bool(bool),
+}
+
+fn float_binary<F>(op: &BinaryOp, left: F, right: F) -> Option<FloatResult<F>>
+where
+    F: Copy + PartialOrd + Add<Output = F> + Sub<Output = F> + Mul<Output = F> + Div<Output = F>,
+{
+    let result = match op {
+        BinaryOp::Add => FloatResult::Value(left + right),
+        BinaryOp::Sub => FloatResult::Value(left - right),
+        BinaryOp::Mul => FloatResult::Value(left * right),
+        BinaryOp::Div => FloatResult::Value(left / right),
+        BinaryOp::Eq => FloatResult::Bool(left == right),
+        BinaryOp::Ne => FloatResult::Bool(left != right),
+        BinaryOp::Gt => FloatResult::Bool(left > right),
+        BinaryOp::Ge => FloatResult::Bool(left >= right),
+        BinaryOp::Lt => FloatResult::Bool(left < right),
+        BinaryOp::Le => FloatResult::Bool(left <= right),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn pointer_type() -> Type {
+    Type::int(usize::BITS as u16).expect("pointers are a Cranelift integer type")
+}
+
+// the types held in `Value::Int`
+fn is_int(type_: &AstType) -> bool {
+    type_.is_integer() || *type_ == AstType::Bool
+}
+
+fn int_bits(type_: &AstType) -> u32 {
+    match type_ {
+        AstType::I8 | AstType::U8 | AstType::Bool | AstType::Char => 8,
+        AstType::I16 | AstType::U16 => 16,
+        AstType::I32 | AstType::U32 => 32,
+        AstType::Usize | AstType::Isize => usize::BITS,
+        _ => 64,
+    }
+}
+
+// `bits` truncated to the width of `type_`, then extended the way `Value::Int`s are
+fn wrap(bits: i64, type_: &AstType) -> i64 {
+    let shift = 64 - int_bits(type_);
+    if type_.is_signed() {
+        bits.wrapping_shl(shift).wrapping_shr(shift)
+    } else {
+        ((bits as u64).wrapping_shl(shift).wrapping_shr(shift)) as i64
+    }
+}
+
+// the number a `Value::Int` of `type_` stands for
+fn exact(value: i64, type_: &AstType) -> i128 {
+    if type_.is_signed() {
+        value as i128
+    } else {
+        value as u64 as i128
+    }
+}
+
+fn fits(value: i128, type_: &AstType) -> bool {
+    let bits = int_bits(type_);
+    if type_.is_signed() {
+        (-(1i128 << (bits - 1))..1i128 << (bits - 1)).contains(&value)
+    } else {
+        (0..1i128 << bits).contains(&value)
+    }
+}
+
+fn min_value(type_: &AstType) -> i64 {
+    i64::MIN >> (64 - int_bits(type_))
+}
+
+fn zero_value(type_: &AstType) -> CompileResult<Value> {
+    let value = match type_ {
+        AstType::F32 => Value::F32(0.0),
+        AstType::F64 => Value::F64(0.0),
+        AstType::String => Value::String(None),
+        AstType::FuncPtr { .. } => Value::FuncPtr(None),
+        AstType::VaList => Value::VaList(Rc::default()),
+        type_ if is_int(type_) => Value::Int(0),
+        _ => return Err(CompileError::Unsupported(format!("type {:?}", type_))),
+    };
+    Ok(value)
+}
+
+// a numeric literal as `Codegen` stores it in a global of type `type_`
+fn constant(literal: &Literal, type_: &AstType) -> Option<Value> {
+    let (int, float) = match *literal {
+        Literal::Int(value) => (value, value as f64),
+        Literal::Float(value) => (value as i64, value),
+        Literal::Bool(value) => (value as i64, value as i64 as f64),
+        Literal::Char(value) => (value as i64, value as u32 as f64),
+        Literal::String(_) => return None,
+    };
+    let value = match type_ {
+        AstType::F32 => Value::F32(float as f32),
+        AstType::F64 => Value::F64(float),
+        AstType::Bool => Value::Int((int != 0 || float != 0.0) as i64),
+        type_ => Value::Int(wrap(int, type_)),
+    };
+    Some(value)
+}
+
+// whether `value` is the kind of value a `type_` is
+fn has_type(value: &Value, type_: &AstType) -> bool {
+    match value {
+        Value::Int(_) => is_int(type_),
+        Value::F32(_) => *type_ == AstType::F32,
+        Value::F64(_) => *type_ == AstType::F64,
+        Value::String(_) => *type_ == AstType::String,
+        Value::FuncPtr(_) => matches!(type_, AstType::FuncPtr { .. }),
+        Value::VaList(_) => *type_ == AstType::VaList,
+    }
+}
+
+// what a value passed to `call` most likely is, for errors
+fn value_type(value: &Value) -> AstType {
+    match value {
+        Value::Int(_) => AstType::I64,
+        Value::F32(_) => AstType::F32,
+        Value::F64(_) => AstType::F64,
+        Value::String(_) => AstType::String,
+        Value::FuncPtr(_) => AstType::FuncPtr {
+            params: Vec::new(),
+            return_type: None,
+        },
+        Value::VaList(_) => AstType::VaList,
+    }
+}
+
+fn string_arg(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(Some(string))) => string.to_string(),
+        _ => "(null)".to_string(),
+    }
+}
+
+// `printf`'s `%d`, `%i`, `%u`, `%x`, `%X`, `%c`, `%s`, `%f` and `%%`, with
+// flags `-` and `0`, a width and a precision. Length modifiers are skipped,
+// since the arguments already have their types.
+fn format_printf(args: &[Value]) -> String {
+    let format = string_arg(args.first());
+    let mut args = args.iter().skip(1);
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let (mut left, mut zero) = (false, false);
+        while let Some(&flag @ ('-' | '0' | '+' | ' ' | '#')) = chars.peek() {
+            left |= flag == '-';
+            zero |= flag == '0';
+            chars.next();
+        }
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut digits = String::new();
+            while let Some(&digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(digit);
+                chars.next();
+            }
+            digits.parse::<usize>().ok()
+        };
+        let width = number(&mut chars).unwrap_or(0);
+        let precision = if chars.peek() == Some(&'.') {
+            chars.next();
+            Some(number(&mut chars).unwrap_or(0))
+        } else {
+            None
+        };
+        while let Some('l' | 'h' | 'z' | 'j' | 't') = chars.peek() {
+            chars.next();
+        }
+        let int = |value: Option<&Value>| match value {
+            Some(Value::Int(value)) => *value,
+            _ => 0,
+        };
+        let text = match chars.next() {
+            Some('%') => "%".to_string(),
+            Some('d' | 'i') => int(args.next()).to_string(),
+            Some('u') => (int(args.next()) as u64).to_string(),
+            Some('x') => format!("{:x}", int(args.next())),
+            Some('X') => format!("{:X}", int(args.next())),
+            Some('c') => (int(args.next()) as u8 as char).to_string(),
+            Some('s') => {
+                let string = string_arg(args.next());
+                match precision {
+                    Some(precision) => string.chars().take(precision).collect(),
+                    None => string,
+                }
+            }
+            Some('f') => {
+                let value = match args.next() {
+                    Some(Value::F64(value)) => *value,
+                    Some(Value::F32(value)) => *value as f64,
+                    _ => 0.0,
+                };
+                format!("{:.*}", precision.unwrap_or(6), value)
+            }
+            Some(other) => format!("%{}", other),
+            None => "%".to_string(),
+        };
+        let padding = width.saturating_sub(text.chars().count());
+        if left {
+            output.push_str(&text);
+            output.extend(std::iter::repeat_n(' ', padding));
+        } else if zero && text.starts_with('-') {
+            output.push('-');
+            output.extend(std::iter::repeat_n('0', padding));
+            output.push_str(&text[1..]);
+        } else {
+            output.extend(std::iter::repeat_n(if zero { '0' } else { ' ' }, padding));
+            output.push_str(&text);
+        }
+    }
+    output
+}
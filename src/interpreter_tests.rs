@@ -0,0 +1,304 @@
+use crate::compiler::{Compiler, CompilerBuilder};
+use crate::error::CompileError;
+use crate::interpreter::{Interpreter, Value};
+use crate::parser::parse;
+use crate::test_helpers::trap_code;
+use crate::trap;
+use cranelift_codegen::ir::TrapCode;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn interpreter(source: &str) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.load_program(parse(source).unwrap()).unwrap();
+    interpreter
+}
+
+fn int(value: Option<Value>) -> i64 {
+    match value {
+        Some(Value::Int(value)) => value,
+        other => panic!("expected an integer, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_matches_jit_on_integer_widths() {
+    let source = "
+signed char narrow(int x) { signed char y = x; return y; }
+unsigned short unsigned_wrap() { unsigned short x = 0; x = x - 1; return x; }
+int shifts(int x) { return (x << 33) + (x >> 1) + (-x >> 2); }
+unsigned unsigned_shift(unsigned x) { return x >> 28; }
+long widen(int x) { long y = x; return y * 3000000000; }
+int division(int x, int y) { return x / y * 100 + x % y; }
+unsigned char bool_arithmetic() { return true + true; }
+_Bool bool_sum(_Bool a, _Bool b) { return a + b; }
+int char_sum(char a, char b) { return a + b; }
+int min_rem() { int x = -2147483647 - 1; return x % -1; }
+double mixed(int x) { float half = 0.5; return x * half + 1; }
+int saturating(double x) { return x; }
+unsigned unsigned_from_float(float x) { return x; }
+";
+    let mut codegen = Compiler::new().unwrap().build();
+    codegen.compile_program(parse(source).unwrap()).unwrap();
+    let mut interpreter = interpreter(source);
+
+    for x in [0, 1, -1, 127, 128, 255, 300, -129, i32::MAX, i32::MIN] {
+        let expected = codegen.call::<_, i8>("narrow", (x,)).unwrap() as i64;
+        assert_eq!(int(interpreter.call("narrow", &[Value::Int(x as i64)]).unwrap()), expected, "narrow({})", x);
+        let expected = codegen.call::<_, i32>("shifts", (x,)).unwrap() as i64;
+        assert_eq!(int(interpreter.call("shifts", &[Value::Int(x as i64)]).unwrap()), expected, "shifts({})", x);
+        let expected = codegen.call::<_, u32>("unsigned_shift", (x as u32,)).unwrap() as i64;
+        let value = interpreter.call("unsigned_shift", &[Value::Int(x as u32 as i64)]).unwrap();
+        assert_eq!(int(value), expected, "unsigned_shift({})", x);
+        let expected = codegen.call::<_, i64>("widen", (x,)).unwrap();
+        assert_eq!(int(interpreter.call("widen", &[Value::Int(x as i64)]).unwrap()), expected, "widen({})", x);
+        let expected = codegen.call::<_, f64>("mixed", (x,)).unwrap();
+        assert_eq!(interpreter.call("mixed", &[Value::Int(x as i64)]).unwrap(), Some(Value::F64(expected)));
+    }
+    for (x, y) in [(7, 2), (-7, 2), (7, -2), (i32::MIN, 3)] {
+        let expected = codegen.call::<_, i32>("division", (x, y)).unwrap() as i64;
+        let value = interpreter.call("division", &[Value::Int(x as i64), Value::Int(y as i64)]).unwrap();
+        assert_eq!(int(value), expected, "division({}, {})", x, y);
+    }
+    for x in [0.0, -1.5, 1e20, -1e20, f64::NAN, f64::INFINITY] {
+        let expected = codegen.call::<_, i32>("saturating", (x,)).unwrap() as i64;
+        assert_eq!(int(interpreter.call("saturating", &[Value::F64(x)]).unwrap()), expected, "saturating({})", x);
+        let x = x as f32;
+        let expected = codegen.call::<_, u32>("unsigned_from_float", (x,)).unwrap() as i64;
+        let value = interpreter.call("unsigned_from_float", &[Value::F32(x)]).unwrap();
+        assert_eq!(int(value), expected, "unsigned_from_float({})", x);
+    }
+    assert_eq!(int(interpreter.call("unsigned_wrap", &[]).unwrap()), 65535);
+    assert_eq!(int(interpreter.call("bool_arithmetic", &[]).unwrap()), 2);
+    for (a, b) in [(false, false), (true, false), (true, true)] {
+        let expected = codegen.call::<_, bool>("bool_sum", (a, b)).unwrap() as i64;
+        let value = interpreter.call("bool_sum", &[Value::Int(a as i64), Value::Int(b as i64)]).unwrap();
+        assert_eq!(int(value), expected, "bool_sum({}, {})", a, b);
+    }
+    let expected = codegen.call::<_, i32>("char_sum", (200u8, 100u8)).unwrap() as i64;
+    assert_eq!(expected, 300);
+    assert_eq!(int(interpreter.call("char_sum", &[Value::Int(200), Value::Int(100)]).unwrap()), expected);
+    assert_eq!(int(interpreter.call("min_rem", &[]).unwrap()), 0);
+}
+
+#[test]
+fn test_traps_like_the_jit() {
+    let source = "int divide(int x, int y) {
+    return x / y;
+}
+int overflow(int x) {
+    return x + 1;
+}
+signed char narrow(int x) { return x; }
+int call_null() { int (*f)(int x); return f(1); }
+int recurse(int n) { return recurse(n + 1); }
+";
+    let mut interpreter = interpreter(source);
+    let err = interpreter.call("divide", &[Value::Int(1), Value::Int(0)]).unwrap_err();
+    assert_eq!(err.to_string(), "trap int_divz in `divide` at line 2");
+    let err = interpreter.call("divide", &[Value::Int(i32::MIN as i64), Value::Int(-1)]).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::INTEGER_OVERFLOW);
+    let err = interpreter.call("call_null", &[]).unwrap_err();
+    assert_eq!(trap_code(err), trap::NULL_CALL);
+
+    // wrapping unless arithmetic is checked
+    assert_eq!(int(interpreter.call("overflow", &[Value::Int(i32::MAX as i64)]).unwrap()), i32::MIN as i64);
+    assert_eq!(int(interpreter.call("narrow", &[Value::Int(200)]).unwrap()), -56);
+    interpreter.set_checked_arithmetic(true);
+    let err = interpreter.call("overflow", &[Value::Int(i32::MAX as i64)]).unwrap_err();
+    assert_eq!(err.to_string(), "trap overflow in `overflow` at line 5");
+    assert_eq!(trap_code(interpreter.call("narrow", &[Value::Int(200)]).unwrap_err()), trap::OVERFLOW);
+
+    // running low on the host's stack is an overflow too
+    let err = interpreter.call("recurse", &[Value::Int(0)]).unwrap_err();
+    assert!(matches!(&err, CompileError::StackOverflow(location) if location.function == "recurse"), "{}", err);
+    interpreter.set_max_call_depth(10);
+    let err = interpreter.call("recurse", &[Value::Int(0)]).unwrap_err();
+    assert!(matches!(err, CompileError::StackOverflow(_)), "{}", err);
+    // the interpreter is still usable afterwards
+    assert_eq!(int(interpreter.call("divide", &[Value::Int(9), Value::Int(3)]).unwrap()), 3);
+}
+
+#[test]
+fn test_checked_float_conversions_match_the_jit() {
+    let source = "long to_char(double x) { char c = x; return c; }
+long to_signed_char(double x) { signed char c = x; return c; }
+long to_short(float x) { short s = x; return s; }
+long to_int(double x) { int i = x; return i; }
+long to_unsigned(double x) { unsigned u = x; return u; }
+long to_long(double x) { return x; }
+long to_unsigned_long(double x) { unsigned long u = x; return u; }
+";
+    let mut codegen = CompilerBuilder::new().checked_arithmetic(true).compiler().unwrap().build();
+    codegen.compile_program(parse(source).unwrap()).unwrap();
+    let mut interpreter = interpreter(source);
+    interpreter.set_checked_arithmetic(true);
+
+    let values = [
+        0.0, -0.5, 127.9, 128.0, 255.9, 256.0, -1.0, -128.9, -129.0, 32768.0, 2147483648.0, -2147483649.0,
+        4294967296.0, 9.3e18, 1.9e19, 1e300, -1e300, f64::NAN, f64::INFINITY,
+    ];
+    let functions = ["to_char", "to_signed_char", "to_short", "to_int", "to_unsigned", "to_long", "to_unsigned_long"];
+    for name in functions {
+        for x in values {
+            let (expected, found) = if name == "to_short" {
+                let x = x as f32;
+                (codegen.call::<_, i64>(name, (x,)), interpreter.call(name, &[Value::F32(x)]))
+            } else {
+                (codegen.call::<_, i64>(name, (x,)), interpreter.call(name, &[Value::F64(x)]))
+            };
+            match expected {
+                Ok(expected) => assert_eq!(int(found.unwrap()), expected, "{}({})", name, x),
+                Err(err) => assert_eq!(trap_code(found.unwrap_err()), trap_code(err), "{}({})", name, x),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fuel_matches_the_jit() {
+    let source = "int fib(int n) {
+    if (n < 2) { return n; }
+    return fib(n - 1) + fib(n - 2);
+}
+int count(int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) { if (i == 5) { continue; } total += i; }
+    do { n--; } while (n > 0);
+    return total;
+}
+int spin() {
+    while (1) { }
+    return 0;
+}
+";
+    let mut codegen = CompilerBuilder::new().consume_fuel(true).compiler().unwrap().build();
+    codegen.compile_program(parse(source).unwrap()).unwrap();
+    let mut interpreter = interpreter(source);
+    interpreter.set_consume_fuel(true);
+
+    for (name, arg) in [("fib", 10), ("count", 10)] {
+        codegen.set_fuel(1000);
+        interpreter.set_fuel(1000);
+        let expected = codegen.call::<_, i32>(name, (arg,)).unwrap() as i64;
+        assert_eq!(int(interpreter.call(name, &[Value::Int(arg as i64)]).unwrap()), expected);
+        assert_eq!(interpreter.fuel(), codegen.fuel(), "fuel left after {}", name);
+    }
+
+    interpreter.set_fuel(50);
+    let err = interpreter.call("spin", &[]).unwrap_err();
+    assert_eq!(err.to_string(), "out of fuel in `spin` at line 12");
+}
+
+#[test]
+fn test_globals_function_pointers_and_strings() {
+    let source = r#"
+signed char small = 300;
+int negative = -5;
+float ratio = 2;
+char *name = "name";
+int twice(int x) { return x * 2; }
+int (*op)(int x) = twice;
+int apply(int (*f)(int x), int x) { return f(x); }
+int use_globals() { negative = negative * 2; return small + negative + op(ratio); }
+bool same_literal() { char *a = "x"; char *b = a; return a == b; }
+bool different_literals() { return "x" == "x"; }
+int main() { return apply(twice, 21); }
+"#;
+    let mut interpreter = interpreter(source);
+    assert_eq!(int(interpreter.run_main::<i32>().unwrap()), 42);
+    assert_eq!(int(interpreter.call("use_globals", &[]).unwrap()), 44 - 10 + 4);
+    assert_eq!(int(interpreter.call("use_globals", &[]).unwrap()), 44 - 20 + 4);
+    assert_eq!(int(interpreter.call("same_literal", &[]).unwrap()), 1);
+    assert_eq!(int(interpreter.call("different_literals", &[]).unwrap()), 0);
+    let applied = interpreter.call("apply", &[Value::FuncPtr(Some("twice".into())), Value::Int(5)]);
+    assert_eq!(int(applied.unwrap()), 10);
+}
+
+#[test]
+fn test_variadics_and_host_functions() {
+    let source = r#"
+int record(char *format, ...);
+double sum(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    double total = 0;
+    for (int i = 0; i < count; i++) {
+        if (i % 2 == 0) { total += va_arg(args, int); } else { total += va_arg(args, double); }
+    }
+    va_end(args);
+    return total;
+}
+int main() { float half = 0.5; return record("%d %c %s", 7, 'x', "ok") + sum(4, 1, half, 2, 0.25); }
+"#;
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut interpreter = interpreter(source);
+    let recorded = calls.clone();
+    interpreter.register_function("record", move |args| {
+        recorded.borrow_mut().push(args.to_vec());
+        Some(Value::Int(100))
+    });
+    // 100 + 1 + 0.5 + 2 + 0.25, truncated
+    assert_eq!(int(interpreter.run_main::<i32>().unwrap()), 103);
+    let calls = calls.borrow();
+    // `char` is promoted to `int`, and `f32` to `f64`
+    assert_eq!(calls[0][1..3], [Value::Int(7), Value::Int('x' as i64)]);
+
+    interpreter.register_function("record", |_| panic!("host failure"));
+    let err = interpreter.run_main::<i32>().unwrap_err();
+    assert!(matches!(&err, CompileError::HostPanic(message) if message == "host failure"), "{}", err);
+}
+
+#[test]
+fn test_load_errors_match_codegen() {
+    let load = |source: &str| Interpreter::new().load_program(parse(source).unwrap()).unwrap_err();
+    assert!(matches!(load("int x = 1 + 2;"), CompileError::Unsupported(_)));
+    let err = load("int f() { return 1; }\nint f() { return 2; }");
+    assert!(matches!(err, CompileError::Module(_)), "{}", err);
+
+    // imports have to be registered
+    let mut interpreter = interpreter("int missing(int x);\nint main() { return missing(1); }\nint g() { break; }");
+    assert!(matches!(interpreter.run_main::<i32>().unwrap_err(), CompileError::Unsupported(_)));
+    assert!(matches!(interpreter.call("g", &[]).unwrap_err(), CompileError::OutsideLoop("break")));
+    let err = interpreter.call("main", &[Value::Int(1)]).unwrap_err();
+    assert!(matches!(err, CompileError::ArgumentCount { .. }), "{}", err);
+}
+
+
+#[test]
+fn test_rejects_what_codegen_rejects() {
+    let programs = [
+        // functions and globals only see what's declared before them
+        "int main() { return later(); }\nint later() { return 7; }",
+        "int x;\nint x;\nint main() { return 0; }",
+        "int x;\nint x() { return 0; }\nint main() { return 0; }",
+        "int f(int a);\nlong f(int a);\nint main() { return 0; }",
+        "long main() { return 0; }",
+        "void main() { }",
+        "int main(int argc) { return argc; }",
+    ];
+    for source in programs {
+        let mut codegen = Compiler::new().unwrap().build();
+        let jit_err = codegen
+            .compile_program(parse(source).unwrap())
+            .and_then(|()| codegen.run_main::<i32>())
+            .unwrap_err();
+        let mut interpreter = Interpreter::new();
+        let err = interpreter
+            .load_program(parse(source).unwrap())
+            .and_then(|()| interpreter.run_main::<i32>())
+            .unwrap_err();
+        assert_eq!(err.to_string(), jit_err.to_string(), "{}", source);
+    }
+}
+
+#[test]
+fn test_float_shifts_are_type_errors() {
+    let source = "int main() { double x = 1.5; return x << 1; }";
+    let err = interpreter(source).run_main::<i32>().unwrap_err();
+    assert_eq!(err.to_string(), "type mismatch: expected I64, found F64");
+
+    let mut codegen = Compiler::new().unwrap().build();
+    let jit_err = codegen.compile_program(parse(source).unwrap()).unwrap_err();
+    assert_eq!(err.to_string(), jit_err.to_string());
+}
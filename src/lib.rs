@@ -1,4 +1,50 @@
 pub mod codegen;
+pub mod compiler;
+mod debug;
+#[cfg(test)]
+mod debug_tests;
+#[cfg(test)]
+mod compiler_tests;
+#[cfg(test)]
+mod codegen_tests;
 pub mod codegen_solo_tests;
+pub mod error;
+mod fuel;
+#[cfg(test)]
+mod fuel_tests;
+pub mod fuzz;
+#[cfg(test)]
+mod fuzz_tests;
+pub mod host;
 pub mod module;
-pub mod ast;
\ No newline at end of file
+pub mod parser;
+pub mod repl;
+pub mod trap;
+#[cfg(test)]
+mod trap_tests;
+pub mod interpreter;
+#[cfg(test)]
+mod interpreter_tests;
+pub mod reduce;
+#[cfg(test)]
+mod reduce_tests;
+pub mod report;
+pub mod sandbox;
+#[cfg(test)]
+mod sandbox_tests;
+pub mod stack;
+#[cfg(test)]
+mod stack_tests;
+#[cfg(test)]
+mod test_helpers;
+mod unwind;
+// `.eh_frame` is only registered where `unwind` can do it; elsewhere a panic
+// through JIT frames aborts
+#[cfg(all(test, not(windows)))]
+mod unwind_tests;
+#[cfg(test)]
+mod repl_tests;
+#[cfg(test)]
+mod parser_tests;
+pub mod variadic;
+pub mod ast;
@@ -1,3 +1,476 @@
-fn main() {
-    println!("Hello, world!");
+use compiler_test::ast::Program;
+use compiler_test::compiler::{self, CompilerBuilder, OptLevel};
+use compiler_test::error::CompileError;
+use compiler_test::interpreter::{Interpreter, Value};
+use compiler_test::parser;
+use compiler_test::reduce;
+use compiler_test::repl::Repl;
+use compiler_test::sandbox::SandboxOptions;
+use compiler_test::stack::StackOptions;
+use compiler_test::report::{CompileOptions, FunctionReport};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::{env, fs, io, thread};
+use target_lexicon::Triple;
+
+const USAGE: &str = "\
+usage: compiler_test <command> [options] <input>
+       compiler_test repl [options]
+
+commands:
+  run      compile with the JIT and run `main`, exiting with its return value
+  build    compile to an executable, or an object file with --object
+  check    parse and type check only
+  emit     print or write an intermediate form, selected with --emit
+  reduce   shrink the program while the --test command keeps succeeding on it,
+           printing what's left as AST JSON
+  repl     read declarations, statements and expressions from stdin, printing
+           the value of each expression; `:quit` or end of input stops
+
+options:
+  --target <triple>     target to compile for (default: the host)
+  -O <level>            none, speed or speed_and_size (default: none)
+  --cpu-flag <name[=value]>
+                        set a Cranelift ISA setting, like has_avx2=false; repeatable
+  --no-verifier         skip checking the generated CLIF
+  --checked             trap on integer overflow instead of wrapping
+  --stack-size <bytes>  with `run`, run `main` on a thread with this much stack
+  --fuel <n>            with `run`, stop after n function calls and loop iterations
+  --interpret           with `run`, evaluate the program with the reference
+                        interpreter instead of compiling it; only printf, puts and
+                        putchar can be imported
+  --sandbox <imports>   with `run`, keep strings in a linear memory of the program's
+                        own and allow only the comma-separated host functions
+  --test <command>      with `reduce`, a shell command run on each candidate, which
+                        is saved as AST JSON and passed as $1; exiting with 0
+                        keeps it
+  -g                    DWARF debug info in ELF objects; with `run`, register the
+                        JIT code with GDB
+  --pic, --no-pic       position-independent code (default: on for ELF and Mach-O
+                        objects, off for COFF and the JIT)
+  -o <path>             output path for `build`, `reduce` and `--emit=obj`
+  --object              make `build` stop at the object file
+  --emit <kind>         ast-json, clif, opt-clif (after optimization), asm, obj,
+                        or report (JSON with all of these and code sizes per function)
+
+<input> is C source, or the AST as JSON when it ends in `.json`; `-` reads stdin.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Run,
+    Build,
+    Check,
+    Emit,
+    Reduce,
+    Repl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    AstJson,
+    Clif,
+    OptClif,
+    Asm,
+    Obj,
+    Report,
+}
+
+struct Options {
+    command: Command,
+    input: String,
+    target: Triple,
+    opt_level: OptLevel,
+    output: Option<PathBuf>,
+    object: bool,
+    emit: Option<Emit>,
+    cpu_flags: Vec<(String, String)>,
+    verifier: bool,
+    pic: Option<bool>,
+    debug_info: bool,
+    checked: bool,
+    stack_size: Option<usize>,
+    fuel: Option<u64>,
+    sandbox: Option<Vec<String>>,
+    interpret: bool,
+    test: Option<String>,
+}
+
+impl Options {
+    fn compiler_builder(&self, jit: bool) -> CompilerBuilder {
+        let mut builder = CompilerBuilder::new();
+        builder
+            .triple(if jit { Triple::host() } else { self.target.clone() })
+            .opt_level(self.opt_level)
+            .enable_verifier(self.verifier)
+            .is_pic(self.pic.unwrap_or(!jit && compiler::pic_objects(&self.target)));
+        for (name, value) in &self.cpu_flags {
+            builder.cpu_flag(name, value);
+        }
+        builder.checked_arithmetic(self.checked).consume_fuel(self.fuel.is_some());
+        if self.debug_info {
+            let file = if self.input.is_empty() || self.input == "-" { "<stdin>" } else { &self.input };
+            builder.debug_info(file);
+        }
+        builder
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(&options) {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let (command, rest) = args.split_first().ok_or("missing command")?;
+    let command = match command.as_str() {
+        "run" => Command::Run,
+        "build" => Command::Build,
+        "check" => Command::Check,
+        "emit" => Command::Emit,
+        "reduce" => Command::Reduce,
+        "repl" => Command::Repl,
+        other => return Err(format!("unknown command `{}`", other)),
+    };
+
+    let mut options = Options {
+        command,
+        input: String::new(),
+        target: Triple::host(),
+        opt_level: OptLevel::None,
+        output: None,
+        object: false,
+        emit: None,
+        cpu_flags: Vec::new(),
+        verifier: true,
+        pic: None,
+        debug_info: false,
+        checked: false,
+        stack_size: None,
+        fuel: None,
+        sandbox: None,
+        interpret: false,
+        test: None,
+    };
+    let mut inputs = Vec::new();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        // `--flag value` and `--flag=value` are both accepted
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| rest.next().cloned())
+                .ok_or_else(|| format!("`{}` needs a value", name))
+        };
+        match flag {
+            "--target" => {
+                let triple = value(flag)?;
+                options.target = Triple::from_str(&triple).map_err(|err| format!("invalid target `{}`: {}", triple, err))?;
+            }
+            "-O" | "--opt-level" => options.opt_level = OptLevel::from_str(&value(flag)?)?,
+            "-o" => options.output = Some(PathBuf::from(value(flag)?)),
+            "--object" => options.object = true,
+            "--cpu-flag" => {
+                let flag = value(flag)?;
+                let (name, value) = flag.split_once('=').unwrap_or((&flag, "true"));
+                options.cpu_flags.push((name.to_string(), value.to_string()));
+            }
+            "--no-verifier" => options.verifier = false,
+            "--checked" => options.checked = true,
+            "--fuel" => {
+                let fuel = value(flag)?;
+                options.fuel = Some(fuel.parse().map_err(|_| format!("invalid fuel `{}`", fuel))?);
+            }
+            "--sandbox" => {
+                let imports = value(flag)?;
+                options.sandbox = Some(imports.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect());
+            }
+            "--interpret" => options.interpret = true,
+            "--test" => options.test = Some(value(flag)?),
+            "--stack-size" => {
+                let size = value(flag)?;
+                options.stack_size = Some(size.parse().map_err(|_| format!("invalid stack size `{}`", size))?);
+            }
+            "-g" => options.debug_info = true,
+            "--pic" => options.pic = Some(true),
+            "--no-pic" => options.pic = Some(false),
+            "--emit" => {
+                options.emit = Some(match value(flag)?.as_str() {
+                    "ast-json" => Emit::AstJson,
+                    "clif" => Emit::Clif,
+                    "opt-clif" => Emit::OptClif,
+                    "asm" => Emit::Asm,
+                    "obj" => Emit::Obj,
+                    "report" => Emit::Report,
+                    other => return Err(format!("unknown emit kind `{}`", other)),
+                })
+            }
+            _ if flag.starts_with("-O") => options.opt_level = OptLevel::from_str(&flag[2..])?,
+            _ if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option `{}`", flag)),
+            _ => inputs.push(arg.clone()),
+        }
+    }
+
+    options.input = match inputs.as_slice() {
+        [] if command == Command::Repl => String::new(),
+        _ if command == Command::Repl => return Err("`repl` doesn't take an input file".to_string()),
+        [input] => input.clone(),
+        [] => return Err("missing input file".to_string()),
+        _ => return Err("expected a single input file".to_string()),
+    };
+    if options.command == Command::Emit && options.emit.is_none() {
+        return Err("`emit` needs `--emit <kind>`".to_string());
+    }
+    if options.command != Command::Emit && options.emit.is_some() {
+        return Err("`--emit` only applies to the `emit` command".to_string());
+    }
+    if (options.command == Command::Reduce) != options.test.is_some() {
+        return Err("`reduce` needs `--test <command>`, which only applies to it".to_string());
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<ExitCode, String> {
+    if options.command == Command::Repl {
+        return repl(options);
+    }
+    let program = load_program(&options.input)?;
+    let error = |err: CompileError| format!("{}: error: {}", options.input, err);
+    let stem = if options.input == "-" {
+        "out".to_string()
+    } else {
+        Path::new(&options.input)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "out".to_string())
+    };
+    let builder = options.compiler_builder(false);
+
+    match (options.command, options.emit) {
+        (Command::Run, _) if options.interpret => {
+            let code = match options.stack_size {
+                Some(stack_size) => thread::scope(|scope| {
+                    thread::Builder::new()
+                        .stack_size(stack_size)
+                        .spawn_scoped(scope, || interpret(options, program).map_err(error))
+                        .map_err(|err| format!("error: can't start the interpreter thread: {}", err))?
+                        .join()
+                        .map_err(|_| "error: the interpreter panicked".to_string())?
+                })?,
+                None => interpret(options, program).map_err(error)?,
+            };
+            // like a C runtime, keep the low byte
+            Ok(ExitCode::from(code as u8))
+        }
+        (Command::Run, _) => {
+            if options.target != Triple::host() {
+                return Err(format!("error: `run` only supports the host target, not {}", options.target));
+            }
+            let mut builder = options.compiler_builder(true);
+            if let Some(imports) = &options.sandbox {
+                builder.sandbox(SandboxOptions {
+                    imports: imports.clone(),
+                    ..SandboxOptions::default()
+                });
+            }
+            let mut codegen = builder.compiler().map_err(error)?.build();
+            codegen.compile_program(program).map_err(error)?;
+            codegen.set_fuel(options.fuel.unwrap_or_default());
+            let stack = options.stack_size.map(StackOptions::thread).unwrap_or_default();
+            let code: i32 = codegen.call_with_stack("main", (), stack).map_err(error)?;
+            // like a C runtime, keep the low byte
+            Ok(ExitCode::from(code as u8))
+        }
+        (Command::Check, _) => {
+            let mut codegen = builder.object_codegen(&stem).map_err(error)?;
+            codegen.compile_program(program).map_err(error)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Build, _) if options.object => {
+            let output = options.output.clone().unwrap_or_else(|| PathBuf::from(format!("{}.o", stem)));
+            compiler::compile_to_object_with(program, &output, &builder).map_err(error)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Build, _) => {
+            if options.target != Triple::host() {
+                return Err(format!(
+                    "error: can't link executables for {}; use `--object` and link them yourself",
+                    options.target
+                ));
+            }
+            let output = options.output.clone().unwrap_or_else(|| PathBuf::from(&stem));
+            compiler::compile_to_executable_with(program, &output, &builder).map_err(error)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Emit, Some(Emit::AstJson)) => {
+            let json = serde_json::to_string_pretty(&program).map_err(|err| format!("error: {}", err))?;
+            write_output(options.output.as_deref(), &json)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Emit, Some(Emit::Obj)) => {
+            let output = options.output.clone().unwrap_or_else(|| PathBuf::from(format!("{}.o", stem)));
+            compiler::compile_to_object_with(program, &output, &builder).map_err(error)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Emit, Some(kind)) => {
+            let mut codegen = builder.object_codegen(&stem).map_err(error)?;
+            let (compile_options, listing): (_, fn(&FunctionReport) -> Option<&String>) = match kind {
+                Emit::Clif => (CompileOptions { clif: true, ..Default::default() }, |f| f.clif.as_ref()),
+                Emit::OptClif => (CompileOptions { optimized_clif: true, ..Default::default() }, |f| f.optimized_clif.as_ref()),
+                Emit::Asm => (CompileOptions { disasm: true, ..Default::default() }, |f| f.disasm.as_ref()),
+                _ => (CompileOptions::all(), |_| None),
+            };
+            codegen.set_options(compile_options);
+            codegen.compile_program(program).map_err(error)?;
+            let text = if kind == Emit::Report {
+                serde_json::to_string_pretty(codegen.report()).map_err(|err| format!("error: {}", err))?
+            } else {
+                let mut text = String::new();
+                for function in &codegen.report().functions {
+                    let listing = listing(function).map_or("", |listing| listing.trim_end());
+                    text.push_str(&format!("; {}\n{}\n", function.name, listing));
+                }
+                text
+            };
+            write_output(options.output.as_deref(), &text)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Reduce, _) => {
+            let command = options.test.as_deref().expect("checked by parse_args");
+            let dir = compiler::private_temp_dir("compiler_test-reduce").map_err(|err| format!("error: {}", err))?;
+            let candidate = dir.join("candidate.json");
+            let mut interesting = reduce::command_predicate(command, &candidate);
+            let reduced = if interesting(&program) { Some(reduce::reduce(program, interesting)) } else { None };
+            let _ = fs::remove_dir_all(&dir);
+            let reduced = reduced.ok_or_else(|| format!("{}: error: `{}` fails on the input itself", options.input, command))?;
+            let json = serde_json::to_string_pretty(&reduced).map_err(|err| format!("error: {}", err))?;
+            write_output(options.output.as_deref(), &json)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (Command::Emit, None) => unreachable!("checked by parse_args"),
+        (Command::Repl, _) => unreachable!("handled above"),
+    }
+}
+
+fn interpret(options: &Options, program: Program) -> Result<i64, CompileError> {
+    let mut interpreter = Interpreter::new();
+    interpreter.register_stdio().set_checked_arithmetic(options.checked);
+    interpreter.set_consume_fuel(options.fuel.is_some());
+    interpreter.set_fuel(options.fuel.unwrap_or_default());
+    interpreter.load_program(program)?;
+    match interpreter.run_main::<i32>()? {
+        Some(Value::Int(code)) => Ok(code),
+        _ => Ok(0),
+    }
+}
+
+fn repl(options: &Options) -> Result<ExitCode, String> {
+    if options.target != Triple::host() {
+        return Err(format!("error: `repl` only supports the host target, not {}", options.target));
+    }
+    let compiler = options.compiler_builder(true).compiler().map_err(|err| format!("error: {}", err))?;
+    let mut repl = Repl::new(compiler);
+    let interactive = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    let mut source = String::new();
+    loop {
+        if interactive {
+            print!("{}", if source.is_empty() { "> " } else { "... " });
+            let _ = io::stdout().flush();
+        }
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| format!("error: can't read stdin: {}", err))?,
+            None => break,
+        };
+        if source.is_empty() && line.trim() == ":quit" {
+            break;
+        }
+        source.push_str(&line);
+        source.push('\n');
+        // keep reading until a definition spanning several lines is complete
+        if nesting(&source) > 0 {
+            continue;
+        }
+        match repl.eval(&std::mem::take(&mut source)) {
+            Ok(values) => {
+                for value in values {
+                    println!("{}", value);
+                }
+            }
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// How many more `{` and `(` than closing ones `source` has, outside literals.
+fn nesting(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{' | '(') => depth += 1,
+            (None, '}' | ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn load_program(input: &str) -> Result<Program, String> {
+    let source = if input == "-" {
+        let mut source = String::new();
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|err| format!("error: can't read stdin: {}", err))?;
+        source
+    } else {
+        fs::read_to_string(input).map_err(|err| format!("error: can't read {}: {}", input, err))?
+    };
+
+    if input.ends_with(".json") || source.trim_start().starts_with('{') {
+        Program::from_json(&source).map_err(|err| format!("{}: error: invalid AST JSON: {}", input, err))
+    } else {
+        parser::parse(&source).map_err(|err| format!("{}:{}:{}: error: {}", input, err.line, err.column, err.message))
+    }
+}
+
+fn write_output(path: Option<&Path>, text: &str) -> Result<(), String> {
+    match path {
+        Some(path) => fs::write(path, text).map_err(|err| format!("error: can't write {}: {}", path.display(), err)),
+        // a closed pipe (`| head`) isn't an error
+        None => match writeln!(io::stdout().lock(), "{}", text.trim_end()) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(format!("error: can't write to stdout: {}", err)),
+            _ => Ok(()),
+        },
+    }
 }
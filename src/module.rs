@@ -7,6 +7,10 @@ use cranelift_module::{
     DataDescription, DataId, FuncId, FuncOrDataId, Linkage, Module, ModuleDeclarations,
     ModuleResult,
 };
+use crate::error::{CompileError, CompileResult};
+use crate::host::{host_signature, HostArgs, HostReturn};
+use crate::stack::{self, StackOptions};
+use crate::trap;
 use delegate::delegate;
 use ir::{FuncRef, Function, GlobalValue};
 use isa::{TargetFrontendConfig, TargetIsa};
@@ -18,6 +22,8 @@ pub enum ModuleType {
 }
 
 // this is a helper to delegate the methods to the correct underlying method
+// (the signatures mirror `cranelift_module::Module`, including its large error type)
+#[allow(clippy::result_large_err)]
 impl ModuleType {
     delegate! {
         to match self {
@@ -32,6 +38,8 @@ impl ModuleType {
             pub fn declare_data(&mut self, name: &str, linkage: Linkage, writable: bool, tls: bool) -> ModuleResult<DataId>;
             pub fn declare_anonymous_data(&mut self, writable: bool, tls: bool) -> ModuleResult<DataId>;
             pub fn declare_data_in_func(&mut self, data_id: DataId, func: &mut Function) -> GlobalValue;
+            pub fn declare_func_in_data(&self, func_id: FuncId, data: &mut DataDescription) -> ir::FuncRef;
+            pub fn declare_data_in_data(&self, data_id: DataId, data: &mut DataDescription) -> GlobalValue;
             pub fn define_function(&mut self, func_id: FuncId, ctx: &mut Context) -> ModuleResult<()>;
             pub fn define_function_with_control_plane(&mut self, func_id: FuncId, ctx: &mut Context, ctrl_plane: &mut ControlPlane) -> ModuleResult<()>;
             pub fn define_function_bytes(&mut self, func_id: FuncId, func: &Function, alignment: u64, bytes: &[u8], relocs: &[FinalizedMachReloc]) -> ModuleResult<()>;
@@ -46,3 +54,66 @@ impl ModuleType {
     }
 }
 
+impl ModuleType {
+    // Calls `func_id`, which has to have a definition in this (JIT) module, after
+    // finalizing any pending definitions. The Rust argument and return types are
+    // checked against the function's signature first. Pointer arguments are passed
+    // through untouched, so they must stay valid for whatever the function does.
+    pub fn call<A: HostArgs, R: HostReturn>(&mut self, func_id: FuncId, args: A) -> CompileResult<R> {
+        self.call_with_stack(func_id, args, StackOptions::default())
+    }
+
+    // `call`, with `stack` limiting how much stack the function may use or
+    // running it on a thread of its own.
+    pub fn call_with_stack<A: HostArgs, R: HostReturn>(
+        &mut self,
+        func_id: FuncId,
+        args: A,
+        stack: StackOptions,
+    ) -> CompileResult<R> {
+        let Self::JITModule(jit) = self else {
+            return Err(CompileError::Unsupported(
+                "running functions from an object module".to_string(),
+            ));
+        };
+
+        let decl = jit.declarations().get_function_decl(func_id);
+        let name = decl.linkage_name(func_id).into_owned();
+        if decl.linkage == Linkage::Import {
+            return Err(CompileError::MissingDefinition(name));
+        }
+        let requested = host_signature::<A, R>(jit.target_config().pointer_type())?;
+        if !same_signature(&decl.signature, &requested) {
+            return Err(CompileError::EntrySignature {
+                name,
+                signature: decl.signature.to_string(),
+                requested: requested.to_string(),
+            });
+        }
+
+        jit.finalize_definitions()?;
+        let func_ptr = jit.get_finalized_function(func_id);
+        // the signature check above makes this the function's actual type
+        stack::run(jit, stack, || trap::catch_traps(|| unsafe { args.invoke(func_ptr) }))
+    }
+
+    // Finishes an object module into the contents of a relocatable object file.
+    pub fn emit_object(self) -> CompileResult<Vec<u8>> {
+        let Self::ObjectModule(obj) = self else {
+            return Err(CompileError::Unsupported(
+                "emitting an object file from a JIT module".to_string(),
+            ));
+        };
+        obj.finish()
+            .emit()
+            .map_err(|err| CompileError::Object(err.to_string()))
+    }
+}
+
+// extensions and other ABI attributes don't change how the host calls a function
+fn same_signature(left: &Signature, right: &Signature) -> bool {
+    let types = |params: &[AbiParam]| params.iter().map(|param| param.value_type).collect::<Vec<_>>();
+    left.call_conv == right.call_conv
+        && types(&left.params) == types(&right.params)
+        && types(&left.returns) == types(&right.returns)
+}
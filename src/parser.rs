@@ -0,0 +1,1147 @@
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+// A parser for the C subset the compiler understands, producing the same
+// `Program` as the JSON form. Constructs the AST can't represent yet are parse
+// errors rather than silent approximations.
+
+#[derive(Debug, Error)]
+#[error("{line}:{column}: {message}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+pub fn parse(source: &str) -> ParseResult<Program> {
+    let tokens = Lexer::new(source).lex()?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        scopes: vec![HashMap::new()],
+        functions: HashSet::new(),
+    };
+    parser.parse_program()
+}
+
+// Names declared by earlier REPL lines: globals with their types, and functions.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    globals: HashMap<String, AstType>,
+    functions: HashSet<String>,
+}
+
+// One piece of a REPL line, in source order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplItem {
+    // function definitions and prototypes, and global variables
+    Decl(Stmt),
+    Stmt(Stmt),
+    Expr(Expr),
+}
+
+// Parses a REPL line: any mix of top-level declarations, statements and
+// expressions. Declarations are added to `symbols`.
+pub fn parse_line(source: &str, symbols: &mut Symbols) -> ParseResult<Vec<ReplItem>> {
+    let tokens = Lexer::new(source).lex()?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        scopes: vec![symbols.globals.clone()],
+        functions: symbols.functions.clone(),
+    };
+    let mut items = parser.parse_repl_items()?;
+    mark_external(items.iter_mut().filter_map(|item| match item {
+        ReplItem::Decl(stmt) => Some(stmt),
+        _ => None,
+    }));
+    symbols.globals = parser.scopes.swap_remove(0);
+    symbols.functions = parser.functions;
+    Ok(items)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+// longest first, so the first match is the right one
+const PUNCTUATORS: &[&str] = &[
+    "...", "<<=", ">>=", "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "->", "+", "-", "*", "/", "%", "=", "<", ">", "!", "~",
+    "&", "|", "^", "(", ")", "{", "}", "[", "]", ";", ",", "?", ":", ".",
+];
+
+const TYPE_KEYWORDS: &[&str] = &[
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+    "bool", "const", "volatile", "va_list", "struct", "enum", "union", "size_t", "uintptr_t",
+    "ssize_t", "intptr_t", "ptrdiff_t",
+];
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    fn lex(mut self) -> ParseResult<Vec<Token>> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_trivia()?;
+            let (line, column) = (self.line, self.column);
+            let Some(c) = self.peek(0) else {
+                tokens.push(Token {
+                    kind: TokenKind::Eof,
+                    line,
+                    column,
+                });
+                return Ok(tokens);
+            };
+            let kind = if c.is_ascii_digit() || (c == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit())) {
+                self.lex_number()?
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut ident = String::new();
+                while let Some(c) = self.peek(0).filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                    self.bump();
+                }
+                TokenKind::Ident(ident)
+            } else if c == '\'' {
+                self.bump();
+                let value = self.lex_char_in_literal('\'')?;
+                if self.bump() != Some('\'') {
+                    return Err(self.error("unterminated character literal"));
+                }
+                TokenKind::Char(value)
+            } else if c == '"' {
+                self.bump();
+                let mut value = String::new();
+                while self.peek(0) != Some('"') {
+                    value.push(self.lex_char_in_literal('"')?);
+                }
+                self.bump();
+                TokenKind::Str(value)
+            } else {
+                let punct = PUNCTUATORS
+                    .iter()
+                    .find(|punct| punct.chars().enumerate().all(|(i, p)| self.peek(i) == Some(p)))
+                    .ok_or_else(|| self.error(format!("unexpected character `{}`", c)))?;
+                for _ in 0..punct.len() {
+                    self.bump();
+                }
+                TokenKind::Punct(punct)
+            };
+            tokens.push(Token { kind, line, column });
+        }
+    }
+
+    // whitespace, comments and preprocessor lines
+    fn skip_trivia(&mut self) -> ParseResult<()> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) | (Some('#'), _) => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    self.bump();
+                    self.bump();
+                    while !(self.peek(0) == Some('*') && self.peek(1) == Some('/')) {
+                        if self.bump().is_none() {
+                            return Err(self.error("unterminated comment"));
+                        }
+                    }
+                    self.bump();
+                    self.bump();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn lex_number(&mut self) -> ParseResult<TokenKind> {
+        let mut text = String::new();
+        while let Some(c) = self.peek(0) {
+            let is_hex = text.starts_with("0x") || text.starts_with("0X");
+            // an exponent's sign is part of the number
+            let is_exponent_sign = (c == '+' || c == '-') && !is_hex && text.ends_with(['e', 'E']);
+            if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || is_exponent_sign) {
+                break;
+            }
+            text.push(c);
+            self.bump();
+        }
+
+        let invalid = || self.error(format!("invalid number `{}`", text));
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            let digits = hex.trim_end_matches(['u', 'U', 'l', 'L']);
+            return i64::from_str_radix(digits, 16)
+                .map(TokenKind::Int)
+                .map_err(|_| invalid());
+        }
+        if text.contains(['.', 'e', 'E']) {
+            let digits = text.trim_end_matches(['f', 'F', 'l', 'L']);
+            return digits.parse().map(TokenKind::Float).map_err(|_| invalid());
+        }
+        let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+        let value = if digits.len() > 1 && digits.starts_with('0') {
+            i64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+        value.map(TokenKind::Int).map_err(|_| invalid())
+    }
+
+    fn lex_char_in_literal(&mut self, quote: char) -> ParseResult<char> {
+        let c = match self.bump() {
+            None | Some('\n') => {
+                return Err(self.error(if quote == '"' {
+                    "unterminated string literal"
+                } else {
+                    "unterminated character literal"
+                }))
+            }
+            Some(c) => c,
+        };
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = match self.bump() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('f') => '\x0c',
+            Some('v') => '\x0b',
+            Some(c @ ('\\' | '\'' | '"' | '?')) => c,
+            Some('x') => {
+                let mut value = 0u32;
+                while let Some(digit) = self.peek(0).and_then(|c| c.to_digit(16)) {
+                    value = value * 16 + digit;
+                    self.bump();
+                }
+                char::from_u32(value).ok_or_else(|| self.error("invalid escape sequence"))?
+            }
+            Some(c @ '0'..='7') => {
+                let mut value = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.peek(0).and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            self.bump();
+                        }
+                        None => break,
+                    }
+                }
+                char::from_u32(value).ok_or_else(|| self.error("invalid escape sequence"))?
+            }
+            _ => return Err(self.error("invalid escape sequence")),
+        };
+        Ok(escaped)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // variables in scope, innermost last; the first scope holds the globals
+    scopes: Vec<HashMap<String, AstType>>,
+    functions: HashSet<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    // where the next token starts
+    fn span(&self) -> Span {
+        let token = &self.tokens[self.pos];
+        Span {
+            line: token.line as u32,
+            column: token.column as u32,
+        }
+    }
+
+    fn peek_at(&self, offset: usize) -> &TokenKind {
+        let index = (self.pos + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    fn advance(&mut self) -> TokenKind {
+        let kind = self.tokens[self.pos].kind.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        kind
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), TokenKind::Punct(p) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), TokenKind::Ident(name) if name == keyword)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> ParseResult<()> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`, found {}", punct, self.describe())))
+        }
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            TokenKind::Ident(name) if !TYPE_KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error(format!("expected an identifier, found {}", self.describe()))),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            TokenKind::Ident(name) => format!("`{}`", name),
+            TokenKind::Int(value) => format!("`{}`", value),
+            TokenKind::Float(value) => format!("`{}`", value),
+            TokenKind::Char(value) => format!("{:?}", value),
+            TokenKind::Str(value) => format!("{:?}", value),
+            TokenKind::Punct(punct) => format!("`{}`", punct),
+            TokenKind::Eof => "end of file".to_string(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let token = &self.tokens[self.pos];
+        ParseError {
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn unsupported(&self, what: &str) -> ParseError {
+        self.error(format!("{} not supported yet", what))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&AstType> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, name: &str, type_: AstType) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), type_);
+    }
+
+    fn parse_program(&mut self) -> ParseResult<Program> {
+        let mut statements = Vec::new();
+        while *self.peek() != TokenKind::Eof {
+            let span = self.span();
+            statements.extend(self.parse_top_level()?.into_iter().map(|stmt| spanned(span, stmt)));
+        }
+        mark_external(statements.iter_mut().map(Stmt::unspanned_mut));
+        Ok(Program { statements })
+    }
+
+    fn parse_repl_items(&mut self) -> ParseResult<Vec<ReplItem>> {
+        let mut items = Vec::new();
+        while *self.peek() != TokenKind::Eof {
+            if self.eat_punct(";") {
+                continue;
+            }
+            let is_decl = self.starts_type()
+                || ["extern", "static", "inline", "typedef"].iter().any(|keyword| self.is_keyword(keyword));
+            let is_stmt = self.is_punct("{")
+                || ["if", "while", "do", "for", "return", "break", "continue", "switch", "goto"]
+                    .iter()
+                    .any(|keyword| self.is_keyword(keyword));
+            if is_decl {
+                items.extend(self.parse_top_level()?.into_iter().map(ReplItem::Decl));
+            } else if is_stmt {
+                items.extend(self.parse_stmt()?.into_iter().map(ReplItem::Stmt));
+            } else {
+                let item = if self.at_assignment() {
+                    ReplItem::Stmt(self.parse_simple_stmt()?)
+                } else {
+                    ReplItem::Expr(self.parse_expr()?)
+                };
+                // the last `;` on a line is optional
+                if !self.eat_punct(";") && *self.peek() != TokenKind::Eof {
+                    return Err(self.error(format!("expected `;`, found {}", self.describe())));
+                }
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    // `x = ...`, `x += ...`, `x++` or `++x`
+    fn at_assignment(&self) -> bool {
+        match (self.peek(), self.peek_at(1)) {
+            (TokenKind::Punct("++" | "--"), _) => true,
+            (TokenKind::Ident(_), &TokenKind::Punct(punct)) => {
+                punct == "=" || punct == "++" || punct == "--" || compound_op(punct).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_top_level(&mut self) -> ParseResult<Vec<Stmt>> {
+        if self.is_keyword("typedef") {
+            return Err(self.unsupported("typedefs are"));
+        }
+        while self.eat_keyword("extern") || self.eat_keyword("static") || self.eat_keyword("inline") {}
+
+        let base = self.parse_base_type()?;
+        let after_base = self.pos;
+        let type_ = self.parse_pointers(base.clone())?;
+        let is_function = matches!(self.peek(), TokenKind::Ident(_))
+            && matches!(self.peek_at(1), TokenKind::Punct("("));
+        if !is_function {
+            self.pos = after_base;
+            return self.parse_declarators(base);
+        }
+
+        let name = self.expect_ident()?;
+        self.advance();
+        let (params, variadic) = self.parse_params()?;
+        let decl = FuncDecl {
+            name: name.clone(),
+            params,
+            return_type: type_,
+            variadic,
+            extern_: false,
+        };
+        self.functions.insert(name);
+        if self.eat_punct(";") {
+            return Ok(vec![Stmt::FuncDecl(decl)]);
+        }
+
+        self.scopes.push(decl.params.iter().cloned().collect());
+        let body = self.parse_block();
+        self.scopes.pop();
+        Ok(vec![Stmt::FuncDef(FuncDef { decl, body: body? })])
+    }
+
+    fn starts_type(&self) -> bool {
+        matches!(self.peek(), TokenKind::Ident(name) if TYPE_KEYWORDS.contains(&name.as_str()))
+    }
+
+    // `None` is `void`
+    fn parse_base_type(&mut self) -> ParseResult<Option<AstType>> {
+        let (mut signed, mut unsigned, mut longs) = (false, false, 0);
+        let mut base: Option<&'static str> = None;
+        while let TokenKind::Ident(name) = self.peek() {
+            let keyword = match name.as_str() {
+                "const" | "volatile" => None,
+                "signed" => {
+                    signed = true;
+                    None
+                }
+                "unsigned" => {
+                    unsigned = true;
+                    None
+                }
+                "long" => {
+                    longs += 1;
+                    None
+                }
+                "struct" | "union" => return Err(self.unsupported("structs are")),
+                "enum" => return Err(self.unsupported("enums are")),
+                "void" => Some("void"),
+                "char" => Some("char"),
+                "short" => Some("short"),
+                "int" => Some("int"),
+                "float" => Some("float"),
+                "double" => Some("double"),
+                "_Bool" | "bool" => Some("bool"),
+                "va_list" => Some("va_list"),
+                "size_t" | "uintptr_t" => Some("size_t"),
+                "ssize_t" | "intptr_t" | "ptrdiff_t" => Some("ssize_t"),
+                _ => break,
+            };
+            if let Some(keyword) = keyword {
+                if base.is_some_and(|base| !(base == "int" || keyword == "int")) {
+                    return Err(self.error(format!("unexpected `{}` in type", keyword)));
+                }
+                if base.is_none() || base == Some("int") {
+                    base = Some(keyword);
+                }
+            }
+            self.advance();
+        }
+
+        let type_ = match (base, longs) {
+            (Some("void"), _) => None,
+            (Some("bool"), _) => Some(AstType::Bool),
+            (Some("float"), _) => Some(AstType::F32),
+            (Some("double"), _) => Some(AstType::F64),
+            (Some("va_list"), _) => Some(AstType::VaList),
+            (Some("size_t"), 0) if !signed && !unsigned => Some(AstType::Usize),
+            (Some("ssize_t"), 0) if !signed && !unsigned => Some(AstType::Isize),
+            (Some("char"), _) if unsigned => Some(AstType::U8),
+            (Some("char"), _) if signed => Some(AstType::I8),
+            (Some("char"), _) => Some(AstType::Char),
+            (Some("short"), _) if unsigned => Some(AstType::U16),
+            (Some("short"), _) => Some(AstType::I16),
+            (Some("int") | None, 1..) if unsigned => Some(AstType::U64),
+            (Some("int") | None, 1..) => Some(AstType::I64),
+            (Some("int"), 0) | (None, 0) if unsigned => Some(AstType::U32),
+            (Some("int"), 0) => Some(AstType::I32),
+            (None, 0) if signed => Some(AstType::I32),
+            _ => return Err(self.error(format!("expected a type, found {}", self.describe()))),
+        };
+        Ok(type_)
+    }
+
+    // Only byte pointers exist in the AST; they're all `String`.
+    fn parse_pointers(&mut self, mut type_: Option<AstType>) -> ParseResult<Option<AstType>> {
+        while self.eat_punct("*") {
+            while self.eat_keyword("const") || self.eat_keyword("volatile") {}
+            type_ = match type_ {
+                None | Some(AstType::Char | AstType::I8 | AstType::U8) => Some(AstType::String),
+                Some(other) => return Err(self.unsupported(&format!("pointers to {:?} are", other))),
+            };
+        }
+        Ok(type_)
+    }
+
+    // A declarator after the base type: `name`, or `(*name)(params)` for a function
+    // pointer. The name is optional for parameters.
+    fn parse_declarator(&mut self, type_: Option<AstType>) -> ParseResult<(Option<String>, Option<AstType>)> {
+        if self.is_punct("(") && matches!(self.peek_at(1), TokenKind::Punct("*")) {
+            self.advance();
+            self.advance();
+            let name = match self.peek() {
+                TokenKind::Ident(_) => Some(self.expect_ident()?),
+                _ => None,
+            };
+            self.expect_punct(")")?;
+            self.expect_punct("(")?;
+            let (params, variadic) = self.parse_params()?;
+            if variadic {
+                return Err(self.unsupported("variadic function pointers are"));
+            }
+            let type_ = AstType::FuncPtr {
+                params: params.into_iter().map(|(_, type_)| type_).collect(),
+                return_type: type_.map(Box::new),
+            };
+            return Ok((name, Some(type_)));
+        }
+        let name = match self.peek() {
+            TokenKind::Ident(name) if !TYPE_KEYWORDS.contains(&name.as_str()) => Some(self.expect_ident()?),
+            _ => None,
+        };
+        if self.is_punct("[") {
+            return Err(self.unsupported("arrays are"));
+        }
+        Ok((name, type_))
+    }
+
+    // after the opening parenthesis
+    fn parse_params(&mut self) -> ParseResult<(Vec<(String, AstType)>, bool)> {
+        let mut params = Vec::new();
+        if self.eat_punct(")") {
+            return Ok((params, false));
+        }
+        if self.is_keyword("void") && matches!(self.peek_at(1), TokenKind::Punct(")")) {
+            self.advance();
+            self.advance();
+            return Ok((params, false));
+        }
+        loop {
+            if self.eat_punct("...") {
+                self.expect_punct(")")?;
+                return Ok((params, true));
+            }
+            let base = self.parse_base_type()?;
+            let type_ = self.parse_pointers(base)?;
+            let (name, type_) = self.parse_declarator(type_)?;
+            let type_ = type_.ok_or_else(|| self.error("parameters can't have type `void`"))?;
+            params.push((name.unwrap_or_else(|| format!("arg{}", params.len())), type_));
+            if self.eat_punct(")") {
+                return Ok((params, false));
+            }
+            self.expect_punct(",")?;
+        }
+    }
+
+    // `a = 1, *s, (*f)(int);` after the base type
+    fn parse_declarators(&mut self, base: Option<AstType>) -> ParseResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        loop {
+            let type_ = self.parse_pointers(base.clone())?;
+            let (name, declared) = self.parse_declarator(type_)?;
+            let name = name.ok_or_else(|| self.error(format!("expected an identifier, found {}", self.describe())))?;
+            let declared = declared.ok_or_else(|| self.error(format!("variable `{}` can't have type `void`", name)))?;
+            let init = if self.eat_punct("=") {
+                Some(Box::new(self.parse_expr()?))
+            } else {
+                None
+            };
+            self.declare(&name, declared.clone());
+            stmts.push(Stmt::VarDecl(VarDecl {
+                name,
+                type_: declared,
+                init,
+            }));
+            if !self.eat_punct(",") {
+                self.expect_punct(";")?;
+                return Ok(stmts);
+            }
+        }
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Block> {
+        self.expect_punct("{")?;
+        self.scopes.push(HashMap::new());
+        let mut stmts = Vec::new();
+        let result = loop {
+            if self.eat_punct("}") {
+                break Ok(stmts);
+            }
+            if *self.peek() == TokenKind::Eof {
+                break Err(self.error("expected `}`, found end of file"));
+            }
+            let span = self.span();
+            match self.parse_stmt() {
+                Ok(parsed) => stmts.extend(parsed.into_iter().map(|stmt| spanned(span, stmt))),
+                Err(err) => break Err(err),
+            }
+        };
+        self.scopes.pop();
+        result
+    }
+
+    // a statement used as an `if` or loop body
+    fn parse_body(&mut self) -> ParseResult<Block> {
+        let span = self.span();
+        let mut stmts = self.parse_stmt()?;
+        if let [Stmt::Block(_)] = stmts.as_slice() {
+            if let Some(Stmt::Block(block)) = stmts.pop() {
+                return Ok(block);
+            }
+        }
+        Ok(stmts.into_iter().map(|stmt| spanned(span, stmt)).collect())
+    }
+
+    fn parse_stmt(&mut self) -> ParseResult<Vec<Stmt>> {
+        if self.is_punct("{") {
+            return Ok(vec![Stmt::Block(self.parse_block()?)]);
+        }
+        if self.eat_punct(";") {
+            return Ok(Vec::new());
+        }
+        if self.starts_type() {
+            let base = self.parse_base_type()?;
+            return self.parse_declarators(base);
+        }
+
+        let keyword = match self.peek() {
+            TokenKind::Ident(name) => name.clone(),
+            _ => String::new(),
+        };
+        let stmt = match keyword.as_str() {
+            "if" => {
+                self.advance();
+                self.expect_punct("(")?;
+                let condition = self.parse_expr()?;
+                self.expect_punct(")")?;
+                let then_branch = self.parse_body()?;
+                let else_branch = if self.eat_keyword("else") {
+                    Some(self.parse_body()?)
+                } else {
+                    None
+                };
+                Stmt::If(IfStmt {
+                    condition: Box::new(condition),
+                    then_branch,
+                    else_branch,
+                })
+            }
+            "while" => {
+                self.advance();
+                self.expect_punct("(")?;
+                let condition = self.parse_expr()?;
+                self.expect_punct(")")?;
+                Stmt::Loop(LoopStmt {
+                    kind: LoopKind::While,
+                    condition: Box::new(condition),
+                    body: self.parse_body()?,
+                })
+            }
+            "do" => {
+                self.advance();
+                let body = self.parse_body()?;
+                if !self.eat_keyword("while") {
+                    return Err(self.error(format!("expected `while`, found {}", self.describe())));
+                }
+                self.expect_punct("(")?;
+                let condition = self.parse_expr()?;
+                self.expect_punct(")")?;
+                self.expect_punct(";")?;
+                Stmt::Loop(LoopStmt {
+                    kind: LoopKind::DoWhile,
+                    condition: Box::new(condition),
+                    body,
+                })
+            }
+            "for" => {
+                self.advance();
+                self.scopes.push(HashMap::new());
+                let stmt = self.parse_for();
+                self.scopes.pop();
+                stmt?
+            }
+            "return" => {
+                self.advance();
+                let value = if self.is_punct(";") {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expr()?))
+                };
+                self.expect_punct(";")?;
+                Stmt::Return(Return { value })
+            }
+            "break" | "continue" => {
+                self.advance();
+                self.expect_punct(";")?;
+                if keyword == "break" {
+                    Stmt::Break
+                } else {
+                    Stmt::Continue
+                }
+            }
+            "switch" => return Err(self.unsupported("switch statements are")),
+            "goto" => return Err(self.unsupported("goto is")),
+            "typedef" => return Err(self.unsupported("typedefs are")),
+            _ => {
+                let stmt = self.parse_simple_stmt()?;
+                self.expect_punct(";")?;
+                stmt
+            }
+        };
+        Ok(vec![stmt])
+    }
+
+    // after `for`
+    fn parse_for(&mut self) -> ParseResult<Stmt> {
+        self.expect_punct("(")?;
+        let init = if self.eat_punct(";") {
+            None
+        } else if self.starts_type() {
+            let base = self.parse_base_type()?;
+            let mut decls = self.parse_declarators(base)?;
+            if decls.len() != 1 {
+                return Err(self.unsupported("several declarations in a `for` initializer are"));
+            }
+            decls.pop().map(Box::new)
+        } else {
+            let stmt = self.parse_simple_stmt()?;
+            self.expect_punct(";")?;
+            Some(Box::new(stmt))
+        };
+        let condition = if self.is_punct(";") {
+            Expr::Literal(Literal::Int(1))
+        } else {
+            self.parse_expr()?
+        };
+        self.expect_punct(";")?;
+        let step = if self.is_punct(")") {
+            None
+        } else {
+            Some(Box::new(self.parse_simple_stmt()?))
+        };
+        self.expect_punct(")")?;
+        Ok(Stmt::Loop(LoopStmt {
+            kind: LoopKind::For { init, step },
+            condition: Box::new(condition),
+            body: self.parse_body()?,
+        }))
+    }
+
+    // an assignment, increment or expression, without the `;`
+    fn parse_simple_stmt(&mut self) -> ParseResult<Stmt> {
+        if let TokenKind::Punct(punct @ ("++" | "--")) = *self.peek() {
+            self.advance();
+            let target = self.parse_assign_target()?;
+            return Ok(increment(target, punct));
+        }
+
+        if self.at_assignment() {
+            let TokenKind::Punct(punct) = *self.peek_at(1) else {
+                unreachable!("checked by at_assignment");
+            };
+            if punct == "++" || punct == "--" {
+                let target = self.parse_assign_target()?;
+                self.advance();
+                return Ok(increment(target, punct));
+            }
+            let target = self.parse_assign_target()?;
+            self.advance();
+            let mut value = self.parse_expr()?;
+            if let Some(op) = compound_op(punct) {
+                value = Expr::Binary(Box::new(Binary {
+                    op,
+                    left: Box::new(Expr::Variable(target.clone())),
+                    right: Box::new(value),
+                }));
+            }
+            return Ok(Stmt::Assign(Assign {
+                target,
+                value: Box::new(value),
+            }));
+        }
+
+        match self.parse_expr()? {
+            Expr::FuncCall(call) => Ok(Stmt::FuncCall(call)),
+            expr => Ok(Stmt::Expr(expr)),
+        }
+    }
+
+    fn parse_assign_target(&mut self) -> ParseResult<Variable_> {
+        let name = self.expect_ident()?;
+        match self.lookup(&name) {
+            Some(type_) => Ok(Variable_ {
+                name,
+                type_: type_.clone(),
+            }),
+            None => {
+                self.pos -= 1;
+                Err(self.error(format!("undeclared variable `{}`", name)))
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let TokenKind::Punct(punct) = *self.peek() else {
+                return Ok(left);
+            };
+            if let "&&" | "||" | "?" = punct {
+                return Err(self.unsupported(&format!("`{}` is", punct)));
+            }
+            if punct == "=" || compound_op(punct).is_some() {
+                return Err(self.unsupported("assignments inside expressions are"));
+            }
+            let Some((precedence, op)) = binary_op(punct) else {
+                return Ok(left);
+            };
+            if precedence < min_precedence {
+                return Ok(left);
+            }
+            self.advance();
+            let right = self.parse_binary(precedence + 1)?;
+            left = Expr::Binary(Box::new(Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            }));
+        }
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        let TokenKind::Punct(punct) = *self.peek() else {
+            return self.parse_postfix();
+        };
+        match punct {
+            "-" => {
+                self.advance();
+                Ok(match self.parse_unary()? {
+                    Expr::Literal(Literal::Int(value)) => Expr::Literal(Literal::Int(value.wrapping_neg())),
+                    Expr::Literal(Literal::Float(value)) => Expr::Literal(Literal::Float(-value)),
+                    expr => Expr::Unary(Box::new(Unary {
+                        op: UnaryOp::Neg,
+                        expr: Box::new(expr),
+                    })),
+                })
+            }
+            "+" => {
+                self.advance();
+                self.parse_unary()
+            }
+            "!" => {
+                self.advance();
+                Ok(Expr::Unary(Box::new(Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(self.parse_unary()?),
+                })))
+            }
+            "~" => {
+                self.advance();
+                Ok(Expr::Binary(Box::new(Binary {
+                    op: BinaryOp::BitXor,
+                    left: Box::new(self.parse_unary()?),
+                    right: Box::new(Expr::Literal(Literal::Int(-1))),
+                })))
+            }
+            "&" => {
+                self.advance();
+                match self.peek().clone() {
+                    TokenKind::Ident(name) if self.lookup(&name).is_none() && self.functions.contains(&name) => {
+                        self.advance();
+                        Ok(Expr::FuncAddr(name))
+                    }
+                    _ => Err(self.unsupported("taking the address of variables is")),
+                }
+            }
+            "*" => Err(self.unsupported("dereferencing pointers is")),
+            "++" | "--" => Err(self.unsupported("increments inside expressions are")),
+            "(" if matches!(self.peek_at(1), TokenKind::Ident(name) if TYPE_KEYWORDS.contains(&name.as_str())) => {
+                Err(self.unsupported("casts are"))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.is_punct("(") {
+                let args = self.parse_args()?;
+                expr = Expr::CallIndirect(CallIndirect {
+                    callee: Box::new(expr),
+                    args,
+                });
+            } else if self.is_punct("[") {
+                return Err(self.unsupported("arrays are"));
+            } else if self.is_punct(".") || self.is_punct("->") {
+                return Err(self.unsupported("structs are"));
+            } else if self.is_punct("++") || self.is_punct("--") {
+                return Err(self.unsupported("increments inside expressions are"));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let expr = match self.peek().clone() {
+            TokenKind::Int(value) => Expr::Literal(Literal::Int(value)),
+            TokenKind::Float(value) => Expr::Literal(Literal::Float(value)),
+            TokenKind::Char(value) => Expr::Literal(Literal::Char(value)),
+            TokenKind::Str(mut value) => {
+                self.advance();
+                // adjacent string literals are concatenated
+                while let TokenKind::Str(next) = self.peek() {
+                    value.push_str(next);
+                    self.advance();
+                }
+                return Ok(Expr::Literal(Literal::String(value)));
+            }
+            TokenKind::Punct("(") => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_punct(")")?;
+                return Ok(expr);
+            }
+            TokenKind::Ident(name) if name == "sizeof" => return Err(self.unsupported("sizeof is")),
+            TokenKind::Ident(name) if name == "true" || name == "false" => {
+                Expr::Literal(Literal::Bool(name == "true"))
+            }
+            TokenKind::Ident(name) if !TYPE_KEYWORDS.contains(&name.as_str()) => {
+                self.advance();
+                if self.is_punct("(") {
+                    let args = self.parse_args()?;
+                    return Ok(Expr::FuncCall(FuncCall { name, args }));
+                }
+                if let Some(type_) = self.lookup(&name) {
+                    return Ok(Expr::Variable(Variable_ {
+                        name,
+                        type_: type_.clone(),
+                    }));
+                }
+                if self.functions.contains(&name) {
+                    return Ok(Expr::FuncAddr(name));
+                }
+                self.pos -= 1;
+                return Err(self.error(format!("undeclared identifier `{}`", name)));
+            }
+            _ => return Err(self.error(format!("expected an expression, found {}", self.describe()))),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    // a call's arguments, where a type name is an argument too (`va_arg(ap, int)`)
+    fn parse_args(&mut self) -> ParseResult<Vec<Expr>> {
+        self.expect_punct("(")?;
+        let mut args = Vec::new();
+        if self.eat_punct(")") {
+            return Ok(args);
+        }
+        loop {
+            if self.starts_type() {
+                let base = self.parse_base_type()?;
+                let type_ = self
+                    .parse_pointers(base)?
+                    .ok_or_else(|| self.error("`void` is not a value"))?;
+                args.push(Expr::Type(type_));
+            } else {
+                args.push(self.parse_expr()?);
+            }
+            if self.eat_punct(")") {
+                return Ok(args);
+            }
+            self.expect_punct(",")?;
+        }
+    }
+}
+
+// Blocks aren't annotated themselves; their statements are.
+fn spanned(span: Span, stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Block(_) => stmt,
+        stmt => Stmt::Spanned(span, Box::new(stmt)),
+    }
+}
+
+// A prototype without a definition among `stmts` refers to an external function.
+fn mark_external<'a>(stmts: impl Iterator<Item = &'a mut Stmt>) {
+    let mut stmts: Vec<&mut Stmt> = stmts.collect();
+    let defined: HashSet<String> = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FuncDef(def) => Some(def.decl.name.clone()),
+            _ => None,
+        })
+        .collect();
+    for stmt in &mut stmts {
+        if let Stmt::FuncDecl(decl) = stmt {
+            decl.extern_ = !defined.contains(&decl.name);
+        }
+    }
+}
+
+fn binary_op(punct: &str) -> Option<(u8, BinaryOp)> {
+    let op = match punct {
+        "|" => (1, BinaryOp::BitOr),
+        "^" => (2, BinaryOp::BitXor),
+        "&" => (3, BinaryOp::BitAnd),
+        "==" => (4, BinaryOp::Eq),
+        "!=" => (4, BinaryOp::Ne),
+        "<" => (5, BinaryOp::Lt),
+        "<=" => (5, BinaryOp::Le),
+        ">" => (5, BinaryOp::Gt),
+        ">=" => (5, BinaryOp::Ge),
+        "<<" => (6, BinaryOp::Shl),
+        ">>" => (6, BinaryOp::Shr),
+        "+" => (7, BinaryOp::Add),
+        "-" => (7, BinaryOp::Sub),
+        "*" => (8, BinaryOp::Mul),
+        "/" => (8, BinaryOp::Div),
+        "%" => (8, BinaryOp::Mod),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn compound_op(punct: &str) -> Option<BinaryOp> {
+    let op = match punct {
+        "+=" => BinaryOp::Add,
+        "-=" => BinaryOp::Sub,
+        "*=" => BinaryOp::Mul,
+        "/=" => BinaryOp::Div,
+        "%=" => BinaryOp::Mod,
+        "&=" => BinaryOp::BitAnd,
+        "|=" => BinaryOp::BitOr,
+        "^=" => BinaryOp::BitXor,
+        "<<=" => BinaryOp::Shl,
+        ">>=" => BinaryOp::Shr,
+        _ => return None,
+    };
+    Some(op)
+}
+
+// `x++` and friends, as `x = x + 1`
+fn increment(target: Variable_, punct: &str) -> Stmt {
+    let op = if punct == "++" { BinaryOp::Add } else { BinaryOp::Sub };
+    Stmt::Assign(Assign {
+        value: Box::new(Expr::Binary(Box::new(Binary {
+            op,
+            left: Box::new(Expr::Variable(target.clone())),
+            right: Box::new(Expr::Literal(Literal::Int(1))),
+        }))),
+        target,
+    })
+}
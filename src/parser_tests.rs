@@ -0,0 +1,190 @@
+use crate::ast::*;
+use crate::compiler::Compiler;
+use crate::parser::parse;
+
+fn run_source(source: &str) -> i32 {
+    let program = parse(source).unwrap();
+    let mut codegen = Compiler::new().unwrap().build();
+    codegen.compile_program(program).unwrap();
+    codegen.run_main().unwrap()
+}
+
+fn parse_error(source: &str) -> (usize, usize, String) {
+    let err = parse(source).unwrap_err();
+    (err.line, err.column, err.message)
+}
+
+#[test]
+fn test_parse_function_with_typed_variables() {
+    let mut program = parse("long twice(long x) { long y = x * 2; return y; }").unwrap();
+    program.strip_spans();
+    let long_var = |name: &str| {
+        Expr::Variable(Variable_ {
+            name: name.to_string(),
+            type_: AstType::I64,
+        })
+    };
+    let expected = Stmt::FuncDef(FuncDef {
+        decl: FuncDecl {
+            name: "twice".to_string(),
+            params: vec![("x".to_string(), AstType::I64)],
+            return_type: Some(AstType::I64),
+            variadic: false,
+            extern_: false,
+        },
+        body: vec![
+            Stmt::VarDecl(VarDecl {
+                name: "y".to_string(),
+                type_: AstType::I64,
+                init: Some(Box::new(Expr::Binary(Box::new(Binary {
+                    op: BinaryOp::Mul,
+                    left: Box::new(long_var("x")),
+                    right: Box::new(Expr::Literal(Literal::Int(2))),
+                })))),
+            }),
+            Stmt::Return(Return {
+                value: Some(Box::new(long_var("y"))),
+            }),
+        ],
+    });
+    assert_eq!(program.statements, vec![expected]);
+}
+
+#[test]
+fn test_parse_records_statement_spans() {
+    let program = parse("int x = 1;\nint main() {\n  x += 1;\n  if (x) {\n    return x;\n  }\n  return 0;\n}").unwrap();
+    let span = |stmt: &Stmt| match stmt {
+        Stmt::Spanned(span, _) => (span.line, span.column),
+        other => panic!("{:?} has no span", other),
+    };
+    assert_eq!(span(&program.statements[0]), (1, 1));
+    assert_eq!(span(&program.statements[1]), (2, 1));
+    let Stmt::FuncDef(main) = program.statements[1].unspanned() else {
+        panic!("expected a definition");
+    };
+    let lines: Vec<_> = main.body.iter().map(|stmt| span(stmt).0).collect();
+    assert_eq!(lines, [3, 4, 7]);
+    let Stmt::If(if_stmt) = main.body[1].unspanned() else {
+        panic!("expected an if");
+    };
+    assert_eq!(span(&if_stmt.then_branch[0]), (5, 5));
+}
+
+#[test]
+fn test_parse_readme_examples() {
+    let examples = [
+        ("int main() { return 0; }", 0),
+        ("int main() { if (0) { return 1; } else { return 0; } }", 0),
+        ("int add(int a, int b) { return a + b; } int main() { return add(1, 2); }", 3),
+        ("int main() { int i = 0; while (i < 10) { i++; } return i; }", 10),
+        ("int main() { int x = 5; x += 3; x *= 2; return x; }", 16),
+        ("int main() { int x = 5; int y = 3; return (x & y) | (x ^ y); }", 7),
+        (
+            "double sqrt_approx(double x) {
+                 double guess = x / 2.0;
+                 for (int i = 0; i < 5; i++) { guess = (guess + x/guess) / 2.0; }
+                 return guess;
+             }
+             int main() { double result = sqrt_approx(16.0); return result; }",
+            4,
+        ),
+        (
+            "int add(int a, int b) { return a + b; }
+             int sub(int a, int b) { return a - b; }
+             int main() { int (*op)(int, int) = add; return op(5, 3); }",
+            8,
+        ),
+        (
+            "#include <stdarg.h>
+             int sum(int count, ...) {
+                 va_list args;
+                 va_start(args, count);
+                 int total = 0;
+                 for (int i = 0; i < count; i++) { total += va_arg(args, int); }
+                 va_end(args);
+                 return total;
+             }
+             int main() { return sum(4, 1, 2, 3, 4); }",
+            10,
+        ),
+    ];
+    for (source, expected) in examples {
+        assert_eq!(run_source(source), expected, "{}", source);
+    }
+}
+
+#[test]
+fn test_parse_statement_forms() {
+    let source = "
+        /* block comment */
+        int main() {
+            unsigned int total = 0, step = 2;
+            for (;;) {
+                total += step;
+                if (total > 20) break;
+            }
+            do { --total; } while (total % 4 != 0);
+            int n = 0x10 + 010 - ~0 + 'a' - 97;
+            return total + n;   // 20 + 25
+        }";
+    assert_eq!(run_source(source), 45);
+}
+
+#[test]
+fn test_parse_prototypes() {
+    // `labs` is only declared, so it's external; `helper` is defined later
+    let source = "
+        long labs(long x);
+        int helper(int x);
+        int main() { return labs(-3) + helper(4); }
+        int helper(int x) { return x * 10; }";
+    let mut program = parse(source).unwrap();
+    program.strip_spans();
+    let externs: Vec<bool> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::FuncDecl(decl) => Some(decl.extern_),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(externs, vec![true, false]);
+    assert_eq!(run_source(source), 43);
+}
+
+#[test]
+fn test_parse_string_escapes_and_variadic_prototype() {
+    let mut program = parse(r#"int printf(const char *format, ...); int main() { return printf("a\tb" "\n"); }"#).unwrap();
+    program.strip_spans();
+    let Stmt::FuncDecl(decl) = &program.statements[0] else {
+        panic!("expected a declaration");
+    };
+    assert!(decl.variadic && decl.extern_);
+    assert_eq!(decl.params, vec![("format".to_string(), AstType::String)]);
+    let Stmt::FuncDef(main) = &program.statements[1] else {
+        panic!("expected a definition");
+    };
+    let Stmt::Return(Return { value: Some(value) }) = &main.body[0] else {
+        panic!("expected a return");
+    };
+    let Expr::FuncCall(call) = value.as_ref() else {
+        panic!("expected a call");
+    };
+    assert_eq!(call.args, vec![Expr::Literal(Literal::String("a\tb\n".to_string()))]);
+}
+
+#[test]
+fn test_parse_errors_have_positions() {
+    assert_eq!(
+        parse_error("int main() {\n  return y;\n}"),
+        (2, 10, "undeclared identifier `y`".to_string())
+    );
+    assert_eq!(
+        parse_error("int main() {\n  int x = 1\n}"),
+        (3, 1, "expected `;`, found `}`".to_string())
+    );
+    assert_eq!(parse_error("int main() { return 1 && 2; }").2, "`&&` is not supported yet");
+    assert_eq!(parse_error("struct Point { int x; };").2, "structs are not supported yet");
+    assert_eq!(parse_error("int main() { int a[5]; }").2, "arrays are not supported yet");
+    assert_eq!(parse_error("int main() { \"open").2, "unterminated string literal");
+}
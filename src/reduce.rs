@@ -0,0 +1,450 @@
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Shrinks `program` while `interesting` keeps holding for it: unused
+// declarations and statements are deleted, control flow is replaced by its
+// body, calls by the functions they call, and expressions by `0`, `1` or one of
+// their operands, one edit at a time until none is accepted.
+pub fn reduce(mut program: Program, mut interesting: impl FnMut(&Program) -> bool) -> Program {
+    let passes: [fn(&mut Program, usize) -> bool; 6] =
+        [remove_unused, delete_stmt, unwrap_stmt, simplify_stmt, inline_call, simplify_expr];
+    loop {
+        let mut changed = false;
+        for pass in passes {
+            changed |= run_pass(&mut program, pass, &mut interesting);
+        }
+        if !changed {
+            return program;
+        }
+    }
+}
+
+// A predicate that saves each candidate as AST JSON to `path` and runs
+// `command` with `sh -c`, the path as `$1`. A candidate is interesting when the
+// command exits with 0.
+pub fn command_predicate<'a>(command: &'a str, path: &'a Path) -> impl FnMut(&Program) -> bool + 'a {
+    move |program| {
+        let Ok(json) = program.to_json() else {
+            return false;
+        };
+        if fs::write(path, json).is_err() {
+            return false;
+        }
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg("sh")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+// Tries each edit `pass` can make, keeping the ones `interesting` accepts.
+fn run_pass(program: &mut Program, pass: fn(&mut Program, usize) -> bool, interesting: &mut impl FnMut(&Program) -> bool) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+    loop {
+        let mut candidate = program.clone();
+        if !pass(&mut candidate, index) {
+            return changed;
+        }
+        // an accepted edit shifts the ones after it down into its place
+        if candidate != *program && interesting(&candidate) {
+            *program = candidate;
+            changed = true;
+        } else {
+            index += 1;
+        }
+    }
+}
+
+// Removes every declaration nothing refers to, at once: functions other than
+// `main`, and globals and locals that are neither read nor assigned.
+fn remove_unused(program: &mut Program, index: usize) -> bool {
+    if index > 0 {
+        return false;
+    }
+    let used = used_names(program);
+    let unused = |stmt: &Stmt| match stmt.unspanned() {
+        Stmt::FuncDef(func_def) => func_def.decl.name != "main" && !used.contains(&func_def.decl.name),
+        Stmt::FuncDecl(decl) => !used.contains(&decl.name),
+        Stmt::VarDecl(var_decl) => !used.contains(&var_decl.name),
+        _ => false,
+    };
+    let mut removed = false;
+    each_block(&mut program.statements, &mut |block| {
+        let len = block.len();
+        block.retain(|stmt| !unused(stmt));
+        removed |= block.len() < len;
+        false
+    });
+    removed
+}
+
+// The names of the variables and functions `program` uses, ignoring scopes.
+fn used_names(program: &mut Program) -> HashSet<String> {
+    let mut used = HashSet::new();
+    each_stmt(&mut program.statements, &mut |stmt| {
+        match stmt {
+            Stmt::Assign(assign) => {
+                used.insert(assign.target.name.clone());
+            }
+            Stmt::FuncCall(func_call) => {
+                used.insert(func_call.name.clone());
+            }
+            _ => {}
+        }
+        each_expr_in_stmt(stmt, &mut |expr| {
+            match expr {
+                Expr::Variable(variable) => {
+                    used.insert(variable.name.clone());
+                }
+                Expr::FuncCall(FuncCall { name, .. }) | Expr::FuncAddr(name) => {
+                    used.insert(name.clone());
+                }
+                _ => {}
+            }
+            false
+        })
+    });
+    used
+}
+
+// Removes the `index`th statement, counting through every block in pre-order.
+fn delete_stmt(program: &mut Program, mut index: usize) -> bool {
+    each_block(&mut program.statements, &mut |block| {
+        if index < block.len() {
+            block.remove(index);
+            return true;
+        }
+        index -= block.len();
+        false
+    })
+}
+
+// Replaces the `index`th `if`, loop or block with the statements it runs.
+fn unwrap_stmt(program: &mut Program, mut index: usize) -> bool {
+    each_block(&mut program.statements, &mut |block| {
+        for position in 0..block.len() {
+            let bodies = bodies(block[position].unspanned());
+            if index < bodies.len() {
+                let body = bodies.into_iter().nth(index).expect("checked the count");
+                block.splice(position..=position, body);
+                return true;
+            }
+            index -= bodies.len();
+        }
+        false
+    })
+}
+
+// What a statement could be replaced by: an `if`'s branches, a loop's body
+// after its `for` init, or a block's statements.
+fn bodies(stmt: &Stmt) -> Vec<Block> {
+    match stmt {
+        Stmt::If(if_stmt) => std::iter::once(if_stmt.then_branch.clone())
+            .chain(if_stmt.else_branch.clone())
+            .collect(),
+        Stmt::Loop(loop_stmt) => {
+            let init = match &loop_stmt.kind {
+                LoopKind::For { init: Some(init), .. } => Some((**init).clone()),
+                _ => None,
+            };
+            vec![init.into_iter().chain(loop_stmt.body.iter().cloned()).collect()]
+        }
+        Stmt::Block(block) => vec![block.clone()],
+        _ => Vec::new(),
+    }
+}
+
+// Drops the `index`th optional part of a statement: an `else`, a `for` init
+// or step, or a returned value.
+fn simplify_stmt(program: &mut Program, mut index: usize) -> bool {
+    each_stmt(&mut program.statements, &mut |stmt| {
+        let options = match stmt {
+            Stmt::If(if_stmt) => usize::from(if_stmt.else_branch.is_some()),
+            Stmt::Loop(LoopStmt {
+                kind: LoopKind::For { init, step },
+                ..
+            }) => usize::from(init.is_some()) + usize::from(step.is_some()),
+            Stmt::Return(ret) => usize::from(ret.value.is_some()),
+            _ => 0,
+        };
+        if index >= options {
+            index -= options;
+            return false;
+        }
+        match stmt {
+            Stmt::If(if_stmt) => if_stmt.else_branch = None,
+            Stmt::Loop(LoopStmt {
+                kind: LoopKind::For { init, step },
+                ..
+            }) => {
+                if index == 0 && init.is_some() {
+                    *init = None;
+                } else {
+                    *step = None;
+                }
+            }
+            Stmt::Return(ret) => ret.value = None,
+            _ => unreachable!("only statements with options get here"),
+        }
+        true
+    })
+}
+
+// Replaces the `index`th expression, counting each of the simpler ones it
+// could become.
+fn simplify_expr(program: &mut Program, mut index: usize) -> bool {
+    each_stmt(&mut program.statements, &mut |stmt| {
+        each_expr_in_stmt(stmt, &mut |expr| {
+            let simpler = simpler_exprs(expr);
+            if index < simpler.len() {
+                *expr = simpler.into_iter().nth(index).expect("checked the count");
+                return true;
+            }
+            index -= simpler.len();
+            false
+        })
+    })
+}
+
+// Literals only become smaller ones, `1` then `0`, so the pass can't go back
+// and forth between two of them.
+fn simpler_exprs(expr: &Expr) -> Vec<Expr> {
+    let literals = match expr {
+        Expr::Literal(Literal::Int(0)) | Expr::Type(_) => 0,
+        Expr::Literal(Literal::Int(1)) => 1,
+        _ => 2,
+    };
+    let mut simpler: Vec<Expr> = [0, 1].into_iter().take(literals).map(|value| Expr::Literal(Literal::Int(value))).collect();
+    match expr {
+        Expr::Binary(binary) => simpler.extend([(*binary.left).clone(), (*binary.right).clone()]),
+        Expr::Unary(unary) => simpler.push((*unary.expr).clone()),
+        Expr::FuncCall(FuncCall { args, .. }) | Expr::CallIndirect(CallIndirect { args, .. }) => {
+            simpler.extend(args.iter().cloned())
+        }
+        _ => {}
+    }
+    simpler
+}
+
+// Replaces the `index`th call to a function that isn't recursive with its
+// body: in an expression when the body is just `return value;`, with the
+// arguments substituted for the parameters, and as a statement when the body
+// doesn't return, as a block declaring the parameters.
+fn inline_call(program: &mut Program, mut index: usize) -> bool {
+    let functions = inlinable_functions(program);
+    let inlined = each_stmt(&mut program.statements, &mut |stmt| {
+        each_expr_in_stmt(stmt, &mut |expr| {
+            let Expr::FuncCall(call) = expr else {
+                return false;
+            };
+            let Some(func_def) = functions.get(&call.name).filter(|func_def| func_def.decl.params.len() == call.args.len())
+            else {
+                return false;
+            };
+            let [body] = func_def.body.as_slice() else {
+                return false;
+            };
+            let Stmt::Return(Return { value: Some(value) }) = body.unspanned() else {
+                return false;
+            };
+            if index > 0 {
+                index -= 1;
+                return false;
+            }
+            let mut value = (**value).clone();
+            substitute(&mut value, &func_def.decl.params, &call.args);
+            *expr = value;
+            true
+        })
+    });
+    inlined
+        || each_block(&mut program.statements, &mut |block| {
+            for stmt in block.iter_mut() {
+                let call = match stmt.unspanned() {
+                    Stmt::FuncCall(call) | Stmt::Expr(Expr::FuncCall(call)) => call,
+                    _ => continue,
+                };
+                let Some(func_def) = functions.get(&call.name).filter(|func_def| func_def.decl.params.len() == call.args.len())
+                else {
+                    continue;
+                };
+                let mut body = func_def.body.clone();
+                if each_stmt(&mut body, &mut |stmt| matches!(stmt, Stmt::Return(_))) {
+                    continue;
+                }
+                if index > 0 {
+                    index -= 1;
+                    continue;
+                }
+                let params = func_def.decl.params.iter().zip(&call.args).map(|((name, type_), arg)| {
+                    Stmt::VarDecl(VarDecl {
+                        name: name.clone(),
+                        type_: type_.clone(),
+                        init: Some(Box::new(arg.clone())),
+                    })
+                });
+                *stmt = Stmt::Block(params.chain(body).collect());
+                return true;
+            }
+            false
+        })
+}
+
+// The defined functions that can't end up calling themselves, so inlining
+// them terminates.
+fn inlinable_functions(program: &mut Program) -> HashMap<String, FuncDef> {
+    let mut functions = HashMap::new();
+    let mut calls: HashMap<String, HashSet<String>> = HashMap::new();
+    for stmt in &mut program.statements {
+        let Stmt::FuncDef(func_def) = stmt.unspanned_mut() else {
+            continue;
+        };
+        let mut callees = HashSet::new();
+        each_stmt(&mut func_def.body, &mut |stmt| {
+            if let Stmt::FuncCall(call) = stmt {
+                callees.insert(call.name.clone());
+            }
+            each_expr_in_stmt(stmt, &mut |expr| {
+                if let Expr::FuncCall(FuncCall { name, .. }) | Expr::FuncAddr(name) = expr {
+                    callees.insert(name.clone());
+                }
+                false
+            })
+        });
+        calls.insert(func_def.decl.name.clone(), callees);
+        functions.insert(func_def.decl.name.clone(), func_def.clone());
+    }
+    let recursive = |name: &String| {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<&String> = calls[name].iter().collect();
+        while let Some(callee) = pending.pop() {
+            if callee == name {
+                return true;
+            }
+            if seen.insert(callee) {
+                pending.extend(calls.get(callee).into_iter().flatten());
+            }
+        }
+        false
+    };
+    functions.retain(|name, func_def| !func_def.decl.variadic && !recursive(name));
+    functions
+}
+
+// Replaces the parameters in `expr` with the arguments passed for them.
+fn substitute(expr: &mut Expr, params: &[(String, AstType)], args: &[Expr]) {
+    if let Expr::Variable(variable) = expr {
+        if let Some(position) = params.iter().position(|(name, _)| *name == variable.name) {
+            if let Some(arg) = args.get(position) {
+                *expr = arg.clone();
+            }
+        }
+        return;
+    }
+    match expr {
+        Expr::Binary(binary) => {
+            substitute(&mut binary.left, params, args);
+            substitute(&mut binary.right, params, args);
+        }
+        Expr::Unary(unary) => substitute(&mut unary.expr, params, args),
+        Expr::FuncCall(FuncCall { args: call_args, .. }) => {
+            call_args.iter_mut().for_each(|arg| substitute(arg, params, args));
+        }
+        Expr::CallIndirect(call) => {
+            substitute(&mut call.callee, params, args);
+            call.args.iter_mut().for_each(|arg| substitute(arg, params, args));
+        }
+        _ => {}
+    }
+}
+
+// Calls `f` on `block` and each block nested in it, in pre-order, until it
+// returns true.
+pub(crate) fn each_block(block: &mut Block, f: &mut dyn FnMut(&mut Block) -> bool) -> bool {
+    if f(block) {
+        return true;
+    }
+    block.iter_mut().any(|stmt| each_block_in_stmt(stmt, f))
+}
+
+fn each_block_in_stmt(stmt: &mut Stmt, f: &mut dyn FnMut(&mut Block) -> bool) -> bool {
+    match stmt {
+        Stmt::Spanned(_, stmt) => each_block_in_stmt(stmt, f),
+        Stmt::FuncDef(func_def) => each_block(&mut func_def.body, f),
+        Stmt::Block(block) => each_block(block, f),
+        Stmt::If(if_stmt) => {
+            each_block(&mut if_stmt.then_branch, f)
+                || if_stmt.else_branch.as_mut().is_some_and(|block| each_block(block, f))
+        }
+        Stmt::Loop(loop_stmt) => each_block(&mut loop_stmt.body, f),
+        _ => false,
+    }
+}
+
+// Calls `f` on every statement in `block`, outer ones first, until it returns true.
+pub(crate) fn each_stmt(block: &mut Block, f: &mut dyn FnMut(&mut Stmt) -> bool) -> bool {
+    block.iter_mut().any(|stmt| each_stmt_in(stmt, f))
+}
+
+fn each_stmt_in(stmt: &mut Stmt, f: &mut dyn FnMut(&mut Stmt) -> bool) -> bool {
+    if let Stmt::Spanned(_, inner) = stmt {
+        return each_stmt_in(inner, f);
+    }
+    if f(stmt) {
+        return true;
+    }
+    match stmt {
+        Stmt::FuncDef(func_def) => each_stmt(&mut func_def.body, f),
+        Stmt::Block(block) => each_stmt(block, f),
+        Stmt::If(if_stmt) => {
+            each_stmt(&mut if_stmt.then_branch, f) || if_stmt.else_branch.as_mut().is_some_and(|block| each_stmt(block, f))
+        }
+        Stmt::Loop(loop_stmt) => {
+            if let LoopKind::For { init, step } = &mut loop_stmt.kind {
+                if init.iter_mut().chain(step.iter_mut()).any(|part| each_stmt_in(part, f)) {
+                    return true;
+                }
+            }
+            each_stmt(&mut loop_stmt.body, f)
+        }
+        _ => false,
+    }
+}
+
+// Calls `f` on each expression directly in `stmt`, and the ones nested in them,
+// outer ones first, until it returns true.
+pub(crate) fn each_expr_in_stmt(stmt: &mut Stmt, f: &mut dyn FnMut(&mut Expr) -> bool) -> bool {
+    match stmt {
+        Stmt::VarDecl(VarDecl { init: Some(init), .. }) => each_expr(init, f),
+        Stmt::Assign(assign) => each_expr(&mut assign.value, f),
+        Stmt::Return(Return { value: Some(value) }) => each_expr(value, f),
+        Stmt::Expr(expr) => each_expr(expr, f),
+        Stmt::FuncCall(func_call) => func_call.args.iter_mut().any(|arg| each_expr(arg, f)),
+        Stmt::If(if_stmt) => each_expr(&mut if_stmt.condition, f),
+        Stmt::Loop(loop_stmt) => each_expr(&mut loop_stmt.condition, f),
+        _ => false,
+    }
+}
+
+pub(crate) fn each_expr(expr: &mut Expr, f: &mut dyn FnMut(&mut Expr) -> bool) -> bool {
+    if f(expr) {
+        return true;
+    }
+    match expr {
+        Expr::Binary(binary) => each_expr(&mut binary.left, f) || each_expr(&mut binary.right, f),
+        Expr::Unary(unary) => each_expr(&mut unary.expr, f),
+        Expr::FuncCall(func_call) => func_call.args.iter_mut().any(|arg| each_expr(arg, f)),
+        Expr::CallIndirect(call) => each_expr(&mut call.callee, f) || call.args.iter_mut().any(|arg| each_expr(arg, f)),
+        _ => false,
+    }
+}
@@ -0,0 +1,112 @@
+use crate::ast::*;
+use crate::compiler::Compiler;
+use crate::interpreter::{Interpreter, Value};
+use crate::parser::parse;
+use crate::reduce::{self, command_predicate};
+
+fn run_main(program: &Program) -> Option<i64> {
+    let mut interpreter = Interpreter::new();
+    interpreter.load_program(program.clone()).ok()?;
+    match interpreter.run_main::<i32>() {
+        Ok(Some(Value::Int(value))) => Some(value),
+        _ => None,
+    }
+}
+
+fn function<'a>(program: &'a Program, name: &str) -> Option<&'a FuncDef> {
+    program.statements.iter().find_map(|stmt| match stmt.unspanned() {
+        Stmt::FuncDef(func_def) if func_def.decl.name == name => Some(func_def),
+        _ => None,
+    })
+}
+
+#[test]
+fn test_inlines_calls_and_removes_what_is_unused() {
+    let source = "
+int unused(int x) { return x * 3; }
+int counter = 0;
+int scale(int x, int y) { return x * y + 1; }
+void bump(int by) { counter = counter + by; }
+int main() {
+    int ignored = 7;
+    bump(2);
+    return scale(counter, 5);
+}
+";
+    let program = parse(source).unwrap();
+    let reduced = reduce::reduce(program, |program| run_main(program) == Some(11));
+    // both calls are inlined, after which nothing uses the functions
+    let names: Vec<_> = reduced
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt.unspanned() {
+            Stmt::FuncDef(func_def) => Some(func_def.decl.name.as_str()),
+            Stmt::VarDecl(var_decl) => Some(var_decl.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, ["counter", "main"]);
+    let main = function(&reduced, "main").unwrap();
+    assert_eq!(run_main(&reduced), Some(11));
+    let mut body = main.body.clone();
+    let mut calls = 0;
+    reduce::each_stmt(&mut body, &mut |stmt| {
+        calls += usize::from(matches!(stmt, Stmt::FuncCall(_)));
+        reduce::each_expr_in_stmt(stmt, &mut |expr| {
+            calls += usize::from(matches!(expr, Expr::FuncCall(_)));
+            false
+        })
+    });
+    assert_eq!(calls, 0, "{:?}", main.body);
+    assert!(!format!("{:?}", main.body).contains("ignored"), "{:?}", main.body);
+}
+
+#[test]
+fn test_replaces_expressions_with_small_literals() {
+    let program = parse("int main() { int x = 40; return (x + 2) * 3 - 125; }").unwrap();
+    // only the sign matters, so everything but one literal goes
+    let reduced = reduce::reduce(program, |program| run_main(program).is_some_and(|value| value > 0));
+    let main = function(&reduced, "main").unwrap();
+    let [stmt] = main.body.as_slice() else {
+        panic!("expected a single statement, got {:?}", main.body);
+    };
+    assert!(
+        matches!(stmt.unspanned(), Stmt::Return(Return { value: Some(value) }) if **value == Expr::Literal(Literal::Int(1))),
+        "{:?}",
+        stmt
+    );
+}
+
+#[test]
+fn test_terminates_with_recursive_functions() {
+    let source = "
+int down(int n) { if (n == 0) { return 0; } return down(n - 1); }
+int loop(int n) { return loop(n); }
+int main() { return down(3); }
+";
+    let program = parse(source).unwrap();
+    // anything that still compiles, so inlining `down` would go on forever
+    let reduced = reduce::reduce(program, |program| {
+        let mut codegen = Compiler::new().unwrap().build();
+        codegen.compile_program(program.clone()).is_ok() && function(program, "main").is_some()
+    });
+    assert!(function(&reduced, "loop").is_none());
+    assert!(function(&reduced, "down").is_none());
+}
+
+#[test]
+fn test_command_predicate() {
+    let path = std::env::temp_dir().join(format!("compiler_test-reduce-test-{}.json", std::process::id()));
+    let program = parse("int keep() { return 1; }\nint drop() { return 2; }\nint main() { return keep(); }").unwrap();
+    let mut interesting = command_predicate("grep -q '\"keep\"' \"$1\"", &path);
+    assert!(interesting(&program));
+    let reduced = reduce::reduce(program, interesting);
+    std::fs::remove_file(&path).unwrap();
+
+    // only something mentioning `keep` is left
+    let json = reduced.to_json().unwrap();
+    assert!(json.contains("\"keep\""), "{}", json);
+    assert!(!json.contains("drop"), "{}", json);
+    assert!(!command_predicate("exit 1", &path)(&reduced));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,126 @@
+use crate::ast::*;
+use crate::codegen::Codegen;
+use crate::compiler::Compiler;
+use crate::error::{CompileError, CompileResult};
+use crate::module::ModuleType;
+use crate::parser::{self, ParseError, ReplItem, Symbols};
+use cranelift_module::FuncId;
+use std::ffi::{c_char, c_void, CStr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReplError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+}
+
+extern "C" {
+    fn fflush(stream: *mut c_void) -> i32;
+}
+
+// An interactive session. Every line is compiled into the same JIT module, so
+// the functions and globals of earlier lines stay usable; statements and
+// expressions run as anonymous functions.
+pub struct Repl {
+    codegen: Codegen,
+    symbols: Symbols,
+}
+
+impl Repl {
+    pub fn new(compiler: Compiler) -> Self {
+        Self {
+            codegen: compiler.build(),
+            symbols: Symbols::default(),
+        }
+    }
+
+    // Runs one line, returning the value of each expression on it formatted by
+    // its type. Expressions without a value, like calls to `void` functions,
+    // produce nothing.
+    pub fn eval(&mut self, line: &str) -> Result<Vec<String>, ReplError> {
+        let items = parser::parse_line(line, &mut self.symbols)?;
+        let mut values = Vec::new();
+        let mut stmts = Vec::new();
+        for item in items {
+            match item {
+                ReplItem::Stmt(stmt) => stmts.push(stmt),
+                ReplItem::Expr(expr) => values.extend(self.run(std::mem::take(&mut stmts), Some(expr))?),
+                // a global's initializer runs as an assignment, so it can be any expression
+                ReplItem::Decl(Stmt::VarDecl(mut var_decl)) => {
+                    self.run(std::mem::take(&mut stmts), None)?;
+                    let init = var_decl.init.take();
+                    let target = Variable_ {
+                        name: var_decl.name.clone(),
+                        type_: var_decl.type_.clone(),
+                    };
+                    self.compile(Stmt::VarDecl(var_decl))?;
+                    if let Some(value) = init {
+                        stmts.push(Stmt::Assign(Assign { target, value }));
+                    }
+                }
+                ReplItem::Decl(decl) => {
+                    self.run(std::mem::take(&mut stmts), None)?;
+                    self.compile(decl)?;
+                }
+            }
+        }
+        self.run(stmts, None)?;
+        Ok(values)
+    }
+
+    fn compile(&mut self, stmt: Stmt) -> CompileResult<()> {
+        self.codegen.compile_program(Program { statements: vec![stmt] })
+    }
+
+    fn run(&mut self, body: Vec<Stmt>, result: Option<Expr>) -> CompileResult<Option<String>> {
+        if body.is_empty() && result.is_none() {
+            return Ok(None);
+        }
+        let (func_id, type_) = self.codegen.compile_anonymous(&body, result.as_ref())?;
+        self.codegen.finalize()?;
+        let module = self.codegen.module_mut();
+        let value = match type_ {
+            Some(type_) => Some(call_and_format(module, func_id, &type_)?),
+            None => {
+                module.call::<(), ()>(func_id, ())?;
+                None
+            }
+        };
+        // C's stdout is buffered separately from ours; keep the output in order
+        unsafe { fflush(std::ptr::null_mut()) };
+        Ok(value)
+    }
+}
+
+fn call_and_format(module: &mut ModuleType, func_id: FuncId, type_: &AstType) -> CompileResult<String> {
+    let text = match type_ {
+        AstType::I8 => module.call::<_, i8>(func_id, ())?.to_string(),
+        AstType::I16 => module.call::<_, i16>(func_id, ())?.to_string(),
+        AstType::I32 => module.call::<_, i32>(func_id, ())?.to_string(),
+        AstType::I64 => module.call::<_, i64>(func_id, ())?.to_string(),
+        AstType::U8 => module.call::<_, u8>(func_id, ())?.to_string(),
+        AstType::U16 => module.call::<_, u16>(func_id, ())?.to_string(),
+        AstType::U32 => module.call::<_, u32>(func_id, ())?.to_string(),
+        AstType::U64 => module.call::<_, u64>(func_id, ())?.to_string(),
+        AstType::Usize => module.call::<_, usize>(func_id, ())?.to_string(),
+        AstType::Isize => module.call::<_, isize>(func_id, ())?.to_string(),
+        AstType::F32 => format!("{:?}", module.call::<_, f32>(func_id, ())?),
+        AstType::F64 => format!("{:?}", module.call::<_, f64>(func_id, ())?),
+        AstType::Bool => module.call::<_, bool>(func_id, ())?.to_string(),
+        AstType::Char => format!("{:?}", module.call::<_, u8>(func_id, ())? as char),
+        AstType::String => {
+            let ptr = module.call::<_, *const c_char>(func_id, ())?;
+            if ptr.is_null() {
+                "NULL".to_string()
+            } else {
+                // like printing a `char *` in C, this trusts the pointer
+                format!("{:?}", unsafe { CStr::from_ptr(ptr) }.to_string_lossy())
+            }
+        }
+        AstType::FuncPtr { .. } => format!("<function at {:p}>", module.call::<_, *const u8>(func_id, ())?),
+        other => return Err(CompileError::Unsupported(format!("printing a {:?}", other))),
+    };
+    Ok(text)
+}
@@ -0,0 +1,79 @@
+use crate::compiler::Compiler;
+use crate::error::CompileError;
+use crate::repl::{Repl, ReplError};
+
+fn repl() -> Repl {
+    Repl::new(Compiler::new().unwrap())
+}
+
+fn eval(repl: &mut Repl, line: &str) -> Vec<String> {
+    repl.eval(line).unwrap_or_else(|err| panic!("`{}` failed: {}", line, err))
+}
+
+#[test]
+fn test_expressions_print_by_type() {
+    let mut repl = repl();
+    assert_eq!(eval(&mut repl, "1 + 2"), ["3"]);
+    assert_eq!(eval(&mut repl, "-5;"), ["-5"]);
+    assert_eq!(eval(&mut repl, "1.5 * 2.0"), ["3.0"]);
+    assert_eq!(eval(&mut repl, "3 < 4"), ["true"]);
+    assert_eq!(eval(&mut repl, "(1 < 2) + (1 < 2)"), ["2"]);
+    assert_eq!(eval(&mut repl, "'a'"), ["'a'"]);
+    assert_eq!(eval(&mut repl, "\"hi\\n\""), ["\"hi\\n\""]);
+    assert_eq!(eval(&mut repl, "1; 2; 3"), ["1", "2", "3"]);
+}
+
+#[test]
+fn test_globals_persist_between_lines() {
+    let mut repl = repl();
+    assert!(eval(&mut repl, "int global = 42;").is_empty());
+    assert!(eval(&mut repl, "global += 1;").is_empty());
+    assert_eq!(eval(&mut repl, "global"), ["43"]);
+
+    // initializers don't have to be constant
+    assert!(eval(&mut repl, "long twice = global * 2;").is_empty());
+    assert_eq!(eval(&mut repl, "twice"), ["86"]);
+}
+
+#[test]
+fn test_functions_defined_on_earlier_lines() {
+    let mut repl = repl();
+    assert!(eval(&mut repl, "int square(int x) { return x * x; }").is_empty());
+    assert_eq!(eval(&mut repl, "square(7)"), ["49"]);
+    assert!(eval(&mut repl, "int counter = 0;").is_empty());
+    assert!(eval(&mut repl, "void bump() { counter += 1; }").is_empty());
+    // `void` calls have no value to print
+    assert!(eval(&mut repl, "bump(); bump()").is_empty());
+    assert_eq!(eval(&mut repl, "counter"), ["2"]);
+}
+
+#[test]
+fn test_session_recovers_from_errors() {
+    let mut repl = repl();
+    assert!(matches!(repl.eval("int f( {"), Err(ReplError::Parse(_))));
+    assert!(matches!(repl.eval("undeclared"), Err(ReplError::Parse(_))));
+
+    // a definition that fails to compile leaves `bad` declared but not defined
+    assert!(matches!(repl.eval("int bad() { break; }"), Err(ReplError::Compile(_))));
+    assert!(matches!(repl.eval("bad()"), Err(ReplError::Compile(_))));
+    // runtime traps are errors too
+    assert!(matches!(repl.eval("1 / 0"), Err(ReplError::Compile(CompileError::Trap { .. }))));
+    assert_eq!(eval(&mut repl, "1 + 1"), ["2"]);
+
+    assert!(eval(&mut repl, "int bad() { return 2; }").is_empty());
+    assert_eq!(eval(&mut repl, "bad() * 21"), ["42"]);
+}
+
+#[test]
+fn test_calling_an_unresolved_prototype() {
+    let mut repl = repl();
+    // with no definition on its line, a prototype is an import
+    assert!(eval(&mut repl, "int f(int a);").is_empty());
+    let err = repl.eval("f(1)").unwrap_err();
+    assert!(matches!(&err, ReplError::Compile(CompileError::MissingDefinition(name)) if name == "f"), "{}", err);
+    assert_eq!(eval(&mut repl, "1 + 1"), ["2"]);
+
+    // nor can a function calling it be defined, which would break every later line
+    assert!(matches!(repl.eval("int g() { return f(1); }"), Err(ReplError::Compile(_))));
+    assert_eq!(eval(&mut repl, "2 + 2"), ["4"]);
+}
@@ -0,0 +1,101 @@
+use crate::error::CompileResult;
+use crate::module::ModuleType;
+use cranelift_codegen::ir::Function;
+use cranelift_codegen::Context;
+use cranelift_module::FuncId;
+use serde::{Deserialize, Serialize};
+
+// What to capture about each function as it is compiled. Code-size statistics
+// are always collected; the listings are opt-in because they're large.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompileOptions {
+    // the CLIF as generated, before Cranelift's optimization passes
+    pub clif: bool,
+    // the CLIF after optimization and legalization, as it was lowered
+    pub optimized_clif: bool,
+    // the machine code, as Cranelift's VCode listing
+    pub disasm: bool,
+}
+
+impl CompileOptions {
+    pub fn all() -> Self {
+        Self {
+            clif: true,
+            optimized_clif: true,
+            disasm: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CompileReport {
+    // in the order the functions were defined
+    pub functions: Vec<FunctionReport>,
+}
+
+impl CompileReport {
+    pub fn function(&self, name: &str) -> Option<&FunctionReport> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    pub fn total_code_size(&self) -> usize {
+        self.functions.iter().map(|function| function.stats.code_size).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FunctionReport {
+    pub name: String,
+    pub clif: Option<String>,
+    pub optimized_clif: Option<String>,
+    pub disasm: Option<String>,
+    pub stats: CodeStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CodeStats {
+    // bytes of machine code
+    pub code_size: usize,
+    pub blocks: usize,
+    pub instructions: usize,
+    pub optimized_blocks: usize,
+    pub optimized_instructions: usize,
+}
+
+// Defines `func_id` from the function in `ctx`, capturing what `options` asks for.
+pub fn define_function(
+    module: &mut ModuleType,
+    name: &str,
+    func_id: FuncId,
+    ctx: &mut Context,
+    options: CompileOptions,
+) -> CompileResult<FunctionReport> {
+    let clif = options.clif.then(|| ctx.func.display().to_string());
+    let (blocks, instructions) = count_insts(&ctx.func);
+    ctx.set_disasm(options.disasm);
+
+    // compiling optimizes `ctx.func` in place
+    module.define_function(func_id, ctx)?;
+
+    let (optimized_blocks, optimized_instructions) = count_insts(&ctx.func);
+    let code = ctx.compiled_code().expect("defined functions are compiled");
+    Ok(FunctionReport {
+        name: name.to_string(),
+        clif,
+        optimized_clif: options.optimized_clif.then(|| ctx.func.display().to_string()),
+        disasm: if options.disasm { code.vcode.clone() } else { None },
+        stats: CodeStats {
+            code_size: code.code_buffer().len(),
+            blocks,
+            instructions,
+            optimized_blocks,
+            optimized_instructions,
+        },
+    })
+}
+
+fn count_insts(func: &Function) -> (usize, usize) {
+    let blocks = func.layout.blocks().count();
+    let insts = func.layout.blocks().map(|block| func.layout.block_insts(block).count()).sum();
+    (blocks, insts)
+}
@@ -0,0 +1,230 @@
+use crate::ast::{AstType, FuncDecl};
+use crate::error::{CompileError, CompileResult};
+use cranelift::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ptr;
+
+// Offsets below this are never handed out, so that 0 can be a null pointer.
+const FIRST_OFFSET: u32 = 8;
+
+// How `Codegen::enable_sandbox` confines a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxOptions {
+    // bytes of linear memory, which holds the program's strings
+    pub memory_size: u32,
+    // the `extern` functions the program may declare
+    pub imports: Vec<String>,
+    // `(import, pointer, length)`: param `length` of `import` is how many bytes
+    // from the `char*` param `pointer` it may touch, and they have to be in
+    // memory. Other `char*` params only have to start there.
+    pub lengths: Vec<(String, usize, usize)>,
+}
+
+impl Default for SandboxOptions {
+    fn default() -> Self {
+        Self {
+            memory_size: 64 * 1024,
+            imports: Vec::new(),
+            lengths: Vec::new(),
+        }
+    }
+}
+
+impl SandboxOptions {
+    pub fn allow_import(mut self, name: &str) -> Self {
+        self.imports.push(name.to_string());
+        self
+    }
+
+    // `allow_import`, with the `(pointer, length)` param pairs of `name`, as in
+    // `(0, 1)` for `snprintf(buffer, size, format, ...)`.
+    pub fn allow_import_with_lengths(mut self, name: &str, lengths: &[(usize, usize)]) -> Self {
+        self.lengths
+            .extend(lengths.iter().map(|&(pointer, length)| (name.to_string(), pointer, length)));
+        self.allow_import(name)
+    }
+}
+
+// A sandboxed program's linear memory and the imports it may use. Its `char*`
+// values are 32-bit offsets into the memory, which only become host pointers,
+// after a bounds check, when they're passed to an import.
+pub(crate) struct Sandbox {
+    memory: LinearMemory,
+    imports: HashSet<String>,
+    lengths: HashMap<String, Vec<(usize, usize)>>,
+    // where the next string literal goes
+    next_offset: u32,
+}
+
+impl Sandbox {
+    pub(crate) fn new(options: SandboxOptions) -> Self {
+        let mut lengths: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        for (name, pointer, length) in options.lengths {
+            lengths.entry(name).or_default().push((pointer, length));
+        }
+        Self {
+            memory: LinearMemory::new(options.memory_size),
+            imports: options.imports.into_iter().collect(),
+            lengths,
+            next_offset: FIRST_OFFSET,
+        }
+    }
+
+    // Whether `decl` may be imported, with the params its lengths name being
+    // a `char*` and a `size_t`.
+    pub(crate) fn check_import(&self, decl: &FuncDecl) -> CompileResult<()> {
+        if !self.imports.contains(&decl.name) {
+            return Err(CompileError::ImportNotAllowed(decl.name.clone()));
+        }
+        let param = |index: usize| decl.params.get(index).map(|(_, type_)| type_);
+        for &(pointer, length) in self.lengths(&decl.name) {
+            if param(pointer) != Some(&AstType::String) || param(length) != Some(&AstType::Usize) {
+                return Err(CompileError::SandboxLength {
+                    name: decl.name.clone(),
+                    pointer,
+                    length,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // the `(pointer, length)` param pairs of the import `name`
+    pub(crate) fn lengths(&self, name: &str) -> &[(usize, usize)] {
+        self.lengths.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    // Copies `value` into memory with a NUL terminator, returning its offset.
+    pub(crate) fn add_string(&mut self, value: &str) -> CompileResult<u32> {
+        let offset = self.next_offset;
+        let end = offset as usize + value.len() + 1;
+        if end > self.memory.size as usize {
+            return Err(CompileError::SandboxMemory(self.memory.size));
+        }
+        let memory = self.memory_mut();
+        memory[offset as usize..end - 1].copy_from_slice(value.as_bytes());
+        memory[end - 1] = 0;
+        self.next_offset = end as u32;
+        Ok(offset)
+    }
+
+    pub(crate) fn memory(&self) -> &[u8] {
+        unsafe { &*ptr::slice_from_raw_parts(self.memory.base, self.memory.size as usize) }
+    }
+
+    pub(crate) fn memory_mut(&mut self) -> &mut [u8] {
+        unsafe { &mut *ptr::slice_from_raw_parts_mut(self.memory.base, self.memory.size as usize) }
+    }
+
+    // The host pointer for an offset the program passes to an import, trapping
+    // with `HEAP_OUT_OF_BOUNDS` if it's outside memory, or if `len` bytes from
+    // it are. 0 stays null.
+    pub(crate) fn host_pointer(
+        &self,
+        offset: Value,
+        len: Option<Value>,
+        pointer_type: Type,
+        builder: &mut FunctionBuilder,
+    ) -> Value {
+        let size = self.memory.size as i64;
+        let extended = builder.ins().uextend(pointer_type, offset);
+        let outside = builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, extended, size);
+        builder.ins().trapnz(outside, TrapCode::HEAP_OUT_OF_BOUNDS);
+        if let Some(len) = len {
+            // checked on its own first, so that adding it can't wrap
+            let too_long = builder.ins().icmp_imm(IntCC::UnsignedGreaterThan, len, size);
+            builder.ins().trapnz(too_long, TrapCode::HEAP_OUT_OF_BOUNDS);
+            let end = builder.ins().iadd(extended, len);
+            let past_end = builder.ins().icmp_imm(IntCC::UnsignedGreaterThan, end, size);
+            builder.ins().trapnz(past_end, TrapCode::HEAP_OUT_OF_BOUNDS);
+        }
+        let address = builder.ins().iadd_imm(extended, self.memory.base as i64);
+        let null = builder.ins().iconst(pointer_type, 0);
+        builder.ins().select(offset, address, null)
+    }
+
+    // The offset of a pointer an import returns, trapping with
+    // `HEAP_OUT_OF_BOUNDS` unless it's null or points into memory.
+    pub(crate) fn offset_of(&self, pointer: Value, builder: &mut FunctionBuilder) -> Value {
+        let offset = builder.ins().iadd_imm(pointer, (self.memory.base as i64).wrapping_neg());
+        let inside = builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedLessThan, offset, self.memory.size as i64);
+        let null = builder.ins().icmp_imm(IntCC::Equal, pointer, 0);
+        let valid = builder.ins().bor(inside, null);
+        builder.ins().trapz(valid, TrapCode::HEAP_OUT_OF_BOUNDS);
+        let offset = builder.ins().ireduce(types::I32, offset);
+        let zero = builder.ins().iconst(types::I32, 0);
+        builder.ins().select(pointer, offset, zero)
+    }
+}
+
+// Zeroed memory at an address that doesn't change for the life of the sandbox,
+// since compiled code has it as a constant. It's followed by at least one zero
+// byte the program can't reach, so imports reading a string from its tail stop
+// there, and then by guard pages, so imports writing past it fault instead of
+// reaching the host's heap.
+struct LinearMemory {
+    base: *mut u8,
+    size: u32,
+}
+
+#[cfg(unix)]
+impl LinearMemory {
+    // comfortably more than an import writes past a string it was given
+    const GUARD_SIZE: usize = 64 * 1024;
+
+    fn new(size: u32) -> Self {
+        let (accessible, reserved) = Self::layout(size);
+        unsafe {
+            let base = libc::mmap(
+                ptr::null_mut(),
+                reserved,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(base, libc::MAP_FAILED, "reserving sandbox memory");
+            let result = libc::mprotect(base, accessible, libc::PROT_READ | libc::PROT_WRITE);
+            assert_eq!(result, 0, "committing sandbox memory");
+            Self { base: base.cast(), size }
+        }
+    }
+
+    // The bytes that are readable and writable, and all the bytes reserved,
+    // including the guard pages.
+    fn layout(size: u32) -> (usize, usize) {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let accessible = (size as usize + 1).next_multiple_of(page_size);
+        (accessible, accessible + Self::GUARD_SIZE.next_multiple_of(page_size))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LinearMemory {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.cast(), Self::layout(self.size).1) };
+    }
+}
+
+// Without `mmap` there are no guard pages, only the zero byte.
+#[cfg(not(unix))]
+impl LinearMemory {
+    fn new(size: u32) -> Self {
+        let memory = vec![0u8; size as usize + 1].into_boxed_slice();
+        Self {
+            base: Box::into_raw(memory).cast(),
+            size,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Drop for LinearMemory {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(ptr::slice_from_raw_parts_mut(self.base, self.size as usize + 1))) };
+    }
+}
@@ -0,0 +1,223 @@
+use crate::codegen::Codegen;
+use crate::compiler::CompilerBuilder;
+use crate::error::CompileError;
+use crate::parser::parse;
+use crate::sandbox::SandboxOptions;
+use crate::test_helpers::trap_code;
+use crate::trap;
+use cranelift_codegen::ir::TrapCode;
+use std::ffi::{c_char, CStr};
+
+extern "C" fn length(s: *const c_char) -> i32 {
+    unsafe { CStr::from_ptr(s) }.to_bytes().len() as i32
+}
+
+extern "C" fn skip(s: *const c_char) -> *const c_char {
+    unsafe { s.add(1) }
+}
+
+extern "C" fn tag(s: *const c_char, tag: usize) -> i64 {
+    unsafe { *s as i64 + tag as i64 }
+}
+
+extern "C" fn escape(_s: *const c_char) -> *const c_char {
+    c"host memory".as_ptr()
+}
+
+fn sandboxed(source: &str, options: SandboxOptions) -> Result<Codegen, CompileError> {
+    let mut compiler = CompilerBuilder::new().sandbox(options).compiler().unwrap();
+    compiler
+        .register_function("length", length as extern "C" fn(*const c_char) -> i32)
+        .register_function("skip", skip as extern "C" fn(*const c_char) -> *const c_char)
+        .register_function("escape", escape as extern "C" fn(*const c_char) -> *const c_char)
+        .register_function("tag", tag as extern "C" fn(*const c_char, usize) -> i64);
+    let mut codegen = compiler.build();
+    codegen.compile_program(parse(source).unwrap())?;
+    Ok(codegen)
+}
+
+fn imports(names: &[&str]) -> SandboxOptions {
+    names
+        .iter()
+        .fold(SandboxOptions::default(), |options, name| options.allow_import(name))
+}
+
+#[test]
+fn test_strings_live_in_linear_memory() {
+    let source = r#"
+int length(char *s);
+char *skip(char *s);
+char *greeting = "hello";
+int greeting_length() { return length(greeting); }
+int tail_length() { return length(skip(skip("sandbox"))); }
+char *name() { return "name"; }
+int measure(char *s) { return length(s); }
+"#;
+    let mut codegen = sandboxed(source, imports(&["length", "skip"])).unwrap();
+    assert_eq!(codegen.call::<_, i32>("greeting_length", ()).unwrap(), 5);
+    // pointers into memory come back from imports as offsets
+    assert_eq!(codegen.call::<_, i32>("tail_length", ()).unwrap(), 5);
+
+    // and the host sees offsets too
+    let offset = codegen.call::<_, u32>("name", ()).unwrap() as usize;
+    let memory = codegen.memory().unwrap();
+    assert_eq!(&memory[offset..offset + 5], b"name\0");
+    let end = memory.len() - 4;
+    codegen.memory_mut().unwrap()[end..].copy_from_slice(b"abc\0");
+    assert_eq!(codegen.call::<_, i32>("measure", (end as u32,)).unwrap(), 3);
+    // a string running to the end of memory stops there
+    codegen.memory_mut().unwrap()[end..].copy_from_slice(b"wxyz");
+    assert_eq!(codegen.call::<_, i32>("measure", (end as u32,)).unwrap(), 4);
+
+    // offsets past the end trap instead of reaching host memory
+    let err = codegen.call::<_, i32>("measure", (1u32 << 20,)).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::HEAP_OUT_OF_BOUNDS);
+    // host pointers aren't accepted for `char*`
+    let err = codegen.call::<_, i32>("measure", (c"host".as_ptr(),)).unwrap_err();
+    assert!(matches!(err, CompileError::EntrySignature { .. }), "{}", err);
+}
+
+#[test]
+fn test_imports_returning_host_pointers_trap() {
+    let source = r#"
+char *escape(char *s);
+int main() { char *s = escape("x"); return 0; }
+"#;
+    let mut codegen = sandboxed(source, imports(&["escape"])).unwrap();
+    let err = codegen.run_main::<i32>().unwrap_err();
+    assert_eq!(err.to_string(), "trap heap_oob in `main` at line 3");
+}
+
+#[test]
+fn test_only_allowed_imports_can_be_declared() {
+    let source = "int length(char *s);\nint main() { return length(\"abc\"); }";
+    let err = sandboxed(source, SandboxOptions::default()).err().unwrap();
+    assert!(matches!(&err, CompileError::ImportNotAllowed(name) if name == "length"), "{}", err);
+
+    // libc is just as out of reach as registered functions
+    let source = "int system(char *command);\nint main() { return system(\"true\"); }";
+    let err = sandboxed(source, imports(&["length"])).err().unwrap();
+    assert_eq!(err.to_string(), "`system` is not an allowed import in the sandbox");
+
+    // functions the program defines itself aren't imports
+    let source = "int twice(int x);\nint main() { return twice(21); }\nint twice(int x) { return x * 2; }";
+    let mut codegen = sandboxed(source, SandboxOptions::default()).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 42);
+}
+
+#[test]
+fn test_variadic_imports_get_host_pointers() {
+    let source = r#"
+int snprintf(char *buffer, size_t size, char *format, ...);
+int length(char *s);
+int main() {
+    char *buffer = "................";
+    snprintf(buffer, 16, "%d-%s", 42, "ok");
+    return length(buffer);
+}
+"#;
+    let options = imports(&["length"]).allow_import_with_lengths("snprintf", &[(0, 1)]);
+    let mut codegen = sandboxed(source, options).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 5);
+    let memory = codegen.memory().unwrap();
+    assert!(memory.windows(6).any(|window| window == b"42-ok\0"));
+}
+
+#[test]
+fn test_lengths_passed_to_imports_are_bounded() {
+    let source = r#"
+int snprintf(char *buffer, size_t size, char *format, ...);
+int fill(char *buffer, size_t size) { return snprintf(buffer, size, "%d", 12345); }
+"#;
+    let options = SandboxOptions::default().allow_import_with_lengths("snprintf", &[(0, 1)]);
+    let mut codegen = sandboxed(source, options).unwrap();
+    let size = codegen.memory().unwrap().len();
+    let end = (size - 8) as u32;
+    assert_eq!(codegen.call::<_, i32>("fill", (end, 8usize)).unwrap(), 5);
+    assert_eq!(&codegen.memory().unwrap()[size - 8..size - 2], b"12345\0");
+
+    // a size reaching past the end traps before the import can write there
+    let err = codegen.call::<_, i32>("fill", (end, 9usize)).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::HEAP_OUT_OF_BOUNDS);
+    let err = codegen.call::<_, i32>("fill", (8u32, usize::MAX)).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::HEAP_OUT_OF_BOUNDS);
+}
+
+#[test]
+fn test_only_stated_lengths_are_checked() {
+    // `n` is how far `memchr` may read from `s`, but the `size_t` after the
+    // `char*` of `tag` isn't a length at all
+    let source = r#"
+char *memchr(char *s, int c, size_t n);
+long tag(char *s, size_t tag);
+char *find(char *s, size_t n) { return memchr(s, 'x', n); }
+long tagged(char *s, size_t n) { return tag(s, n); }
+"#;
+    let options = SandboxOptions::default()
+        .allow_import_with_lengths("memchr", &[(0, 2)])
+        .allow_import("tag");
+    let mut codegen = sandboxed(source, options).unwrap();
+    let size = codegen.memory().unwrap().len();
+    let end = (size - 4) as u32;
+    codegen.memory_mut().unwrap()[size - 4..].copy_from_slice(b"12x\0");
+    assert_eq!(codegen.call::<_, u32>("find", (end, 4usize)).unwrap(), end + 2);
+    let err = codegen.call::<_, u32>("find", (end, 5usize)).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::HEAP_OUT_OF_BOUNDS);
+    assert_eq!(codegen.call::<_, i64>("tagged", (end, usize::MAX)).unwrap(), b'1' as i64 - 1);
+
+    // lengths have to name a `char*` and a `size_t`
+    let options = SandboxOptions::default().allow_import_with_lengths("memchr", &[(0, 1)]);
+    let err = sandboxed(source, options).err().unwrap();
+    assert_eq!(err.to_string(), "import `memchr` has no `char*` param 0 with a `size_t` length in param 1");
+}
+
+#[test]
+fn test_sandboxed_modules_stay_with_their_memory() {
+    let codegen = sandboxed("int main() { return 0; }", SandboxOptions::default()).unwrap();
+    let err = codegen.into_module().err().unwrap();
+    assert!(matches!(err, CompileError::Unsupported(_)), "{}", err);
+}
+
+#[test]
+fn test_ways_to_forge_pointers_are_rejected() {
+    let source = "int first(int count, ...) { va_list args; va_start(args, count); \
+                  int x = va_arg(args, int); va_end(args); return x; }";
+    let err = sandboxed(source, SandboxOptions::default()).err().unwrap();
+    assert_eq!(err.to_string(), "va_list in sandboxed code is not supported yet");
+
+    let source = "int length(char *s);\nint main() { int (*f)(char *s) = length; return f(\"abc\"); }";
+    let err = sandboxed(source, imports(&["length"])).err().unwrap();
+    assert!(matches!(err, CompileError::Unsupported(_)), "{}", err);
+
+    // pointers other than `char*` would be host addresses
+    let source = "int apply(int (*f)(int x));\nint main() { return 0; }";
+    let err = sandboxed(source, imports(&["apply"])).err().unwrap();
+    assert!(matches!(err, CompileError::Unsupported(_)), "{}", err);
+    let source = "int next(va_list args) { return 0; }";
+    let err = sandboxed(source, SandboxOptions::default()).err().unwrap();
+    assert_eq!(err.to_string(), "va_list in sandboxed code is not supported yet");
+
+    let source = "int (*callback)(int x);\nint main() { return callback(1); }";
+    let mut codegen = sandboxed(source, SandboxOptions::default()).unwrap();
+    assert_eq!(trap_code(codegen.run_main::<i32>().unwrap_err()), trap::NULL_CALL);
+}
+
+#[test]
+fn test_memory_size_limits_strings() {
+    let options = SandboxOptions {
+        memory_size: 16,
+        ..SandboxOptions::default()
+    };
+    let source = r#"char *a = "12345678"; char *b = "123456789";"#;
+    let err = sandboxed(source, options).err().unwrap();
+    assert!(matches!(err, CompileError::SandboxMemory(16)), "{}", err);
+}
+
+#[test]
+fn test_sandbox_is_jit_only() {
+    let mut builder = CompilerBuilder::new();
+    builder.sandbox(SandboxOptions::default());
+    assert!(builder.compiler().is_ok());
+    let err = builder.object_codegen("sandboxed").err().unwrap();
+    assert!(matches!(err, CompileError::Unsupported(_)), "{}", err);
+}
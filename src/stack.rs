@@ -0,0 +1,172 @@
+use crate::error::{CompileError, CompileResult};
+use crate::module::ModuleType;
+use crate::trap;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{Function, InstBuilder, MemFlags, SourceLoc, TrapCode};
+use cranelift_jit::JITModule;
+use cranelift_module::{DataDescription, FuncOrDataId, Linkage, Module};
+use std::{ptr, thread};
+
+// The word JIT functions compare the stack pointer against on entry, one per module.
+const LIMIT_SYMBOL: &str = "__stack_limit";
+
+// What the host needs below the limit: the frame of the function that trapped,
+// and unwinding from the trap back to `catch_traps`.
+pub const RESERVED_STACK: usize = 256 * 1024;
+
+// How much stack one call into JIT code may use, and where it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StackOptions {
+    // bytes below the caller's frame; by default everything down to
+    // `RESERVED_STACK` above the end of the thread's stack
+    pub limit: Option<usize>,
+    // run on a new thread with this much stack for the JIT code
+    pub thread_stack_size: Option<usize>,
+}
+
+impl StackOptions {
+    pub fn limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    pub fn thread(stack_size: usize) -> Self {
+        Self {
+            thread_stack_size: Some(stack_size),
+            ..Self::default()
+        }
+    }
+}
+
+// Makes `func` trap with `STACK_OVERFLOW` when it's entered with the stack
+// pointer below its module's limit. Cranelift's own `stack_limit` can only load
+// the limit through a `vmctx` parameter, which our signatures don't have.
+// Functions that make no calls and have no stack slots can't use much stack,
+// and go unchecked.
+pub(crate) fn insert_stack_check(module: &mut ModuleType, func: &mut Function, srcloc: SourceLoc) -> CompileResult<()> {
+    let calls = func.layout.blocks().any(|block| {
+        func.layout
+            .block_insts(block)
+            .any(|inst| func.dfg.insts[inst].opcode().is_call())
+    });
+    if !calls && func.sized_stack_slots.is_empty() {
+        return Ok(());
+    }
+    let Some(entry) = func.layout.entry_block() else {
+        return Ok(());
+    };
+
+    let limit_id = match module.get_name(LIMIT_SYMBOL) {
+        Some(FuncOrDataId::Data(id)) => id,
+        _ => {
+            let id = module.declare_data(LIMIT_SYMBOL, Linkage::Local, true, false)?;
+            let mut data = DataDescription::new();
+            data.define_zeroinit(module.target_config().pointer_bytes() as usize);
+            module.define_data(id, &data)?;
+            id
+        }
+    };
+    let pointer_type = module.target_config().pointer_type();
+    let limit_gv = module.declare_data_in_func(limit_id, func);
+
+    let mut pos = FuncCursor::new(func).at_first_insertion_point(entry);
+    pos.set_srcloc(srcloc);
+    let address = pos.ins().global_value(pointer_type, limit_gv);
+    let limit = pos.ins().load(pointer_type, MemFlags::trusted(), address, 0);
+    let sp = pos.ins().get_stack_pointer(pointer_type);
+    let overflowed = pos.ins().icmp(IntCC::UnsignedLessThan, sp, limit);
+    pos.ins().trapnz(overflowed, TrapCode::STACK_OVERFLOW);
+    Ok(())
+}
+
+// Runs `call` on the stack `options` asks for, with the stack limit of `jit`
+// set for it.
+pub(crate) fn run<R>(jit: &JITModule, options: StackOptions, call: impl FnOnce() -> CompileResult<R>) -> CompileResult<R> {
+    let limit = match jit.get_name(LIMIT_SYMBOL) {
+        Some(FuncOrDataId::Data(id)) => Some(jit.get_finalized_data(id).0 as *mut usize),
+        _ => None,
+    };
+    let Some(stack_size) = options.thread_stack_size else {
+        return with_limit(limit, options.limit, call);
+    };
+
+    // The caller waits for the thread, so nothing it passes is used by two
+    // threads at once.
+    let call = AssertSend((limit, call));
+    thread::scope(|scope| {
+        let thread = thread::Builder::new()
+            .name("jit".to_string())
+            .stack_size(stack_size + RESERVED_STACK)
+            .spawn_scoped(scope, move || {
+                let (limit, call) = call.into_inner();
+                AssertSend(with_limit(limit, options.limit, call))
+            })?;
+        match thread.join() {
+            Ok(result) => result.into_inner(),
+            Err(payload) => Err(CompileError::HostPanic(trap::panic_message(&*payload))),
+        }
+    })
+}
+
+struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    // taking `self` whole, so closures capture the wrapper rather than its field
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+fn with_limit<R>(word: Option<*mut usize>, limit: Option<usize>, call: impl FnOnce() -> CompileResult<R>) -> CompileResult<R> {
+    let Some(word) = word else {
+        return call();
+    };
+    let sp = ptr::addr_of!(word) as usize;
+    let floor = stack_end().map_or(0, |end| end + RESERVED_STACK);
+    let value = match limit {
+        Some(limit) => sp.saturating_sub(limit).max(floor),
+        None => floor,
+    };
+    unsafe {
+        let previous = ptr::read_volatile(word);
+        ptr::write_volatile(word, value);
+        let result = call();
+        ptr::write_volatile(word, previous);
+        result
+    }
+}
+
+// The lowest address of the current thread's stack.
+#[cfg(target_os = "linux")]
+pub(crate) fn stack_end() -> Option<usize> {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut low = ptr::null_mut();
+        let mut size = 0;
+        let found = libc::pthread_attr_getstack(&attr, &mut low, &mut size) == 0;
+        libc::pthread_attr_destroy(&mut attr);
+        found.then_some(low as usize)
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn stack_end() -> Option<usize> {
+    unsafe {
+        let thread = libc::pthread_self();
+        Some(libc::pthread_get_stackaddr_np(thread) as usize - libc::pthread_get_stacksize_np(thread))
+    }
+}
+
+// Elsewhere only an explicit limit is enforced.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn stack_end() -> Option<usize> {
+    None
+}
@@ -0,0 +1,66 @@
+use crate::compiler::CompilerBuilder;
+use crate::stack::StackOptions;
+use crate::test_helpers::{jit, overflowed};
+
+#[test]
+fn test_unbounded_recursion_overflows() {
+    let source = "int depth(int n) {
+    if (n == 0) { return 0; }
+    return depth(n - 1) + 1;
+}
+int forever(int n) {
+    return forever(n + 1) + 1;
+}
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    let err = codegen.call::<_, i32>("forever", (0,)).unwrap_err();
+    assert_eq!(err.to_string(), "stack overflow in `forever` at line 5");
+
+    // the unwound frames leave the stack as it was
+    assert_eq!(codegen.call::<_, i32>("depth", (1000,)).unwrap(), 1000);
+    overflowed(codegen.call::<_, i32>("forever", (0,)).unwrap_err());
+}
+
+#[test]
+fn test_stack_limit_per_call() {
+    let source = "int depth(int n) {
+    if (n == 0) { return 0; }
+    return depth(n - 1) + 1;
+}
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    let small = StackOptions::limit(16 * 1024);
+    assert_eq!(codegen.call_with_stack::<_, i32>("depth", (10,), small).unwrap(), 10);
+    let location = overflowed(codegen.call_with_stack::<_, i32>("depth", (10_000,), small).unwrap_err());
+    assert_eq!(location.function, "depth");
+
+    // the limit only applies to that call
+    assert_eq!(codegen.call::<_, i32>("depth", (10_000,)).unwrap(), 10_000);
+}
+
+#[test]
+fn test_dedicated_stack_thread() {
+    let source = "int depth(int n) {
+    if (n == 0) { return 0; }
+    return depth(n - 1) + 1;
+}
+int forever(int n) {
+    return forever(n + 1) + 1;
+}
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    // far more than the 2 MiB test threads have
+    let deep = 1_000_000;
+    overflowed(codegen.call::<_, i32>("depth", (deep,)).unwrap_err());
+
+    let thread = StackOptions::thread(256 * 1024 * 1024);
+    assert_eq!(codegen.call_with_stack::<_, i32>("depth", (deep,), thread).unwrap(), deep);
+
+    // limits and traps work the same on the thread
+    let limited = StackOptions {
+        limit: Some(64 * 1024),
+        ..thread
+    };
+    overflowed(codegen.call_with_stack::<_, i32>("depth", (deep,), limited).unwrap_err());
+    overflowed(codegen.call_with_stack::<_, i32>("forever", (0,), thread).unwrap_err());
+}
@@ -0,0 +1,38 @@
+use crate::codegen::Codegen;
+use crate::compiler::CompilerBuilder;
+use crate::error::CompileError;
+use crate::parser::parse;
+use crate::trap::TrapLocation;
+use cranelift_codegen::ir::TrapCode;
+
+// A JIT codegen from `builder` with `source` compiled into it.
+pub(crate) fn jit(builder: &CompilerBuilder, source: &str) -> Codegen {
+    let mut codegen = builder.compiler().unwrap().build();
+    codegen.compile_program(parse(source).unwrap()).unwrap();
+    codegen
+}
+
+pub(crate) fn trap(err: CompileError) -> (TrapCode, TrapLocation) {
+    match err {
+        CompileError::Trap { code, location } => (code, location),
+        other => panic!("expected a trap, got {}", other),
+    }
+}
+
+pub(crate) fn trap_code(err: CompileError) -> TrapCode {
+    trap(err).0
+}
+
+pub(crate) fn overflowed(err: CompileError) -> TrapLocation {
+    match err {
+        CompileError::StackOverflow(location) => location,
+        other => panic!("expected a stack overflow, got {}", other),
+    }
+}
+
+pub(crate) fn out_of_fuel(err: CompileError) -> TrapLocation {
+    match err {
+        CompileError::OutOfFuel(location) => location,
+        other => panic!("expected to run out of fuel, got {}", other),
+    }
+}
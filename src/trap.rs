@@ -0,0 +1,295 @@
+use crate::error::{CompileError, CompileResult};
+use cranelift_codegen::ir::TrapCode;
+use cranelift_codegen::Context;
+use cranelift_module::FuncId;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{hint, ptr};
+
+// What checked arithmetic traps with on overflow.
+pub const OVERFLOW: TrapCode = TrapCode::unwrap_user(1);
+// What code compiled to consume fuel traps with when it runs out.
+pub const OUT_OF_FUEL: TrapCode = TrapCode::unwrap_user(2);
+// What sandboxed code traps with when it calls a null function pointer.
+pub const NULL_CALL: TrapCode = TrapCode::unwrap_user(3);
+
+// How trap codes appear in error messages: Cranelift's own names, and ours for
+// the user codes the compiler traps with.
+pub fn code_name(code: &TrapCode) -> String {
+    match *code {
+        OVERFLOW => "overflow".to_string(),
+        OUT_OF_FUEL => "out_of_fuel".to_string(),
+        NULL_CALL => "null_call".to_string(),
+        code => code.to_string(),
+    }
+}
+
+// Where in the program a trap happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapLocation {
+    pub function: String,
+    // only known when the program was parsed from source
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for TrapLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}`", self.function)?;
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TrapSite {
+    code: TrapCode,
+    location: TrapLocation,
+}
+
+// every trapping instruction of finalized JIT code, by address
+static TRAP_SITES: Mutex<BTreeMap<usize, TrapSite>> = Mutex::new(BTreeMap::new());
+
+// The addresses in `TRAP_SITES`, sorted, for the signal handler, which can't
+// take a lock: it could have interrupted the lock's holder. Every change to
+// `TRAP_SITES` publishes a new table, and frees the old one once no handler
+// is reading it.
+static TRAP_PCS: AtomicPtr<Vec<usize>> = AtomicPtr::new(ptr::null_mut());
+// signal handlers looking in `TRAP_PCS`
+static TRAP_PC_READERS: AtomicUsize = AtomicUsize::new(0);
+
+fn publish_trap_pcs(sites: &BTreeMap<usize, TrapSite>) {
+    let pcs = Box::into_raw(Box::new(sites.keys().copied().collect::<Vec<_>>()));
+    let previous = TRAP_PCS.swap(pcs, Ordering::SeqCst);
+    // a handler that could still see `previous` counted itself in first
+    while TRAP_PC_READERS.load(Ordering::SeqCst) != 0 {
+        hint::spin_loop();
+    }
+    if !previous.is_null() {
+        drop(unsafe { Box::from_raw(previous) });
+    }
+}
+
+thread_local! {
+    // how many calls into JIT code are active on this thread
+    static JIT_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // the address that trapped, for `raise_trap`
+    static TRAP_PC: Cell<usize> = const { Cell::new(0) };
+}
+
+// The trap sites of one `Codegen`'s JIT functions, removed from the global
+// table when it's dropped.
+#[derive(Default)]
+pub(crate) struct TrapRegistry {
+    // defined functions that haven't been finalized yet, with the offsets of
+    // their trap sites
+    pending: Vec<(FuncId, Vec<(u32, TrapSite)>)>,
+    registered: Vec<usize>,
+}
+
+impl TrapRegistry {
+    pub(crate) fn add_function(&mut self, func_id: FuncId, name: &str, ctx: &Context) {
+        let code = ctx.compiled_code().expect("defined functions are compiled");
+        let srclocs = code.buffer.get_srclocs_sorted();
+        let sites = code
+            .buffer
+            .traps()
+            .iter()
+            .map(|trap| {
+                let line = srclocs
+                    .iter()
+                    .find(|srcloc| (srcloc.start..srcloc.end).contains(&trap.offset) && !srcloc.loc.is_default())
+                    .map(|srcloc| srcloc.loc.bits());
+                let location = TrapLocation {
+                    function: name.to_string(),
+                    line,
+                };
+                (trap.offset, TrapSite { code: trap.code, location })
+            })
+            .collect();
+        self.pending.push((func_id, sites));
+    }
+
+    pub(crate) fn register(&mut self, address: impl Fn(FuncId) -> *const u8) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut sites = TRAP_SITES.lock().unwrap_or_else(|err| err.into_inner());
+        for (func_id, function_sites) in self.pending.drain(..) {
+            let start = address(func_id) as usize;
+            for (offset, site) in function_sites {
+                sites.insert(start + offset as usize, site);
+                self.registered.push(start + offset as usize);
+            }
+        }
+        publish_trap_pcs(&sites);
+    }
+}
+
+impl Drop for TrapRegistry {
+    fn drop(&mut self) {
+        if self.registered.is_empty() {
+            return;
+        }
+        let mut sites = TRAP_SITES.lock().unwrap_or_else(|err| err.into_inner());
+        for pc in &self.registered {
+            sites.remove(pc);
+        }
+        publish_trap_pcs(&sites);
+    }
+}
+
+// what `raise_trap` unwinds with
+struct TrapPayload {
+    pc: usize,
+}
+
+// Calls into JIT code. A trap in it, or a panic in a host function it calls,
+// unwinds back here and is returned as an error.
+pub(crate) fn catch_traps<R>(call: impl FnOnce() -> R) -> CompileResult<R> {
+    signals::install();
+    let depth = JIT_DEPTH.get();
+    JIT_DEPTH.set(depth + 1);
+    let result = panic::catch_unwind(AssertUnwindSafe(call));
+    JIT_DEPTH.set(depth);
+    result.map_err(|payload| match payload.downcast::<TrapPayload>() {
+        Ok(trap) => {
+            let sites = TRAP_SITES.lock().unwrap_or_else(|err| err.into_inner());
+            let site = sites[&trap.pc].clone();
+            match site.code {
+                TrapCode::STACK_OVERFLOW => return CompileError::StackOverflow(site.location),
+                OUT_OF_FUEL => return CompileError::OutOfFuel(site.location),
+                _ => {}
+            }
+            CompileError::Trap {
+                code: site.code,
+                location: site.location,
+            }
+        }
+        Err(payload) => CompileError::HostPanic(panic_message(&*payload)),
+    })
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+// Only atomics, so that the signal handler can call it.
+fn is_trap_site(pc: usize) -> bool {
+    if JIT_DEPTH.get() == 0 {
+        return false;
+    }
+    TRAP_PC_READERS.fetch_add(1, Ordering::SeqCst);
+    let pcs = TRAP_PCS.load(Ordering::SeqCst);
+    let found = !pcs.is_null() && unsafe { &*pcs }.binary_search(&pc).is_ok();
+    TRAP_PC_READERS.fetch_sub(1, Ordering::SeqCst);
+    found
+}
+
+// The signal handler makes a trapping instruction look like it called this, so
+// that the trap unwinds through the JIT frames using their `.eh_frame`.
+#[inline(never)]
+extern "C-unwind" fn raise_trap() -> ! {
+    panic::resume_unwind(Box::new(TrapPayload { pc: TRAP_PC.get() }))
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod signals {
+    use super::{is_trap_site, raise_trap, TRAP_PC};
+    use libc::{c_int, c_void, siginfo_t};
+    use std::sync::OnceLock;
+    use std::{mem, ptr};
+
+    const SIGNALS: [c_int; 5] = [libc::SIGILL, libc::SIGFPE, libc::SIGSEGV, libc::SIGBUS, libc::SIGTRAP];
+
+    // the handlers ours replaced, for faults that aren't JIT traps
+    static PREVIOUS: OnceLock<Vec<(c_int, libc::sigaction)>> = OnceLock::new();
+
+    pub(super) fn install() {
+        PREVIOUS.get_or_init(|| {
+            SIGNALS
+                .iter()
+                .map(|&signal| unsafe {
+                    let mut action: libc::sigaction = mem::zeroed();
+                    action.sa_sigaction = handle_signal as *const () as usize;
+                    action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    let mut previous: libc::sigaction = mem::zeroed();
+                    libc::sigaction(signal, &action, &mut previous);
+                    (signal, previous)
+                })
+                .collect()
+        });
+    }
+
+    unsafe extern "C" fn handle_signal(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        if redirect_to_raise_trap(context.cast()) {
+            return;
+        }
+        let Some((_, previous)) = PREVIOUS.get().and_then(|all| all.iter().find(|(s, _)| *s == signal)) else {
+            return;
+        };
+        if previous.sa_sigaction == libc::SIG_DFL || previous.sa_sigaction == libc::SIG_IGN {
+            // returning faults again, this time under the previous disposition
+            libc::sigaction(signal, previous, ptr::null_mut());
+        } else if previous.sa_flags & libc::SA_SIGINFO != 0 {
+            let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(previous.sa_sigaction);
+            handler(signal, info, context);
+        } else {
+            let handler: extern "C" fn(c_int) = mem::transmute(previous.sa_sigaction);
+            handler(signal);
+        }
+    }
+
+    // Sets up the interrupted context as if the instruction at its PC had called
+    // `raise_trap`, returning into the middle of that instruction so unwinding
+    // uses its frame's state at the trap.
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn redirect_to_raise_trap(context: *mut libc::ucontext_t) -> bool {
+        let registers = &mut (*context).uc_mcontext.gregs;
+        let pc = registers[libc::REG_RIP as usize] as usize;
+        if !is_trap_site(pc) {
+            return false;
+        }
+        TRAP_PC.set(pc);
+        // `raise_trap` expects the stack alignment of a call. Skipping a few
+        // bytes to get it doesn't confuse unwinding, since JIT frames keep
+        // frame pointers and find their caller through those.
+        let sp = (registers[libc::REG_RSP as usize] as usize & !15) - 8;
+        *(sp as *mut usize) = pc + 1;
+        registers[libc::REG_RSP as usize] = sp as i64;
+        registers[libc::REG_RIP as usize] = raise_trap as *const () as usize as i64;
+        true
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn redirect_to_raise_trap(context: *mut libc::ucontext_t) -> bool {
+        let registers = &mut (*context).uc_mcontext;
+        let pc = registers.pc as usize;
+        if !is_trap_site(pc) {
+            return false;
+        }
+        TRAP_PC.set(pc);
+        registers.regs[30] = pc as u64 + 1;
+        registers.pc = raise_trap as *const () as usize as u64;
+        true
+    }
+}
+
+// Elsewhere traps still kill the process.
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod signals {
+    pub(super) fn install() {}
+}
@@ -0,0 +1,177 @@
+use crate::compiler::CompilerBuilder;
+use crate::error::CompileError;
+use crate::parser::parse;
+use crate::trap::{self, TrapLocation};
+use crate::report::CompileOptions;
+use crate::test_helpers::{jit, trap};
+use cranelift_codegen::ir::TrapCode;
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+#[test]
+fn test_division_traps_become_errors() {
+    let source = "int divide(int a, int b) {
+    int quotient = a / b;
+    return quotient;
+}
+int twice(int a, int b) { return divide(a, b) * 2; }
+int main() { return twice(1, 0); }
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+
+    let (code, location) = trap(codegen.run_main::<i32>().unwrap_err());
+    assert_eq!(code, TrapCode::INTEGER_DIVISION_BY_ZERO);
+    assert_eq!(
+        location,
+        TrapLocation {
+            function: "divide".to_string(),
+            line: Some(2)
+        }
+    );
+
+    let (code, location) = trap(codegen.call::<_, i32>("twice", (i32::MIN, -1)).unwrap_err());
+    assert_eq!(code, TrapCode::INTEGER_OVERFLOW);
+    assert_eq!(location.function, "divide");
+
+    // nothing is left behind by the unwound frames
+    assert_eq!(codegen.call::<_, i32>("twice", (10, 2)).unwrap(), 10);
+}
+
+#[test]
+fn test_trap_messages() {
+    let source = "int divide(int a, int b) {
+    return a / b;
+}
+int main() { return divide(1, 0); }
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    let err = codegen.run_main::<i32>().unwrap_err();
+    assert_eq!(err.to_string(), "trap int_divz in `divide` at line 2");
+}
+
+#[test]
+fn test_checked_arithmetic_traps_on_overflow() {
+    let source = "int add(int a, int b) { return a + b; }
+int sub(int a, int b) { return a - b; }
+int mul(int a, int b) { return a * b; }
+int neg(int a) { return -a; }
+unsigned int uadd(unsigned int a, unsigned int b) { return a + b; }
+unsigned int usub(unsigned int a, unsigned int b) { return a - b; }
+unsigned int umul(unsigned int a, unsigned int b) { return a * b; }
+char narrow(int a) { char c = a; return c; }
+signed char snarrow(unsigned int a) { signed char c = a; return c; }
+int wrap_add(int a, int b) { return wrapping_add(a, b); }
+int wrap_neg(int a) { return wrapping_neg(a); }
+";
+    let mut codegen = jit(CompilerBuilder::new().checked_arithmetic(true), source);
+    let overflows = |err: CompileError| trap(err).0 == trap::OVERFLOW;
+
+    assert_eq!(codegen.call::<_, i32>("add", (i32::MAX - 1, 1)).unwrap(), i32::MAX);
+    assert!(overflows(codegen.call::<_, i32>("add", (i32::MAX, 1)).unwrap_err()));
+    assert!(overflows(codegen.call::<_, i32>("sub", (i32::MIN, 1)).unwrap_err()));
+    assert_eq!(codegen.call::<_, i32>("mul", (-46341, 46340)).unwrap(), -2147441940);
+    assert!(overflows(codegen.call::<_, i32>("mul", (46341, 46341)).unwrap_err()));
+    assert_eq!(codegen.call::<_, i32>("neg", (i32::MAX,)).unwrap(), -i32::MAX);
+    assert!(overflows(codegen.call::<_, i32>("neg", (i32::MIN,)).unwrap_err()));
+
+    assert!(overflows(codegen.call::<_, u32>("uadd", (u32::MAX, 1u32)).unwrap_err()));
+    assert!(overflows(codegen.call::<_, u32>("usub", (1u32, 2u32)).unwrap_err()));
+    assert_eq!(codegen.call::<_, u32>("umul", (65535u32, 65537u32)).unwrap(), u32::MAX);
+    assert!(overflows(codegen.call::<_, u32>("umul", (65536u32, 65536u32)).unwrap_err()));
+
+    assert_eq!(codegen.call::<_, u8>("narrow", (255,)).unwrap(), 255);
+    assert!(overflows(codegen.call::<_, u8>("narrow", (256,)).unwrap_err()));
+    assert!(overflows(codegen.call::<_, u8>("narrow", (-1,)).unwrap_err()));
+    assert_eq!(codegen.call::<_, i8>("snarrow", (127u32,)).unwrap(), 127);
+    assert!(overflows(codegen.call::<_, i8>("snarrow", (128u32,)).unwrap_err()));
+    assert!(overflows(codegen.call::<_, i8>("snarrow", (u32::MAX,)).unwrap_err()));
+
+    // the intrinsics decide for themselves
+    assert_eq!(codegen.call::<_, i32>("wrap_add", (i32::MAX, 1)).unwrap(), i32::MIN);
+    assert_eq!(codegen.call::<_, i32>("wrap_neg", (i32::MIN,)).unwrap(), i32::MIN);
+
+    let err = codegen.call::<_, i32>("add", (i32::MAX, 1)).unwrap_err();
+    assert_eq!(err.to_string(), "trap overflow in `add` at line 1");
+}
+
+#[test]
+fn test_arithmetic_wraps_by_default() {
+    let source = "int add(int a, int b) { return a + b; }
+int neg(int a) { return -a; }
+unsigned int usub(unsigned int a, unsigned int b) { return a - b; }
+char narrow(int a) { char c = a; return c; }
+int check_mul(int a, int b) { return checked_mul(a, b); }
+";
+    let mut codegen = jit(&CompilerBuilder::new(), source);
+    assert_eq!(codegen.call::<_, i32>("add", (i32::MAX, 1)).unwrap(), i32::MIN);
+    assert_eq!(codegen.call::<_, i32>("neg", (i32::MIN,)).unwrap(), i32::MIN);
+    assert_eq!(codegen.call::<_, u32>("usub", (1u32, 2u32)).unwrap(), u32::MAX);
+    assert_eq!(codegen.call::<_, u8>("narrow", (256,)).unwrap(), 0);
+
+    let (code, location) = trap(codegen.call::<_, i32>("check_mul", (46341, 46341)).unwrap_err());
+    assert_eq!(code, trap::OVERFLOW);
+    assert_eq!(location.function, "check_mul");
+    assert_eq!(codegen.call::<_, i32>("check_mul", (-3, 7)).unwrap(), -21);
+}
+
+#[test]
+fn test_checked_float_conversions_trap() {
+    let source = "unsigned char to_char(double x) { char c = x; return c; }
+signed char to_signed_char(double x) { return x; }
+int to_int(double x) { return x; }
+unsigned to_unsigned(float x) { return x; }
+";
+    let mut codegen = jit(CompilerBuilder::new().checked_arithmetic(true), source);
+    let trap_code = |err: CompileError| trap(err).0;
+
+    assert_eq!(codegen.call::<_, u8>("to_char", (255.9,)).unwrap(), 255);
+    assert_eq!(codegen.call::<_, u8>("to_char", (-0.5,)).unwrap(), 0);
+    assert_eq!(trap_code(codegen.call::<_, u8>("to_char", (256.0,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+    assert_eq!(trap_code(codegen.call::<_, u8>("to_char", (1e300,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+    assert_eq!(trap_code(codegen.call::<_, u8>("to_char", (-1.0,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+    assert_eq!(codegen.call::<_, i8>("to_signed_char", (-128.9,)).unwrap(), -128);
+    assert_eq!(trap_code(codegen.call::<_, i8>("to_signed_char", (128.0,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+    assert_eq!(codegen.call::<_, i32>("to_int", (-2147483648.9,)).unwrap(), i32::MIN);
+    assert_eq!(trap_code(codegen.call::<_, i32>("to_int", (2147483648.0,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+    let err = codegen.call::<_, i32>("to_int", (f64::NAN,)).unwrap_err();
+    assert_eq!(trap_code(err), TrapCode::BAD_CONVERSION_TO_INTEGER);
+    assert_eq!(trap_code(codegen.call::<_, u32>("to_unsigned", (-1.0f32,)).unwrap_err()), TrapCode::INTEGER_OVERFLOW);
+
+    let err = codegen.call::<_, u8>("to_char", (1e300,)).unwrap_err();
+    assert_eq!(err.to_string(), "trap int_ovf in `to_char` at line 1");
+}
+
+// riscv64 and s390x have no overflow-flag instructions, so they get the checks
+// spelled out
+#[test]
+fn test_checked_arithmetic_cross_compiles() {
+    let source = "int add(int a, int b) { return a + b; }
+int neg(int a) { return -a; }
+unsigned int umul(unsigned int a, unsigned int b) { return a * b; }
+signed char snarrow(unsigned int a) { signed char c = a; return c; }
+long lmul(long a, long b) { return a * b - a; }
+short smul(short a, short b) { return a * b; }
+unsigned char bmul(unsigned char a, unsigned char b) { return a * b + a; }
+";
+    for target in [
+        "x86_64-unknown-linux-gnu",
+        "aarch64-unknown-linux-gnu",
+        "riscv64gc-unknown-linux-gnu",
+        "s390x-unknown-linux-gnu",
+    ] {
+        let mut codegen = CompilerBuilder::new()
+            .triple(Triple::from_str(target).unwrap())
+            .checked_arithmetic(true)
+            .object_codegen("checked")
+            .unwrap();
+        codegen.set_options(CompileOptions {
+            clif: true,
+            ..Default::default()
+        });
+        codegen.compile_program(parse(source).unwrap()).unwrap();
+        let clif = codegen.report().function("lmul").unwrap().clif.clone().unwrap();
+        assert_eq!(clif.contains("smul_overflow"), !target.starts_with("riscv64") && !target.starts_with("s390x"), "{}", target);
+        assert!(clif.contains("trapnz"), "{}", target);
+        codegen.into_module().unwrap().emit_object().unwrap();
+    }
+}
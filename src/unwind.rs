@@ -0,0 +1,132 @@
+use crate::error::{CompileError, CompileResult};
+use cranelift_codegen::gimli::write::{Address, EhFrame, EndianVec, FrameTable};
+use cranelift_codegen::gimli::RunTimeEndian;
+use cranelift_codegen::ir::Endianness;
+use cranelift_codegen::isa::unwind::{systemv, UnwindInfo};
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::Context;
+use cranelift_module::FuncId;
+
+// `.eh_frame` entries for JIT code, registered with the system unwinder so
+// that panics and backtraces can walk through generated frames.
+#[derive(Default)]
+pub(crate) struct UnwindRegistry {
+    // defined functions that haven't been finalized yet
+    pending: Vec<(FuncId, systemv::UnwindInfo)>,
+    registrations: Vec<EhFrameRegistration>,
+}
+
+impl UnwindRegistry {
+    pub(crate) fn add_function(&mut self, func_id: FuncId, ctx: &Context, isa: &dyn TargetIsa) -> CompileResult<()> {
+        let code = ctx.compiled_code().expect("defined functions are compiled");
+        let info = code
+            .create_unwind_info(isa)
+            .map_err(|err| CompileError::Object(err.to_string()))?;
+        if let Some(UnwindInfo::SystemV(info)) = info {
+            self.pending.push((func_id, info));
+        }
+        Ok(())
+    }
+
+    // Registers the functions added since the last call, once `address` can
+    // tell where they were finalized.
+    pub(crate) fn register(&mut self, isa: &dyn TargetIsa, address: impl Fn(FuncId) -> *const u8) -> CompileResult<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let Some(cie) = isa.create_systemv_cie() else {
+            self.pending.clear();
+            return Ok(());
+        };
+        let mut table = FrameTable::default();
+        let cie = table.add_cie(cie);
+        for (func_id, info) in self.pending.drain(..) {
+            table.add_fde(cie, info.to_fde(Address::Constant(address(func_id) as u64)));
+        }
+
+        let endian = match isa.endianness() {
+            Endianness::Little => RunTimeEndian::Little,
+            Endianness::Big => RunTimeEndian::Big,
+        };
+        let mut eh_frame = EhFrame(EndianVec::new(endian));
+        table
+            .write_eh_frame(&mut eh_frame)
+            .map_err(|err| CompileError::Object(err.to_string()))?;
+        let mut bytes = eh_frame.0.into_vec();
+        // a zero-length entry ends the table
+        bytes.extend_from_slice(&[0; 4]);
+        self.registrations.push(EhFrameRegistration::new(bytes));
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+extern "C" {
+    fn __register_frame(entry: *const u8);
+    fn __deregister_frame(entry: *const u8);
+}
+
+// An `.eh_frame` table known to the unwinder until dropped.
+struct EhFrameRegistration {
+    _eh_frame: Box<[u8]>,
+    entries: Vec<*const u8>,
+}
+
+// the entries point into `_eh_frame`, which nothing mutates
+unsafe impl Send for EhFrameRegistration {}
+
+impl EhFrameRegistration {
+    fn new(eh_frame: Vec<u8>) -> Self {
+        let eh_frame = eh_frame.into_boxed_slice();
+        let entries = registered_entries(&eh_frame);
+        #[cfg(not(windows))]
+        for &entry in &entries {
+            unsafe { __register_frame(entry) };
+        }
+        Self {
+            _eh_frame: eh_frame,
+            entries,
+        }
+    }
+}
+
+impl Drop for EhFrameRegistration {
+    fn drop(&mut self) {
+        #[cfg(not(windows))]
+        for &entry in self.entries.iter().rev() {
+            unsafe { __deregister_frame(entry) };
+        }
+    }
+}
+
+// libgcc takes the whole table and walks it itself.
+#[cfg(all(not(windows), not(target_os = "macos")))]
+fn registered_entries(eh_frame: &[u8]) -> Vec<*const u8> {
+    vec![eh_frame.as_ptr()]
+}
+
+// libunwind on macOS takes one FDE at a time.
+#[cfg(target_os = "macos")]
+fn registered_entries(eh_frame: &[u8]) -> Vec<*const u8> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    loop {
+        let word = |at: usize| u32::from_ne_bytes(eh_frame[at..at + 4].try_into().unwrap());
+        let length = word(offset) as usize;
+        if length == 0 {
+            break;
+        }
+        // a CIE has an ID of 0 where an FDE has its CIE pointer
+        if word(offset + 4) != 0 {
+            entries.push(eh_frame[offset..].as_ptr());
+        }
+        offset += 4 + length;
+    }
+    entries
+}
+
+// Windows unwinds with its own function tables, which the JIT doesn't register.
+#[cfg(windows)]
+fn registered_entries(_eh_frame: &[u8]) -> Vec<*const u8> {
+    Vec::new()
+}
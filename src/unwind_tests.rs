@@ -0,0 +1,55 @@
+use crate::compiler::Compiler;
+use crate::error::CompileError;
+use crate::parser::parse;
+use std::backtrace::Backtrace;
+use std::sync::Mutex;
+
+const SOURCE: &str = "
+int check(int x);
+int nested(int x) { return check(x) + 1; }
+int outer(int x) { return nested(x) * 2; }
+int main() { return outer(1); }
+";
+
+extern "C-unwind" fn check(x: i32) -> i32 {
+    if x > 2 {
+        panic!("{} is too big", x);
+    }
+    x
+}
+
+#[test]
+fn test_host_panics_become_errors() {
+    let mut compiler = Compiler::new().unwrap();
+    compiler.register_function("check", check as extern "C-unwind" fn(i32) -> i32);
+    let mut codegen = compiler.build();
+    codegen.compile_program(parse(SOURCE).unwrap()).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 4);
+
+    // the panic unwinds through `nested` and `outer`
+    let err = codegen.call::<_, i32>("outer", (3,)).unwrap_err();
+    assert!(matches!(&err, CompileError::HostPanic(message) if message == "3 is too big"), "{}", err);
+
+    // and the module is still usable afterwards
+    assert_eq!(codegen.call::<_, i32>("outer", (2,)).unwrap(), 6);
+}
+
+static BACKTRACE: Mutex<String> = Mutex::new(String::new());
+
+extern "C-unwind" fn capture(x: i32) -> i32 {
+    *BACKTRACE.lock().unwrap() = Backtrace::force_capture().to_string();
+    x
+}
+
+#[test]
+fn test_backtraces_walk_through_jit_frames() {
+    let mut compiler = Compiler::new().unwrap();
+    compiler.register_function("check", capture as extern "C-unwind" fn(i32) -> i32);
+    let mut codegen = compiler.build();
+    codegen.compile_program(parse(SOURCE).unwrap()).unwrap();
+    assert_eq!(codegen.run_main::<i32>().unwrap(), 4);
+
+    // the frames past the JIT code are only found by unwinding through it
+    let backtrace = BACKTRACE.lock().unwrap().clone();
+    assert!(backtrace.contains("test_backtraces_walk_through_jit_frames"), "{}", backtrace);
+}
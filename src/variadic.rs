@@ -0,0 +1,100 @@
+use crate::ast::AstType;
+use cranelift_codegen::binemit::Reloc;
+use cranelift_codegen::ir::ExternalName;
+use cranelift_codegen::{FinalizedMachReloc, FinalizedRelocTarget};
+use target_lexicon::{Architecture, CallingConvention, OperatingSystem, Triple};
+
+// How a target passes the variable part of a C variadic call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarargsAbi {
+    // variadic arguments are passed exactly like named ones
+    Plain,
+    // x86-64 System V: like named arguments, but %al must hold an upper bound
+    // on the number of vector registers used
+    SysV,
+    // RISC-V: variadic floating-point arguments travel in integer registers
+    IntegerRegisters,
+    // Apple arm64 (everything on the stack) and Windows x64 (floats duplicated
+    // in integer registers) need lowering Cranelift can't express yet
+    Unsupported,
+}
+
+pub fn varargs_abi(triple: &Triple) -> VarargsAbi {
+    match triple.architecture {
+        Architecture::X86_64 if triple.operating_system == OperatingSystem::Windows => {
+            VarargsAbi::Unsupported
+        }
+        Architecture::X86_64 => VarargsAbi::SysV,
+        Architecture::Aarch64(_)
+            if triple.default_calling_convention() == Ok(CallingConvention::AppleAarch64) =>
+        {
+            VarargsAbi::Unsupported
+        }
+        Architecture::Aarch64(_) | Architecture::S390x => VarargsAbi::Plain,
+        Architecture::Riscv64(_) => VarargsAbi::IntegerRegisters,
+        _ => VarargsAbi::Unsupported,
+    }
+}
+
+// C's default argument promotions for arguments matching the `...`
+pub fn promote(type_: &AstType) -> AstType {
+    match type_ {
+        AstType::F32 => AstType::F64,
+        AstType::I8 | AstType::I16 | AstType::U8 | AstType::U16 | AstType::Bool | AstType::Char => {
+            AstType::I32
+        }
+        other => other.clone(),
+    }
+}
+
+// Machine code for a SysV x86-64 call shim: set %al, then tail-jump to `target`.
+// Arguments are already in place, so the callee sees the caller's frame untouched.
+pub fn sysv_al_shim(
+    vector_regs: u8,
+    target: ExternalName,
+    is_pic: bool,
+) -> (Vec<u8>, Vec<FinalizedMachReloc>) {
+    // mov al, imm8
+    let mut bytes = vec![0xB0, vector_regs];
+    let reloc = if is_pic {
+        // jmp *target@GOTPCREL(%rip)
+        bytes.extend_from_slice(&[0xFF, 0x25, 0, 0, 0, 0]);
+        FinalizedMachReloc {
+            offset: 4,
+            kind: Reloc::X86GOTPCRel4,
+            target: FinalizedRelocTarget::ExternalName(target),
+            addend: -4,
+        }
+    } else {
+        // movabs r11, imm64; jmp r11
+        bytes.extend_from_slice(&[0x49, 0xBB, 0, 0, 0, 0, 0, 0, 0, 0, 0x41, 0xFF, 0xE3]);
+        FinalizedMachReloc {
+            offset: 4,
+            kind: Reloc::Abs8,
+            target: FinalizedRelocTarget::ExternalName(target),
+            addend: 0,
+        }
+    };
+    (bytes, vec![reloc])
+}
+
+// x86-64 System V `va_list`, as laid out by C compilers:
+//   struct { u32 gp_offset; u32 fp_offset; void *overflow_arg_area; void *reg_save_area; }
+pub const SYSV_VA_LIST_SIZE: u32 = 24;
+pub const SYSV_GP_OFFSET: i32 = 0;
+pub const SYSV_FP_OFFSET: i32 = 4;
+pub const SYSV_OVERFLOW_ARG_AREA: i32 = 8;
+pub const SYSV_REG_SAVE_AREA: i32 = 16;
+
+// The register save area holds rdi, rsi, rdx, rcx, r8, r9 followed by xmm0-xmm7.
+pub const SYSV_GP_REGS: u32 = 6;
+pub const SYSV_FP_REGS: u32 = 8;
+pub const SYSV_GP_SAVE_SIZE: u32 = SYSV_GP_REGS * 8;
+pub const SYSV_REG_SAVE_AREA_SIZE: u32 = SYSV_GP_SAVE_SIZE + SYSV_FP_REGS * 16;
+
+// How many integer and vector argument registers `params` occupy. Params that
+// don't fit are passed on the stack, one eightbyte each.
+pub fn sysv_register_counts(params: &[AstType]) -> (u32, u32) {
+    let fp = params.iter().filter(|type_| type_.is_float()).count() as u32;
+    (params.len() as u32 - fp, fp)
+}
@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("compiler_test-cli-{}-{}", std::process::id(), name))
+}
+
+fn compiler(args: &[&str], source_name: &str, source: &str) -> Output {
+    let input = scratch_path(source_name);
+    fs::write(&input, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_compiler_test"))
+        .args(args)
+        .arg(&input)
+        .output()
+        .unwrap();
+    fs::remove_file(&input).unwrap();
+    output
+}
+
+const ADD: &str = "int add(int a, int b) { return a + b; }\nint main() { return add(40, 2); }\n";
+
+#[test]
+fn test_run_exits_with_main_result() {
+    let output = compiler(&["run"], "run.c", ADD);
+    assert_eq!(output.status.code(), Some(42));
+    let output = compiler(&["run", "-O", "speed"], "run_opt.c", ADD);
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn test_build_executable() {
+    let exe = scratch_path("build_exe");
+    let output = compiler(&["build", "-o", exe.to_str().unwrap()], "build.c", ADD);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let status = Command::new(&exe).status().unwrap();
+    fs::remove_file(&exe).unwrap();
+    assert_eq!(status.code(), Some(42));
+}
+
+#[test]
+fn test_check_reports_diagnostics() {
+    let output = compiler(&["check"], "ok.c", ADD);
+    assert!(output.status.success());
+
+    let output = compiler(&["check"], "bad.c", "int main() {\n  return y;\n}\n");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bad.c:2:10: error: undeclared identifier `y`"), "{}", stderr);
+
+    let output = compiler(&["check"], "args.c", "int f(int a) { return a; }\nint main() { return f(1, 2); }\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: function `f` expects 1 arguments, found 2"), "{}", stderr);
+}
+
+#[test]
+fn test_emit_ast_json_round_trips() {
+    let output = compiler(&["emit", "--emit=ast-json"], "json.c", ADD);
+    assert!(output.status.success());
+    let output = compiler(&["run"], "from.json", &String::from_utf8(output.stdout).unwrap());
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn test_emit_clif_and_asm() {
+    let output = compiler(&["emit", "--emit", "clif"], "clif.c", ADD);
+    let clif = String::from_utf8(output.stdout).unwrap();
+    assert!(clif.contains("; add\nfunction") && clif.contains("; main\nfunction"), "{}", clif);
+    assert!(clif.contains("iadd"), "{}", clif);
+
+    let output = compiler(&["emit", "--emit", "asm"], "asm.c", ADD);
+    let asm = String::from_utf8(output.stdout).unwrap();
+    assert!(asm.contains("; add\n") && asm.contains("ret"), "{}", asm);
+}
+
+#[test]
+fn test_emit_report() {
+    let output = compiler(&["emit", "--emit=report", "-O", "speed"], "report.c", ADD);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let functions = report["functions"].as_array().unwrap();
+    assert_eq!(functions[0]["name"], "add");
+    assert_eq!(functions[1]["name"], "main");
+    for function in functions {
+        assert!(function["optimized_clif"].as_str().unwrap().starts_with("function"));
+        assert!(function["stats"]["code_size"].as_u64().unwrap() > 0);
+    }
+}
+
+#[test]
+fn test_usage_errors() {
+    let output = compiler(&["emit"], "usage.c", ADD);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage:"));
+    let output = compiler(&["frobnicate"], "usage2.c", ADD);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_repl_reads_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiler_test"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = "int square(int x) {\n  return x * x;\n}\nsquare(6)\nmissing\n1.0 / 4.0\n:quit\n2\n";
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "36\n0.25\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: 1:1: undeclared identifier `missing`"), "{}", stderr);
+}
+
+#[test]
+fn test_cranelift_settings_options() {
+    let output = compiler(&["run", "--no-verifier", "--cpu-flag=has_avx=false", "-O2"], "settings.c", ADD);
+    assert_eq!(output.status.code(), Some(42), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = compiler(&["check", "--cpu-flag", "has_nothing"], "bad_flag.c", ADD);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has_nothing"));
+}
+
+#[test]
+fn test_build_object_for_another_target() {
+    let object = scratch_path("cross.o");
+    let output = compiler(
+        &["build", "--object", "--target", "aarch64-unknown-linux-gnu", "-o", object.to_str().unwrap()],
+        "cross.c",
+        ADD,
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bytes = fs::read(&object).unwrap();
+    fs::remove_file(&object).unwrap();
+    // ELF, with e_machine EM_AARCH64
+    assert_eq!(&bytes[..4], b"\x7fELF");
+    assert_eq!(u16::from_le_bytes([bytes[18], bytes[19]]), 183);
+}
+
+#[test]
+fn test_debug_info_option() {
+    let object = scratch_path("debug.o");
+    let output = compiler(
+        &["build", "--object", "-g", "--target", "x86_64-unknown-linux-gnu", "-o", object.to_str().unwrap()],
+        "debug.c",
+        ADD,
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bytes = fs::read(&object).unwrap();
+    fs::remove_file(&object).unwrap();
+    assert!(bytes.windows(b".debug_line".len()).any(|window| window == b".debug_line"));
+    assert!(bytes.windows(b"debug.c".len()).any(|window| window.ends_with(b"debug.c")));
+
+    let output = compiler(&["run", "-g"], "debug_run.c", ADD);
+    assert_eq!(output.status.code(), Some(42), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_checked_option() {
+    let source = "int main() {\n    int big = 2147483647;\n    return big + 1;\n}\n";
+    let output = compiler(&["run"], "wrapping.c", source);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = compiler(&["run", "--checked"], "checked.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("checked.c: error: trap overflow in `main` at line 3\n"), "{}", stderr);
+}
+
+#[test]
+fn test_stack_size_option() {
+    let source = "int depth(int n) {\n    if (n == 0) { return 0; }\n    return depth(n - 1) + 1;\n}\n\
+                  int main() { return depth(2000000) - 1999958; }\n";
+    let output = compiler(&["run"], "deep.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("deep.c: error: stack overflow in `depth` at line 1\n"), "{}", stderr);
+
+    let output = compiler(&["run", "--stack-size", "536870912"], "deep_thread.c", source);
+    assert_eq!(output.status.code(), Some(42), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_fuel_option() {
+    let source = "int main() {\n    int i = 0;\n    while (i >= 0) { i = i % 7; }\n    return 1;\n}\n";
+    let output = compiler(&["run", "--fuel", "1000"], "spin.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("spin.c: error: out of fuel in `main` at line 3\n"), "{}", stderr);
+
+    let output = compiler(&["run", "--fuel", "1000"], "fueled.c", ADD);
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn test_sandbox_option() {
+    let source = "int puts(char *s);\nint main() {\n    puts(\"sandboxed\");\n    return 3;\n}\n";
+    let output = compiler(&["run", "--sandbox", "puts"], "puts.c", source);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sandboxed\n");
+
+    let output = compiler(&["run", "--sandbox="], "puts.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("puts.c: error: `puts` is not an allowed import in the sandbox\n"), "{}", stderr);
+}
+
+#[test]
+fn test_interpret_option() {
+    let source = "int printf(char *format, ...);\nint main() {\n    printf(\"%d-%s\\n\", 42, \"interpreted\");\n\
+                  int big = 2147483647;\n    return big + 1;\n}\n";
+    let output = compiler(&["run", "--interpret"], "interpreted.c", source);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42-interpreted\n");
+
+    let output = compiler(&["run", "--interpret", "--checked"], "interpreted_checked.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("interpreted_checked.c: error: trap overflow in `main` at line 5\n"), "{}", stderr);
+
+    let output = compiler(&["run", "--interpret", "--fuel", "1000"], "interpreted_fuel.c", ADD);
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn test_reduce_command() {
+    let source = "int unused(int x) { return x; }\nint main() {\n    int a = 5;\n    int b = a - 5;\n    return a / b;\n}\n";
+    let exe = env!("CARGO_BIN_EXE_compiler_test");
+    let test = format!("'{}' run \"$1\" 2>&1 | grep -q int_divz", exe);
+    let output = compiler(&["reduce", "--test", &test], "reduce.c", source);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json = String::from_utf8_lossy(&output.stdout);
+    assert!(json.contains("\"Div\"") && !json.contains("unused"), "{}", json);
+
+    let output = compiler(&["reduce", "--test", "exit 1"], "reduce_fails.c", source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("reduce_fails.c: error: `exit 1` fails on the input itself\n"), "{}", stderr);
+
+    let output = compiler(&["reduce"], "reduce_no_test.c", source);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,222 @@
+// Runs each program in tests/programs through the JIT and through an object
+// linked into an executable, checking both against the expectations in the
+// file's header comments:
+//
+//     // exit: 42                   main's result, as an exit status
+//     // stdout: one line of output  repeated for each line
+//     // error: 3:5: message        a diagnostic, with the line and column if it has them
+//
+// `.src` files are C source and `.json` files AST JSON after the header.
+// With BLESS=1, headers are rewritten to what the JIT does.
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    exit: Option<i32>,
+    stdout: Vec<String>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn parse_header(text: &str) -> Outcome {
+        let mut outcome = Outcome::default();
+        for line in header(text) {
+            let Some((key, value)) = line.trim_start_matches("//").trim_start().split_once(':') else {
+                continue;
+            };
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match key {
+                "exit" => outcome.exit = Some(value.parse().expect("exit statuses are numbers")),
+                "stdout" => outcome.stdout.push(value.to_string()),
+                "error" => outcome.error = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        outcome
+    }
+
+    fn header(&self) -> String {
+        let mut header = String::new();
+        if let Some(exit) = self.exit {
+            writeln!(header, "// exit: {}", exit).unwrap();
+        }
+        for line in &self.stdout {
+            writeln!(header, "// stdout: {}", line).unwrap();
+        }
+        if let Some(error) = &self.error {
+            writeln!(header, "// error: {}", error).unwrap();
+        }
+        header
+    }
+
+    // What the compiler or the program did. A diagnostic names the input, which
+    // is left out.
+    fn from_output(output: &Output, input: &Path) -> Outcome {
+        let stdout = String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect();
+        if output.status.success() || output.stderr.is_empty() {
+            return Outcome {
+                exit: output.status.code(),
+                stdout,
+                error: None,
+            };
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let line = stderr.lines().next().unwrap_or_default();
+        let line = line.strip_prefix(input.to_str().unwrap()).unwrap_or(line).trim_start_matches(':');
+        let error = line.replacen("error: ", "", 1).trim().to_string();
+        Outcome {
+            exit: None,
+            stdout,
+            error: Some(error),
+        }
+    }
+}
+
+// the comment lines a case starts with
+fn header(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().take_while(|line| line.starts_with("//"))
+}
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("compiler_test-golden-{}-{}", std::process::id(), name))
+}
+
+fn compiler(args: &[&str], input: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_compiler_test"))
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap()
+}
+
+// The JIT's outcome and the linked executable's.
+fn run_case(path: &Path, text: &str) -> (Outcome, Outcome) {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    // the CLI reads JSON without the header
+    let json = path.extension().is_some_and(|extension| extension == "json");
+    let input = if json { scratch_path(name) } else { path.to_path_buf() };
+    if json {
+        let body: Vec<&str> = text.lines().skip(header(text).count()).collect();
+        fs::write(&input, body.join("\n")).unwrap();
+    }
+
+    let jit = Outcome::from_output(&compiler(&["run"], &input), &input);
+    let exe = scratch_path(&format!("{}.exe", name));
+    let build = compiler(&["build", "-o", exe.to_str().unwrap()], &input);
+    let linked = if build.status.success() {
+        let output = Command::new(&exe).output().unwrap();
+        fs::remove_file(&exe).unwrap();
+        Outcome::from_output(&output, &exe)
+    } else {
+        Outcome::from_output(&build, &input)
+    };
+    if json {
+        fs::remove_file(&input).unwrap();
+    }
+    (jit, linked)
+}
+
+// Rewrites `path`'s header to `outcome`. The header's length moves the source
+// below it, so this repeats until diagnostics stop changing.
+fn bless(path: &Path, mut text: String, mut outcome: Outcome) {
+    for _ in 0..3 {
+        let body: Vec<&str> = text.lines().skip(header(&text).count()).collect();
+        let blessed = format!("{}{}\n", outcome.header(), body.join("\n"));
+        fs::write(path, &blessed).unwrap();
+        let (jit, _) = run_case(path, &blessed);
+        if jit == outcome {
+            break;
+        }
+        (text, outcome) = (blessed, jit);
+    }
+}
+
+#[test]
+fn test_programs() {
+    let blessing = std::env::var_os("BLESS").is_some_and(|value| value != "0");
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "src" || extension == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let mut text = fs::read_to_string(path).unwrap();
+        let (mut jit, mut linked) = run_case(path, &text);
+        if blessing && jit != Outcome::parse_header(&text) {
+            bless(path, text, jit);
+            text = fs::read_to_string(path).unwrap();
+            (jit, linked) = run_case(path, &text);
+        }
+        let expected = Outcome::parse_header(&text);
+        for (how, found) in [("the JIT", jit), ("the linked executable", linked)] {
+            if found != expected {
+                failures.push(format!(
+                    "{}: {} did\n{}but expected\n{}",
+                    path.display(),
+                    how,
+                    found.header(),
+                    expected.header()
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}\nrerun with BLESS=1 to accept the JIT's outcomes", failures.join("\n"));
+}
+
+#[test]
+fn test_readme_snippets_are_cases() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let readme = fs::read_to_string(root.join("readme.md")).unwrap();
+    let cases: Vec<String> = fs::read_dir(root.join("tests/programs"))
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    let features = &readme[readme.find("## Features to Test").unwrap()..];
+    let snippets: Vec<&str> = features
+        .split("```c")
+        .skip(1)
+        .map(|block| {
+            let block = &block[block.find('\n').unwrap() + 1..];
+            &block[..block.find("```").unwrap()]
+        })
+        .collect();
+    assert!(!snippets.is_empty());
+    for snippet in snippets {
+        assert!(cases.iter().any(|case| case.contains(snippet)), "no case in tests/programs has\n{}", snippet);
+    }
+}
+
+// A feature's ✓ or ✗ in the readme says whether its case compiles, so the
+// marks can't drift from what `Codegen` does.
+#[test]
+fn test_readme_marks_match_cases() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let readme = fs::read_to_string(root.join("readme.md")).unwrap();
+    let features = &readme[readme.find("## Features to Test").unwrap()..];
+    let mut marked = 0;
+    for line in features.lines().filter(|line| line.starts_with("- ")) {
+        let (name, supported) = match (line.find('✓'), line.find('✗')) {
+            (Some(mark), _) => (&line[2..mark], true),
+            (_, Some(mark)) => (&line[2..mark], false),
+            _ => panic!("readme feature `{}` has no ✓ or ✗", line),
+        };
+        let file: String = name
+            .trim()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let case = fs::read_to_string(root.join("tests/programs").join(file + ".src")).unwrap();
+        let outcome = Outcome::parse_header(&case);
+        assert_eq!(outcome.error.is_none(), supported, "readme feature `{}`: {:?}", name.trim(), outcome);
+        marked += 1;
+    }
+    assert!(marked > 0);
+}